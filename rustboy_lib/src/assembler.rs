@@ -0,0 +1,905 @@
+use std::collections::HashMap;
+
+use crate::cpu::instructions::{BitPosition, Indirect, Instructions, JumpTest, LoadType, RegisterTarget, StackTarget, VirtualRegisterTarget};
+use crate::symbols::Symbols;
+
+// Assembles SM83 code written for rgbasm: the usual mnemonics, global labels and .local
+// ones under them, db/dw/ds, EQU constants, expressions over numbers and labels and
+// SECTIONs placed at fixed addresses. Lines before the first SECTION go in ROM0 from
+// 0x0000, or wherever assemble_at puts them, which is all a test program needs. Macros,
+// includes and floating sections aren't supported. It takes two passes, the first to find
+// where every label lands and the second to fill in the bytes
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const MIN_ROM_SIZE: usize = 0x8000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SectionType {
+    Rom0,
+    RomX,
+    Vram,
+    Sram,
+    Wram0,
+    WramX,
+    Oam,
+    Hram,
+}
+impl SectionType {
+    fn parse(name: &str) -> Option<SectionType> {
+        match name.to_ascii_uppercase().as_str() {
+            "ROM0" => Some(SectionType::Rom0),
+            "ROMX" => Some(SectionType::RomX),
+            "VRAM" => Some(SectionType::Vram),
+            "SRAM" => Some(SectionType::Sram),
+            "WRAM0" => Some(SectionType::Wram0),
+            "WRAMX" => Some(SectionType::WramX),
+            "OAM" => Some(SectionType::Oam),
+            "HRAM" => Some(SectionType::Hram),
+            _ => None,
+        }
+    }
+
+    fn containing(address: u16) -> Option<SectionType> {
+        [SectionType::Rom0, SectionType::RomX, SectionType::Vram, SectionType::Sram, SectionType::Wram0, SectionType::WramX, SectionType::Oam, SectionType::Hram]
+            .into_iter()
+            .find(|kind| (kind.range().0..kind.range().1).contains(&(address as usize)))
+    }
+
+    // The addresses sections of this type can cover, end exclusive
+    fn range(&self) -> (usize, usize) {
+        match self {
+            SectionType::Rom0 => (0x0000, 0x4000),
+            SectionType::RomX => (0x4000, 0x8000),
+            SectionType::Vram => (0x8000, 0xA000),
+            SectionType::Sram => (0xA000, 0xC000),
+            SectionType::Wram0 => (0xC000, 0xD000),
+            SectionType::WramX => (0xD000, 0xE000),
+            SectionType::Oam => (0xFE00, 0xFEA0),
+            SectionType::Hram => (0xFF80, 0xFFFF),
+        }
+    }
+
+    pub fn is_rom(&self) -> bool {
+        matches!(self, SectionType::Rom0 | SectionType::RomX)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub kind: SectionType,
+    pub address: u16,
+    pub bank: usize,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+impl std::fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// The assembled sections and every label in them, by bank as rgblink writes them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    pub sections: Vec<Section>,
+    pub symbols: Symbols,
+}
+impl Program {
+    // The ROM sections laid out as a ROM image, padded with zeros to a power of two of at
+    // least 32 KiB. Sections in RAM are left out
+    pub fn rom(&self) -> Vec<u8> {
+        let mut rom = Vec::new();
+        self.patch(&mut rom);
+        return rom
+    }
+
+    // Writes the ROM sections over rom, which grows to fit them when they run past its end
+    pub fn patch(&self, rom: &mut Vec<u8>) {
+        let offset = |section: &Section| match section.kind {
+            SectionType::RomX => section.bank * ROM_BANK_SIZE + section.address as usize - ROM_BANK_SIZE,
+            _ => section.address as usize,
+        };
+        let sections: Vec<&Section> = self.sections.iter().filter(|section| section.kind.is_rom()).collect();
+        let end = sections.iter().map(|section| offset(section) + section.bytes.len()).max().unwrap_or(0);
+        if end > rom.len() || rom.len() < MIN_ROM_SIZE {
+            rom.resize(end.next_power_of_two().max(rom.len()).max(MIN_ROM_SIZE), 0);
+        }
+        for section in sections {
+            let start = offset(section);
+            rom[start..start + section.bytes.len()].copy_from_slice(&section.bytes);
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, AssemblyError> {
+    return assemble_at(source, 0x0000)
+}
+
+// Assembles with the lines before the first SECTION starting at origin, in whatever
+// memory is there
+pub fn assemble_at(source: &str, origin: u16) -> Result<Program, AssemblyError> {
+    let Some(kind) = SectionType::containing(origin) else {
+        return Err(AssemblyError { line: 0, message: format!("nothing can be assembled at ${:04X}", origin) });
+    };
+    let mut assembler = Assembler::new(kind, origin);
+    for (index, line) in source.lines().enumerate() {
+        assembler.line(line).map_err(|message| AssemblyError { line: index + 1, message })?;
+    }
+    return assembler.finish()
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Register(RegisterTarget), // a b c d e h l and [hl]
+    Word(VirtualRegisterTarget),
+    AF,
+    Indirect(Indirect), // [bc] [de] [hli] [hld] [c], the ones without a number in them
+    Memory(Expr),
+    SpOffset(Expr), // sp + e8
+    Value(Expr),
+}
+
+#[derive(Clone, Debug)]
+enum DataItem {
+    Value(Expr),
+    Text(Vec<u8>),
+}
+
+#[derive(Clone, Debug)]
+enum Statement {
+    Instruction(String, Vec<Operand>),
+    Data(usize, Vec<DataItem>), // Item width in bytes
+}
+
+// A statement waiting for the second pass, with where its bytes go
+struct Pending {
+    line: usize,
+    section: usize,
+    offset: usize,
+    statement: Statement,
+}
+
+struct Assembler {
+    sections: Vec<Section>,
+    values: HashMap<String, i64>,
+    labels: Vec<(String, usize)>, // With the section they are in
+    pending: Vec<Pending>,
+    scope: String, // The last global label, which .local ones belong to
+    line: usize,
+    origin: (SectionType, u16),
+}
+impl Assembler {
+    fn new(kind: SectionType, origin: u16) -> Assembler {
+        return Assembler {
+            sections: Vec::new(),
+            values: HashMap::new(),
+            labels: Vec::new(),
+            pending: Vec::new(),
+            scope: String::new(),
+            line: 0,
+            origin: (kind, origin),
+        }
+    }
+
+    fn line(&mut self, text: &str) -> Result<(), String> {
+        self.line += 1;
+        let mut text = strip_comment(text).trim_end();
+        // A label starts the line, ending with : or :: unless it's a local one
+        let first = text.split_whitespace().next().unwrap_or_default();
+        let starts_line = !text.starts_with(char::is_whitespace);
+        if let Some(name) = first.strip_suffix(':').map(|name| name.trim_end_matches(':')) && !name.is_empty() && !name.contains('[') {
+            self.label(name)?;
+            text = text.trim_start()[first.len()..].trim_start();
+        } else if starts_line && first.starts_with('.') {
+            self.label(first)?;
+            text = text[first.len()..].trim_start();
+        }
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(());
+        }
+        let (keyword, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let rest = rest.trim();
+        let lower = keyword.to_ascii_lowercase();
+        match lower.as_str() {
+            "section" => return self.section(rest),
+            "def" => {
+                let (name, rest) = rest.split_once(char::is_whitespace).ok_or("expected DEF name EQU value")?;
+                return self.constant(name, rest);
+            }
+            "db" => return self.data(1, rest),
+            "dw" => return self.data(2, rest),
+            "ds" => return self.space(rest),
+            _ => {}
+        }
+        if let Some((word, value)) = rest.split_once(char::is_whitespace) && word.eq_ignore_ascii_case("equ") {
+            return self.constant(keyword, &format!("EQU {}", value));
+        }
+        let operands = split_operands(rest)?.iter().map(|operand| parse_operand(operand, &self.scope)).collect::<Result<Vec<_>, _>>()?;
+        // Sizes don't depend on values, so a build with everything as 0 gives the length
+        let length = build(&lower, &operands, &Evaluator { values: &self.values, here: 0, strict: false })?.len();
+        self.reserve(Statement::Instruction(lower, operands), length)
+    }
+
+    fn label(&mut self, name: &str) -> Result<(), String> {
+        let name = if name.starts_with('.') {
+            if self.scope.is_empty() {
+                return Err(format!("local label {} has no global label before it", name));
+            }
+            format!("{}{}", self.scope, name)
+        } else {
+            if !name.contains('.') {
+                self.scope = String::from(name);
+            }
+            String::from(name)
+        };
+        let section = self.current()?;
+        let address = self.sections[section].address as i64 + self.sections[section].bytes.len() as i64;
+        self.define(&name, address)?;
+        self.labels.push((name, section));
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if !is_name(name) {
+            return Err(format!("{} isn't a valid name", name));
+        }
+        if self.values.insert(String::from(name), value).is_some() {
+            return Err(format!("{} is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn constant(&mut self, name: &str, rest: &str) -> Result<(), String> {
+        let (keyword, value) = rest.trim().split_once(char::is_whitespace).ok_or("expected NAME EQU value")?;
+        if !keyword.eq_ignore_ascii_case("equ") {
+            return Err(format!("expected EQU, got {}", keyword));
+        }
+        let value = self.constant_value(&parse_expr(value, &self.scope)?)?;
+        self.define(name, value)
+    }
+
+    // A value needed during the first pass, which can only use what came before
+    fn constant_value(&self, expr: &Expr) -> Result<i64, String> {
+        let here = match self.sections.last() {
+            Some(section) => section.address as i64 + section.bytes.len() as i64,
+            None => 0,
+        };
+        Evaluator { values: &self.values, here, strict: true }.value(expr)
+    }
+
+    // SECTION "name", TYPE[$address] with an optional BANK[n] after
+    fn section(&mut self, rest: &str) -> Result<(), String> {
+        let parts = split_operands(rest)?;
+        let usage = || String::from("expected SECTION \"name\", TYPE[$address]");
+        let name = parts.first().and_then(|name| name.strip_prefix('"')).and_then(|name| name.strip_suffix('"')).ok_or_else(usage)?;
+        let placement = parts.get(1).ok_or_else(usage)?;
+        let (kind, address) = placement.split_once('[').ok_or("sections need a fixed address")?;
+        let kind = SectionType::parse(kind.trim()).ok_or_else(|| format!("unknown section type {}", kind.trim()))?;
+        let address = self.constant_value(&parse_expr(address.trim().strip_suffix(']').ok_or_else(usage)?, &self.scope)?)?;
+        let (start, end) = kind.range();
+        if address < start as i64 || address >= end as i64 {
+            return Err(format!("${:04X} isn't in {:?}, which is ${:04X}-${:04X}", address, kind, start, end - 1));
+        }
+        let bank = match parts.get(2) {
+            Some(bank) => {
+                let value = bank.trim().strip_prefix("BANK[").or_else(|| bank.trim().strip_prefix("bank[")).and_then(|value| value.strip_suffix(']'));
+                self.constant_value(&parse_expr(value.ok_or_else(usage)?, &self.scope)?)? as usize
+            }
+            None if kind == SectionType::RomX || kind == SectionType::WramX => 1,
+            None => 0,
+        };
+        if kind == SectionType::RomX && bank == 0 {
+            return Err(String::from("ROMX sections can't be in bank 0"));
+        }
+        self.sections.push(Section { name: String::from(name), kind, address: address as u16, bank, bytes: Vec::new() });
+        Ok(())
+    }
+
+    fn data(&mut self, width: usize, rest: &str) -> Result<(), String> {
+        let mut items = Vec::new();
+        let mut length = 0;
+        for item in split_operands(rest)? {
+            if let Some(text) = item.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+                if width != 1 {
+                    return Err(String::from("strings only go in db"));
+                }
+                length += text.len();
+                items.push(DataItem::Text(text.as_bytes().to_vec()));
+            } else {
+                length += width;
+                items.push(DataItem::Value(parse_expr(&item, &self.scope)?));
+            }
+        }
+        if items.is_empty() {
+            return Err(String::from("expected at least one value"));
+        }
+        self.reserve(Statement::Data(width, items), length)
+    }
+
+    // ds count, with the fill byte after it, 0 by default
+    fn space(&mut self, rest: &str) -> Result<(), String> {
+        let parts = split_operands(rest)?;
+        let count = parts.first().ok_or("expected ds count")?;
+        let count = self.constant_value(&parse_expr(count, &self.scope)?)?;
+        if !(0..=0x10000).contains(&count) {
+            return Err(format!("can't reserve {} bytes", count));
+        }
+        let fill = match parts.get(1) {
+            Some(fill) => byte(self.constant_value(&parse_expr(fill, &self.scope)?)?)?,
+            None => 0,
+        };
+        let section = self.current()?;
+        self.sections[section].bytes.extend(std::iter::repeat_n(fill, count as usize));
+        self.check_size(section)
+    }
+
+    fn reserve(&mut self, statement: Statement, length: usize) -> Result<(), String> {
+        let section = self.current()?;
+        let offset = self.sections[section].bytes.len();
+        self.sections[section].bytes.resize(offset + length, 0);
+        self.pending.push(Pending { line: self.line, section, offset, statement });
+        self.check_size(section)
+    }
+
+    fn check_size(&self, section: usize) -> Result<(), String> {
+        let section = &self.sections[section];
+        let (_, end) = section.kind.range();
+        if section.address as usize + section.bytes.len() > end {
+            return Err(format!("section \"{}\" runs past ${:04X}", section.name, end - 1));
+        }
+        Ok(())
+    }
+
+    // The section being written, which starts at the origin until there's a SECTION
+    fn current(&mut self) -> Result<usize, String> {
+        if self.sections.is_empty() {
+            let (kind, address) = self.origin;
+            let bank = if matches!(kind, SectionType::RomX | SectionType::WramX) { 1 } else { 0 };
+            self.sections.push(Section { name: String::from("code"), kind, address, bank, bytes: Vec::new() });
+        }
+        Ok(self.sections.len() - 1)
+    }
+
+    fn finish(mut self) -> Result<Program, AssemblyError> {
+        for pending in std::mem::take(&mut self.pending) {
+            let section = &self.sections[pending.section];
+            let here = section.address as i64 + pending.offset as i64;
+            let evaluator = Evaluator { values: &self.values, here, strict: true };
+            let error = |message| AssemblyError { line: pending.line, message };
+            let bytes = match &pending.statement {
+                Statement::Instruction(mnemonic, operands) => build(mnemonic, operands, &evaluator).map_err(error)?,
+                Statement::Data(width, items) => {
+                    let mut bytes = Vec::new();
+                    for item in items {
+                        match item {
+                            DataItem::Text(text) => bytes.extend_from_slice(text),
+                            DataItem::Value(expr) if *width == 1 => bytes.push(evaluator.byte(expr).map_err(error)?),
+                            DataItem::Value(expr) => bytes.extend_from_slice(&evaluator.word(expr).map_err(error)?.to_le_bytes()),
+                        }
+                    }
+                    bytes
+                }
+            };
+            self.sections[pending.section].bytes[pending.offset..pending.offset + bytes.len()].copy_from_slice(&bytes);
+        }
+        let mut symbols = Symbols::new();
+        for (name, section) in &self.labels {
+            symbols.insert(self.sections[*section].bank, self.values[name] as u16, name);
+        }
+        return Ok(Program { sections: self.sections, symbols })
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (index, character) in text.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..index],
+            _ => {}
+        }
+    }
+    return text
+}
+
+fn is_name(name: &str) -> bool {
+    let mut characters = name.chars();
+    matches!(characters.next(), Some(first) if first.is_ascii_alphabetic() || first == '_' || first == '.')
+        && characters.all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '.')
+}
+
+// Splits at the commas that aren't in brackets, parentheses or quotes
+fn split_operands(text: &str) -> Result<Vec<String>, String> {
+    if text.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut operands = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut current = String::new();
+    for character in text.chars() {
+        match character {
+            '"' => quoted = !quoted,
+            '[' | '(' if !quoted => depth += 1,
+            ']' | ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(character);
+    }
+    if quoted || depth != 0 {
+        return Err(format!("unbalanced brackets or quotes in {}", text));
+    }
+    operands.push(current.trim().to_string());
+    if operands.iter().any(|operand| operand.is_empty()) {
+        return Err(format!("empty operand in {}", text));
+    }
+    return Ok(operands)
+}
+
+fn parse_operand(text: &str, scope: &str) -> Result<Operand, String> {
+    let compact: String = text.chars().filter(|character| !character.is_whitespace()).collect::<String>().to_ascii_lowercase();
+    if let Some(inner) = compact.strip_prefix('[').and_then(|inner| inner.strip_suffix(']')) {
+        return Ok(match inner {
+            "hl" => Operand::Register(RegisterTarget::HLI),
+            "bc" => Operand::Indirect(Indirect::BC),
+            "de" => Operand::Indirect(Indirect::DE),
+            "hl+" | "hli" => Operand::Indirect(Indirect::HLInc),
+            "hl-" | "hld" => Operand::Indirect(Indirect::HLDec),
+            "c" | "$ff00+c" | "0xff00+c" => Operand::Indirect(Indirect::HighC),
+            _ => {
+                let text = text.trim();
+                Operand::Memory(parse_expr(&text[1..text.len() - 1], scope)?)
+            }
+        });
+    }
+    return Ok(match compact.as_str() {
+        "a" => Operand::Register(RegisterTarget::A),
+        "b" => Operand::Register(RegisterTarget::B),
+        "c" => Operand::Register(RegisterTarget::C),
+        "d" => Operand::Register(RegisterTarget::D),
+        "e" => Operand::Register(RegisterTarget::E),
+        "h" => Operand::Register(RegisterTarget::H),
+        "l" => Operand::Register(RegisterTarget::L),
+        "bc" => Operand::Word(VirtualRegisterTarget::BC),
+        "de" => Operand::Word(VirtualRegisterTarget::DE),
+        "hl" => Operand::Word(VirtualRegisterTarget::HL),
+        "sp" => Operand::Word(VirtualRegisterTarget::SP),
+        "af" => Operand::AF,
+        _ if compact.starts_with("sp+") || compact.starts_with("sp-") => Operand::SpOffset(parse_expr(&text.trim()[2..], scope)?),
+        _ => Operand::Value(parse_expr(text, scope)?),
+    })
+}
+
+fn condition(operand: &Operand) -> Option<JumpTest> {
+    match operand {
+        Operand::Register(RegisterTarget::C) => Some(JumpTest::Carry),
+        Operand::Value(Expr::Symbol(name)) => match name.to_ascii_lowercase().as_str() {
+            "nz" => Some(JumpTest::NotZero),
+            "z" => Some(JumpTest::Zero),
+            "nc" => Some(JumpTest::NotCarry),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The bytes for one instruction
+fn build(mnemonic: &str, operands: &[Operand], evaluator: &Evaluator) -> Result<Vec<u8>, String> {
+    use Operand::{Indirect as Ind, Memory, Register, SpOffset, Value, Word};
+    let alu: Option<fn(RegisterTarget) -> Instructions> = match mnemonic {
+        "add" => Some(Instructions::ADD),
+        "adc" => Some(Instructions::ADC),
+        "sub" => Some(Instructions::SUB),
+        "sbc" => Some(Instructions::SBC),
+        "and" => Some(Instructions::AND),
+        "xor" => Some(Instructions::XOR),
+        "or" => Some(Instructions::OR),
+        "cp" => Some(Instructions::CP),
+        _ => None,
+    };
+    let prefixed: Option<fn(RegisterTarget) -> Instructions> = match mnemonic {
+        "rlc" => Some(Instructions::RLC),
+        "rrc" => Some(Instructions::RRC),
+        "rl" => Some(Instructions::RL),
+        "rr" => Some(Instructions::RR),
+        "sla" => Some(Instructions::SLA),
+        "sra" => Some(Instructions::SRA),
+        "swap" => Some(Instructions::SWAP),
+        "srl" => Some(Instructions::SRL),
+        _ => None,
+    };
+    // Conditional jumps take the condition first
+    let (test, operands) = match operands.split_first() {
+        Some((first, rest)) if matches!(mnemonic, "jp" | "jr" | "call" | "ret") && (rest.len() == 1 || mnemonic == "ret") && condition(first).is_some() => {
+            (condition(first).unwrap(), rest)
+        }
+        _ => (JumpTest::Always, operands),
+    };
+    let instruction = match (mnemonic, operands) {
+        ("nop", []) => Instructions::NOP,
+        ("halt", []) => Instructions::HALT,
+        ("di", []) => Instructions::DI,
+        ("ei", []) => Instructions::EI,
+        ("ccf", []) => Instructions::CCF,
+        ("scf", []) => Instructions::SCF,
+        ("rra", []) => Instructions::RRA,
+        ("rla", []) => Instructions::RLA,
+        ("rrca", []) => Instructions::RRCA,
+        ("rlca", []) => Instructions::RRLA,
+        ("cpl", []) => Instructions::CPL,
+        ("daa", []) => Instructions::DAA,
+        ("reti", []) => Instructions::RETI,
+        // Like rgbasm, stop is followed by a byte the CPU skips
+        ("stop", []) => return Ok(vec![0x10, 0x00]),
+        ("stop", [Value(value)]) => return Ok(vec![0x10, evaluator.byte(value)?]),
+
+        (_, [Register(RegisterTarget::A), source]) if alu.is_some() => alu_operand(alu.unwrap(), source, mnemonic, evaluator)?,
+        (_, [source]) if alu.is_some() => alu_operand(alu.unwrap(), source, mnemonic, evaluator)?,
+        ("add", [Word(VirtualRegisterTarget::HL), Word(source)]) => Instructions::ADDHL(*source),
+        ("add", [Word(VirtualRegisterTarget::SP), Value(offset)]) => Instructions::ADDSP(evaluator.offset(offset)?),
+
+        ("ld", [Register(RegisterTarget::HLI), Register(RegisterTarget::HLI)]) => return Err(String::from("can't load [hl] into [hl]")),
+        ("ld", [Register(target), Register(source)]) => Instructions::LD(LoadType::Byte(*target, *source)),
+        ("ld", [Register(target), Value(value)]) => Instructions::LD(LoadType::Byte(*target, RegisterTarget::D8(evaluator.byte(value)?))),
+        ("ld", [Word(VirtualRegisterTarget::SP), Word(VirtualRegisterTarget::HL)]) => Instructions::LD(LoadType::SPFromHL),
+        ("ld", [Word(target), Value(value)]) => Instructions::LD(LoadType::Word(*target, evaluator.word(value)?)),
+        ("ld", [Word(VirtualRegisterTarget::HL), SpOffset(offset)]) => Instructions::LD(LoadType::HLFromSPOffset(evaluator.offset(offset)?)),
+        ("ld" | "ldh", [Register(RegisterTarget::A), Ind(Indirect::HighC)]) => Instructions::LD(LoadType::AFromIndirect(Indirect::HighC)),
+        ("ld" | "ldh", [Ind(Indirect::HighC), Register(RegisterTarget::A)]) => Instructions::LD(LoadType::IndirectFromA(Indirect::HighC)),
+        ("ld", [Register(RegisterTarget::A), Ind(indirect)]) => Instructions::LD(LoadType::AFromIndirect(*indirect)),
+        ("ld", [Ind(indirect), Register(RegisterTarget::A)]) => Instructions::LD(LoadType::IndirectFromA(*indirect)),
+        ("ld", [Register(RegisterTarget::A), Memory(address)]) => Instructions::LD(LoadType::AFromIndirect(Indirect::Word(evaluator.word(address)?))),
+        ("ld", [Memory(address), Register(RegisterTarget::A)]) => Instructions::LD(LoadType::IndirectFromA(Indirect::Word(evaluator.word(address)?))),
+        ("ld", [Memory(address), Word(VirtualRegisterTarget::SP)]) => Instructions::LD(LoadType::IndirectFromSP(evaluator.word(address)?)),
+        ("ldh", [Register(RegisterTarget::A), Memory(address)]) => Instructions::LD(LoadType::AFromIndirect(Indirect::High(evaluator.high(address)?))),
+        ("ldh", [Memory(address), Register(RegisterTarget::A)]) => Instructions::LD(LoadType::IndirectFromA(Indirect::High(evaluator.high(address)?))),
+        ("ldi", [Register(RegisterTarget::A), Register(RegisterTarget::HLI)]) => Instructions::LD(LoadType::AFromIndirect(Indirect::HLInc)),
+        ("ldi", [Register(RegisterTarget::HLI), Register(RegisterTarget::A)]) => Instructions::LD(LoadType::IndirectFromA(Indirect::HLInc)),
+        ("ldd", [Register(RegisterTarget::A), Register(RegisterTarget::HLI)]) => Instructions::LD(LoadType::AFromIndirect(Indirect::HLDec)),
+        ("ldd", [Register(RegisterTarget::HLI), Register(RegisterTarget::A)]) => Instructions::LD(LoadType::IndirectFromA(Indirect::HLDec)),
+
+        ("inc", [Register(target)]) => Instructions::INC(*target),
+        ("dec", [Register(target)]) => Instructions::DEC(*target),
+        ("inc", [Word(target)]) => Instructions::INC16(*target),
+        ("dec", [Word(target)]) => Instructions::DEC16(*target),
+
+        (_, [Register(target)]) if prefixed.is_some() => prefixed.unwrap()(*target),
+        ("bit" | "res" | "set", [Value(bit), Register(target)]) => {
+            let bit = evaluator.value(bit)?;
+            if !(0..8).contains(&bit) {
+                return Err(format!("bit {} is out of range", bit));
+            }
+            let bit = BitPosition::from(bit as u8);
+            match mnemonic {
+                "bit" => Instructions::BIT(*target, bit),
+                "res" => Instructions::RESET(*target, bit),
+                _ => Instructions::SET(*target, bit),
+            }
+        }
+
+        ("push" | "pop", [target]) => {
+            let target = match target {
+                Operand::AF => StackTarget::AF,
+                Word(VirtualRegisterTarget::BC) => StackTarget::BC,
+                Word(VirtualRegisterTarget::DE) => StackTarget::DE,
+                Word(VirtualRegisterTarget::HL) => StackTarget::HL,
+                _ => return Err(format!("{} only takes af, bc, de or hl", mnemonic)),
+            };
+            if mnemonic == "push" { Instructions::PUSH(target) } else { Instructions::POP(target) }
+        }
+
+        ("jp", [Word(VirtualRegisterTarget::HL) | Register(RegisterTarget::HLI)]) if test == JumpTest::Always => Instructions::JPHL,
+        ("jp", [Value(address)]) => Instructions::JP(test, evaluator.word(address)?),
+        ("jr", [Value(target)]) => Instructions::JR(test, evaluator.relative(target)?),
+        ("call", [Value(address)]) => Instructions::CALL(test, evaluator.word(address)?),
+        ("ret", []) => Instructions::RET(test),
+        ("rst", [Value(vector)]) => {
+            let vector = evaluator.value(vector)?;
+            if !(0..=0x38).contains(&vector) || vector % 8 != 0 {
+                return Err(format!("rst can't go to ${:X}", vector));
+            }
+            Instructions::RST(vector as u8)
+        }
+        _ if is_mnemonic(mnemonic) => return Err(format!("{} can't take those operands", mnemonic)),
+        _ => return Err(format!("unknown instruction {}", mnemonic)),
+    };
+    return Ok(instruction.encode())
+}
+
+fn alu_operand(alu: fn(RegisterTarget) -> Instructions, source: &Operand, mnemonic: &str, evaluator: &Evaluator) -> Result<Instructions, String> {
+    match source {
+        Operand::Register(source) => Ok(alu(*source)),
+        Operand::Value(value) => Ok(alu(RegisterTarget::D8(evaluator.byte(value)?))),
+        _ => Err(format!("{} can't take those operands", mnemonic)),
+    }
+}
+
+fn is_mnemonic(mnemonic: &str) -> bool {
+    matches!(
+        mnemonic,
+        "ld" | "ldh" | "ldi" | "ldd" | "add" | "adc" | "sub" | "sbc" | "and" | "xor" | "or" | "cp" | "inc" | "dec"
+        | "rlc" | "rrc" | "rl" | "rr" | "sla" | "sra" | "swap" | "srl" | "bit" | "res" | "set" | "push" | "pop"
+        | "jp" | "jr" | "call" | "ret" | "rst" | "nop" | "halt" | "di" | "ei" | "ccf" | "scf" | "rra" | "rla"
+        | "rrca" | "rlca" | "cpl" | "daa" | "reti" | "stop"
+    )
+}
+
+fn byte(value: i64) -> Result<u8, String> {
+    if !(-128..=255).contains(&value) {
+        return Err(format!("{} doesn't fit in a byte", value));
+    }
+    Ok(value as u8)
+}
+
+// Values in the first pass are all 0, only lengths matter there
+struct Evaluator<'a> {
+    values: &'a HashMap<String, i64>,
+    here: i64, // What @ stands for, the address of the instruction
+    strict: bool,
+}
+impl Evaluator<'_> {
+    fn value(&self, expr: &Expr) -> Result<i64, String> {
+        if !self.strict {
+            return Ok(0);
+        }
+        expr.evaluate(self)
+    }
+
+    fn byte(&self, expr: &Expr) -> Result<u8, String> {
+        byte(self.value(expr)?)
+    }
+
+    fn word(&self, expr: &Expr) -> Result<u16, String> {
+        let value = self.value(expr)?;
+        if !(-0x8000..=0xFFFF).contains(&value) {
+            return Err(format!("{} doesn't fit in a word", value));
+        }
+        Ok(value as u16)
+    }
+
+    fn offset(&self, expr: &Expr) -> Result<i8, String> {
+        let value = self.value(expr)?;
+        i8::try_from(value).map_err(|_| format!("offset {} is out of range", value))
+    }
+
+    // An address in 0xFF00-0xFFFF, or the offset into it
+    fn high(&self, expr: &Expr) -> Result<u8, String> {
+        match self.value(expr)? {
+            value @ 0xFF00..=0xFFFF => Ok(value as u8),
+            value @ 0..=0xFF => Ok(value as u8),
+            value => Err(format!("${:X} isn't in $FF00-$FFFF", value)),
+        }
+    }
+
+    // A JR target as the offset from the end of the instruction
+    fn relative(&self, expr: &Expr) -> Result<i8, String> {
+        if !self.strict {
+            return Ok(0);
+        }
+        let distance = self.value(expr)? - (self.here + 2);
+        i8::try_from(distance).map_err(|_| format!("jr target is {} bytes away, more than jr can reach", distance))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Here,
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    High(Box<Expr>),
+    Low(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+impl Expr {
+    fn evaluate(&self, evaluator: &Evaluator) -> Result<i64, String> {
+        return Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Symbol(name) => *evaluator.values.get(name).ok_or_else(|| format!("{} isn't defined", name))?,
+            Expr::Here => evaluator.here,
+            Expr::Negate(expr) => expr.evaluate(evaluator)?.wrapping_neg(),
+            Expr::Not(expr) => !expr.evaluate(evaluator)?,
+            Expr::High(expr) => (expr.evaluate(evaluator)? >> 8) & 0xFF,
+            Expr::Low(expr) => expr.evaluate(evaluator)? & 0xFF,
+            Expr::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(evaluator)?, right.evaluate(evaluator)?);
+                match operator {
+                    Operator::Add => left.wrapping_add(right),
+                    Operator::Subtract => left.wrapping_sub(right),
+                    Operator::Multiply => left.wrapping_mul(right),
+                    Operator::Divide => left.checked_div(right).ok_or("division by zero")?,
+                    Operator::Modulo => left.checked_rem(right).ok_or("division by zero")?,
+                    Operator::And => left & right,
+                    Operator::Or => left | right,
+                    Operator::Xor => left ^ right,
+                    Operator::ShiftLeft => left.wrapping_shl(right as u32),
+                    Operator::ShiftRight => left.wrapping_shr(right as u32),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Here,
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let characters: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    let digits = |index: usize, radix: u32| characters[index..].iter().take_while(|character| character.is_digit(radix) || **character == '_').count();
+    let number = |start: usize, length: usize, radix: u32| {
+        let text: String = characters[start..start + length].iter().filter(|&&character| character != '_').collect();
+        i64::from_str_radix(&text, radix).map_err(|_| format!("bad number {}", text))
+    };
+    while index < characters.len() {
+        let character = characters[index];
+        // % is a binary number unless it follows something it can be the remainder of
+        let follows_value = matches!(tokens.last(), Some(Token::Number(_) | Token::Name(_) | Token::Here | Token::Close));
+        match character {
+            _ if character.is_whitespace() => index += 1,
+            '$' => {
+                let length = digits(index + 1, 16);
+                tokens.push(Token::Number(number(index + 1, length, 16)?));
+                index += 1 + length;
+            }
+            '%' if !follows_value => {
+                let length = digits(index + 1, 2);
+                tokens.push(Token::Number(number(index + 1, length, 2)?));
+                index += 1 + length;
+            }
+            '0' if matches!(characters.get(index + 1), Some('x' | 'X')) => {
+                let length = digits(index + 2, 16);
+                tokens.push(Token::Number(number(index + 2, length, 16)?));
+                index += 2 + length;
+            }
+            '0'..='9' => {
+                let length = digits(index, 10);
+                tokens.push(Token::Number(number(index, length, 10)?));
+                index += length;
+            }
+            '\'' if characters.get(index + 2) == Some(&'\'') => {
+                tokens.push(Token::Number(characters[index + 1] as i64));
+                index += 3;
+            }
+            '@' => {
+                tokens.push(Token::Here);
+                index += 1;
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                index += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                index += 1;
+            }
+            _ if character.is_ascii_alphabetic() || character == '_' || character == '.' => {
+                let length = characters[index..].iter().take_while(|character| character.is_ascii_alphanumeric() || **character == '_' || **character == '.').count();
+                tokens.push(Token::Name(characters[index..index + length].iter().collect()));
+                index += length;
+            }
+            _ => {
+                let operator = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~"]
+                    .into_iter()
+                    .find(|operator| characters[index..].starts_with(&operator.chars().collect::<Vec<char>>()))
+                    .ok_or_else(|| format!("unexpected {} in {}", character, text))?;
+                tokens.push(Token::Operator(operator));
+                index += operator.len();
+            }
+        }
+    }
+    return Ok(tokens)
+}
+
+// rgbasm's precedence, from loosest: + -, then & | ^, then << >>, then * / %
+const PRECEDENCE: [&[(&str, Operator)]; 4] = [
+    &[("+", Operator::Add), ("-", Operator::Subtract)],
+    &[("&", Operator::And), ("|", Operator::Or), ("^", Operator::Xor)],
+    &[("<<", Operator::ShiftLeft), (">>", Operator::ShiftRight)],
+    &[("*", Operator::Multiply), ("/", Operator::Divide), ("%", Operator::Modulo)],
+];
+
+// Local names are spelled out in full with the global label they are under
+fn parse_expr(text: &str, scope: &str) -> Result<Expr, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = ExprParser { tokens: &tokens, index: 0, scope };
+    let expr = parser.binary(0)?;
+    if parser.index != tokens.len() {
+        return Err(format!("unexpected {:?} in {}", tokens[parser.index], text.trim()));
+    }
+    return Ok(expr)
+}
+
+struct ExprParser<'a> {
+    tokens: &'a [Token],
+    index: usize,
+    scope: &'a str,
+}
+impl ExprParser<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.index);
+        self.index += 1;
+        token
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Operator(symbol)) = self.tokens.get(self.index)
+            && let Some(&(_, operator)) = PRECEDENCE[level].iter().find(|(candidate, _)| candidate == symbol)
+        {
+            self.index += 1;
+            left = Expr::Binary(operator, Box::new(left), Box::new(self.binary(level + 1)?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next().cloned() {
+            Some(Token::Operator("-")) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Operator("~")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Here) => Ok(Expr::Here),
+            Some(Token::Open) => {
+                let expr = self.binary(0)?;
+                self.close()?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) if self.tokens.get(self.index) == Some(&Token::Open) && matches!(name.to_ascii_lowercase().as_str(), "high" | "low") => {
+                self.index += 1;
+                let expr = Box::new(self.binary(0)?);
+                self.close()?;
+                Ok(if name.eq_ignore_ascii_case("high") { Expr::High(expr) } else { Expr::Low(expr) })
+            }
+            Some(Token::Name(name)) if name.starts_with('.') => Ok(Expr::Symbol(format!("{}{}", self.scope, name))),
+            Some(Token::Name(name)) => Ok(Expr::Symbol(name)),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err(String::from("expression ends too soon")),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close) => Ok(()),
+            _ => Err(String::from("missing )")),
+        }
+    }
+}
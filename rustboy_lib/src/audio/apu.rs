@@ -0,0 +1,761 @@
+use std::collections::VecDeque;
+
+use super::{AudioFrame, StereoSample};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::scheduler::{EventKind, Scheduler};
+
+pub const NR10_ADDRESS: u16 = 0xFF10;
+pub const NR14_ADDRESS: u16 = 0xFF14;
+pub const NR52_ADDRESS: u16 = 0xFF26; // Sound on/off
+pub const WAVE_RAM_START: u16 = 0xFF30;
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+// The APU is clocked at this rate no matter which speed the CPU runs at
+const APU_CLOCK: u64 = 4_194_304;
+const FRAME_SEQUENCER_PERIOD: u64 = 8192; // 512 Hz
+// Samples kept around for a frontend that isn't collecting them, about a second's worth
+const MAX_BUFFERED_SECONDS: usize = 1;
+
+// Bits that always read back as 1, for NR10-NR52. Write only parts of the registers, like
+// the frequency, read back as 1 as well
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, 0x70,             // NR50-NR52
+];
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Shared by the square and noise channels: NRx2 fades the volume up or down 1 step every
+// period/64 seconds
+#[derive(Clone, Debug, Default)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0b111;
+    }
+
+    // A channel's DAC is off when the upper 5 bits of NRx2 are all 0
+    fn dac_enabled(&self) -> bool {
+        self.initial_volume != 0 || self.increase
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn step(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// NRx1 length timer, which turns the channel off when it runs out
+#[derive(Clone, Debug, Default)]
+struct Length {
+    counter: u16,
+    enabled: bool,
+}
+impl Length {
+    fn load(&mut self, value: u16, maximum: u16) {
+        self.counter = maximum - value;
+    }
+
+    fn trigger(&mut self, maximum: u16) {
+        if self.counter == 0 {
+            self.counter = maximum;
+        }
+    }
+
+    // Returns true when the channel has to be turned off
+    fn step(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        return false
+    }
+}
+
+// Channels 1 and 2. Only channel 1 has the frequency sweep
+#[derive(Clone, Debug, Default)]
+struct SquareChannel {
+    enabled: bool,
+    duty: u8,
+    duty_step: usize,
+    frequency: u16,
+    timer: i64,
+    length: Length,
+    envelope: Envelope,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow_frequency: u16,
+}
+impl SquareChannel {
+    fn period(&self) -> i64 {
+        (2048 - self.frequency as i64) * 4
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(64);
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.shadow_frequency = self.frequency;
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+        if self.sweep_shift != 0 {
+            self.sweep_frequency();
+        }
+    }
+
+    // The next frequency of the sweep, turning the channel off if it goes past 2047
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow_frequency >> self.sweep_shift;
+        let frequency = if self.sweep_negate {
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        return frequency
+    }
+
+    fn step_sweep(&mut self) {
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer > 0 {
+            return;
+        }
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        if self.sweep_enabled && self.sweep_period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= 2047 && self.sweep_shift != 0 {
+                self.frequency = frequency;
+                self.shadow_frequency = frequency;
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    fn advance(&mut self, cycles: u64) {
+        self.timer -= cycles as i64;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_PATTERNS[self.duty as usize][self.duty_step] * self.envelope.volume
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: i64,
+    position: usize, // Which of the 32 4 bit samples is playing
+    length: Length,
+    ram: [u8; 16],
+}
+impl WaveChannel {
+    fn period(&self) -> i64 {
+        (2048 - self.frequency as i64) * 2
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(256);
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    fn advance(&mut self, cycles: u64) {
+        self.timer -= cycles as i64;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) % 32;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.volume_code == 0 {
+            return 0;
+        }
+        let byte = self.ram[self.position / 2];
+        let sample = if self.position & 0b1 == 0 { byte >> 4 } else { byte & 0x0F };
+        sample >> (self.volume_code - 1)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct NoiseChannel {
+    enabled: bool,
+    clock_shift: u8,
+    narrow: bool, // 7 bit LFSR instead of 15
+    divisor_code: u8,
+    lfsr: u16,
+    timer: i64,
+    length: Length,
+    envelope: Envelope,
+}
+impl NoiseChannel {
+    fn period(&self) -> i64 {
+        (NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift) as i64
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(64);
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn advance(&mut self, cycles: u64) {
+        // Shifts of 14 and 15 stop the LFSR from being clocked at all
+        if self.clock_shift >= 14 {
+            return;
+        }
+        self.timer -= cycles as i64;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0b1;
+            self.lfsr = (self.lfsr >> 1) | bit << 14;
+            if self.narrow {
+                self.lfsr = (self.lfsr & !0x40) | bit << 6;
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0b1 != 0 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+}
+
+// The sound hardware. Like the timer it is caught up lazily: the channel timers only run
+// when a register is touched or a frame sequencer or sample event fires. Samples are
+// buffered at the host sample rate until the frontend takes them
+#[derive(Debug)]
+pub struct Apu {
+    registers: [u8; 0x17], // NR10-NR52 as written
+    powered: bool,
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    frame_sequencer_step: u8,
+    // Events are scheduled relative to when the last one was due, not when it was handled
+    next_frame_sequencer: u64,
+    next_sample: u64,
+    last_sync: u64, // Scheduler time the channels have been run up to
+    sample_rate: u32,
+    sample_remainder: u64, // Fraction of a sample period carried over, in 1/sample_rate
+    samples: VecDeque<AudioFrame>,
+    wave_ram_readable_while_playing: bool,
+}
+impl Apu {
+    pub fn new(sample_rate: u32, wave_ram_readable_while_playing: bool) -> Apu {
+        return Apu {
+            registers: [0; 0x17],
+            powered: false,
+            square1: SquareChannel::default(),
+            square2: SquareChannel::default(),
+            wave: WaveChannel::default(),
+            noise: NoiseChannel::default(),
+            frame_sequencer_step: 0,
+            next_frame_sequencer: 0,
+            next_sample: 0,
+            last_sync: 0,
+            sample_rate,
+            sample_remainder: 0,
+            samples: VecDeque::new(),
+            wave_ram_readable_while_playing,
+        }
+    }
+
+    // Starts the frame sequencer and, unless the sample rate is 0, sampling
+    pub fn start(&mut self, scheduler: &mut Scheduler, double_speed: bool) {
+        self.last_sync = scheduler.now();
+        self.next_frame_sequencer = scheduler.now();
+        self.next_sample = scheduler.now();
+        self.schedule_frame_sequencer(scheduler, double_speed);
+        scheduler.cancel(EventKind::ApuSample);
+        if self.sample_rate != 0 {
+            self.schedule_sample(scheduler, double_speed);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // A sample rate of 0 turns sample output off, for running without sound. Only sampling
+    // starts over, the channels and frame sequencer carry on as they were
+    pub fn set_sample_rate(&mut self, sample_rate: u32, scheduler: &mut Scheduler, double_speed: bool) {
+        self.sample_rate = sample_rate;
+        self.sample_remainder = 0;
+        self.samples.clear();
+        self.next_sample = scheduler.now();
+        scheduler.cancel(EventKind::ApuSample);
+        if self.sample_rate != 0 {
+            self.schedule_sample(scheduler, double_speed);
+        }
+    }
+
+    // Catches the channels up at the old speed, then moves the frame sequencer and the next
+    // sample so they stay the same real time away when the CPU changes speed
+    pub fn switch_speed(&mut self, scheduler: &mut Scheduler, double_speed: bool) {
+        self.sync(scheduler.now(), !double_speed);
+        self.next_frame_sequencer = scheduler.rescale_time(self.next_frame_sequencer, double_speed);
+        self.next_sample = scheduler.rescale_time(self.next_sample, double_speed);
+        scheduler.rescale(EventKind::ApuFrameSequencer, double_speed);
+        scheduler.rescale(EventKind::ApuSample, double_speed);
+    }
+
+    // Takes every sample produced since the last call
+    pub fn take_samples(&mut self) -> Vec<AudioFrame> {
+        self.samples.drain(..).collect()
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            NR52_ADDRESS => {
+                let channels = [self.square1.enabled, self.square2.enabled, self.wave.enabled, self.noise.enabled];
+                let status = channels.iter().enumerate().fold(0, |status, (bit, &on)| status | (on as u8) << bit);
+                0x70 | (self.powered as u8) << 7 | status
+            }
+            NR10_ADDRESS..NR52_ADDRESS => {
+                let index = (address - NR10_ADDRESS) as usize;
+                self.registers[index] | READ_MASKS[index]
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                if !self.wave.enabled {
+                    self.wave.ram[(address - WAVE_RAM_START) as usize]
+                } else if self.wave_ram_readable_while_playing {
+                    self.wave.ram[self.wave.position / 2]
+                } else {
+                    0xFF
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, scheduler: &mut Scheduler, double_speed: bool) {
+        self.sync(scheduler.now(), double_speed);
+        if let WAVE_RAM_START..=WAVE_RAM_END = address {
+            self.wave.ram[(address - WAVE_RAM_START) as usize] = value;
+            return;
+        }
+        if address == NR52_ADDRESS {
+            self.write_power(value & 0x80 != 0, scheduler, double_speed);
+            return;
+        }
+        // Everything but NR52 ignores writes while the APU is off
+        if !self.powered || !(NR10_ADDRESS..NR52_ADDRESS).contains(&address) {
+            return;
+        }
+        self.registers[(address - NR10_ADDRESS) as usize] = value;
+        match address {
+            0xFF10 => {
+                self.square1.sweep_period = (value >> 4) & 0b111;
+                self.square1.sweep_negate = value & 0x08 != 0;
+                self.square1.sweep_shift = value & 0b111;
+            }
+            0xFF11 => {
+                self.square1.duty = value >> 6;
+                self.square1.length.load((value & 0x3F) as u16, 64);
+            }
+            0xFF12 => {
+                self.square1.envelope.write(value);
+                self.square1.enabled &= self.square1.envelope.dac_enabled();
+            }
+            0xFF13 => self.square1.frequency = (self.square1.frequency & 0x700) | value as u16,
+            0xFF14 => {
+                self.square1.frequency = (self.square1.frequency & 0xFF) | ((value & 0b111) as u16) << 8;
+                self.square1.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.square1.trigger();
+                }
+            }
+            0xFF16 => {
+                self.square2.duty = value >> 6;
+                self.square2.length.load((value & 0x3F) as u16, 64);
+            }
+            0xFF17 => {
+                self.square2.envelope.write(value);
+                self.square2.enabled &= self.square2.envelope.dac_enabled();
+            }
+            0xFF18 => self.square2.frequency = (self.square2.frequency & 0x700) | value as u16,
+            0xFF19 => {
+                self.square2.frequency = (self.square2.frequency & 0xFF) | ((value & 0b111) as u16) << 8;
+                self.square2.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.square2.trigger();
+                }
+            }
+            0xFF1A => {
+                self.wave.dac_enabled = value & 0x80 != 0;
+                self.wave.enabled &= self.wave.dac_enabled;
+            }
+            0xFF1B => self.wave.length.load(value as u16, 256),
+            0xFF1C => self.wave.volume_code = (value >> 5) & 0b11,
+            0xFF1D => self.wave.frequency = (self.wave.frequency & 0x700) | value as u16,
+            0xFF1E => {
+                self.wave.frequency = (self.wave.frequency & 0xFF) | ((value & 0b111) as u16) << 8;
+                self.wave.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.wave.trigger();
+                }
+            }
+            0xFF20 => self.noise.length.load((value & 0x3F) as u16, 64),
+            0xFF21 => {
+                self.noise.envelope.write(value);
+                self.noise.enabled &= self.noise.envelope.dac_enabled();
+            }
+            0xFF22 => {
+                self.noise.clock_shift = value >> 4;
+                self.noise.narrow = value & 0x08 != 0;
+                self.noise.divisor_code = value & 0b111;
+            }
+            0xFF23 => {
+                self.noise.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.noise.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_event(&mut self, kind: EventKind, scheduler: &mut Scheduler, double_speed: bool) {
+        self.sync(scheduler.now(), double_speed);
+        match kind {
+            EventKind::ApuFrameSequencer => {
+                self.step_frame_sequencer();
+                self.schedule_frame_sequencer(scheduler, double_speed);
+            }
+            EventKind::ApuSample => {
+                if self.samples.len() >= self.sample_rate as usize * MAX_BUFFERED_SECONDS {
+                    self.samples.pop_front();
+                }
+                self.samples.push_back(self.mix());
+                self.schedule_sample(scheduler, double_speed);
+            }
+            _ => {}
+        }
+    }
+
+    // Runs the channel timers up to now
+    fn sync(&mut self, now: u64, double_speed: bool) {
+        let elapsed = now - self.last_sync;
+        self.last_sync = now;
+        if !self.powered {
+            return;
+        }
+        let cycles = if double_speed { elapsed / 2 } else { elapsed };
+        self.square1.advance(cycles);
+        self.square2.advance(cycles);
+        self.wave.advance(cycles);
+        self.noise.advance(cycles);
+    }
+
+    fn write_power(&mut self, on: bool, scheduler: &mut Scheduler, double_speed: bool) {
+        if on && !self.powered {
+            self.frame_sequencer_step = 0;
+            self.next_frame_sequencer = scheduler.now();
+            self.schedule_frame_sequencer(scheduler, double_speed);
+        } else if !on && self.powered {
+            // Turning the APU off clears every register, wave RAM survives
+            let ram = self.wave.ram;
+            self.registers = [0; 0x17];
+            self.square1 = SquareChannel::default();
+            self.square2 = SquareChannel::default();
+            self.wave = WaveChannel { ram, ..WaveChannel::default() };
+            self.noise = NoiseChannel::default();
+        }
+        self.powered = on;
+    }
+
+    fn step_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        self.frame_sequencer_step = (step + 1) % 8;
+        if !self.powered {
+            return;
+        }
+        // Length on even steps, sweep on 2 and 6, envelopes on 7
+        if step & 0b1 == 0 {
+            if self.square1.length.step() {
+                self.square1.enabled = false;
+            }
+            if self.square2.length.step() {
+                self.square2.enabled = false;
+            }
+            if self.wave.length.step() {
+                self.wave.enabled = false;
+            }
+            if self.noise.length.step() {
+                self.noise.enabled = false;
+            }
+        }
+        if step == 2 || step == 6 {
+            self.square1.step_sweep();
+        }
+        if step == 7 {
+            self.square1.envelope.step();
+            self.square2.envelope.step();
+            self.noise.envelope.step();
+        }
+    }
+
+    fn schedule_frame_sequencer(&mut self, scheduler: &mut Scheduler, double_speed: bool) {
+        self.next_frame_sequencer += cpu_cycles(FRAME_SEQUENCER_PERIOD, double_speed);
+        scheduler.cancel(EventKind::ApuFrameSequencer);
+        scheduler.schedule_at(EventKind::ApuFrameSequencer, self.next_frame_sequencer);
+    }
+
+    fn schedule_sample(&mut self, scheduler: &mut Scheduler, double_speed: bool) {
+        let rate = self.sample_rate as u64;
+        self.sample_remainder += APU_CLOCK;
+        let delay = self.sample_remainder / rate;
+        self.sample_remainder %= rate;
+        self.next_sample += cpu_cycles(delay, double_speed);
+        scheduler.cancel(EventKind::ApuSample);
+        scheduler.schedule_at(EventKind::ApuSample, self.next_sample);
+    }
+
+    // Each DAC maps 0-15 to 1.0..-1.0, NR51 pans the channels and NR50 sets the volume of
+    // each side
+    fn mix(&self) -> AudioFrame {
+        let mut frame = AudioFrame::default();
+        if !self.powered {
+            return frame;
+        }
+        let dacs = [
+            self.square1.envelope.dac_enabled(),
+            self.square2.envelope.dac_enabled(),
+            self.wave.dac_enabled,
+            self.noise.envelope.dac_enabled(),
+        ];
+        let outputs = [self.square1.output(), self.square2.output(), self.wave.output(), self.noise.output()];
+        let nr50 = self.registers[0x14];
+        let nr51 = self.registers[0x15];
+        let left_volume = ((nr50 >> 4) & 0b111) as f32 + 1.0;
+        let right_volume = (nr50 & 0b111) as f32 + 1.0;
+        for channel in 0..4 {
+            if !dacs[channel] {
+                continue;
+            }
+            let analog = 1.0 - outputs[channel] as f32 / 7.5;
+            let left = if nr51 & (0x10 << channel) != 0 { analog * left_volume / 32.0 } else { 0.0 };
+            let right = if nr51 & (0x01 << channel) != 0 { analog * right_volume / 32.0 } else { 0.0 };
+            frame.channels[channel] = StereoSample::new(left, right);
+            frame.mixed.left += left;
+            frame.mixed.right += right;
+        }
+        return frame
+    }
+}
+
+// The APU's clock doesn't double with the CPU's
+fn cpu_cycles(apu_cycles: u64, double_speed: bool) -> u64 {
+    if double_speed { apu_cycles * 2 } else { apu_cycles }
+}
+
+impl SaveState for Envelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for value in [self.initial_volume, self.increase as u8, self.period, self.volume, self.timer] {
+            writer.write_u8(value);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.initial_volume = reader.read_u8()?;
+        self.increase = reader.read_bool()?;
+        self.period = reader.read_u8()?;
+        self.volume = reader.read_u8()?;
+        self.timer = reader.read_u8()?;
+        return Ok(())
+    }
+}
+
+impl SaveState for Length {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.counter);
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.counter = reader.read_u16()?;
+        self.enabled = reader.read_bool()?;
+        return Ok(())
+    }
+}
+
+impl SaveState for SquareChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.duty);
+        writer.write_u8(self.duty_step as u8);
+        writer.write_u16(self.frequency);
+        writer.write_i64(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.write_u8(self.sweep_period);
+        writer.write_bool(self.sweep_negate);
+        writer.write_u8(self.sweep_shift);
+        writer.write_u8(self.sweep_timer);
+        writer.write_bool(self.sweep_enabled);
+        writer.write_u16(self.shadow_frequency);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.duty = reader.read_u8()? & 0b11;
+        self.duty_step = reader.read_u8()? as usize & 0b111;
+        self.frequency = reader.read_u16()?;
+        self.timer = reader.read_i64()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.sweep_period = reader.read_u8()?;
+        self.sweep_negate = reader.read_bool()?;
+        self.sweep_shift = reader.read_u8()?;
+        self.sweep_timer = reader.read_u8()?;
+        self.sweep_enabled = reader.read_bool()?;
+        self.shadow_frequency = reader.read_u16()?;
+        return Ok(())
+    }
+}
+
+impl SaveState for WaveChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.dac_enabled);
+        writer.write_u8(self.volume_code);
+        writer.write_u16(self.frequency);
+        writer.write_i64(self.timer);
+        writer.write_u8(self.position as u8);
+        self.length.save_state(writer);
+        writer.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.dac_enabled = reader.read_bool()?;
+        self.volume_code = reader.read_u8()? & 0b11;
+        self.frequency = reader.read_u16()?;
+        self.timer = reader.read_i64()?;
+        self.position = reader.read_u8()? as usize & 0x1F;
+        self.length.load_state(reader)?;
+        reader.read_bytes_into(&mut self.ram)?;
+        return Ok(())
+    }
+}
+
+impl SaveState for NoiseChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.clock_shift);
+        writer.write_bool(self.narrow);
+        writer.write_u8(self.divisor_code);
+        writer.write_u16(self.lfsr);
+        writer.write_i64(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.enabled = reader.read_bool()?;
+        self.clock_shift = reader.read_u8()?;
+        self.narrow = reader.read_bool()?;
+        self.divisor_code = reader.read_u8()? & 0b111;
+        self.lfsr = reader.read_u16()?;
+        self.timer = reader.read_i64()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        return Ok(())
+    }
+}
+
+// Buffered samples are host side and get dropped, the sample rate is put back by whoever
+// owns the APU if it doesn't match
+impl SaveState for Apu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.registers);
+        writer.write_bool(self.powered);
+        self.square1.save_state(writer);
+        self.square2.save_state(writer);
+        self.wave.save_state(writer);
+        self.noise.save_state(writer);
+        writer.write_u8(self.frame_sequencer_step);
+        writer.write_u64(self.next_frame_sequencer);
+        writer.write_u64(self.next_sample);
+        writer.write_u64(self.last_sync);
+        writer.write_u32(self.sample_rate);
+        writer.write_u64(self.sample_remainder);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.registers)?;
+        self.powered = reader.read_bool()?;
+        self.square1.load_state(reader)?;
+        self.square2.load_state(reader)?;
+        self.wave.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.frame_sequencer_step = reader.read_u8()? & 0b111;
+        self.next_frame_sequencer = reader.read_u64()?;
+        self.next_sample = reader.read_u64()?;
+        self.last_sync = reader.read_u64()?;
+        self.sample_rate = reader.read_u32()?;
+        self.sample_remainder = reader.read_u64()?;
+        if self.sample_rate != 0 && self.sample_remainder >= self.sample_rate as u64 {
+            return Err(SaveStateError::Invalid("sample remainder"));
+        }
+        self.samples.clear();
+        return Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+pub mod apu;
+pub mod wav;
+
+// The four sound channels of the Game Boy APU, in register order (NR1x, NR2x, NR3x, NR4x)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AudioChannel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+impl AudioChannel {
+    pub const ALL: [AudioChannel; 4] = [
+        AudioChannel::Square1,
+        AudioChannel::Square2,
+        AudioChannel::Wave,
+        AudioChannel::Noise,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioChannel::Square1 => "square1",
+            AudioChannel::Square2 => "square2",
+            AudioChannel::Wave => "wave",
+            AudioChannel::Noise => "noise",
+        }
+    }
+}
+
+// One stereo output sample. Values are expected to be in the range -1.0..=1.0,
+// anything outside of that gets clamped when it is written out
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct StereoSample {
+    pub left: f32,
+    pub right: f32,
+}
+impl StereoSample {
+    pub fn new(left: f32, right: f32) -> StereoSample {
+        return StereoSample { left, right }
+    }
+}
+
+// Everything the APU produces for a single output sample: the final mix plus what each
+// channel contributed to it (after panning through NR51), indexed in AudioChannel::ALL order
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AudioFrame {
+    pub mixed: StereoSample,
+    pub channels: [StereoSample; 4],
+}
+impl AudioFrame {
+    pub fn channel(&self, channel: AudioChannel) -> StereoSample {
+        self.channels[channel as usize]
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::{AudioChannel, AudioFrame, StereoSample};
+
+// Size of the RIFF/fmt/data headers of a canonical 16-bit PCM WAV file
+const HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_SAMPLE: u16 = BITS_PER_SAMPLE / 8;
+const FORMAT_PCM: u16 = 1;
+
+// Writes signed 16-bit little endian PCM samples into a WAV container.
+// The chunk sizes in the header are unknown until recording stops, so they are
+// written as 0 up front and patched in by finish()
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    channels: u16,
+    sample_rate: u32,
+    data_len: u32,
+}
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, channels: u16, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        let block_align = channels * BYTES_PER_SAMPLE;
+        let byte_rate = sample_rate * block_align as u32;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&FORMAT_PCM.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        return Ok(WavWriter {
+            writer,
+            channels,
+            sample_rate,
+            data_len: 0,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Number of sample frames (one sample per channel) written so far
+    pub fn frames_written(&self) -> u32 {
+        self.data_len / (self.channels * BYTES_PER_SAMPLE) as u32
+    }
+
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        self.writer.write_all(&to_pcm16(sample).to_le_bytes())?;
+        self.data_len += BYTES_PER_SAMPLE as u32;
+        Ok(())
+    }
+
+    pub fn write_stereo(&mut self, sample: StereoSample) -> io::Result<()> {
+        self.write_sample(sample.left)?;
+        self.write_sample(sample.right)
+    }
+
+    // Patches the RIFF and data chunk sizes and hands back the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(HEADER_SIZE - 8 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        return Ok(self.writer)
+    }
+}
+
+// Converts a -1.0..=1.0 sample into a signed 16-bit PCM value
+pub fn to_pcm16(sample: f32) -> i16 {
+    let sample = if sample.is_nan() { 0.0 } else { sample.clamp(-1.0, 1.0) };
+    (sample * i16::MAX as f32) as i16
+}
+
+// Records the emulator's audio output into <path> and, when stems are enabled,
+// every channel into its own file next to it, e.g. "run.wav" -> "run_square1.wav".
+// All files share the same sample rate and length so they line up when diffed
+pub struct WavRecorder {
+    mixed: WavWriter<BufWriter<File>>,
+    stems: Option<Vec<WavWriter<BufWriter<File>>>>,
+}
+impl WavRecorder {
+    pub fn create(path: &Path, sample_rate: u32, record_stems: bool) -> io::Result<WavRecorder> {
+        let mixed = WavWriter::new(BufWriter::new(File::create(path)?), 2, sample_rate)?;
+        let stems = if record_stems {
+            let mut stems = Vec::with_capacity(AudioChannel::ALL.len());
+            for channel in AudioChannel::ALL {
+                let file = File::create(stem_path(path, channel))?;
+                stems.push(WavWriter::new(BufWriter::new(file), 2, sample_rate)?);
+            }
+            Some(stems)
+        } else {
+            None
+        };
+        return Ok(WavRecorder { mixed, stems })
+    }
+
+    pub fn push_frame(&mut self, frame: &AudioFrame) -> io::Result<()> {
+        self.mixed.write_stereo(frame.mixed)?;
+        if let Some(stems) = &mut self.stems {
+            for (stem, channel) in stems.iter_mut().zip(AudioChannel::ALL) {
+                stem.write_stereo(frame.channel(channel))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn frames_written(&self) -> u32 {
+        self.mixed.frames_written()
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.mixed.finish()?;
+        for stem in self.stems.into_iter().flatten() {
+            stem.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// "out/run.wav" -> "out/run_square1.wav"
+pub fn stem_path(path: &Path, channel: AudioChannel) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned()).unwrap_or_else(|| String::from("wav"));
+    path.with_file_name(format!("{}_{}.{}", stem, channel.name(), extension))
+}
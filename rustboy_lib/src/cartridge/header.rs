@@ -0,0 +1,90 @@
+use super::CartridgeError;
+
+// Locations of the header fields within the ROM
+pub const TITLE_START: usize = 0x0134;
+pub const TITLE_END: usize = 0x0143; // Inclusive. On CGB cartridges this byte is the CGB flag
+pub const CGB_FLAG_ADDRESS: usize = 0x0143;
+pub const NEW_LICENSEE_ADDRESS: usize = 0x0144;
+pub const SGB_FLAG_ADDRESS: usize = 0x0146;
+pub const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+pub const ROM_SIZE_ADDRESS: usize = 0x0148;
+pub const RAM_SIZE_ADDRESS: usize = 0x0149;
+pub const OLD_LICENSEE_ADDRESS: usize = 0x014B;
+pub const HEADER_CHECKSUM_ADDRESS: usize = 0x014D;
+pub const GLOBAL_CHECKSUM_ADDRESS: usize = 0x014E;
+pub const HEADER_END: usize = 0x0150;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,       // DMG cartridge, runs in compatibility mode on a CGB
+    Compatible, // 0x80: uses CGB features but still works on a DMG
+    Only,       // 0xC0: CGB only
+}
+impl std::convert::From<u8> for CgbSupport {
+    fn from(byte: u8) -> CgbSupport {
+        // Only bit 7 is checked by the boot ROM, bit 6 just documents CGB-only titles
+        match byte {
+            0xC0 => CgbSupport::Only,
+            byte if byte & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: [u8; 16], // Raw title bytes, including the CGB flag byte at the end
+    pub cgb_support: CgbSupport,
+    pub new_licensee: [u8; 2],
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub old_licensee: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::RomTooSmall(rom.len()));
+        }
+        let mut title = [0u8; 16];
+        title.copy_from_slice(&rom[TITLE_START..=TITLE_END]);
+        return Ok(CartridgeHeader {
+            title,
+            cgb_support: CgbSupport::from(rom[CGB_FLAG_ADDRESS]),
+            new_licensee: [rom[NEW_LICENSEE_ADDRESS], rom[NEW_LICENSEE_ADDRESS + 1]],
+            sgb_flag: rom[SGB_FLAG_ADDRESS],
+            cartridge_type: rom[CARTRIDGE_TYPE_ADDRESS],
+            rom_size: rom[ROM_SIZE_ADDRESS],
+            ram_size: rom[RAM_SIZE_ADDRESS],
+            old_licensee: rom[OLD_LICENSEE_ADDRESS],
+            header_checksum: rom[HEADER_CHECKSUM_ADDRESS],
+            global_checksum: (rom[GLOBAL_CHECKSUM_ADDRESS] as u16) << 8 | rom[GLOBAL_CHECKSUM_ADDRESS + 1] as u16,
+        })
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.cgb_support != CgbSupport::None
+    }
+
+    // The SGB only listens to command packets when the header asks for it and uses the
+    // new licensee code
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee == 0x33
+    }
+
+    // The title as text. CGB titles are shorter since the last bytes hold the
+    // manufacturer code and CGB flag
+    pub fn title_string(&self) -> String {
+        let length = if self.is_cgb() { 15 } else { 16 };
+        self.title[..length]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| byte as char)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+}
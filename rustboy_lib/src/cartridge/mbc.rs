@@ -0,0 +1,399 @@
+use std::sync::Arc;
+
+use super::header::CartridgeHeader;
+use super::CartridgeError;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+// Cartridges without a header get this much RAM, as if they were ROM+RAM carts
+const HEADERLESS_RAM_SIZE: usize = RAM_BANK_SIZE;
+// MBC2 has 512 half-bytes of RAM built in
+const MBC2_RAM_SIZE: usize = 0x200;
+
+// The memory bank controller on the cartridge, picked from the cartridge type byte
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MapperKind {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+impl MapperKind {
+    pub fn from_cartridge_type(cartridge_type: u8) -> Option<MapperKind> {
+        match cartridge_type {
+            0x00 | 0x08 | 0x09 => Some(MapperKind::None),
+            0x01..=0x03 => Some(MapperKind::Mbc1),
+            0x05 | 0x06 => Some(MapperKind::Mbc2),
+            0x0F..=0x13 => Some(MapperKind::Mbc3),
+            0x19..=0x1E => Some(MapperKind::Mbc5),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapperKind::None => "ROM",
+            MapperKind::Mbc1 => "MBC1",
+            MapperKind::Mbc2 => "MBC2",
+            MapperKind::Mbc3 => "MBC3",
+            MapperKind::Mbc5 => "MBC5",
+        }
+    }
+}
+
+// External RAM in bytes for the RAM size byte of the header
+pub fn ram_size(ram_size_code: u8) -> usize {
+    match ram_size_code {
+        0x01 => 0x800,
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _ => 0,
+    }
+}
+
+// The MBC3 real time clock. It counts seconds of emulated time, so a recorded run plays
+// back the same no matter how fast it is emulated
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rtc {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days: u16,     // 9 bits
+    pub halted: bool,  // DH bit 6
+    pub carry: bool,   // DH bit 7, set once the day counter overflows
+    latched: [u8; 5],  // S, M, H, DL, DH as of the last latch
+    latch_armed: bool, // 0x00 written to 0x6000-0x7FFF, latching on the following 0x01
+}
+impl Rtc {
+    pub fn new() -> Rtc {
+        return Rtc::default()
+    }
+
+    pub fn tick_second(&mut self) {
+        if self.halted {
+            return;
+        }
+        // The counters are compared for equality, so out of range values written by the
+        // game keep counting up until they wrap at the register width
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1FF {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.carry as u8) << 7 | (self.halted as u8) << 6 | (self.days >> 8) as u8,
+        ]
+    }
+
+    fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        self.latched.get((register - 0x08) as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => self.seconds = value & 0x3F,
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 0b1) << 8);
+                self.halted = value & 0x40 != 0;
+                self.carry = value & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+// The cartridge as the bus sees it: ROM, external RAM and whatever the mapper does to
+// page them in
+#[derive(Clone, Debug)]
+pub struct Cartridge {
+    rom: Arc<[u8]>, // Shared between clones, it never changes
+    ram: Vec<u8>,
+    kind: MapperKind,
+    has_battery: bool,
+    ram_enabled: bool,
+    rom_bank: u16,      // MBC1: the 5 bit BANK1 register
+    ram_bank: u8,       // MBC1: the 2 bit BANK2 register, MBC3: RAM bank or RTC register
+    banking_mode: bool, // MBC1 mode 1, BANK2 also applies to 0x0000-0x3FFF and RAM
+    pub rtc: Option<Rtc>,
+}
+impl Cartridge {
+    // Never fails so the bus can be built around any ROM, including the empty ones tests
+    // use. A missing header or unknown mapper falls back to a flat 32 KiB ROM with 8 KiB of
+    // RAM, CPU::power_on rejects unknown mappers before getting here
+    pub fn new(rom: Vec<u8>) -> Cartridge {
+        let header = CartridgeHeader::parse(&rom).ok();
+        let cartridge_type = header.as_ref().map_or(0x08, |header| header.cartridge_type);
+        let kind = MapperKind::from_cartridge_type(cartridge_type).unwrap_or(MapperKind::None);
+        let ram_size = match (&header, kind) {
+            (None, _) => HEADERLESS_RAM_SIZE,
+            (Some(_), MapperKind::Mbc2) => MBC2_RAM_SIZE,
+            (Some(header), _) => ram_size(header.ram_size),
+        };
+        let has_rtc = matches!(cartridge_type, 0x0F | 0x10);
+        return Cartridge {
+            rom: rom.into(),
+            ram: vec![0; ram_size],
+            kind,
+            has_battery: matches!(cartridge_type, 0x03 | 0x06 | 0x09 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E),
+            // Without a mapper there's nothing to enable RAM with, it's always there
+            ram_enabled: kind == MapperKind::None,
+            rom_bank: 1,
+            ram_bank: 0,
+            banking_mode: false,
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+        }
+    }
+
+    // Checks the cartridge type is one we can map
+    pub fn validate(header: &CartridgeHeader) -> Result<MapperKind, CartridgeError> {
+        MapperKind::from_cartridge_type(header.cartridge_type)
+            .ok_or(CartridgeError::UnsupportedMapper(header.cartridge_type))
+    }
+
+    pub fn kind(&self) -> MapperKind {
+        self.kind
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Battery backed RAM is what goes into .sav files
+    pub fn has_battery(&self) -> bool {
+        self.has_battery
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let length = self.ram.len().min(data.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    // The ROM bank currently mapped at 0x4000-0x7FFF
+    pub fn rom_bank(&self) -> usize {
+        self.high_rom_bank()
+    }
+
+    // The ROM bank mapped at address, which isn't always 0 below 0x4000 on an MBC1
+    pub fn rom_bank_at(&self, address: u16) -> usize {
+        if address < ROM_BANK_SIZE as u16 { self.low_rom_bank() } else { self.high_rom_bank() }
+    }
+
+    pub fn read_rom(&self, address: u16) -> u8 {
+        let bank = self.rom_bank_at(address);
+        let offset = bank * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    // Writes to the ROM area set mapper registers
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        match self.kind {
+            MapperKind::None => {}
+            MapperKind::Mbc1 => match address {
+                0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+                0x2000..=0x3FFF => self.rom_bank = (value & 0x1F) as u16,
+                0x4000..=0x5FFF => self.ram_bank = value & 0b11,
+                _ => self.banking_mode = value & 0b1 == 1,
+            },
+            MapperKind::Mbc2 => {
+                if address < 0x4000 {
+                    // Address bit 8 picks between the two registers
+                    if address & 0x100 == 0 {
+                        self.ram_enabled = value & 0x0F == 0x0A;
+                    } else {
+                        self.rom_bank = (value & 0x0F) as u16;
+                    }
+                }
+            }
+            MapperKind::Mbc3 => match address {
+                0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+                0x2000..=0x3FFF => self.rom_bank = (value & 0x7F) as u16,
+                0x4000..=0x5FFF => self.ram_bank = value,
+                _ => {
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.write_latch(value);
+                    }
+                }
+            },
+            MapperKind::Mbc5 => match address {
+                0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+                0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+                0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0b1) << 8),
+                0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+                _ => {}
+            },
+        }
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if let Some(register) = self.rtc_register() {
+            return self.rtc.as_ref().map_or(0xFF, |rtc| rtc.read(register));
+        }
+        match self.ram_offset(address) {
+            // Only the low nibble of MBC2 RAM exists
+            Some(offset) if self.kind == MapperKind::Mbc2 => 0xF0 | self.ram[offset],
+            Some(offset) => self.ram[offset],
+            None => 0xFF,
+        }
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(register) = self.rtc_register() {
+            if let Some(rtc) = &mut self.rtc {
+                rtc.write(register, value);
+            }
+            return;
+        }
+        if let Some(offset) = self.ram_offset(address) {
+            self.ram[offset] = if self.kind == MapperKind::Mbc2 { value & 0x0F } else { value };
+        }
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        (self.rom.len() / ROM_BANK_SIZE).max(1)
+    }
+
+    fn low_rom_bank(&self) -> usize {
+        match self.kind {
+            MapperKind::Mbc1 if self.banking_mode => ((self.ram_bank as usize) << 5) % self.rom_bank_count(),
+            _ => 0,
+        }
+    }
+
+    fn high_rom_bank(&self) -> usize {
+        let bank = match self.kind {
+            MapperKind::None => 1,
+            // Bank 0 can't be selected through BANK1, it turns into bank 1. This happens
+            // before BANK2 is added, so 0x20, 0x40 and 0x60 become 0x21, 0x41 and 0x61
+            MapperKind::Mbc1 => ((self.ram_bank as usize) << 5) | (self.rom_bank as usize).max(1),
+            MapperKind::Mbc2 | MapperKind::Mbc3 => (self.rom_bank as usize).max(1),
+            MapperKind::Mbc5 => self.rom_bank as usize,
+        };
+        bank % self.rom_bank_count()
+    }
+
+    // The RTC register mapped into 0xA000-0xBFFF instead of RAM, if any
+    fn rtc_register(&self) -> Option<u8> {
+        match self.kind {
+            MapperKind::Mbc3 if (0x08..=0x0C).contains(&self.ram_bank) => Some(self.ram_bank),
+            _ => None,
+        }
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let address = (address - 0xA000) as usize;
+        let offset = match self.kind {
+            // MBC2 RAM repeats all the way through the area
+            MapperKind::Mbc2 => address & (MBC2_RAM_SIZE - 1),
+            MapperKind::Mbc1 if self.banking_mode => (self.ram_bank as usize) * RAM_BANK_SIZE + address,
+            MapperKind::Mbc1 | MapperKind::None => address,
+            MapperKind::Mbc3 => (self.ram_bank as usize & 0b11) * RAM_BANK_SIZE + address,
+            MapperKind::Mbc5 => self.ram_bank as usize * RAM_BANK_SIZE + address,
+        };
+        Some(offset % self.ram.len())
+    }
+}
+
+impl SaveState for Rtc {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.seconds);
+        writer.write_u8(self.minutes);
+        writer.write_u8(self.hours);
+        writer.write_u16(self.days);
+        writer.write_bool(self.halted);
+        writer.write_bool(self.carry);
+        writer.write_bytes(&self.latched);
+        writer.write_bool(self.latch_armed);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.seconds = reader.read_u8()?;
+        self.minutes = reader.read_u8()?;
+        self.hours = reader.read_u8()?;
+        self.days = reader.read_u16()?;
+        self.halted = reader.read_bool()?;
+        self.carry = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.latched)?;
+        self.latch_armed = reader.read_bool()?;
+        return Ok(())
+    }
+}
+
+// The ROM itself is left out, savestate::load makes sure it is the same one
+impl SaveState for Cartridge {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.ram);
+        writer.write_bool(self.ram_enabled);
+        writer.write_u16(self.rom_bank);
+        writer.write_u8(self.ram_bank);
+        writer.write_bool(self.banking_mode);
+        writer.write_bool(self.rtc.is_some());
+        if let Some(rtc) = &self.rtc {
+            rtc.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.ram)?;
+        self.ram_enabled = reader.read_bool()?;
+        self.rom_bank = reader.read_u16()?;
+        self.ram_bank = reader.read_u8()?;
+        self.banking_mode = reader.read_bool()?;
+        if reader.read_bool()? != self.rtc.is_some() {
+            return Err(SaveStateError::Invalid("RTC"));
+        }
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load_state(reader)?;
+        }
+        return Ok(())
+    }
+}
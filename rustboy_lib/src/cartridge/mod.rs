@@ -0,0 +1,17 @@
+pub mod header;
+pub mod mbc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    RomTooSmall(usize), // The ROM ends before the header at 0x0100-0x014F does
+    UnsupportedMapper(u8), // Cartridge type byte of a mapper we don't emulate
+}
+impl std::fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CartridgeError::RomTooSmall(size) => write!(f, "ROM is only {} bytes, too small to hold a cartridge header", size),
+            CartridgeError::UnsupportedMapper(kind) => write!(f, "cartridge type 0x{:02X} is not supported", kind),
+        }
+    }
+}
+impl std::error::Error for CartridgeError {}
@@ -1,3 +1,4 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Instructions {
     ADD(RegisterTarget),   // Adds a specific register to the A register
     ADDHL(VirtualRegisterTarget), // ADD to the HL register
@@ -14,7 +15,7 @@ pub enum Instructions {
     SCF,                   // Set carry flag to true
     RRA,                   // Bit rotate A register right through the carry flag
     RLA,                   // Bit rotate A register left through the carry flag
-    RRCA,                  // Bit rotate A register right 
+    RRCA,                  // Bit rotate A register right
     RRLA,                  // Bit rotate A register left
     CPL,                   // Toggle every bit of A register
     DAA,                   // Decimal adjust A register
@@ -28,17 +29,152 @@ pub enum Instructions {
     RLC(RegisterTarget),   // Bit rotate a specific register left by 1
     SRA(RegisterTarget),   // Arithmetic shift a specific register right by 1
     SLA(RegisterTarget),   // Arithmetic shift a specific register left by 1
-    SWAP(RegisterTarget),  // Switch upper and lower nibble of a specific register 
+    SWAP(RegisterTarget),  // Switch upper and lower nibble of a specific register
+    STOP,                  // Enter low power mode, or switch CPU speed on a CGB when armed through KEY1
+    NOP,                   // Do nothing for a cycle
+    HALT,                  // Stop executing until an interrupt is pending
+    DI,                    // Disable interrupts
+    EI,                    // Enable interrupts after the next instruction
+    LD(LoadType),          // Copy a byte or word between registers and memory
+    PUSH(StackTarget),     // Push a register pair onto the stack
+    POP(StackTarget),      // Pop a register pair off the stack
+    INC16(VirtualRegisterTarget), // Increment a 16 bit register by 1, flags are untouched
+    DEC16(VirtualRegisterTarget), // Decrement a 16 bit register by 1, flags are untouched
+    ADDSP(i8),             // Add a signed offset to SP
+    JP(JumpTest, u16),     // Jump to an address if the condition holds
+    JPHL,                  // Jump to the address in HL
+    JR(JumpTest, i8),      // Jump relative to the next instruction if the condition holds
+    CALL(JumpTest, u16),   // Push the return address and jump if the condition holds
+    RET(JumpTest),         // Pop the return address if the condition holds
+    RETI,                  // RET and enable interrupts straight away
+    RST(u8),               // CALL one of the fixed vectors 0x00, 0x08, .. 0x38
+    INVALID(u8),           // One of the 11 unused opcodes, which lock up the CPU
 }
 
+// The operand of the 8 bit instructions. HLI is the byte in memory HL points to and D8 an
+// immediate byte following the opcode, which is only ever read from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum RegisterTarget {
-    A, B, C, D, E, H, L, 
+    A, B, C, D, E, H, L, HLI, D8(u8),
+}
+impl RegisterTarget {
+    // The 3 bit register field used throughout the opcode table
+    pub fn from_index(index: u8) -> RegisterTarget {
+        match index & 0b111 {
+            0 => RegisterTarget::B,
+            1 => RegisterTarget::C,
+            2 => RegisterTarget::D,
+            3 => RegisterTarget::E,
+            4 => RegisterTarget::H,
+            5 => RegisterTarget::L,
+            6 => RegisterTarget::HLI,
+            _ => RegisterTarget::A,
+        }
+    }
+    // The other way around. Immediates have no field, they take the [hl] slot's opcodes
+    // with the byte following
+    pub fn index(&self) -> u8 {
+        match self {
+            RegisterTarget::B => 0,
+            RegisterTarget::C => 1,
+            RegisterTarget::D => 2,
+            RegisterTarget::E => 3,
+            RegisterTarget::H => 4,
+            RegisterTarget::L => 5,
+            RegisterTarget::HLI | RegisterTarget::D8(_) => 6,
+            RegisterTarget::A => 7,
+        }
+    }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum VirtualRegisterTarget {
     BC, DE, HL, SP,
 }
+impl VirtualRegisterTarget {
+    fn index(&self) -> u8 {
+        match self {
+            VirtualRegisterTarget::BC => 0,
+            VirtualRegisterTarget::DE => 1,
+            VirtualRegisterTarget::HL => 2,
+            VirtualRegisterTarget::SP => 3,
+        }
+    }
+}
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StackTarget {
+    AF, BC, DE, HL,
+}
+impl StackTarget {
+    fn index(&self) -> u8 {
+        match self {
+            StackTarget::BC => 0,
+            StackTarget::DE => 1,
+            StackTarget::HL => 2,
+            StackTarget::AF => 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum JumpTest {
+    NotZero, Zero, NotCarry, Carry, Always,
+}
+impl JumpTest {
+    // The condition field of conditional jumps, calls and returns
+    fn index(&self) -> u8 {
+        match self {
+            JumpTest::NotZero => 0,
+            JumpTest::Zero => 1,
+            JumpTest::NotCarry => 2,
+            JumpTest::Carry | JumpTest::Always => 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoadType {
+    Byte(RegisterTarget, RegisterTarget), // LD r, r' including (HL) and immediates
+    Word(VirtualRegisterTarget, u16),     // LD rr, n16
+    AFromIndirect(Indirect),              // LD A, (..)
+    IndirectFromA(Indirect),              // LD (..), A
+    IndirectFromSP(u16),                  // LD (n16), SP
+    SPFromHL,                             // LD SP, HL
+    HLFromSPOffset(i8),                   // LD HL, SP + e8
+}
+
+// Memory operands that can only be loaded to or from A
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Indirect {
+    BC,
+    DE,
+    HLInc,     // (HL+), HL is incremented after the access
+    HLDec,     // (HL-), HL is decremented after the access
+    Word(u16), // (n16)
+    HighC,     // (0xFF00 + C)
+    High(u8),  // (0xFF00 + n8)
+}
+impl Indirect {
+    // Loads to and from A have the same layout, starting from opcode for the (BC) form and
+    // high for the (0xFF00 + n8) one
+    fn encode(&self, opcode: u8, high: u8) -> Vec<u8> {
+        match self {
+            Indirect::BC => vec![opcode],
+            Indirect::DE => vec![opcode | 0x10],
+            Indirect::HLInc => vec![opcode | 0x20],
+            Indirect::HLDec => vec![opcode | 0x30],
+            Indirect::High(offset) => vec![high, *offset],
+            Indirect::HighC => vec![high | 0x02],
+            Indirect::Word(address) => {
+                let [low, high_byte] = address.to_le_bytes();
+                vec![high | 0x0A, low, high_byte]
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum BitPosition {
     B0, B1, B2, B3, B4, B5, B6, B7
 }
@@ -55,4 +191,391 @@ impl std::convert::From<BitPosition> for u8 {
             BitPosition::B7 => 7,
         }
     }
-}
\ No newline at end of file
+}
+impl std::convert::From<u8> for BitPosition {
+    fn from(position: u8) -> BitPosition {
+        match position & 0b111 {
+            0 => BitPosition::B0,
+            1 => BitPosition::B1,
+            2 => BitPosition::B2,
+            3 => BitPosition::B3,
+            4 => BitPosition::B4,
+            5 => BitPosition::B5,
+            6 => BitPosition::B6,
+            _ => BitPosition::B7,
+        }
+    }
+}
+
+impl Instructions {
+    // Decodes the instruction starting with opcode. Operand bytes are pulled through fetch
+    // in order, which lets the CPU spend one M-cycle on each of them like the hardware does
+    pub fn decode<F: FnMut() -> u8>(opcode: u8, mut fetch: F) -> Instructions {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        match x {
+            1 if opcode == 0x76 => Instructions::HALT,
+            1 => Instructions::LD(LoadType::Byte(RegisterTarget::from_index(y), RegisterTarget::from_index(z))),
+            2 => alu(y, RegisterTarget::from_index(z)),
+            _ => match opcode {
+                0x00 => Instructions::NOP,
+                0x08 => Instructions::LD(LoadType::IndirectFromSP(u16::from_le_bytes([fetch(), fetch()]))),
+                0x10 => Instructions::STOP,
+                0x18 => Instructions::JR(JumpTest::Always, fetch() as i8),
+                0x20 | 0x28 | 0x30 | 0x38 => Instructions::JR(jump_test(y - 4), fetch() as i8),
+                0x01 | 0x11 | 0x21 | 0x31 => {
+                    Instructions::LD(LoadType::Word(word_register(y >> 1), u16::from_le_bytes([fetch(), fetch()])))
+                }
+                0x09 | 0x19 | 0x29 | 0x39 => Instructions::ADDHL(word_register(y >> 1)),
+                0x02 | 0x12 | 0x22 | 0x32 => Instructions::LD(LoadType::IndirectFromA(indirect(y >> 1))),
+                0x0A | 0x1A | 0x2A | 0x3A => Instructions::LD(LoadType::AFromIndirect(indirect(y >> 1))),
+                0x03 | 0x13 | 0x23 | 0x33 => Instructions::INC16(word_register(y >> 1)),
+                0x0B | 0x1B | 0x2B | 0x3B => Instructions::DEC16(word_register(y >> 1)),
+                _ if x == 0 && z == 4 => Instructions::INC(RegisterTarget::from_index(y)),
+                _ if x == 0 && z == 5 => Instructions::DEC(RegisterTarget::from_index(y)),
+                _ if x == 0 && z == 6 => Instructions::LD(LoadType::Byte(RegisterTarget::from_index(y), RegisterTarget::D8(fetch()))),
+                0x07 => Instructions::RRLA,
+                0x0F => Instructions::RRCA,
+                0x17 => Instructions::RLA,
+                0x1F => Instructions::RRA,
+                0x27 => Instructions::DAA,
+                0x2F => Instructions::CPL,
+                0x37 => Instructions::SCF,
+                0x3F => Instructions::CCF,
+                0xC0 | 0xC8 | 0xD0 | 0xD8 => Instructions::RET(jump_test(y)),
+                0xC9 => Instructions::RET(JumpTest::Always),
+                0xD9 => Instructions::RETI,
+                0xC2 | 0xCA | 0xD2 | 0xDA => Instructions::JP(jump_test(y), u16::from_le_bytes([fetch(), fetch()])),
+                0xC3 => Instructions::JP(JumpTest::Always, u16::from_le_bytes([fetch(), fetch()])),
+                0xE9 => Instructions::JPHL,
+                0xC4 | 0xCC | 0xD4 | 0xDC => Instructions::CALL(jump_test(y), u16::from_le_bytes([fetch(), fetch()])),
+                0xCD => Instructions::CALL(JumpTest::Always, u16::from_le_bytes([fetch(), fetch()])),
+                0xC1 | 0xD1 | 0xE1 | 0xF1 => Instructions::POP(stack_register(y >> 1)),
+                0xC5 | 0xD5 | 0xE5 | 0xF5 => Instructions::PUSH(stack_register(y >> 1)),
+                0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => alu(y, RegisterTarget::D8(fetch())),
+                0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Instructions::RST(y * 8),
+                0xCB => Instructions::decode_prefixed(fetch()),
+                0xE0 => Instructions::LD(LoadType::IndirectFromA(Indirect::High(fetch()))),
+                0xF0 => Instructions::LD(LoadType::AFromIndirect(Indirect::High(fetch()))),
+                0xE2 => Instructions::LD(LoadType::IndirectFromA(Indirect::HighC)),
+                0xF2 => Instructions::LD(LoadType::AFromIndirect(Indirect::HighC)),
+                0xEA => Instructions::LD(LoadType::IndirectFromA(Indirect::Word(u16::from_le_bytes([fetch(), fetch()])))),
+                0xFA => Instructions::LD(LoadType::AFromIndirect(Indirect::Word(u16::from_le_bytes([fetch(), fetch()])))),
+                0xE8 => Instructions::ADDSP(fetch() as i8),
+                0xF8 => Instructions::LD(LoadType::HLFromSPOffset(fetch() as i8)),
+                0xF9 => Instructions::LD(LoadType::SPFromHL),
+                0xF3 => Instructions::DI,
+                0xFB => Instructions::EI,
+                _ => Instructions::INVALID(opcode),
+            },
+        }
+    }
+
+    // The second byte of a 0xCB prefixed instruction
+    pub fn decode_prefixed(opcode: u8) -> Instructions {
+        let target = RegisterTarget::from_index(opcode);
+        let bit = BitPosition::from(opcode >> 3);
+        match opcode >> 3 {
+            0 => Instructions::RLC(target),
+            1 => Instructions::RRC(target),
+            2 => Instructions::RL(target),
+            3 => Instructions::RR(target),
+            4 => Instructions::SLA(target),
+            5 => Instructions::SRA(target),
+            6 => Instructions::SWAP(target),
+            7 => Instructions::SRL(target),
+            8..=15 => Instructions::BIT(target, bit),
+            16..=23 => Instructions::RESET(target, bit),
+            _ => Instructions::SET(target, bit),
+        }
+    }
+
+    // The bytes decode turns back into this instruction. STOP is the single opcode byte
+    // here, though assemblers follow it with a 0x00
+    pub fn encode(&self) -> Vec<u8> {
+        let alu = |operation: u8, target: &RegisterTarget| match target {
+            RegisterTarget::D8(value) => vec![0xC6 | operation << 3, *value],
+            target => vec![0x80 | operation << 3 | target.index()],
+        };
+        let prefixed = |operation: u8, target: &RegisterTarget| vec![0xCB, operation << 3 | target.index()];
+        let word = |opcode: u8, value: u16| {
+            let [low, high] = value.to_le_bytes();
+            vec![opcode, low, high]
+        };
+        match self {
+            Instructions::ADD(target) => alu(0, target),
+            Instructions::ADC(target) => alu(1, target),
+            Instructions::SUB(target) => alu(2, target),
+            Instructions::SBC(target) => alu(3, target),
+            Instructions::AND(target) => alu(4, target),
+            Instructions::XOR(target) => alu(5, target),
+            Instructions::OR(target) => alu(6, target),
+            Instructions::CP(target) => alu(7, target),
+            Instructions::ADDHL(target) => vec![0x09 | target.index() << 4],
+            Instructions::INC(target) => vec![0x04 | target.index() << 3],
+            Instructions::DEC(target) => vec![0x05 | target.index() << 3],
+            Instructions::CCF => vec![0x3F],
+            Instructions::SCF => vec![0x37],
+            Instructions::RRA => vec![0x1F],
+            Instructions::RLA => vec![0x17],
+            Instructions::RRCA => vec![0x0F],
+            Instructions::RRLA => vec![0x07],
+            Instructions::CPL => vec![0x2F],
+            Instructions::DAA => vec![0x27],
+            Instructions::RLC(target) => prefixed(0, target),
+            Instructions::RRC(target) => prefixed(1, target),
+            Instructions::RL(target) => prefixed(2, target),
+            Instructions::RR(target) => prefixed(3, target),
+            Instructions::SLA(target) => prefixed(4, target),
+            Instructions::SRA(target) => prefixed(5, target),
+            Instructions::SWAP(target) => prefixed(6, target),
+            Instructions::SRL(target) => prefixed(7, target),
+            Instructions::BIT(target, bit) => prefixed(8 | u8::from(*bit), target),
+            Instructions::RESET(target, bit) => prefixed(16 | u8::from(*bit), target),
+            Instructions::SET(target, bit) => prefixed(24 | u8::from(*bit), target),
+            Instructions::STOP => vec![0x10],
+            Instructions::NOP => vec![0x00],
+            Instructions::HALT => vec![0x76],
+            Instructions::DI => vec![0xF3],
+            Instructions::EI => vec![0xFB],
+            Instructions::LD(LoadType::Byte(target, RegisterTarget::D8(value))) => vec![0x06 | target.index() << 3, *value],
+            Instructions::LD(LoadType::Byte(target, source)) => vec![0x40 | target.index() << 3 | source.index()],
+            Instructions::LD(LoadType::Word(target, value)) => word(0x01 | target.index() << 4, *value),
+            Instructions::LD(LoadType::AFromIndirect(indirect)) => indirect.encode(0x0A, 0xF0),
+            Instructions::LD(LoadType::IndirectFromA(indirect)) => indirect.encode(0x02, 0xE0),
+            Instructions::LD(LoadType::IndirectFromSP(address)) => word(0x08, *address),
+            Instructions::LD(LoadType::SPFromHL) => vec![0xF9],
+            Instructions::LD(LoadType::HLFromSPOffset(offset)) => vec![0xF8, *offset as u8],
+            Instructions::PUSH(target) => vec![0xC5 | target.index() << 4],
+            Instructions::POP(target) => vec![0xC1 | target.index() << 4],
+            Instructions::INC16(target) => vec![0x03 | target.index() << 4],
+            Instructions::DEC16(target) => vec![0x0B | target.index() << 4],
+            Instructions::ADDSP(offset) => vec![0xE8, *offset as u8],
+            Instructions::JP(JumpTest::Always, address) => word(0xC3, *address),
+            Instructions::JP(test, address) => word(0xC2 | test.index() << 3, *address),
+            Instructions::JPHL => vec![0xE9],
+            Instructions::JR(JumpTest::Always, offset) => vec![0x18, *offset as u8],
+            Instructions::JR(test, offset) => vec![0x20 | test.index() << 3, *offset as u8],
+            Instructions::CALL(JumpTest::Always, address) => word(0xCD, *address),
+            Instructions::CALL(test, address) => word(0xC4 | test.index() << 3, *address),
+            Instructions::RET(JumpTest::Always) => vec![0xC9],
+            Instructions::RET(test) => vec![0xC0 | test.index() << 3],
+            Instructions::RETI => vec![0xD9],
+            Instructions::RST(vector) => vec![0xC7 | (vector & 0x38)],
+            Instructions::INVALID(opcode) => vec![*opcode],
+        }
+    }
+}
+
+fn alu(operation: u8, target: RegisterTarget) -> Instructions {
+    match operation {
+        0 => Instructions::ADD(target),
+        1 => Instructions::ADC(target),
+        2 => Instructions::SUB(target),
+        3 => Instructions::SBC(target),
+        4 => Instructions::AND(target),
+        5 => Instructions::XOR(target),
+        6 => Instructions::OR(target),
+        _ => Instructions::CP(target),
+    }
+}
+
+fn jump_test(condition: u8) -> JumpTest {
+    match condition {
+        0 => JumpTest::NotZero,
+        1 => JumpTest::Zero,
+        2 => JumpTest::NotCarry,
+        _ => JumpTest::Carry,
+    }
+}
+
+fn word_register(index: u8) -> VirtualRegisterTarget {
+    match index {
+        0 => VirtualRegisterTarget::BC,
+        1 => VirtualRegisterTarget::DE,
+        2 => VirtualRegisterTarget::HL,
+        _ => VirtualRegisterTarget::SP,
+    }
+}
+
+fn stack_register(index: u8) -> StackTarget {
+    match index {
+        0 => StackTarget::BC,
+        1 => StackTarget::DE,
+        2 => StackTarget::HL,
+        _ => StackTarget::AF,
+    }
+}
+
+fn indirect(index: u8) -> Indirect {
+    match index {
+        0 => Indirect::BC,
+        1 => Indirect::DE,
+        2 => Indirect::HLInc,
+        _ => Indirect::HLDec,
+    }
+}
+
+// RGBDS syntax, lower case with hexadecimal operands. JR targets are relative to the
+// instruction (@) since the address it sits at isn't known here
+impl std::fmt::Display for Instructions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Instructions::ADD(target) => write!(f, "add a, {}", target),
+            Instructions::ADDHL(target) => write!(f, "add hl, {}", target),
+            Instructions::ADC(target) => write!(f, "adc a, {}", target),
+            Instructions::SUB(target) => write!(f, "sub a, {}", target),
+            Instructions::SBC(target) => write!(f, "sbc a, {}", target),
+            Instructions::AND(target) => write!(f, "and a, {}", target),
+            Instructions::OR(target) => write!(f, "or a, {}", target),
+            Instructions::XOR(target) => write!(f, "xor a, {}", target),
+            Instructions::CP(target) => write!(f, "cp a, {}", target),
+            Instructions::INC(target) => write!(f, "inc {}", target),
+            Instructions::DEC(target) => write!(f, "dec {}", target),
+            Instructions::CCF => write!(f, "ccf"),
+            Instructions::SCF => write!(f, "scf"),
+            Instructions::RRA => write!(f, "rra"),
+            Instructions::RLA => write!(f, "rla"),
+            Instructions::RRCA => write!(f, "rrca"),
+            Instructions::RRLA => write!(f, "rlca"),
+            Instructions::CPL => write!(f, "cpl"),
+            Instructions::DAA => write!(f, "daa"),
+            Instructions::BIT(target, bit) => write!(f, "bit {}, {}", bit, target),
+            Instructions::RESET(target, bit) => write!(f, "res {}, {}", bit, target),
+            Instructions::SET(target, bit) => write!(f, "set {}, {}", bit, target),
+            Instructions::SRL(target) => write!(f, "srl {}", target),
+            Instructions::RR(target) => write!(f, "rr {}", target),
+            Instructions::RL(target) => write!(f, "rl {}", target),
+            Instructions::RRC(target) => write!(f, "rrc {}", target),
+            Instructions::RLC(target) => write!(f, "rlc {}", target),
+            Instructions::SRA(target) => write!(f, "sra {}", target),
+            Instructions::SLA(target) => write!(f, "sla {}", target),
+            Instructions::SWAP(target) => write!(f, "swap {}", target),
+            Instructions::STOP => write!(f, "stop"),
+            Instructions::NOP => write!(f, "nop"),
+            Instructions::HALT => write!(f, "halt"),
+            Instructions::DI => write!(f, "di"),
+            Instructions::EI => write!(f, "ei"),
+            Instructions::LD(load) => write!(f, "{}", load),
+            Instructions::PUSH(target) => write!(f, "push {}", target),
+            Instructions::POP(target) => write!(f, "pop {}", target),
+            Instructions::INC16(target) => write!(f, "inc {}", target),
+            Instructions::DEC16(target) => write!(f, "dec {}", target),
+            Instructions::ADDSP(offset) => write!(f, "add sp, {}", offset),
+            Instructions::JP(JumpTest::Always, address) => write!(f, "jp ${:04X}", address),
+            Instructions::JP(test, address) => write!(f, "jp {}, ${:04X}", test, address),
+            Instructions::JPHL => write!(f, "jp hl"),
+            Instructions::JR(JumpTest::Always, offset) => write!(f, "jr {}", RelativeTarget(*offset)),
+            Instructions::JR(test, offset) => write!(f, "jr {}, {}", test, RelativeTarget(*offset)),
+            Instructions::CALL(JumpTest::Always, address) => write!(f, "call ${:04X}", address),
+            Instructions::CALL(test, address) => write!(f, "call {}, ${:04X}", test, address),
+            Instructions::RET(JumpTest::Always) => write!(f, "ret"),
+            Instructions::RET(test) => write!(f, "ret {}", test),
+            Instructions::RETI => write!(f, "reti"),
+            Instructions::RST(vector) => write!(f, "rst ${:02X}", vector),
+            Instructions::INVALID(opcode) => write!(f, "db ${:02X}", opcode),
+        }
+    }
+}
+
+// A JR offset as an address relative to the start of the instruction, which is 2 bytes long
+struct RelativeTarget(i8);
+impl std::fmt::Display for RelativeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let offset = self.0 as i16 + 2;
+        if offset < 0 {
+            write!(f, "@-{}", -offset)
+        } else {
+            write!(f, "@+{}", offset)
+        }
+    }
+}
+
+impl std::fmt::Display for RegisterTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RegisterTarget::A => write!(f, "a"),
+            RegisterTarget::B => write!(f, "b"),
+            RegisterTarget::C => write!(f, "c"),
+            RegisterTarget::D => write!(f, "d"),
+            RegisterTarget::E => write!(f, "e"),
+            RegisterTarget::H => write!(f, "h"),
+            RegisterTarget::L => write!(f, "l"),
+            RegisterTarget::HLI => write!(f, "[hl]"),
+            RegisterTarget::D8(value) => write!(f, "${:02X}", value),
+        }
+    }
+}
+
+impl std::fmt::Display for VirtualRegisterTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VirtualRegisterTarget::BC => write!(f, "bc"),
+            VirtualRegisterTarget::DE => write!(f, "de"),
+            VirtualRegisterTarget::HL => write!(f, "hl"),
+            VirtualRegisterTarget::SP => write!(f, "sp"),
+        }
+    }
+}
+
+impl std::fmt::Display for StackTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StackTarget::AF => write!(f, "af"),
+            StackTarget::BC => write!(f, "bc"),
+            StackTarget::DE => write!(f, "de"),
+            StackTarget::HL => write!(f, "hl"),
+        }
+    }
+}
+
+// The condition of a jump, empty for an unconditional one
+impl std::fmt::Display for JumpTest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            JumpTest::NotZero => write!(f, "nz"),
+            JumpTest::Zero => write!(f, "z"),
+            JumpTest::NotCarry => write!(f, "nc"),
+            JumpTest::Carry => write!(f, "c"),
+            JumpTest::Always => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for LoadType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadType::Byte(target, source) => write!(f, "ld {}, {}", target, source),
+            LoadType::Word(target, value) => write!(f, "ld {}, ${:04X}", target, value),
+            LoadType::AFromIndirect(indirect @ (Indirect::High(_) | Indirect::HighC)) => write!(f, "ldh a, {}", indirect),
+            LoadType::AFromIndirect(indirect) => write!(f, "ld a, {}", indirect),
+            LoadType::IndirectFromA(indirect @ (Indirect::High(_) | Indirect::HighC)) => write!(f, "ldh {}, a", indirect),
+            LoadType::IndirectFromA(indirect) => write!(f, "ld {}, a", indirect),
+            LoadType::IndirectFromSP(address) => write!(f, "ld [${:04X}], sp", address),
+            LoadType::SPFromHL => write!(f, "ld sp, hl"),
+            LoadType::HLFromSPOffset(offset) if *offset < 0 => write!(f, "ld hl, sp - {}", -(*offset as i16)),
+            LoadType::HLFromSPOffset(offset) => write!(f, "ld hl, sp + {}", offset),
+        }
+    }
+}
+
+impl std::fmt::Display for Indirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Indirect::BC => write!(f, "[bc]"),
+            Indirect::DE => write!(f, "[de]"),
+            Indirect::HLInc => write!(f, "[hli]"),
+            Indirect::HLDec => write!(f, "[hld]"),
+            Indirect::Word(address) => write!(f, "[${:04X}]", address),
+            Indirect::HighC => write!(f, "[c]"),
+            Indirect::High(offset) => write!(f, "[$FF{:02X}]", offset),
+        }
+    }
+}
+
+impl std::fmt::Display for BitPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", u8::from(*self))
+    }
+}
@@ -0,0 +1,34 @@
+pub const INTERRUPT_FLAG_ADDRESS: u16 = 0xFF0F; // IF, IE lives at the top of the bus
+
+// The five interrupt sources, in priority order. Each owns one bit of IF and IE
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+impl Interrupt {
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    pub fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+
+    // Where the CPU jumps to when servicing the interrupt
+    pub fn vector(&self) -> u16 {
+        0x40 + 8 * (*self as u16)
+    }
+
+    // The interrupt that gets serviced first out of the IE & IF bits
+    pub fn highest_priority(pending: u8) -> Option<Interrupt> {
+        Interrupt::ALL.into_iter().find(|interrupt| pending & interrupt.bit() != 0)
+    }
+}
@@ -1,20 +1,246 @@
 pub mod registers;
 pub mod instructions;
+pub mod interrupts;
+pub mod speed;
+pub mod timing;
 
 use registers::{Registers, FlagsRegister};
-use instructions::{Instructions, RegisterTarget, VirtualRegisterTarget};
+use instructions::{Indirect, Instructions, JumpTest, LoadType, RegisterTarget, StackTarget, VirtualRegisterTarget};
+use interrupts::Interrupt;
+use speed::SPEED_SWITCH_CYCLES;
+use crate::cartridge::{header::CartridgeHeader, mbc::Cartridge, CartridgeError};
+use crate::debugger::watch::{Access, MemoryWatch};
+use crate::memory::{boot::BootRom, bus::MemoryBus};
+use crate::model::Model;
+use crate::ppu::compat::CompatibilityPalettes;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::sgb::Sgb;
+use crate::trace::{BusCycle, Tracer};
 #[derive(Debug)]
 pub struct CPU {
     pub registers: Registers,
-    // pc: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub bus: MemoryBus,
+    pub model: Model,
+    pub cgb_mode: bool, // Running a CGB cartridge with the CGB features enabled
+    pub stopped: bool, // In STOP mode, waiting for a button press
+    pub halted: bool, // In HALT, waiting for an interrupt
+    pub ime: bool, // Interrupt master enable
+    pub ime_pending: bool, // Set by EI, IME turns on once the next instruction starts
+    pub halt_bug: bool, // HALT was skipped with IME off, so the next opcode fetch doesn't move PC
+    pub locked: bool, // Hung by one of the invalid opcodes
+    pub cycles: u64, // M-cycles since power on
+    pub watch: MemoryWatch, // Watchpoints, set by the debugger
+    pub trace: Option<Tracer>, // Logs each instruction before it runs
+    pub bus_log: Option<Vec<BusCycle>>, // Collects what the CPU does on the bus every M-cycle
 }
 impl CPU {
     pub fn new() -> CPU {
         return CPU {
             registers: Registers::new(),
-            // pc: 1
+            pc: 0,
+            sp: 0,
+            bus: MemoryBus::new(Vec::new(), Model::Dmg, false),
+            model: Model::Dmg,
+            cgb_mode: false,
+            stopped: false,
+            halted: false,
+            ime: false,
+            ime_pending: false,
+            halt_bug: false,
+            locked: false,
+            cycles: 0,
+            watch: MemoryWatch::new(),
+            trace: None,
+            bus_log: None,
         }
     }
+    // The CGB boot ROM hands over with A = 0x11, which is how games tell they are on a CGB
+    pub fn new_cgb() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.model = Model::Cgb;
+        cpu.cgb_mode = true;
+        cpu.bus = MemoryBus::new(Vec::new(), Model::Cgb, true);
+        cpu.registers.a = 0x11;
+        return cpu
+    }
+    // Enables the CGB features when the cartridge header asks for them
+    pub fn for_cartridge(header: &CartridgeHeader) -> CPU {
+        if header.is_cgb() { CPU::new_cgb() } else { CPU::new() }
+    }
+    // Powers on a model with the ROM inserted. With a boot ROM everything starts out zeroed
+    // and the boot ROM runs from 0x0000 like on hardware. Without one, the CPU, I/O
+    // registers and memory are set up the way that model's boot ROM would have left them
+    // and execution starts at the cartridge entry point
+    pub fn power_on(rom: Vec<u8>, model: Model, boot_rom: Option<BootRom>) -> Result<CPU, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        Cartridge::validate(&header)?;
+        let cgb_mode = model.runs_in_cgb_mode(header.is_cgb());
+        let mut cpu = CPU::new();
+        cpu.model = model;
+        cpu.cgb_mode = cgb_mode;
+        cpu.bus = MemoryBus::new(rom, model, cgb_mode);
+        if model.is_sgb() && header.supports_sgb() {
+            cpu.bus.sgb = Some(Sgb::new());
+        }
+        match boot_rom {
+            Some(boot_rom) => cpu.bus.load_boot_rom(boot_rom),
+            None => {
+                cpu.registers = Registers::post_boot(model, &header);
+                cpu.pc = 0x0100;
+                cpu.sp = 0xFFFE;
+                cpu.bus.apply_post_boot_state();
+                if model.is_cgb() && !cgb_mode {
+                    CompatibilityPalettes::for_cartridge(&header, None).load_into(&mut cpu.bus.palettes);
+                }
+            }
+        }
+        return Ok(cpu)
+    }
+    // Runs the next instruction, or services an interrupt, and returns how many M-cycles
+    // that took. Memory is accessed on the M-cycle the hardware accesses it, with the rest
+    // of the system ticked in between
+    pub fn step(&mut self) -> u32 {
+        let start = self.cycles;
+        if self.stopped {
+            // The main oscillator is off, nothing moves until a button is pressed
+            self.cycles += 1;
+            if self.bus.joypad.pressed() != 0 {
+                self.stopped = false;
+            }
+            return 1;
+        }
+        if self.locked {
+            self.tick();
+            return 1;
+        }
+        if self.halted {
+            // Waking up takes a cycle of its own before the interrupt is serviced
+            self.tick();
+            if self.bus.pending_interrupts() != 0 {
+                self.halted = false;
+            }
+            return (self.cycles - start) as u32;
+        }
+        if self.ime && self.bus.pending_interrupts() != 0 {
+            self.service_interrupt();
+            return (self.cycles - start) as u32;
+        }
+        if std::mem::replace(&mut self.ime_pending, false) {
+            self.ime = true;
+        }
+        if let Some(mut trace) = self.trace.take() {
+            trace.record(self);
+            self.trace = Some(trace);
+        }
+        let opcode = self.fetch_byte();
+        let instruction = Instructions::decode(opcode, || self.fetch_byte());
+        self.execute(instruction);
+        return (self.cycles - start) as u32
+    }
+
+    // Pushes PC and jumps to the vector of the highest priority pending interrupt, 5 M-cycles
+    fn service_interrupt(&mut self) {
+        self.ime = false;
+        self.tick();
+        self.tick();
+        let [low, high] = self.pc.to_le_bytes();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(self.sp, high);
+        // The interrupt is only picked after the upper byte of PC is pushed. If that push
+        // overwrote IE and nothing is left pending, PC ends up at 0x0000
+        let interrupt = Interrupt::highest_priority(self.bus.pending_interrupts());
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(self.sp, low);
+        self.pc = match interrupt {
+            Some(interrupt) => {
+                self.bus.acknowledge_interrupt(interrupt);
+                interrupt.vector()
+            }
+            None => 0x0000,
+        };
+        self.tick();
+    }
+
+    // One M-cycle without a memory access of the CPU
+    fn tick(&mut self) {
+        self.bus.cpu_halted = self.halted;
+        self.bus.tick();
+        self.cycles += 1;
+        if let Some(log) = &mut self.bus_log {
+            log.push(BusCycle::Idle);
+        }
+        // HDMA halts the CPU until the copy it started is done, which may run into an
+        // HBlank copying yet another block
+        loop {
+            let stall = self.bus.take_dma_stall_cycles();
+            if stall == 0 {
+                break;
+            }
+            for _ in 0..stall {
+                self.bus.tick();
+                self.cycles += 1;
+                if let Some(log) = &mut self.bus_log {
+                    log.push(BusCycle::Idle);
+                }
+            }
+        }
+    }
+
+    fn read_cycle(&mut self, address: u16) -> u8 {
+        self.tick();
+        self.bus.oam_bug_read(address);
+        let value = self.bus.read(address);
+        self.watch.check(address, value, Access::Read);
+        self.log_access(BusCycle::Read(address, value));
+        return value
+    }
+
+    fn write_cycle(&mut self, address: u16, value: u8) {
+        self.tick();
+        self.watch.check(address, value, Access::Write);
+        self.log_access(BusCycle::Write(address, value));
+        self.bus.oam_bug_write(address);
+        self.bus.write(address, value);
+    }
+
+    // The access happens on the M-cycle just ticked
+    fn log_access(&mut self, cycle: BusCycle) {
+        if let Some(last) = self.bus_log.as_mut().and_then(|log| log.last_mut()) {
+            *last = cycle;
+        }
+    }
+
+    // Opcode fetches don't set off read watchpoints
+    fn fetch_byte(&mut self) -> u8 {
+        self.tick();
+        let byte = self.bus.read(self.pc);
+        self.log_access(BusCycle::Read(self.pc, byte));
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+        return byte
+    }
+
+    fn push(&mut self, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(self.sp, high);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(self.sp, low);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let low = self.read_cycle(self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        let high = self.read_cycle(self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        return u16::from_le_bytes([low, high])
+    }
+
     pub fn execute(&mut self, instruction: Instructions) {
         match instruction {
             Instructions::ADD(target) => {
@@ -32,16 +258,12 @@ impl CPU {
                 self.set_target_register(RegisterTarget::A, result);
             }
             Instructions::ADDHL(target) => {
-                let register_value = match target {
-                    VirtualRegisterTarget::BC => self.registers.get_bc(),
-                    VirtualRegisterTarget::DE => self.registers.get_de(),
-                    VirtualRegisterTarget::HL => self.registers.get_hl(),
-                    _ => 0,
-                };
+                let register_value = self.get_word_register(target);
                 let hl = self.registers.get_hl();
                 let (result, did_overflow) = hl.overflowing_add(register_value);
+                self.tick();
                 self.set_flags_register(
-                    result == 0, 
+                    self.registers.f.zero, 
                     false, 
                     did_overflow, 
                     ((register_value & 0xFFF) + (hl & 0xFFF)) > 0xFFF
@@ -54,7 +276,7 @@ impl CPU {
                 let (result, did_overflow) = self.registers.a.overflowing_add(register_value);
                 let (new_result, new_did_overflow) = result.overflowing_add(additional_carry);
                 self.set_flags_register(
-                    result == 0, 
+                    new_result == 0, 
                     false, 
                     new_did_overflow || did_overflow, 
                     ((self.registers.a & 0xF) + (register_value & 0xF) + additional_carry) > 0xF
@@ -136,22 +358,22 @@ impl CPU {
             }
             Instructions::INC(target) => {
                 let register_value = self.get_target_register(&target);
-                let (result, did_overflow) = register_value.overflowing_add(1);
+                let result = register_value.wrapping_add(1);
                 self.set_flags_register(
                     result == 0,
                     false,
-                    did_overflow,
-                    ((result & 0xF) + (register_value & 0xF)) > 0xF
+                    self.registers.f.carry,
+                    (register_value & 0xF) + 1 > 0xF
                 );
                 self.set_target_register(target, result);
             }
             Instructions::DEC(target) => {
                 let register_value = self.get_target_register(&target);
-                let (result, did_overflow) = register_value.overflowing_sub(1);
+                let result = register_value.wrapping_sub(1);
                 self.set_flags_register(
                     result == 0,
                     true,
-                    did_overflow,
+                    self.registers.f.carry,
                     (register_value & 0xF) < (result & 0xF)
                 );
                 self.set_target_register(target, result);
@@ -185,7 +407,7 @@ impl CPU {
                 self.set_target_register(RegisterTarget::A, new_value);
             }
             Instructions::RLA => {
-                let carry_bit = if self.registers.f.carry { 1 } else { 0 } << 7;
+                let carry_bit = if self.registers.f.carry { 1 } else { 0 };
                 let register_value = self.get_target_register(&RegisterTarget::A);
                 let new_value = (register_value << 1) | carry_bit;
                 self.set_flags_register(
@@ -222,9 +444,9 @@ impl CPU {
             Instructions::CPL => {
                 let new_value = !self.get_target_register(&RegisterTarget::A);
                 self.set_flags_register(
-                    false, 
-                    true, 
-                    false, 
+                    self.registers.f.zero,
+                    true,
+                    self.registers.f.carry,
                     true);
                 self.set_target_register(RegisterTarget::A, new_value);
             }
@@ -278,7 +500,7 @@ impl CPU {
             Instructions::SET(target, bit_pos) => {
                 let register_value = self.get_target_register(&target);
                 let bit_pos: u8 = bit_pos.into();
-                self.set_target_register(target, register_value & (1 << bit_pos));
+                self.set_target_register(target, register_value | (1 << bit_pos));
             }
             Instructions::SRL(target) => {
                 let register_value = self.get_target_register(&target);
@@ -305,7 +527,7 @@ impl CPU {
             }
             Instructions::RL(target) => {
                 let register_value = self.get_target_register(&target);
-                let carry_bit = if self.registers.f.carry { 1 } else { 0 } << 7;
+                let carry_bit = if self.registers.f.carry { 1 } else { 0 };
                 let result = (register_value << 1) | carry_bit;
                 self.set_flags_register(
                     result == 0,
@@ -328,12 +550,11 @@ impl CPU {
             }
             Instructions::RLC(target) => {
                 let register_value = self.get_target_register(&target);
-                let carry = (register_value & 0x80) >> 7;
-                let result = register_value.rotate_left(1) | carry;
+                let result = register_value.rotate_left(1);
                 self.set_flags_register(
                     result == 0,
                     false,
-                    register_value & 0b1 == 0b1,
+                    (register_value & 0x80) == 0x80,
                     false,
                 );
                 self.set_target_register(target, result);
@@ -356,11 +577,25 @@ impl CPU {
                 self.set_flags_register(
                     result == 0,
                     false,
-                    register_value & 0b1 == 0b1,
+                    (register_value & 0x80) == 0x80,
                     false,
                 );
                 self.set_target_register(target, result);
             }
+            Instructions::STOP => {
+                // On a CGB with a speed switch armed through KEY1, STOP changes the CPU speed
+                // and carries on once the clock has settled. Otherwise it enters low power
+                // mode until a button is pressed
+                if self.cgb_mode && self.bus.switch_speed() {
+                    for _ in 0..SPEED_SWITCH_CYCLES {
+                        self.tick();
+                    }
+                } else {
+                    self.stopped = true;
+                }
+                // STOP is followed by a padding byte that gets skipped
+                self.pc = self.pc.wrapping_add(1);
+            }
             Instructions::SWAP(target) => {
                 let register_value = self.get_target_register(&target);
                 let result = ((register_value & 0xf) << 4) | ((register_value & 0xf0) >> 4);
@@ -372,14 +607,208 @@ impl CPU {
                 );  
                 self.set_target_register(target, result);
             }
-            // _ => {
-            //     println!("Other instructions coming soon...")
-            // }
+            Instructions::NOP => {}
+            Instructions::HALT => {
+                // With IME off and an interrupt already pending HALT doesn't halt, instead
+                // the CPU fails to move past the next opcode byte
+                if !self.ime && self.bus.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
+            Instructions::DI => {
+                self.ime = false;
+                self.ime_pending = false;
+            }
+            Instructions::EI => self.ime_pending = true,
+            Instructions::LD(load_type) => self.execute_load(load_type),
+            Instructions::PUSH(target) => {
+                let value = self.get_stack_register(target);
+                self.tick();
+                self.push(value);
+            }
+            Instructions::POP(target) => {
+                let value = self.pop();
+                self.set_stack_register(target, value);
+            }
+            Instructions::INC16(target) => {
+                let value = self.get_word_register(target);
+                self.tick();
+                self.bus.oam_bug_write(value);
+                self.set_word_register(target, value.wrapping_add(1));
+            }
+            Instructions::DEC16(target) => {
+                let value = self.get_word_register(target);
+                self.tick();
+                self.bus.oam_bug_write(value);
+                self.set_word_register(target, value.wrapping_sub(1));
+            }
+            Instructions::ADDSP(offset) => {
+                let result = self.sp_with_offset(offset);
+                self.tick();
+                self.tick();
+                self.sp = result;
+            }
+            Instructions::JP(test, address) => {
+                if self.check_jump(test) {
+                    self.tick();
+                    self.pc = address;
+                }
+            }
+            Instructions::JPHL => self.pc = self.registers.get_hl(),
+            Instructions::JR(test, offset) => {
+                if self.check_jump(test) {
+                    self.tick();
+                    self.pc = self.pc.wrapping_add_signed(offset as i16);
+                }
+            }
+            Instructions::CALL(test, address) => {
+                if self.check_jump(test) {
+                    self.tick();
+                    self.push(self.pc);
+                    self.pc = address;
+                }
+            }
+            Instructions::RET(test) => {
+                // Conditional returns spend a cycle checking the flags first
+                if test != JumpTest::Always {
+                    self.tick();
+                }
+                if self.check_jump(test) {
+                    let address = self.pop();
+                    self.tick();
+                    self.pc = address;
+                }
+            }
+            Instructions::RETI => {
+                let address = self.pop();
+                self.tick();
+                self.pc = address;
+                self.ime = true;
+            }
+            Instructions::RST(vector) => {
+                self.tick();
+                self.push(self.pc);
+                self.pc = vector as u16;
+            }
+            Instructions::INVALID(_) => self.locked = true,
+        }
+
+    }
+
+    fn execute_load(&mut self, load_type: LoadType) {
+        match load_type {
+            LoadType::Byte(target, source) => {
+                let value = self.get_target_register(&source);
+                self.set_target_register(target, value);
+            }
+            LoadType::Word(target, value) => self.set_word_register(target, value),
+            LoadType::AFromIndirect(indirect) => {
+                let address = self.indirect_address(indirect);
+                self.registers.a = self.read_cycle(address);
+            }
+            LoadType::IndirectFromA(indirect) => {
+                let address = self.indirect_address(indirect);
+                self.write_cycle(address, self.registers.a);
+            }
+            LoadType::IndirectFromSP(address) => {
+                let [low, high] = self.sp.to_le_bytes();
+                self.write_cycle(address, low);
+                self.write_cycle(address.wrapping_add(1), high);
+            }
+            LoadType::SPFromHL => {
+                self.tick();
+                self.sp = self.registers.get_hl();
+            }
+            LoadType::HLFromSPOffset(offset) => {
+                let result = self.sp_with_offset(offset);
+                self.tick();
+                self.registers.set_hl(result);
+            }
+        }
+    }
+
+    fn indirect_address(&mut self, indirect: Indirect) -> u16 {
+        match indirect {
+            Indirect::BC => self.registers.get_bc(),
+            Indirect::DE => self.registers.get_de(),
+            Indirect::HLInc => {
+                let hl = self.registers.get_hl();
+                self.registers.set_hl(hl.wrapping_add(1));
+                hl
+            }
+            Indirect::HLDec => {
+                let hl = self.registers.get_hl();
+                self.registers.set_hl(hl.wrapping_sub(1));
+                hl
+            }
+            Indirect::Word(address) => address,
+            Indirect::HighC => 0xFF00 | self.registers.c as u16,
+            Indirect::High(offset) => 0xFF00 | offset as u16,
+        }
+    }
+
+    // SP plus a signed offset, used by ADD SP, e8 and LD HL, SP + e8. The flags come from
+    // adding the offset to the low byte of SP as an unsigned value
+    fn sp_with_offset(&mut self, offset: i8) -> u16 {
+        let unsigned = offset as u8 as u16;
+        self.set_flags_register(
+            false,
+            false,
+            (self.sp & 0xFF) + unsigned > 0xFF,
+            (self.sp & 0xF) + (unsigned & 0xF) > 0xF
+        );
+        return self.sp.wrapping_add_signed(offset as i16)
+    }
+
+    fn check_jump(&self, test: JumpTest) -> bool {
+        match test {
+            JumpTest::NotZero => !self.registers.f.zero,
+            JumpTest::Zero => self.registers.f.zero,
+            JumpTest::NotCarry => !self.registers.f.carry,
+            JumpTest::Carry => self.registers.f.carry,
+            JumpTest::Always => true,
+        }
+    }
+
+    fn get_word_register(&self, target: VirtualRegisterTarget) -> u16 {
+        match target {
+            VirtualRegisterTarget::BC => self.registers.get_bc(),
+            VirtualRegisterTarget::DE => self.registers.get_de(),
+            VirtualRegisterTarget::HL => self.registers.get_hl(),
+            VirtualRegisterTarget::SP => self.sp,
+        }
+    }
+
+    fn set_word_register(&mut self, target: VirtualRegisterTarget, value: u16) {
+        match target {
+            VirtualRegisterTarget::BC => self.registers.set_bc(value),
+            VirtualRegisterTarget::DE => self.registers.set_de(value),
+            VirtualRegisterTarget::HL => self.registers.set_hl(value),
+            VirtualRegisterTarget::SP => self.sp = value,
         }
+    }
+
+    fn get_stack_register(&self, target: StackTarget) -> u16 {
+        match target {
+            StackTarget::AF => self.registers.get_af(),
+            StackTarget::BC => self.registers.get_bc(),
+            StackTarget::DE => self.registers.get_de(),
+            StackTarget::HL => self.registers.get_hl(),
+        }
+    }
 
+    fn set_stack_register(&mut self, target: StackTarget, value: u16) {
+        match target {
+            StackTarget::AF => self.registers.set_af(value),
+            StackTarget::BC => self.registers.set_bc(value),
+            StackTarget::DE => self.registers.set_de(value),
+            StackTarget::HL => self.registers.set_hl(value),
+        }
     }
 
-    fn get_target_register(&self, target: &RegisterTarget) -> u8 {
+    fn get_target_register(&mut self, target: &RegisterTarget) -> u8 {
         match target {
             RegisterTarget::A => self.registers.a,
             RegisterTarget::B => self.registers.b,
@@ -388,6 +817,8 @@ impl CPU {
             RegisterTarget::E => self.registers.e,
             RegisterTarget::H => self.registers.h,
             RegisterTarget::L => self.registers.l,
+            RegisterTarget::HLI => self.read_cycle(self.registers.get_hl()),
+            RegisterTarget::D8(value) => *value,
         }
     }
 
@@ -400,8 +831,9 @@ impl CPU {
             RegisterTarget::E => self.registers.e = value,
             RegisterTarget::H => self.registers.h = value,
             RegisterTarget::L => self.registers.l = value,
-
-
+            RegisterTarget::HLI => self.write_cycle(self.registers.get_hl(), value),
+            // Immediates are only ever read from
+            RegisterTarget::D8(_) => {}
         }
     }
 
@@ -414,3 +846,33 @@ impl CPU {
         }
     }
 }
+
+// The model isn't part of this, savestate::load checks it matches before getting here
+impl SaveState for CPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.registers.save_state(writer);
+        writer.write_u16(self.pc);
+        writer.write_u16(self.sp);
+        for flag in [self.cgb_mode, self.stopped, self.halted, self.ime, self.ime_pending, self.halt_bug, self.locked] {
+            writer.write_bool(flag);
+        }
+        writer.write_u64(self.cycles);
+        self.bus.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.registers.load_state(reader)?;
+        self.pc = reader.read_u16()?;
+        self.sp = reader.read_u16()?;
+        self.cgb_mode = reader.read_bool()?;
+        self.stopped = reader.read_bool()?;
+        self.halted = reader.read_bool()?;
+        self.ime = reader.read_bool()?;
+        self.ime_pending = reader.read_bool()?;
+        self.halt_bug = reader.read_bool()?;
+        self.locked = reader.read_bool()?;
+        self.cycles = reader.read_u64()?;
+        self.bus.load_state(reader)?;
+        return Ok(())
+    }
+}
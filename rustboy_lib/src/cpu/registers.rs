@@ -1,3 +1,8 @@
+use crate::cartridge::header::CartridgeHeader;
+use crate::model::Model;
+use crate::ppu::compat::{is_nintendo_title, title_checksum};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
 #[derive(Debug)]
 pub struct Registers {
     // "Virtual" 16 bit registers are af, bc, de, and hl
@@ -26,6 +31,58 @@ impl Registers {
             l: 0,
         }
     }
+    // The values the boot ROM of each model leaves behind. Some games look at these to
+    // work out what they are running on, most famously A = 0x11 on a CGB
+    pub fn post_boot(model: Model, header: &CartridgeHeader) -> Registers {
+        let mut registers = Registers::new();
+        // The DMG boot ROM ends on the header checksum check, which leaves H and C set
+        // unless the checksum byte is 0
+        let checksum_flags = FlagsRegister::from(if header.header_checksum == 0 { 0x80 } else { 0xB0 });
+        match model {
+            Model::Dmg0 => {
+                registers.set_af(0x0100);
+                registers.set_bc(0xFF13);
+                registers.set_de(0x00C1);
+                registers.set_hl(0x8403);
+            }
+            Model::Dmg | Model::Mgb => {
+                registers.a = if model == Model::Mgb { 0xFF } else { 0x01 };
+                registers.f = checksum_flags;
+                registers.set_bc(0x0013);
+                registers.set_de(0x00D8);
+                registers.set_hl(0x014D);
+            }
+            Model::Sgb => {
+                registers.set_af(0x0100);
+                registers.set_bc(0x0014);
+                registers.set_de(0x0000);
+                registers.set_hl(0xC060);
+            }
+            Model::Cgb | Model::Agb => {
+                registers.set_af(0x1180);
+                if header.is_cgb() {
+                    registers.set_bc(0x0000);
+                    registers.set_de(0xFF56);
+                    registers.set_hl(0x000D);
+                } else {
+                    // DMG cartridges get B set to the title checksum the palette lookup used
+                    registers.b = if is_nintendo_title(header) { title_checksum(header) } else { 0x00 };
+                    registers.c = 0x00;
+                    registers.set_de(0x0008);
+                    registers.set_hl(0x007C);
+                }
+                if model == Model::Agb {
+                    // The AGB boot ROM is the CGB one with an extra INC B at the end
+                    let b = registers.b.wrapping_add(1);
+                    registers.b = b;
+                    registers.f.zero = b == 0;
+                    registers.f.subtract = false;
+                    registers.f.half_carry = b & 0xF == 0;
+                }
+            }
+        }
+        return registers
+    }
     // Getting/Setting the virtual 16 bit registers
     pub fn get_af(&self) -> u16 {
         (self.a as u16) << 8 | u8::from(self.f) as u16
@@ -104,4 +161,38 @@ impl std::convert::From<u8> for FlagsRegister {
             carry
         }
     }
-}
\ No newline at end of file
+}
+
+impl SaveState for FlagsRegister {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(u8::from(*self));
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        *self = FlagsRegister::from(reader.read_u8()?);
+        return Ok(())
+    }
+}
+
+impl SaveState for Registers {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for value in [self.a, self.b, self.c, self.d, self.e] {
+            writer.write_u8(value);
+        }
+        self.f.save_state(writer);
+        writer.write_u8(self.h);
+        writer.write_u8(self.l);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.a = reader.read_u8()?;
+        self.b = reader.read_u8()?;
+        self.c = reader.read_u8()?;
+        self.d = reader.read_u8()?;
+        self.e = reader.read_u8()?;
+        self.f.load_state(reader)?;
+        self.h = reader.read_u8()?;
+        self.l = reader.read_u8()?;
+        return Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const KEY1_ADDRESS: u16 = 0xFF4D;
+// M-cycles the CPU sits still after STOP while the clock changes speed
+pub const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+// KEY1, the CGB speed switch. Writing bit 0 arms a switch which then happens on the next
+// STOP instruction. Bit 7 reports the current speed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpeedSwitch {
+    pub double_speed: bool,
+    pub armed: bool,
+}
+impl SpeedSwitch {
+    pub fn new() -> SpeedSwitch {
+        return SpeedSwitch {
+            double_speed: false,
+            armed: false,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        0x7E | (self.double_speed as u8) << 7 | self.armed as u8
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.armed = value & 0b1 == 0b1;
+    }
+
+    // Called by STOP. Returns whether the speed actually changed
+    pub fn try_switch(&mut self) -> bool {
+        if !self.armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.armed = false;
+        return true
+    }
+}
+
+impl SaveState for SpeedSwitch {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.double_speed);
+        writer.write_bool(self.armed);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.double_speed = reader.read_bool()?;
+        self.armed = reader.read_bool()?;
+        return Ok(())
+    }
+}
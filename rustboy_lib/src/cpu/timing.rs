@@ -0,0 +1,60 @@
+// How many M-cycles every opcode takes. Conditional jumps, calls and returns list the
+// duration when the condition fails, OPCODE_CYCLES_BRANCHED has the taken durations.
+// Entries for the unused opcodes and the 0xCB prefix are 0, prefixed instructions are
+// covered by CB_OPCODE_CYCLES, which includes fetching the prefix
+#[rustfmt::skip]
+pub const OPCODE_CYCLES: [u8; 256] = [
+//  x0 x1 x2 x3 x4 x5 x6 x7 x8 x9 xA xB xC xD xE xF
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0x
+    1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 1x
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 2x
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 3x
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 4x
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 5x
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 6x
+    2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1, // 7x
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 8x
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 9x
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // Ax
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // Bx
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4, // Cx
+    2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4, // Dx
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4, // Ex
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4, // Fx
+];
+
+#[rustfmt::skip]
+pub const OPCODE_CYCLES_BRANCHED: [u8; 256] = {
+    let mut table = OPCODE_CYCLES;
+    table[0x20] = 3; table[0x28] = 3; table[0x30] = 3; table[0x38] = 3; // JR cc
+    table[0xC0] = 5; table[0xC8] = 5; table[0xD0] = 5; table[0xD8] = 5; // RET cc
+    table[0xC2] = 4; table[0xCA] = 4; table[0xD2] = 4; table[0xDA] = 4; // JP cc
+    table[0xC4] = 6; table[0xCC] = 6; table[0xD4] = 6; table[0xDC] = 6; // CALL cc
+    table
+};
+
+// Register operands take 2 M-cycles, (HL) operands 4 for the read-modify-write
+// instructions and 3 for BIT, which only reads
+pub const CB_OPCODE_CYCLES: [u8; 256] = {
+    let mut table = [2; 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        if opcode & 0b111 == 6 {
+            table[opcode] = if opcode >> 6 == 1 { 3 } else { 4 };
+        }
+        opcode += 1;
+    }
+    table
+};
+
+// M-cycles taken by the instruction starting with opcode, with prefix holding the second
+// byte of 0xCB prefixed instructions
+pub fn instruction_cycles(opcode: u8, prefix: u8, branch_taken: bool) -> u8 {
+    if opcode == 0xCB {
+        CB_OPCODE_CYCLES[prefix as usize]
+    } else if branch_taken {
+        OPCODE_CYCLES_BRANCHED[opcode as usize]
+    } else {
+        OPCODE_CYCLES[opcode as usize]
+    }
+}
@@ -0,0 +1,205 @@
+use crate::cpu::CPU;
+
+// Breakpoint conditions like "a == 0x3C" or "hl >= $C000 && [hl] != 0". Numbers are
+// decimal unless they start with $ or 0x (hexadecimal) or % (binary)
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Register {
+    A, F, B, C, D, E, H, L, AF, BC, DE, HL, SP, PC,
+}
+impl Register {
+    pub const ALL: [Register; 14] = [
+        Register::A, Register::F, Register::B, Register::C, Register::D, Register::E, Register::H,
+        Register::L, Register::AF, Register::BC, Register::DE, Register::HL, Register::SP, Register::PC,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Register::A => "a",
+            Register::F => "f",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+            Register::E => "e",
+            Register::H => "h",
+            Register::L => "l",
+            Register::AF => "af",
+            Register::BC => "bc",
+            Register::DE => "de",
+            Register::HL => "hl",
+            Register::SP => "sp",
+            Register::PC => "pc",
+        }
+    }
+
+    pub fn value(&self, cpu: &CPU) -> u16 {
+        let registers = &cpu.registers;
+        match self {
+            Register::A => registers.a as u16,
+            Register::F => u8::from(registers.f) as u16,
+            Register::B => registers.b as u16,
+            Register::C => registers.c as u16,
+            Register::D => registers.d as u16,
+            Register::E => registers.e as u16,
+            Register::H => registers.h as u16,
+            Register::L => registers.l as u16,
+            Register::AF => registers.get_af(),
+            Register::BC => registers.get_bc(),
+            Register::DE => registers.get_de(),
+            Register::HL => registers.get_hl(),
+            Register::SP => cpu.sp,
+            Register::PC => cpu.pc,
+        }
+    }
+}
+impl std::str::FromStr for Register {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Register, String> {
+        Register::ALL
+            .into_iter()
+            .find(|register| register.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown register \"{}\"", name))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Value(u16),
+    Memory(u16),             // [$C000]
+    MemoryAtRegister(Register), // [hl]
+}
+impl Operand {
+    fn value(&self, cpu: &CPU) -> u16 {
+        match self {
+            Operand::Register(register) => register.value(cpu),
+            Operand::Value(value) => *value,
+            Operand::Memory(address) => cpu.bus.peek(*address) as u16,
+            Operand::MemoryAtRegister(register) => cpu.bus.peek(register.value(cpu)) as u16,
+        }
+    }
+}
+impl std::str::FromStr for Operand {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Operand, String> {
+        let text = text.trim();
+        if let Some(inner) = text.strip_prefix('[').and_then(|inner| inner.strip_suffix(']')) {
+            return match inner.trim().parse::<Register>() {
+                Ok(register) => Ok(Operand::MemoryAtRegister(register)),
+                Err(_) => parse_number(inner).map(Operand::Memory),
+            };
+        }
+        match text.parse::<Register>() {
+            Ok(register) => Ok(Operand::Register(register)),
+            Err(_) => parse_number(text).map(Operand::Value),
+        }
+    }
+}
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "{}", register.name()),
+            Operand::Value(value) => write!(f, "${:02X}", value),
+            Operand::Memory(address) => write!(f, "[${:04X}]", address),
+            Operand::MemoryAtRegister(register) => write!(f, "[{}]", register.name()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+impl Comparison {
+    // Longest first, so <= isn't taken for <
+    const SYMBOLS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    fn symbol(&self) -> &'static str {
+        Comparison::SYMBOLS.iter().find(|(_, comparison)| comparison == self).unwrap().0
+    }
+
+    fn holds(&self, left: u16, right: u16) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Clause {
+    pub left: Operand,
+    pub comparison: Comparison,
+    pub right: Operand,
+}
+
+// Clauses joined with &&, all of which have to hold
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub clauses: Vec<Clause>,
+}
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        self.clauses.iter().all(|clause| clause.comparison.holds(clause.left.value(cpu), clause.right.value(cpu)))
+    }
+}
+impl std::str::FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Condition, String> {
+        let mut clauses = Vec::new();
+        for clause in text.split("&&") {
+            let (position, symbol, comparison) = Comparison::SYMBOLS
+                .iter()
+                .filter_map(|&(symbol, comparison)| clause.find(symbol).map(|position| (position, symbol, comparison)))
+                .min_by_key(|&(position, symbol, _)| (position, std::cmp::Reverse(symbol.len())))
+                .ok_or_else(|| format!("\"{}\" doesn't compare anything", clause.trim()))?;
+            let left = clause[..position].parse()?;
+            let right = clause[position + symbol.len()..].parse()?;
+            clauses.push(Clause { left, comparison, right });
+        }
+        return Ok(Condition { clauses })
+    }
+}
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, clause) in self.clauses.iter().enumerate() {
+            if index > 0 {
+                write!(f, " && ")?;
+            }
+            write!(f, "{} {} {}", clause.left, clause.comparison.symbol(), clause.right)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let lower = text.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix('$').or(lower.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(binary) = lower.strip_prefix('%') {
+        u16::from_str_radix(binary, 2)
+    } else {
+        lower.parse()
+    };
+    parsed.map_err(|_| format!("\"{}\" isn't a number", text))
+}
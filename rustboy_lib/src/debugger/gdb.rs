@@ -0,0 +1,485 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use super::watch::{Access, WatchKind};
+use super::{Debugger, StopReason};
+use crate::gameboy::GameBoy;
+use crate::ppu::lcd::DOTS_PER_FRAME;
+
+// The GDB remote serial protocol, so GDB and the frontends built on it can debug ROMs over
+// a socket. Registers go over the wire as AF, BC, DE, HL, SP and PC, 16 bits each and
+// little endian, as the target description handed out says. Breakpoints and watchpoints
+// live in a Debugger, so the client never has to patch code to stop somewhere
+
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rustboy.sm83">
+    <reg name="af" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="bc" bitsize="16" type="uint16"/>
+    <reg name="de" bitsize="16" type="uint16"/>
+    <reg name="hl" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const REGISTER_COUNT: usize = 6;
+// The most data a reply carries, well under the PacketSize advertised
+const MAX_PAYLOAD: usize = 0x800;
+// How long a continue runs between checks for the client interrupting it, in M-cycles
+const RUN_SLICE: u64 = DOTS_PER_FRAME / 4;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+// What the server should do after a packet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    Packet(String),
+    Resume(Resume),
+    Close(Option<String>), // With a last reply to send, for detaching
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    Step,
+}
+
+// One thing read off the wire
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Incoming {
+    Packet(String),
+    Interrupt, // Ctrl-C, 0x03 outside a packet
+    Ack,
+    Nack,
+    Corrupt, // A packet whose checksum didn't match
+}
+
+// The sum of the bytes, modulo 256
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// $data#checksum, with the characters the protocol uses escaped
+pub fn encode_packet(data: &str) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data.as_bytes() {
+        if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+            escaped.extend([b'}', byte ^ 0x20]);
+        } else {
+            escaped.push(byte);
+        }
+    }
+    let mut packet = vec![b'$'];
+    packet.extend(&escaped);
+    packet.extend(format!("#{:02x}", checksum(&escaped)).into_bytes());
+    return packet
+}
+
+// Splits what arrives on the socket into packets, acks and interrupts
+#[derive(Clone, Debug, Default)]
+pub struct PacketReader {
+    buffer: Vec<u8>,
+}
+impl PacketReader {
+    pub fn new() -> PacketReader {
+        return PacketReader::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn take(&mut self) -> Option<Incoming> {
+        loop {
+            let &first = self.buffer.first()?;
+            let incoming = match first {
+                b'+' => Incoming::Ack,
+                b'-' => Incoming::Nack,
+                0x03 => Incoming::Interrupt,
+                b'$' => {
+                    let end = self.buffer.iter().position(|&byte| byte == b'#')?;
+                    if self.buffer.len() < end + 3 {
+                        return None;
+                    }
+                    let data = self.buffer[1..end].to_vec();
+                    let sent = std::str::from_utf8(&self.buffer[end + 1..end + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    self.buffer.drain(..end + 3);
+                    if sent != Some(checksum(&data)) {
+                        return Some(Incoming::Corrupt);
+                    }
+                    return Some(Incoming::Packet(unescape(&data)));
+                }
+                // Line noise between packets
+                _ => {
+                    self.buffer.remove(0);
+                    continue;
+                }
+            };
+            self.buffer.remove(0);
+            return Some(incoming);
+        }
+    }
+}
+
+fn unescape(data: &[u8]) -> String {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => unescaped.push(bytes.next().map_or(0, |&byte| byte ^ 0x20)),
+            _ => unescaped.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+// Answers packets against a GameBoy. It knows nothing about sockets, GdbServer does that
+#[derive(Clone, Debug)]
+pub struct GdbStub {
+    debugger: Debugger,
+    // What the client asked for, by packet type, address and kind, and the id it got
+    points: Vec<(u8, u16, u16, usize)>,
+    last_stop: String,
+}
+impl GdbStub {
+    pub fn new() -> GdbStub {
+        return GdbStub {
+            debugger: Debugger::new(),
+            points: Vec::new(),
+            last_stop: format!("S{:02x}", SIGTRAP),
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn handle(&mut self, gameboy: &mut GameBoy, packet: &str) -> Reply {
+        let reply = |text: &str| Reply::Packet(String::from(text));
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        match command {
+            "?" => Reply::Packet(self.last_stop.clone()),
+            "g" => Reply::Packet(registers(gameboy).iter().map(|&value| hex_word(value)).collect()),
+            "G" => {
+                let values: Option<Vec<u16>> = (0..REGISTER_COUNT).map(|index| arguments.get(index * 4..index * 4 + 4).and_then(parse_word)).collect();
+                match values {
+                    Some(values) => {
+                        for (index, value) in values.into_iter().enumerate() {
+                            set_register(gameboy, index, value);
+                        }
+                        reply("OK")
+                    }
+                    None => reply("E01"),
+                }
+            }
+            "p" => match parse_hex(arguments) {
+                Some(index) if (index as usize) < REGISTER_COUNT => Reply::Packet(hex_word(registers(gameboy)[index as usize])),
+                _ => reply("E01"),
+            },
+            "P" => {
+                let parsed = arguments.split_once('=').and_then(|(index, value)| Some((parse_hex(index)? as usize, parse_word(value)?)));
+                match parsed {
+                    Some((index, value)) if index < REGISTER_COUNT => {
+                        set_register(gameboy, index, value);
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            "m" => match parse_range(arguments) {
+                Some((address, length)) => {
+                    let length = (length as usize).min(MAX_PAYLOAD / 2) as u16;
+                    Reply::Packet((0..length).map(|offset| format!("{:02x}", gameboy.cpu.bus.peek(address.wrapping_add(offset)))).collect())
+                }
+                None => reply("E01"),
+            },
+            // Writes go through the bus like the CPU's own, so writing to ROM reaches the
+            // mapper's registers rather than changing the ROM
+            "M" => {
+                let parsed = arguments.split_once(':').and_then(|(range, data)| Some((parse_range(range)?, parse_bytes(data)?)));
+                match parsed {
+                    Some(((address, length), data)) if data.len() == length as usize => {
+                        for (offset, &value) in data.iter().enumerate() {
+                            gameboy.cpu.bus.write(address.wrapping_add(offset as u16), value);
+                        }
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            "c" | "s" => {
+                if !arguments.is_empty() {
+                    match parse_word_address(arguments) {
+                        Some(address) => gameboy.cpu.pc = address,
+                        None => return reply("E01"),
+                    }
+                }
+                Reply::Resume(if command == "c" { Resume::Continue } else { Resume::Step })
+            }
+            "Z" | "z" => self.point(command == "Z", arguments),
+            "H" | "T" => reply("OK"),
+            "k" => Reply::Close(None),
+            "D" => Reply::Close(Some(String::from("OK"))),
+            _ => self.handle_long(packet),
+        }
+    }
+
+    // The packets with names rather than a single letter
+    fn handle_long(&self, packet: &str) -> Reply {
+        let reply = |text: &str| Reply::Packet(String::from(text));
+        if packet.starts_with("qSupported") {
+            return Reply::Packet(format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;vContSupported+", MAX_PAYLOAD * 2));
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, length)) = request.split_once(',').and_then(|(offset, length)| Some((parse_hex(offset)? as usize, parse_hex(length)? as usize))) else {
+                return reply("E01");
+            };
+            let start = offset.min(TARGET_XML.len());
+            let end = start.saturating_add(length.min(MAX_PAYLOAD)).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            return Reply::Packet(format!("{}{}", more, &TARGET_XML[start..end]));
+        }
+        if let Some(action) = packet.strip_prefix("vCont;") {
+            return match action.chars().next() {
+                Some('c' | 'C') => Reply::Resume(Resume::Continue),
+                Some('s' | 'S') => Reply::Resume(Resume::Step),
+                _ => reply("E01"),
+            };
+        }
+        match packet {
+            "vCont?" => reply("vCont;c;C;s;S"),
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            "QStartNoAckMode" => reply("OK"),
+            // Empty means not supported
+            _ => reply(""),
+        }
+    }
+
+    // Z and z: type,address,kind where kind is the instruction length for breakpoints and
+    // the number of bytes for watchpoints. Conditions after a ; are ignored
+    fn point(&mut self, insert: bool, arguments: &str) -> Reply {
+        let arguments = arguments.split(';').next().unwrap_or_default();
+        let mut fields = arguments.split(',');
+        let parsed = (|| Some((parse_hex(fields.next()?)? as u8, parse_word_address(fields.next()?)?, parse_hex(fields.next()?)?.min(0xFFFF) as u16)))();
+        let Some((kind, address, length)) = parsed else {
+            return Reply::Packet(String::from("E01"));
+        };
+        if !insert {
+            let Some(index) = self.points.iter().position(|point| (point.0, point.1, point.2) == (kind, address, length)) else {
+                return Reply::Packet(String::from("E01"));
+            };
+            let (_, _, _, id) = self.points.remove(index);
+            self.debugger.remove(id);
+            return Reply::Packet(String::from("OK"));
+        }
+        let end = address.wrapping_add(length.max(1) - 1);
+        let id = match kind {
+            0 | 1 => self.debugger.add_breakpoint(address, None, None),
+            2 => self.debugger.add_watchpoint(address, end, WatchKind::Write),
+            3 => self.debugger.add_watchpoint(address, end, WatchKind::Read),
+            4 => self.debugger.add_watchpoint(address, end, WatchKind::Access),
+            _ => return Reply::Packet(String::new()),
+        };
+        self.points.push((kind, address, length, id));
+        return Reply::Packet(String::from("OK"))
+    }
+
+    // Runs until something stops it and returns the stop reply. A continue asks interrupted
+    // every so often whether the client wants it to stop
+    pub fn resume<F: FnMut() -> bool>(&mut self, gameboy: &mut GameBoy, resume: Resume, mut interrupted: F) -> String {
+        let reason = match resume {
+            Resume::Step => self.debugger.step(gameboy),
+            Resume::Continue => loop {
+                let reason = self.debugger.run(gameboy, RUN_SLICE);
+                if reason != StopReason::Limit || interrupted() {
+                    break reason;
+                }
+            },
+        };
+        self.last_stop = match reason {
+            StopReason::Done | StopReason::Breakpoint(_) => format!("S{:02x}", SIGTRAP),
+            StopReason::Limit => format!("S{:02x}", SIGINT),
+            StopReason::Watchpoint(id, access) => {
+                let kind = self.debugger.watchpoints().iter().find(|watchpoint| watchpoint.id == id).map(|watchpoint| watchpoint.kind);
+                let name = match (kind, access.access) {
+                    (Some(WatchKind::Access), _) => "awatch",
+                    (_, Access::Read) => "rwatch",
+                    (_, Access::Write) => "watch",
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, name, access.address)
+            }
+        };
+        return self.last_stop.clone()
+    }
+}
+
+// AF, BC, DE, HL, SP, PC in the order of the target description
+fn registers(gameboy: &GameBoy) -> [u16; REGISTER_COUNT] {
+    let cpu = &gameboy.cpu;
+    let registers = &cpu.registers;
+    return [registers.get_af(), registers.get_bc(), registers.get_de(), registers.get_hl(), cpu.sp, cpu.pc]
+}
+
+fn set_register(gameboy: &mut GameBoy, index: usize, value: u16) {
+    let cpu = &mut gameboy.cpu;
+    match index {
+        0 => cpu.registers.set_af(value),
+        1 => cpu.registers.set_bc(value),
+        2 => cpu.registers.set_de(value),
+        3 => cpu.registers.set_hl(value),
+        4 => cpu.sp = value,
+        _ => cpu.pc = value,
+    }
+}
+
+// Registers are target byte order, so little endian
+fn hex_word(value: u16) -> String {
+    let [low, high] = value.to_le_bytes();
+    format!("{:02x}{:02x}", low, high)
+}
+
+fn parse_word(text: &str) -> Option<u16> {
+    let bytes = parse_bytes(text)?;
+    match bytes[..] {
+        [low, high] => Some(u16::from_le_bytes([low, high])),
+        _ => None,
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+// Addresses are plain big hex numbers, GDB may send them wider than 16 bits
+fn parse_word_address(text: &str) -> Option<u16> {
+    u64::from_str_radix(text, 16).ok().map(|address| address as u16)
+}
+
+fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (address, length) = text.split_once(',')?;
+    return Some((parse_word_address(address)?, parse_hex(length)?.min(0xFFFF) as u16))
+}
+
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+// A GDB client on a TCP socket on localhost, served until it detaches or disconnects
+#[derive(Debug)]
+pub struct GdbServer {
+    stream: TcpStream,
+    reader: PacketReader,
+    stub: GdbStub,
+    last_sent: Vec<u8>, // For when the client asks for it again
+    no_ack: bool,
+}
+impl GdbServer {
+    // Wait on 127.0.0.1:<port> for a client to connect
+    pub fn host(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let (stream, _) = listener.accept()?;
+        return GdbServer::from_stream(stream)
+    }
+    pub fn from_stream(stream: TcpStream) -> io::Result<GdbServer> {
+        stream.set_nodelay(true)?;
+        return Ok(GdbServer {
+            stream,
+            reader: PacketReader::new(),
+            stub: GdbStub::new(),
+            last_sent: Vec::new(),
+            no_ack: false,
+        })
+    }
+
+    pub fn serve(&mut self, gameboy: &mut GameBoy) -> io::Result<()> {
+        loop {
+            let incoming = match self.reader.take() {
+                Some(incoming) => incoming,
+                None => {
+                    if !poll(&mut self.stream, &mut self.reader)? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+            let packet = match incoming {
+                Incoming::Packet(packet) => packet,
+                Incoming::Nack => {
+                    self.stream.write_all(&self.last_sent)?;
+                    continue;
+                }
+                Incoming::Corrupt => {
+                    if !self.no_ack {
+                        self.stream.write_all(b"-")?;
+                    }
+                    continue;
+                }
+                // An interrupt while stopped has nothing to stop
+                Incoming::Ack | Incoming::Interrupt => continue,
+            };
+            if !self.no_ack {
+                self.stream.write_all(b"+")?;
+            }
+            match self.stub.handle(gameboy, &packet) {
+                Reply::Packet(reply) => self.send(&reply)?,
+                Reply::Resume(resume) => {
+                    self.stream.set_nonblocking(true)?;
+                    let (stream, reader) = (&mut self.stream, &mut self.reader);
+                    let mut disconnected = false;
+                    let stop = self.stub.resume(gameboy, resume, || {
+                        disconnected = !matches!(poll(stream, reader), Ok(true));
+                        disconnected || reader.buffer.contains(&0x03)
+                    });
+                    if disconnected {
+                        return Ok(());
+                    }
+                    self.stream.set_nonblocking(false)?;
+                    self.reader.buffer.retain(|&byte| byte != 0x03);
+                    self.send(&stop)?;
+                }
+                Reply::Close(reply) => {
+                    if let Some(reply) = reply {
+                        self.send(&reply)?;
+                    }
+                    return Ok(());
+                }
+            }
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.last_sent = encode_packet(data);
+        self.stream.write_all(&self.last_sent)
+    }
+
+}
+
+// One read into reader. False when the other end closed the connection
+fn poll(stream: &mut TcpStream, reader: &mut PacketReader) -> io::Result<bool> {
+    let mut buffer = [0u8; 4096];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok(false),
+            Ok(count) => {
+                reader.push(&buffer[..count]);
+                return Ok(true);
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+            Err(e) => return Err(e),
+        }
+    }
+}
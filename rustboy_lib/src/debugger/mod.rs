@@ -0,0 +1,277 @@
+pub mod condition;
+pub mod gdb;
+pub mod watch;
+
+use condition::{parse_number, Condition};
+use watch::{MemoryAccess, WatchKind, Watchpoint};
+use crate::cpu::instructions::Instructions;
+use crate::cpu::CPU;
+use crate::disassembler::DisassemblyLine;
+use crate::gameboy::GameBoy;
+use crate::memory::bus::MemoryBus;
+use crate::symbols::Symbols;
+
+// Stops execution when PC reaches address. With a bank it only stops while that bank is
+// mapped there, and with a condition only when it holds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub address: u16,
+    pub bank: Option<usize>,
+    pub condition: Option<Condition>,
+}
+impl Breakpoint {
+    fn hits(&self, cpu: &CPU) -> bool {
+        cpu.pc == self.address
+            && self.bank.is_none_or(|bank| cpu.bus.bank_at(self.address) == bank)
+            && self.condition.as_ref().is_none_or(|condition| condition.holds(cpu))
+    }
+}
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "#{} ", self.id)?;
+        if let Some(bank) = self.bank {
+            write!(f, "{:02X}:", bank)?;
+        }
+        write!(f, "${:04X}", self.address)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Done, // The step, step over, step out or run to cursor finished
+    Breakpoint(usize),
+    Watchpoint(usize, MemoryAccess),
+    Limit, // Ran out of cycles before anything else happened
+}
+
+// Breakpoints, watchpoints and the ways of running a GameBoy until one of them, or the
+// end of a step, is reached. Breakpoints are checked between instructions, so running
+// never stops on the instruction it started at
+#[derive(Clone, Debug)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+}
+impl Debugger {
+    pub fn new() -> Debugger {
+        return Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Returns the new breakpoint's id
+    pub fn add_breakpoint(&mut self, address: u16, bank: Option<usize>, condition: Option<Condition>) -> usize {
+        let id = self.take_id();
+        self.breakpoints.push(Breakpoint { id, address, bank, condition });
+        return id
+    }
+
+    // Returns the new watchpoint's id
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) -> usize {
+        let id = self.take_id();
+        self.watchpoints.push(Watchpoint { id, start: start.min(end), end: start.max(end), kind });
+        return id
+    }
+
+    // Removes a breakpoint or watchpoint, false if there is none with that id
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        return self.breakpoints.len() + self.watchpoints.len() != count
+    }
+
+    fn take_id(&mut self) -> usize {
+        self.next_id += 1;
+        return self.next_id - 1
+    }
+
+    // Runs one instruction, or one M-cycle of HALT
+    pub fn step(&mut self, gameboy: &mut GameBoy) -> StopReason {
+        return self.run_until(gameboy, u64::MAX, |_, _| true)
+    }
+
+    // Runs until a breakpoint or watchpoint, or for limit M-cycles
+    pub fn run(&mut self, gameboy: &mut GameBoy, limit: u64) -> StopReason {
+        return self.run_until(gameboy, limit, |_, _| false)
+    }
+
+    // Like step, but a CALL or RST runs until it returns
+    pub fn step_over(&mut self, gameboy: &mut GameBoy, limit: u64) -> StopReason {
+        let cpu = &gameboy.cpu;
+        let (instruction, length) = decode_at(&cpu.bus, cpu.pc);
+        if !matches!(instruction, Instructions::CALL(..) | Instructions::RST(_)) || cpu.halted {
+            return self.step(gameboy);
+        }
+        let return_address = cpu.pc.wrapping_add(length);
+        let sp = cpu.sp;
+        // Recursion comes back through the same address with less on the stack
+        return self.run_until(gameboy, limit, |cpu, _| cpu.pc == return_address && cpu.sp >= sp)
+    }
+
+    // Runs until the current function returns
+    pub fn step_out(&mut self, gameboy: &mut GameBoy, limit: u64) -> StopReason {
+        let sp = gameboy.cpu.sp;
+        return self.run_until(gameboy, limit, |cpu, executed| {
+            matches!(executed, Some(Instructions::RET(_) | Instructions::RETI)) && cpu.sp > sp
+        })
+    }
+
+    // Runs until PC gets to address
+    pub fn run_to(&mut self, gameboy: &mut GameBoy, address: u16, limit: u64) -> StopReason {
+        return self.run_until(gameboy, limit, |cpu, _| cpu.pc == address && !cpu.halted)
+    }
+
+    // Steps until done says so. It gets the CPU after each step and the instruction that
+    // step executed, None for HALT cycles and interrupt dispatch
+    fn run_until<F: FnMut(&CPU, Option<Instructions>) -> bool>(&mut self, gameboy: &mut GameBoy, limit: u64, mut done: F) -> StopReason {
+        gameboy.cpu.watch.set_watchpoints(&self.watchpoints);
+        let deadline = gameboy.cpu.cycles.saturating_add(limit);
+        loop {
+            let cpu = &gameboy.cpu;
+            let runs_instruction = !(cpu.halted || cpu.stopped || cpu.locked || cpu.ime && cpu.bus.pending_interrupts() != 0);
+            let instruction = if runs_instruction { Some(decode_at(&cpu.bus, cpu.pc).0) } else { None };
+            gameboy.step();
+            let cpu = &mut gameboy.cpu;
+            if let Some((id, access)) = cpu.watch.take_hit() {
+                return StopReason::Watchpoint(id, access);
+            }
+            if done(cpu, instruction) {
+                return StopReason::Done;
+            }
+            if !cpu.halted && let Some(breakpoint) = self.breakpoints.iter().find(|breakpoint| breakpoint.hits(cpu)) {
+                return StopReason::Breakpoint(breakpoint.id);
+            }
+            if cpu.cycles >= deadline {
+                return StopReason::Limit;
+            }
+        }
+    }
+}
+
+// Decodes the instruction at address without touching anything, along with its length
+pub fn decode_at(bus: &MemoryBus, address: u16) -> (Instructions, u16) {
+    let mut length = 1;
+    let instruction = Instructions::decode(bus.peek(address), || {
+        let byte = bus.peek(address.wrapping_add(length));
+        length += 1;
+        byte
+    });
+    return (instruction, length)
+}
+
+// Disassembles up to before instructions leading up to address, and after from it on.
+// Going backwards is guesswork with instructions of 1 to 3 bytes, so it starts as far back
+// as it can while still lining up with address
+pub fn disassemble_around(bus: &MemoryBus, address: u16, before: usize, after: usize) -> Vec<DisassemblyLine> {
+    let mut lines = Vec::new();
+    for distance in (1..=before as u16 * 3).rev() {
+        let mut candidate = Vec::new();
+        let mut current = address.wrapping_sub(distance);
+        while current != address && address.wrapping_sub(current) <= distance {
+            let line = disassemble_line(bus, current);
+            current = current.wrapping_add(line.bytes.len() as u16);
+            candidate.push(line);
+        }
+        if current == address {
+            lines = candidate;
+            break;
+        }
+    }
+    lines.drain(..lines.len().saturating_sub(before));
+    let mut current = address;
+    for _ in 0..after {
+        let line = disassemble_line(bus, current);
+        current = current.wrapping_add(line.bytes.len() as u16);
+        lines.push(line);
+    }
+    return lines
+}
+
+fn disassemble_line(bus: &MemoryBus, address: u16) -> DisassemblyLine {
+    let (instruction, length) = decode_at(bus, address);
+    let bytes = (0..length).map(|offset| bus.peek(address.wrapping_add(offset))).collect();
+    let instruction = Some(instruction).filter(|instruction| !matches!(instruction, Instructions::INVALID(_)));
+    return DisassemblyLine { address, bytes, instruction }
+}
+
+// The registers, flags and what the CPU is up to, over two lines
+pub fn format_registers(cpu: &CPU) -> String {
+    let registers = &cpu.registers;
+    let flags = &registers.f;
+    let flag = |set: bool, name: char| if set { name } else { '-' };
+    let state = if cpu.locked {
+        " LOCKED"
+    } else if cpu.stopped {
+        " STOPPED"
+    } else if cpu.halted {
+        " HALTED"
+    } else {
+        ""
+    };
+    return format!(
+        "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:02X}:{:04X}\nF={}{}{}{} IME={} LY={} cycles={}{}",
+        registers.get_af(), registers.get_bc(), registers.get_de(), registers.get_hl(), cpu.sp,
+        cpu.bus.bank_at(cpu.pc), cpu.pc,
+        flag(flags.zero, 'Z'), flag(flags.subtract, 'N'), flag(flags.half_carry, 'H'), flag(flags.carry, 'C'),
+        cpu.ime as u8, cpu.bus.ppu.ly(), cpu.cycles, state,
+    )
+}
+
+// The top count words of the stack, one line each
+pub fn format_stack(cpu: &CPU, count: usize) -> Vec<String> {
+    (0..count as u16)
+        .map(|index| {
+            let address = cpu.sp.wrapping_add(index * 2);
+            let word = u16::from_le_bytes([cpu.bus.peek(address), cpu.bus.peek(address.wrapping_add(1))]);
+            format!("${:04X}: {:04X}", address, word)
+        })
+        .collect()
+}
+
+// Where PC is, named after the closest label before it when there is one
+pub fn format_pc(cpu: &CPU, symbols: &Symbols) -> String {
+    let bank = cpu.bus.bank_at(cpu.pc);
+    match symbols.name(bank, cpu.pc) {
+        Some(name) => format!("{:02X}:{:04X} {}", bank, cpu.pc, name),
+        None => format!("{:02X}:{:04X}", bank, cpu.pc),
+    }
+}
+
+// A code location as typed into a debugger: an address, optionally with a bank in front
+// like "3:$4000", or a label from symbols. Labels in a switchable ROM bank only stand for
+// that address while their bank is mapped
+pub fn parse_location(text: &str, symbols: &Symbols) -> Result<(Option<usize>, u16), String> {
+    if let Some(symbol) = symbols.find(text) {
+        let bank = Some(symbol.bank).filter(|_| (0x4000..0x8000).contains(&symbol.address));
+        return Ok((bank, symbol.address));
+    }
+    match text.split_once(':') {
+        Some((bank, address)) => Ok((Some(parse_number(bank)? as usize), parse_number(address)?)),
+        None => Ok((None, parse_number(text)?)),
+    }
+}
+
+// An address typed into a debugger, as a number or a label from symbols
+pub fn parse_address(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    match symbols.find(text) {
+        Some(symbol) => Ok(symbol.address),
+        None => parse_number(text),
+    }
+}
@@ -0,0 +1,89 @@
+// Watchpoints are checked by the CPU itself on every memory access it makes, so they catch
+// stack pushes and interrupt dispatch as well as loads and stores. Opcode fetches and DMA
+// don't count
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // Either
+}
+impl WatchKind {
+    pub fn matches(&self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: u16,
+    pub value: u8, // Read, or about to be written
+    pub access: Access,
+}
+
+// An address range, both ends included
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "read/write",
+        };
+        if self.start == self.end {
+            write!(f, "#{} ${:04X} {}", self.id, self.start, kind)
+        } else {
+            write!(f, "#{} ${:04X}-${:04X} {}", self.id, self.start, self.end, kind)
+        }
+    }
+}
+
+// The watchpoints the CPU is checking and the first one hit since the last take_hit
+#[derive(Clone, Debug, Default)]
+pub struct MemoryWatch {
+    watchpoints: Vec<Watchpoint>,
+    hit: Option<(usize, MemoryAccess)>,
+}
+impl MemoryWatch {
+    pub fn new() -> MemoryWatch {
+        return MemoryWatch::default()
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: &[Watchpoint]) {
+        self.watchpoints = watchpoints.to_vec();
+        self.hit = None;
+    }
+
+    pub fn check(&mut self, address: u16, value: u8, access: Access) {
+        if self.watchpoints.is_empty() || self.hit.is_some() {
+            return;
+        }
+        let watchpoint = self.watchpoints.iter().find(|watchpoint| {
+            (watchpoint.start..=watchpoint.end).contains(&address) && watchpoint.kind.matches(access)
+        });
+        if let Some(watchpoint) = watchpoint {
+            self.hit = Some((watchpoint.id, MemoryAccess { address, value, access }));
+        }
+    }
+
+    // The id of the watchpoint hit and the access that hit it
+    pub fn take_hit(&mut self) -> Option<(usize, MemoryAccess)> {
+        self.hit.take()
+    }
+}
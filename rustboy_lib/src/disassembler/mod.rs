@@ -0,0 +1,171 @@
+pub mod recursive;
+
+use crate::cpu::instructions::{Indirect, Instructions, JumpTest, LoadType};
+use crate::symbols::Symbols;
+
+// Turns machine code back into RGBDS assembly. Bytes that don't decode, the unused opcodes
+// and instructions cut off by the end of the input, come out as db data
+
+const DATA_PER_LINE: usize = 8;
+const ROM_BANK_SIZE: u16 = 0x4000;
+
+// One instruction, or a run of data bytes when instruction is None
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassemblyLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Option<Instructions>,
+}
+impl DisassemblyLine {
+    pub fn is_data(&self) -> bool {
+        self.instruction.is_none()
+    }
+
+    // Like the Display form, but with JR targets as absolute addresses and the addresses
+    // operands refer to as labels from symbols
+    pub fn labelled(&self, bank: usize, symbols: &Symbols) -> String {
+        let Some(instruction) = self.instruction else {
+            return self.to_string();
+        };
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("${:04X}  {:<9} {}", self.address, bytes.join(" "), format_instruction(&instruction, self.address, bank, symbols))
+    }
+}
+impl std::fmt::Display for DisassemblyLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        write!(f, "${:04X}  {:<9} ", self.address, bytes.join(" "))?;
+        match self.instruction {
+            Some(instruction) => write!(f, "{}", instruction)?,
+            None => write!(f, "{}", format_data(&self.bytes))?,
+        }
+        // Spell out where relative jumps go
+        if let Some(Instructions::JR(_, offset)) = self.instruction {
+            write!(f, " ; ${:04X}", jr_target(self.address, offset))?;
+        }
+        Ok(())
+    }
+}
+
+// The instruction at the start of bytes and its length, None if it runs off the end
+pub fn decode(bytes: &[u8]) -> Option<(Instructions, usize)> {
+    let (&opcode, mut operands) = bytes.split_first()?;
+    let mut missing = false;
+    let instruction = Instructions::decode(opcode, || match operands.split_first() {
+        Some((&byte, rest)) => {
+            operands = rest;
+            byte
+        }
+        None => {
+            missing = true;
+            0
+        }
+    });
+    if missing {
+        return None;
+    }
+    return Some((instruction, bytes.len() - operands.len()))
+}
+
+// A linear sweep over bytes, as if they were mapped at base_address
+pub fn disassemble(bytes: &[u8], base_address: u16) -> Vec<DisassemblyLine> {
+    let mut lines: Vec<DisassemblyLine> = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = base_address.wrapping_add(offset as u16);
+        match decode(&bytes[offset..]) {
+            Some((instruction, length)) if !matches!(instruction, Instructions::INVALID(_)) => {
+                lines.push(DisassemblyLine { address, bytes: bytes[offset..offset + length].to_vec(), instruction: Some(instruction) });
+                offset += length;
+            }
+            _ => {
+                match lines.last_mut() {
+                    Some(line) if line.is_data() && line.bytes.len() < DATA_PER_LINE => line.bytes.push(bytes[offset]),
+                    _ => lines.push(DisassemblyLine { address, bytes: vec![bytes[offset]], instruction: None }),
+                }
+                offset += 1;
+            }
+        }
+    }
+    return lines
+}
+
+pub fn format_data(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+    format!("db {}", bytes.join(", "))
+}
+
+// An instruction at address in ROM bank, with JR targets as absolute addresses and the
+// operands that are addresses swapped for their labels where there are any. Immediate
+// words stay numbers, since they are as often counts as pointers
+pub fn format_instruction(instruction: &Instructions, address: u16, bank: usize, symbols: &Symbols) -> String {
+    let (mut text, target) = match *instruction {
+        Instructions::JR(JumpTest::Always, offset) => {
+            let target = jr_target(address, offset);
+            (format!("jr ${:04X}", target), Some(target))
+        }
+        Instructions::JR(test, offset) => {
+            let target = jr_target(address, offset);
+            (format!("jr {}, ${:04X}", test, target), Some(target))
+        }
+        Instructions::JP(_, target) | Instructions::CALL(_, target) => (instruction.to_string(), Some(target)),
+        Instructions::LD(LoadType::AFromIndirect(indirect) | LoadType::IndirectFromA(indirect)) => {
+            let target = match indirect {
+                Indirect::Word(target) => Some(target),
+                Indirect::High(offset) => Some(0xFF00 | offset as u16),
+                _ => None,
+            };
+            (instruction.to_string(), target)
+        }
+        Instructions::LD(LoadType::IndirectFromSP(target)) => (instruction.to_string(), Some(target)),
+        _ => (instruction.to_string(), None),
+    };
+    if let Some(target) = target && let Some(label) = symbols.label_at(operand_bank(target, address, bank), target) {
+        text = text.replacen(&format!("${:04X}", target), label, 1);
+    }
+    return text
+}
+
+// Code in ROM bank 0 can't tell which bank a 0x4000-0x7FFF address lands in, and RAM banks
+// aren't tracked at all
+fn operand_bank(target: u16, address: u16, bank: usize) -> Option<usize> {
+    match target {
+        0x0000..ROM_BANK_SIZE => Some(0),
+        ROM_BANK_SIZE..0x8000 if address >= ROM_BANK_SIZE => Some(bank),
+        _ => None,
+    }
+}
+
+fn jr_target(address: u16, offset: i8) -> u16 {
+    address.wrapping_add(2).wrapping_add(offset as i16 as u16)
+}
+
+// Lines from ROM bank as assembly text: a label line wherever a symbol sits and each
+// instruction followed by its address and bytes as a comment. Runs of data are split at
+// labels so that every label lands on a line of its own
+pub fn listing(lines: &[DisassemblyLine], bank: usize, symbols: &Symbols) -> String {
+    let mut text = String::new();
+    let label_at = |address: u16| symbols.label_at(Some(if address < ROM_BANK_SIZE { 0 } else { bank }), address);
+    for line in lines {
+        let pieces = match line.instruction {
+            Some(instruction) => vec![(line.address, &line.bytes[..], format_instruction(&instruction, line.address, bank, symbols))],
+            None => {
+                let mut starts: Vec<usize> = (1..line.bytes.len()).filter(|&offset| label_at(line.address.wrapping_add(offset as u16)).is_some()).collect();
+                starts.insert(0, 0);
+                starts.push(line.bytes.len());
+                starts.windows(2).map(|range| {
+                    let bytes = &line.bytes[range[0]..range[1]];
+                    (line.address.wrapping_add(range[0] as u16), bytes, format_data(bytes))
+                }).collect()
+            }
+        };
+        for (address, bytes, code) in pieces {
+            if let Some(label) = label_at(address) {
+                text.push_str(&format!("{}:\n", label));
+            }
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            text.push_str(&format!("    {:<28} ; ${:04X}: {}\n", code, address, bytes.join(" ")));
+        }
+    }
+    return text
+}
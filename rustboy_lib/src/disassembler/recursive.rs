@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+
+use super::{decode, format_data, format_instruction};
+use crate::cpu::instructions::{Indirect, Instructions, JumpTest, LoadType, RegisterTarget, StackTarget};
+use crate::symbols::Symbols;
+
+// Finds the code in a ROM by following execution from the entry point and the RST and
+// interrupt vectors through every JP, JR, CALL and RST, so that whatever is never reached
+// is left as data. Jumps into 0x4000-0x7FFF are followed into the bank mapped there, which
+// is known inside a switchable bank and after a constant "ld a, n; ld [$2000], a" style
+// bank switch. Everything else, like jump tables and JP HL, isn't followed
+
+const BANK_SIZE: usize = 0x4000;
+// Runs of the same byte at least this long come out as ds instead of db
+const FILL_RUN: usize = 16;
+const DATA_PER_LINE: usize = 8;
+
+const VECTORS: [(u16, &str); 14] = [
+    (0x0000, "RST_00"),
+    (0x0008, "RST_08"),
+    (0x0010, "RST_10"),
+    (0x0018, "RST_18"),
+    (0x0020, "RST_20"),
+    (0x0028, "RST_28"),
+    (0x0030, "RST_30"),
+    (0x0038, "RST_38"),
+    (0x0040, "VBlankInterrupt"),
+    (0x0048, "LCDInterrupt"),
+    (0x0050, "TimerInterrupt"),
+    (0x0058, "SerialInterrupt"),
+    (0x0060, "JoypadInterrupt"),
+    (0x0100, "Entry"),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteKind {
+    Data,
+    Opcode, // The first byte of an instruction
+    Operand,
+}
+
+// How code got to a labelled address, which decides the generated name
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reference {
+    Vector(&'static str),
+    Call,
+    Jump,
+}
+
+// What every byte of a ROM turned out to be, and the places code goes to
+#[derive(Clone, Debug)]
+pub struct CodeMap {
+    kinds: Vec<ByteKind>,
+    references: BTreeMap<usize, Reference>, // By offset in the ROM
+}
+impl CodeMap {
+    pub fn analyze(rom: &[u8]) -> CodeMap {
+        let mut map = CodeMap { kinds: vec![ByteKind::Data; rom.len()], references: BTreeMap::new() };
+        let mut queue = Vec::new();
+        for &(address, name) in VECTORS.iter().rev() {
+            if (address as usize) < rom.len() {
+                map.references.insert(address as usize, Reference::Vector(name));
+                // Bank 1 is mapped at power on, interrupts can come with any bank
+                queue.push((address as usize, if address == 0x0100 { Some(1) } else { None }));
+            }
+        }
+        while let Some((offset, mapped)) = queue.pop() {
+            map.trace(rom, offset, mapped, &mut queue);
+        }
+        return map
+    }
+
+    pub fn kind(&self, offset: usize) -> ByteKind {
+        self.kinds[offset]
+    }
+
+    pub fn references(&self) -> &BTreeMap<usize, Reference> {
+        &self.references
+    }
+
+    // Follows one path of execution until it ends, runs into code already seen or runs out
+    // of the bank it is in, queueing the places it branches to along the way
+    fn trace(&mut self, rom: &[u8], start: usize, mapped: Option<usize>, queue: &mut Vec<(usize, Option<usize>)>) {
+        let bank = start / BANK_SIZE;
+        let end = ((bank + 1) * BANK_SIZE).min(rom.len());
+        // Code in a switchable bank runs with that bank mapped
+        let mut mapped = if bank > 0 { Some(bank) } else { mapped };
+        let mut a = None;
+        let mut offset = start;
+        while offset < end && self.kinds[offset] == ByteKind::Data {
+            let Some((instruction, length)) = decode(&rom[offset..end]) else {
+                break;
+            };
+            if matches!(instruction, Instructions::INVALID(_)) || self.kinds[offset..offset + length].iter().any(|&kind| kind != ByteKind::Data) {
+                break;
+            }
+            self.kinds[offset] = ByteKind::Opcode;
+            self.kinds[offset + 1..offset + length].fill(ByteKind::Operand);
+            let address = cpu_address(offset);
+            let target = match instruction {
+                Instructions::JP(_, target) => Some((target, Reference::Jump)),
+                Instructions::JR(_, jump) => Some((address.wrapping_add(2).wrapping_add(jump as i16 as u16), Reference::Jump)),
+                Instructions::CALL(_, target) => Some((target, Reference::Call)),
+                Instructions::RST(vector) => Some((vector as u16, Reference::Call)),
+                _ => None,
+            };
+            if let Some((target, reference)) = target && let Some(target_offset) = rom_offset(rom, target, mapped) {
+                self.references.entry(target_offset).or_insert(reference);
+                let target_mapped = if target_offset < BANK_SIZE { mapped } else { Some(target_offset / BANK_SIZE) };
+                queue.push((target_offset, target_mapped));
+            }
+            match instruction {
+                Instructions::LD(LoadType::Byte(RegisterTarget::A, RegisterTarget::D8(value))) => a = Some(value),
+                Instructions::XOR(RegisterTarget::A) => a = Some(0),
+                // Writes to 0x2000-0x3FFF pick the ROM bank on every common mapper. Bank 0
+                // there means bank 1 on all but MBC5
+                Instructions::LD(LoadType::IndirectFromA(Indirect::Word(0x2000..=0x3FFF))) => {
+                    mapped = a.map(|value| (value as usize).max(1));
+                }
+                // Whatever is called may change A
+                Instructions::CALL(..) | Instructions::RST(_) => a = None,
+                _ if writes_a(&instruction) => a = None,
+                _ => {}
+            }
+            if matches!(
+                instruction,
+                Instructions::JP(JumpTest::Always, _) | Instructions::JR(JumpTest::Always, _) | Instructions::JPHL | Instructions::RET(JumpTest::Always) | Instructions::RETI
+            ) {
+                break;
+            }
+            offset += length;
+        }
+    }
+
+    // The whole ROM as RGBDS assembly, one SECTION per bank, which assembles back to the
+    // same bytes. Names from symbols win over the generated ones, and symbols outside the
+    // ROM are defined with EQU
+    pub fn to_asm(&self, rom: &[u8], symbols: &Symbols) -> String {
+        let mut names = Symbols::new();
+        let mut text = String::new();
+        for symbol in symbols.iter() {
+            if symbol.address >= 0x8000 && !symbol.name.contains('.') {
+                text.push_str(&format!("DEF {} EQU ${:04X}\n", symbol.name, symbol.address));
+                names.insert(symbol.bank, symbol.address, &symbol.name);
+            }
+        }
+        // Only places that start a line can have a label
+        for offset in (0..rom.len()).filter(|&offset| self.kinds[offset] != ByteKind::Operand) {
+            let (bank, address) = (offset / BANK_SIZE, cpu_address(offset));
+            if let Some(name) = symbols.label_at(Some(bank), address) {
+                names.insert(bank, address, name);
+            } else if let Some(reference) = self.references.get(&offset) {
+                let name = match reference {
+                    Reference::Vector(name) => String::from(*name),
+                    Reference::Call => format!("Call_{:02X}_{:04X}", bank, address),
+                    Reference::Jump => format!("Jump_{:02X}_{:04X}", bank, address),
+                };
+                names.insert(bank, address, &name);
+            }
+        }
+        for bank in 0..rom.len().div_ceil(BANK_SIZE) {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            if bank == 0 {
+                text.push_str("SECTION \"ROM Bank $00\", ROM0[$0000]\n\n");
+            } else {
+                text.push_str(&format!("SECTION \"ROM Bank ${:02X}\", ROMX[$4000], BANK[${:02X}]\n\n", bank, bank));
+            }
+            let end = ((bank + 1) * BANK_SIZE).min(rom.len());
+            let mut offset = bank * BANK_SIZE;
+            while offset < end {
+                let address = cpu_address(offset);
+                if let Some(name) = names.label_at(Some(bank), address) {
+                    text.push_str(&format!("{}:\n", name));
+                }
+                if self.kinds[offset] == ByteKind::Opcode {
+                    let (instruction, length) = decode(&rom[offset..end]).unwrap();
+                    // rgbasm always follows stop with a 0x00 byte, which may not be there
+                    let code = match instruction {
+                        Instructions::STOP => format_data(&rom[offset..offset + 1]),
+                        _ => format_instruction(&instruction, address, bank, &names),
+                    };
+                    text.push_str(&format!("    {}\n", code));
+                    offset += length;
+                    continue;
+                }
+                // Data up to the next instruction or label
+                let mut run_end = offset + 1;
+                while run_end < end && self.kinds[run_end] == ByteKind::Data && names.label_at(Some(bank), cpu_address(run_end)).is_none() {
+                    run_end += 1;
+                }
+                text.push_str(&format_data_run(&rom[offset..run_end]));
+                offset = run_end;
+            }
+        }
+        return text
+    }
+}
+
+fn format_data_run(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let same = bytes[index..].iter().take_while(|&&byte| byte == bytes[index]).count();
+        if same >= FILL_RUN {
+            text.push_str(&format!("    ds {}, ${:02X}\n", same, bytes[index]));
+            index += same;
+            continue;
+        }
+        // Up to a line's worth, stopping short of a fill run
+        let mut line_end = index + 1;
+        while line_end < bytes.len() && line_end - index < DATA_PER_LINE
+            && bytes[line_end..].iter().take_while(|&&byte| byte == bytes[line_end]).count() < FILL_RUN
+        {
+            line_end += 1;
+        }
+        text.push_str(&format!("    {}\n", format_data(&bytes[index..line_end])));
+        index = line_end;
+    }
+    return text
+}
+
+// Where an offset in the ROM is mapped when its bank is
+fn cpu_address(offset: usize) -> u16 {
+    if offset < BANK_SIZE { offset as u16 } else { (BANK_SIZE + offset % BANK_SIZE) as u16 }
+}
+
+// The offset in the ROM that address reaches with bank mapped at 0x4000. With two banks
+// there's only the one it can be
+fn rom_offset(rom: &[u8], address: u16, mapped: Option<usize>) -> Option<usize> {
+    let address = address as usize;
+    let offset = match address {
+        0..BANK_SIZE => address,
+        BANK_SIZE..0x8000 => {
+            let bank = mapped.or(if rom.len() <= 2 * BANK_SIZE { Some(1) } else { None })?;
+            bank * BANK_SIZE + address - BANK_SIZE
+        }
+        _ => return None,
+    };
+    Some(offset).filter(|&offset| offset < rom.len())
+}
+
+fn writes_a(instruction: &Instructions) -> bool {
+    match instruction {
+        Instructions::ADD(_) | Instructions::ADC(_) | Instructions::SUB(_) | Instructions::SBC(_)
+        | Instructions::AND(_) | Instructions::OR(_) | Instructions::XOR(_) => true,
+        Instructions::RRA | Instructions::RLA | Instructions::RRCA | Instructions::RRLA | Instructions::CPL | Instructions::DAA => true,
+        Instructions::LD(LoadType::Byte(RegisterTarget::A, _) | LoadType::AFromIndirect(_)) => true,
+        Instructions::POP(StackTarget::AF) => true,
+        Instructions::INC(target) | Instructions::DEC(target) | Instructions::SRL(target) | Instructions::RR(target)
+        | Instructions::RL(target) | Instructions::RRC(target) | Instructions::RLC(target) | Instructions::SRA(target)
+        | Instructions::SLA(target) | Instructions::SWAP(target) | Instructions::RESET(target, _)
+        | Instructions::SET(target, _) => *target == RegisterTarget::A,
+        _ => false,
+    }
+}
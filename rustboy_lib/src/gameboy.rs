@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+
+use crate::audio::apu::DEFAULT_SAMPLE_RATE;
+use crate::audio::AudioFrame;
+use crate::cartridge::header::CartridgeHeader;
+use crate::cartridge::CartridgeError;
+use crate::cpu::CPU;
+use crate::joypad::Button;
+use crate::memory::boot::BootRom;
+use crate::model::Model;
+use crate::movie::{frame_checksum, InputEvent, Movie, MovieStart, ReplayReport};
+use crate::ppu::compat::{CompatibilityPalettes, PaletteOverride};
+use crate::ppu::lcd::DOTS_PER_FRAME;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::rewind::{RewindBuffer, RewindOptions};
+use crate::savestate::{self, crc32, SaveStateError};
+use crate::sgb::{SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
+
+// How a GameBoy is set up. The defaults are a DMG without a boot ROM, producing audio at
+// 48 kHz and with rewinding off, since snapshots cost time every few frames. Frontends that
+// offer rewinding turn it on with RewindOptions::new()
+#[derive(Clone, Debug)]
+pub struct GameBoyOptions {
+    pub model: Model,
+    pub boot_rom: Option<BootRom>,
+    pub sample_rate: u32, // 0 turns audio output off
+    pub rewind: RewindOptions,
+    pub palette_override: Option<PaletteOverride>, // The buttons held while a CGB boots a DMG game
+}
+impl GameBoyOptions {
+    pub fn new() -> GameBoyOptions {
+        return GameBoyOptions {
+            model: Model::Dmg,
+            boot_rom: None,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            rewind: RewindOptions::disabled(),
+            palette_override: None,
+        }
+    }
+}
+
+// A movie being recorded, and the frame_count it started at
+#[derive(Debug)]
+struct Recording {
+    movie: Movie,
+    start_frame: u64,
+}
+
+// The whole console: CPU, bus, cartridge, PPU, APU and joypad behind one object, for
+// frontends and tools that just want to run a ROM a frame at a time
+#[derive(Debug)]
+pub struct GameBoy {
+    pub cpu: CPU,
+    options: GameBoyOptions,
+    frame: Vec<u16>,
+    frame_count: u64, // Frames finished since power on, or since the state that was loaded
+    frame_deadline: u64, // When the frame ends anyway, since there's no VBlank with the LCD off
+    rewind: RewindBuffer,
+    recording: Option<Recording>,
+    playback: VecDeque<InputEvent>, // Joypad changes still to come from the movie playing
+}
+impl GameBoy {
+    pub fn new(rom: Vec<u8>, options: GameBoyOptions) -> Result<GameBoy, CartridgeError> {
+        let cpu = GameBoy::power_on(rom, &options)?;
+        let rewind = RewindBuffer::new(options.rewind);
+        let mut gameboy = GameBoy {
+            cpu,
+            options,
+            frame: Vec::new(),
+            frame_count: 0,
+            frame_deadline: 0,
+            rewind,
+            recording: None,
+            playback: VecDeque::new(),
+        };
+        gameboy.update_frame();
+        gameboy.start_frame();
+        return Ok(gameboy)
+    }
+
+    fn power_on(rom: Vec<u8>, options: &GameBoyOptions) -> Result<CPU, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let mut cpu = CPU::power_on(rom, options.model, options.boot_rom.clone())?;
+        // A boot ROM reads the buttons itself, without one they're picked up front
+        if let Some(palette_override) = options.palette_override
+            && options.boot_rom.is_none()
+            && cpu.model.is_cgb()
+            && !cpu.cgb_mode
+        {
+            CompatibilityPalettes::for_cartridge(&header, Some(palette_override)).load_into(&mut cpu.bus.palettes);
+        }
+        let bus = &mut cpu.bus;
+        bus.apu.set_sample_rate(options.sample_rate, &mut bus.scheduler, false);
+        return Ok(cpu)
+    }
+
+    pub fn options(&self) -> &GameBoyOptions {
+        &self.options
+    }
+
+    // Runs a single instruction (or interrupt dispatch) and returns the M-cycles it took
+    pub fn step(&mut self) -> u32 {
+        self.apply_playback();
+        let cycles = self.cpu.step();
+        if self.cpu.bus.ppu.take_frame_ready() || self.cpu.cycles >= self.frame_deadline {
+            self.end_frame();
+        }
+        return cycles
+    }
+
+    // Runs until the PPU enters VBlank and returns the finished frame. With the LCD off
+    // there is no VBlank, so the frame ends after a frame's worth of cycles with the blank
+    // screen. Frames end at the same points when running a step at a time
+    pub fn run_frame(&mut self) -> &[u16] {
+        let frame = self.frame_count;
+        while self.frame_count == frame {
+            self.step();
+        }
+        return &self.frame
+    }
+
+    // Frames finished so far. Rewinding and loading a state move this back
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // The last finished frame as RGB555, framebuffer_size() pixels
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.frame
+    }
+
+    // Width and height of the framebuffer, which includes the border on an SGB
+    pub fn framebuffer_size(&self) -> (usize, usize) {
+        if self.cpu.bus.sgb.is_some() {
+            (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT)
+        } else {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        }
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.set_button(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set_button(button, false);
+    }
+
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        let joypad = &mut self.cpu.bus.joypad;
+        if (joypad.pressed() & button.bit() != 0) == pressed {
+            return;
+        }
+        if pressed {
+            joypad.press(button);
+        } else {
+            joypad.release(button);
+        }
+        if let Some(recording) = &mut self.recording {
+            let frame = self.frame_count - recording.start_frame;
+            recording.movie.events.push(InputEvent { frame, cycle: self.cpu.cycles, button, pressed });
+        }
+    }
+
+    // Takes the audio produced since the last call, at the sample rate from the options
+    pub fn audio_samples(&mut self) -> Vec<AudioFrame> {
+        self.cpu.bus.apu.take_samples()
+    }
+
+    // Power cycles the console with the same cartridge. Cartridge RAM and the RTC survive,
+    // like they would on a battery
+    pub fn reset(&mut self) {
+        let cartridge = self.cpu.bus.cartridge.clone();
+        let mut cpu = GameBoy::power_on(cartridge.rom().to_vec(), &self.options)
+            .expect("the cartridge was accepted when the GameBoy was created");
+        cpu.bus.cartridge.load_ram(cartridge.ram());
+        cpu.bus.cartridge.rtc = cartridge.rtc;
+        // A trace or bus log carries on through the reset
+        cpu.trace = self.cpu.trace.take();
+        cpu.bus_log = self.cpu.bus_log.take();
+        self.cpu = cpu;
+        self.frame_count = 0;
+        self.rewind.clear();
+        self.recording = None;
+        self.playback.clear();
+        self.update_frame();
+        self.start_frame();
+    }
+
+    // Snapshots the whole machine, see savestate for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save(&self.cpu)
+    }
+
+    // Restores a snapshot from save_state. States for another ROM or model are rejected and
+    // leave the running game alone. The rewind history belongs to the game that was
+    // running, so it goes
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        self.restore(data)?;
+        self.frame_count = 0;
+        self.rewind.clear();
+        self.recording = None;
+        self.playback.clear();
+        return Ok(())
+    }
+
+    // Goes back about n frames, to the nearest rewind snapshot at or before then, and returns
+    // how many frames it actually went back. That is fewer than n once the history runs
+    // out, and 0 with rewinding turned off. A movie being recorded loses what was rewound,
+    // or is dropped altogether when going back past its start, and playback stops
+    pub fn rewind_frames(&mut self, n: u64) -> u64 {
+        let target = self.frame_count.saturating_sub(n);
+        let Some((frame, state)) = self.rewind.rewind_to(target) else {
+            return 0
+        };
+        let state = state.to_vec();
+        self.restore(&state).expect("rewind snapshots are taken from this machine");
+        let rewound = self.frame_count - frame;
+        self.frame_count = frame;
+        self.playback.clear();
+        if let Some(recording) = &mut self.recording {
+            if frame < recording.start_frame {
+                self.recording = None;
+            } else {
+                let cycles = self.cpu.cycles;
+                recording.movie.events.retain(|event| event.cycle < cycles);
+                recording.movie.checksums.truncate((frame - recording.start_frame) as usize);
+            }
+        }
+        return rewound
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        savestate::load(&mut self.cpu, data)?;
+        let bus = &mut self.cpu.bus;
+        if bus.apu.sample_rate() != self.options.sample_rate {
+            let double_speed = bus.double_speed();
+            bus.apu.set_sample_rate(self.options.sample_rate, &mut bus.scheduler, double_speed);
+        }
+        self.update_frame();
+        self.start_frame();
+        return Ok(())
+    }
+
+    // Starts recording a movie from the current state. The frame starts over, like it will
+    // when the state is loaded to play the movie back
+    pub fn start_recording(&mut self) {
+        self.start_frame();
+        let start = MovieStart::State(self.save_state());
+        self.recording = Some(Recording { movie: self.new_movie(start), start_frame: self.frame_count });
+    }
+
+    // Resets the console and starts recording a movie from power on
+    pub fn start_recording_from_power_on(&mut self) {
+        self.reset();
+        let cartridge = &self.cpu.bus.cartridge;
+        let start = MovieStart::PowerOn { ram: cartridge.ram().to_vec(), rtc: cartridge.rtc.clone() };
+        self.recording = Some(Recording { movie: self.new_movie(start), start_frame: 0 });
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Finishes the recording, None if there wasn't one
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recording.take().map(|recording| recording.movie)
+    }
+
+    fn new_movie(&self, start: MovieStart) -> Movie {
+        return Movie {
+            rom_crc32: crc32(self.cpu.bus.rom()),
+            model: self.cpu.model,
+            start,
+            events: Vec::new(),
+            checksums: Vec::new(),
+        }
+    }
+
+    // Puts the console in the movie's starting state and queues up its input, which is then
+    // applied as the game runs
+    pub fn play_movie(&mut self, movie: &Movie) -> Result<(), SaveStateError> {
+        let found = crc32(self.cpu.bus.rom());
+        if movie.rom_crc32 != found {
+            return Err(SaveStateError::RomMismatch { expected: movie.rom_crc32, found });
+        }
+        if movie.model != self.cpu.model {
+            return Err(SaveStateError::ModelMismatch);
+        }
+        match &movie.start {
+            MovieStart::PowerOn { ram, rtc } => {
+                self.reset();
+                self.cpu.bus.cartridge.load_ram(ram);
+                self.cpu.bus.cartridge.rtc = rtc.clone();
+            }
+            MovieStart::State(state) => self.load_state(state)?,
+        }
+        self.playback = movie.events.iter().copied().collect();
+        return Ok(())
+    }
+
+    // Plays the whole movie, checking every frame against the recording. Stops at the first
+    // frame that comes out differently
+    pub fn replay_movie(&mut self, movie: &Movie) -> Result<ReplayReport, SaveStateError> {
+        self.play_movie(movie)?;
+        for (index, &checksum) in movie.checksums.iter().enumerate() {
+            let frame = index as u64 + 1;
+            if frame_checksum(self.run_frame()) != checksum {
+                return Ok(ReplayReport { frames: frame, first_divergence: Some(frame) });
+            }
+        }
+        return Ok(ReplayReport { frames: movie.frames(), first_divergence: None })
+    }
+
+    fn apply_playback(&mut self) {
+        while let Some(event) = self.playback.front() && event.cycle <= self.cpu.cycles {
+            let joypad = &mut self.cpu.bus.joypad;
+            if event.pressed {
+                joypad.press(event.button);
+            } else {
+                joypad.release(event.button);
+            }
+            self.playback.pop_front();
+        }
+    }
+
+    fn start_frame(&mut self) {
+        let speed = if self.cpu.bus.double_speed() { 2 } else { 1 };
+        self.frame_deadline = self.cpu.cycles + DOTS_PER_FRAME / 4 * speed;
+    }
+
+    fn end_frame(&mut self) {
+        self.start_frame();
+        self.update_frame();
+        self.frame_count += 1;
+        if let Some(recording) = &mut self.recording {
+            recording.movie.checksums.push(frame_checksum(&self.frame));
+        }
+        if self.rewind.is_due(self.frame_count) {
+            self.rewind.push(self.frame_count, savestate::save(&self.cpu));
+        }
+    }
+
+    fn update_frame(&mut self) {
+        let bus = &mut self.cpu.bus;
+        self.frame = match &mut bus.sgb {
+            Some(sgb) => sgb.render(bus.ppu.screen()),
+            None => bus.ppu.frame().to_vec(),
+        };
+    }
+}
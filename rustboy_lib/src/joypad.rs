@@ -0,0 +1,145 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const JOYPAD_ADDRESS: u16 = 0xFF00; // P1
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    // One bit per button, d-pad in the low nibble and the buttons in the high one
+    pub fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Button::Right => "right",
+            Button::Left => "left",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::A => "a",
+            Button::B => "b",
+            Button::Select => "select",
+            Button::Start => "start",
+        }
+    }
+}
+impl std::str::FromStr for Button {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Button, String> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown button \"{}\"", name))
+    }
+}
+
+// P1. The game pulls bit 4 low to read the d-pad and bit 5 low to read the buttons, and
+// pressed buttons read back as 0 in the low nibble
+#[derive(Debug)]
+pub struct Joypad {
+    pressed: u8, // Button::bit() of every button held down
+    select: u8,  // Bits 4 and 5 as last written
+    interrupt_requested: bool,
+}
+impl Joypad {
+    pub fn new() -> Joypad {
+        return Joypad {
+            pressed: 0,
+            select: 0x30,
+            interrupt_requested: false,
+        }
+    }
+
+    pub fn select(&self) -> u8 {
+        self.select
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let before = self.lines();
+        self.select = value & 0x30;
+        self.check_interrupt(before);
+    }
+
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    // Replaces the whole button state at once, bits as in Button::bit()
+    pub fn set_pressed(&mut self, pressed: u8) {
+        let before = self.lines();
+        self.pressed = pressed;
+        self.check_interrupt(before);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.set_pressed(self.pressed | button.bit());
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set_pressed(self.pressed & !button.bit());
+    }
+
+    // Whether the joypad interrupt (IF bit 4) has been raised since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::replace(&mut self.interrupt_requested, false)
+    }
+
+    // The low nibble of P1, 0 for every pressed button in a selected row
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.select & 0x10 == 0 {
+            lines &= !(self.pressed & 0x0F);
+        }
+        if self.select & 0x20 == 0 {
+            lines &= !(self.pressed >> 4);
+        }
+        lines
+    }
+
+    // The interrupt fires when any of the lines goes from high to low
+    fn check_interrupt(&mut self, before: u8) {
+        if before & !self.lines() != 0 {
+            self.interrupt_requested = true;
+        }
+    }
+}
+
+impl SaveState for Joypad {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.pressed);
+        writer.write_u8(self.select);
+        writer.write_bool(self.interrupt_requested);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.pressed = reader.read_u8()?;
+        self.select = reader.read_u8()? & 0x30;
+        self.interrupt_requested = reader.read_bool()?;
+        return Ok(())
+    }
+}
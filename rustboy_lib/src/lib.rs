@@ -1 +1,20 @@
+pub mod assembler;
 pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod gameboy;
+pub mod audio;
+pub mod cartridge;
+pub mod joypad;
+pub mod memory;
+pub mod model;
+pub mod movie;
+pub mod ppu;
+pub mod rewind;
+pub mod savestate;
+pub mod serial;
+pub mod scheduler;
+pub mod sgb;
+pub mod symbols;
+pub mod timer;
+pub mod trace;
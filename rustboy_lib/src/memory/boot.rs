@@ -0,0 +1,50 @@
+pub const BOOT_ADDRESS: u16 = 0xFF50; // Writing a non-zero value unmaps the boot ROM
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BootRomError {
+    InvalidSize(usize),
+}
+impl std::fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BootRomError::InvalidSize(size) => write!(f, "boot ROM is {} bytes, expected {} (DMG/MGB/SGB) or {} (CGB/AGB)", size, DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE),
+        }
+    }
+}
+impl std::error::Error for BootRomError {}
+
+// A user supplied boot ROM. The DMG ones cover 0x0000-0x00FF. The CGB ones are larger and
+// also cover 0x0200-0x08FF, leaving a hole at 0x0100-0x01FF where the cartridge header is
+// read through
+#[derive(Clone, Debug)]
+pub struct BootRom {
+    data: Vec<u8>,
+}
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> Result<BootRom, BootRomError> {
+        match data.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(BootRom { data }),
+            size => Err(BootRomError::InvalidSize(size)),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    // The byte at address if the boot ROM covers it
+    pub fn read(&self, address: u16) -> Option<u8> {
+        let address = address as usize;
+        if (0x0100..0x0200).contains(&address) {
+            return None;
+        }
+        self.data.get(address).copied()
+    }
+}
@@ -0,0 +1,596 @@
+use super::boot::{BootRom, BOOT_ADDRESS};
+use super::hdma::{Hdma, HdmaMode, HDMA1_ADDRESS, HDMA5_ADDRESS};
+use super::oam_dma::{OamDma, DMA_ADDRESS};
+use super::vram::VideoRam;
+use super::wram::WorkRam;
+use super::{ECHO_RAM_END, SVBK_ADDRESS, VBK_ADDRESS, VRAM_END, VRAM_START, WRAM_START};
+use crate::audio::apu::{Apu, DEFAULT_SAMPLE_RATE, NR10_ADDRESS, NR14_ADDRESS, WAVE_RAM_END};
+use crate::cartridge::mbc::Cartridge;
+use crate::cpu::interrupts::{Interrupt, INTERRUPT_FLAG_ADDRESS};
+use crate::cpu::speed::{SpeedSwitch, KEY1_ADDRESS};
+use crate::joypad::{Joypad, JOYPAD_ADDRESS};
+use crate::model::Model;
+use crate::ppu::lcd::{Ppu, PpuMode, LCDC_ADDRESS, WX_ADDRESS};
+use crate::ppu::palette::{CgbPalettes, BCPS_ADDRESS, OCPD_ADDRESS};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::scheduler::{Event, EventKind, Scheduler};
+use crate::serial::{Serial, CYCLES_PER_BIT, SB_ADDRESS, SC_ADDRESS};
+use crate::sgb::{vram_transfer_data, Sgb};
+use crate::timer::{Timer, DIV_ADDRESS, TAC_ADDRESS};
+
+pub const ROM_END: u16 = 0x7FFF;
+pub const EXTERNAL_RAM_START: u16 = 0xA000;
+pub const EXTERNAL_RAM_END: u16 = 0xBFFF;
+pub const OAM_START: u16 = 0xFE00;
+pub const OAM_END: u16 = 0xFE9F;
+pub const IO_START: u16 = 0xFF00;
+pub const IO_END: u16 = 0xFF7F;
+pub const HRAM_START: u16 = 0xFF80;
+pub const HRAM_END: u16 = 0xFFFE;
+pub const INTERRUPT_ENABLE_ADDRESS: u16 = 0xFFFF;
+
+// The RTC counts seconds of the 4 MiHz clock
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+
+// I/O registers as the boot ROM leaves them. NR52 comes first, as the APU ignores writes
+// to the other sound registers while it is off
+const POST_BOOT_IO: [(u16, u8); 28] = [
+    (0xFF00, 0xCF), // P1
+    (0xFF0F, 0xE1), // IF
+    (0xFF26, 0xF1), // NR52
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+];
+
+// Everything the CPU can reach through its address space
+pub struct MemoryBus {
+    pub cartridge: Cartridge,
+    boot_rom: Option<BootRom>,
+    pub vram: VideoRam,
+    pub wram: WorkRam,
+    oam: [u8; 0xA0],
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    interrupt_enable: u8,
+    pub serial: Serial,
+    pub speed: SpeedSwitch, // KEY1
+    pub palettes: CgbPalettes,
+    pub hdma: Hdma,
+    pub sgb: Option<Sgb>, // Only present on an SGB running a cartridge with SGB support
+    pub timer: Timer,
+    pub oam_dma: OamDma,
+    pub scheduler: Scheduler,
+    pub joypad: Joypad,
+    pub ppu: Ppu,
+    pub apu: Apu,
+    pub cpu_halted: bool, // Kept up to date by the CPU, HBlank HDMA waits while it is halted
+    model: Model,
+    cgb_mode: bool,
+    dma_stall_cycles: u32, // M-cycles the CPU owes to a general purpose HDMA
+    flat: Option<Box<[u8]>>, // All 64 KiB as plain memory, see MemoryBus::flat
+}
+impl MemoryBus {
+    pub fn new(rom: Vec<u8>, model: Model, cgb_mode: bool) -> MemoryBus {
+        return MemoryBus::with_cartridge(Cartridge::new(rom), model, cgb_mode)
+    }
+
+    // A bus around a cartridge that is already set up, like one taken from another bus
+    pub fn with_cartridge(cartridge: Cartridge, model: Model, cgb_mode: bool) -> MemoryBus {
+        let mut bus = MemoryBus {
+            cartridge,
+            boot_rom: None,
+            vram: VideoRam::new(cgb_mode),
+            wram: WorkRam::new(cgb_mode),
+            oam: [0; 0xA0],
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            interrupt_enable: 0,
+            serial: Serial::new(),
+            speed: SpeedSwitch::new(),
+            palettes: CgbPalettes::new(),
+            hdma: Hdma::new(),
+            sgb: None,
+            timer: Timer::new(),
+            oam_dma: OamDma::new(),
+            scheduler: Scheduler::new(),
+            joypad: Joypad::new(),
+            ppu: Ppu::new(cgb_mode, model.is_cgb()),
+            apu: Apu::new(DEFAULT_SAMPLE_RATE, model.wave_ram_readable_while_playing()),
+            cpu_halted: false,
+            model,
+            cgb_mode,
+            dma_stall_cycles: 0,
+            flat: None,
+        };
+        bus.apu.start(&mut bus.scheduler, false);
+        if bus.cartridge.rtc.is_some() {
+            bus.scheduler.schedule(EventKind::RtcSecond, CYCLES_PER_SECOND);
+        }
+        return bus
+    }
+
+    // A bus that is nothing but 64 KiB of RAM, for running the CPU on its own like
+    // single-step CPU tests do. Nothing else is ticked, and IE and IF are the bytes at
+    // 0xFFFF and 0xFF0F
+    pub fn flat() -> MemoryBus {
+        let mut bus = MemoryBus::new(Vec::new(), Model::Dmg, false);
+        bus.flat = Some(vec![0; 0x10000].into_boxed_slice());
+        return bus
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        self.cartridge.rom()
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.speed.double_speed
+    }
+
+    // Called by STOP. Switches speed if KEY1 has a switch armed and returns whether it did.
+    // The PPU, APU and RTC run in real time, so their pending events are moved to stay as
+    // far away as they were. The timer counts CPU cycles and DIV starts over
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed.try_switch() {
+            return false;
+        }
+        let double_speed = self.speed.double_speed;
+        self.ppu.switch_speed(&mut self.scheduler, double_speed);
+        self.apu.switch_speed(&mut self.scheduler, double_speed);
+        self.scheduler.rescale(EventKind::RtcSecond, double_speed);
+        self.timer.write(DIV_ADDRESS, 0, &mut self.scheduler);
+        return true
+    }
+
+    // Maps a boot ROM over the start of the cartridge until the game writes to 0xFF50
+    pub fn load_boot_rom(&mut self, boot_rom: BootRom) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    // The I/O registers as the boot ROM leaves them
+    pub fn apply_post_boot_state(&mut self) {
+        let model = self.model;
+        for (address, value) in POST_BOOT_IO {
+            match address {
+                // Straight to the joypad, the SGB shouldn't see this as a packet bit
+                JOYPAD_ADDRESS => self.joypad.write(value),
+                // The DMG boot ROM leaves channel 1 playing its chime, the SGB's is silent
+                NR14_ADDRESS if model.is_sgb() => self.write_io(address, value & 0x7F),
+                _ => self.write_io(address, value),
+            }
+        }
+        let counter = match model {
+            Model::Dmg0 => 0x1800,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            // The boot ROMs of these run for a varying amount of time, so DIV isn't fixed
+            Model::Sgb | Model::Cgb | Model::Agb => 0x0000,
+        };
+        self.timer.set_counter(counter, &mut self.scheduler);
+        self.timer.write(TAC_ADDRESS, 0xF8, &mut self.scheduler);
+    }
+
+    // Advances everything clocked by the CPU by one M-cycle. The CPU calls this before
+    // each of its memory accesses, so components see reads and writes on the right cycle.
+    // Components only do work when one of their scheduled events comes due
+    pub fn tick(&mut self) {
+        if self.flat.is_some() {
+            return;
+        }
+        self.scheduler.advance(4);
+        while let Some(event) = self.scheduler.pop_due() {
+            self.handle_event(event);
+        }
+        if let Some((source, offset)) = self.oam_dma.step() {
+            self.oam[offset] = self.read_unblocked(source);
+        }
+        if self.joypad.take_interrupt() {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        let kind = event.kind;
+        match kind {
+            EventKind::TimerOverflow | EventKind::TimerReload => {
+                self.timer.handle_event(kind, &mut self.scheduler);
+                if self.timer.take_interrupt() {
+                    self.request_interrupt(Interrupt::Timer);
+                }
+            }
+            EventKind::SerialBit => {
+                self.serial.step(CYCLES_PER_BIT);
+                if self.serial.take_interrupt() {
+                    self.request_interrupt(Interrupt::Serial);
+                }
+                self.schedule_serial();
+            }
+            EventKind::PpuMode => {
+                let double_speed = self.speed.double_speed;
+                let mode = self.ppu.handle_event(event.time, &mut self.scheduler, double_speed, &self.vram, &self.oam, &self.palettes);
+                match mode {
+                    PpuMode::HBlank => self.run_hblank_dma(),
+                    PpuMode::VBlank => {
+                        if let Some(sgb) = &mut self.sgb
+                            && sgb.pending_transfer().is_some()
+                        {
+                            sgb.complete_transfer(&vram_transfer_data(&self.vram, self.ppu.lcdc()));
+                        }
+                    }
+                    _ => {}
+                }
+                if self.ppu.take_vblank_interrupt() {
+                    self.request_interrupt(Interrupt::VBlank);
+                }
+                if self.ppu.take_stat_interrupt() {
+                    self.request_interrupt(Interrupt::LcdStat);
+                }
+            }
+            EventKind::ApuFrameSequencer | EventKind::ApuSample => {
+                self.apu.handle_event(kind, &mut self.scheduler, self.speed.double_speed);
+            }
+            EventKind::RtcSecond => {
+                if let Some(rtc) = &mut self.cartridge.rtc {
+                    rtc.tick_second();
+                }
+                self.scheduler.schedule(EventKind::RtcSecond, CYCLES_PER_SECOND * if self.speed.double_speed { 2 } else { 1 });
+            }
+        }
+    }
+
+    // While a transfer is running the serial port shifts a bit every CYCLES_PER_BIT
+    fn schedule_serial(&mut self) {
+        if self.serial.is_transferring() {
+            self.scheduler.reschedule(EventKind::SerialBit, CYCLES_PER_BIT as u64);
+        } else {
+            self.scheduler.cancel(EventKind::SerialBit);
+        }
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        if let Some(flat) = &mut self.flat {
+            flat[INTERRUPT_FLAG_ADDRESS as usize] |= interrupt.bit();
+            return;
+        }
+        self.io[(INTERRUPT_FLAG_ADDRESS - IO_START) as usize] |= interrupt.bit();
+    }
+
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        if let Some(flat) = &mut self.flat {
+            flat[INTERRUPT_FLAG_ADDRESS as usize] &= !interrupt.bit();
+            return;
+        }
+        self.io[(INTERRUPT_FLAG_ADDRESS - IO_START) as usize] &= !interrupt.bit();
+    }
+
+    // Interrupts that are both requested in IF and enabled in IE
+    pub fn pending_interrupts(&self) -> u8 {
+        if let Some(flat) = &self.flat {
+            return flat[INTERRUPT_ENABLE_ADDRESS as usize] & flat[INTERRUPT_FLAG_ADDRESS as usize] & 0x1F;
+        }
+        self.interrupt_enable & self.io[(INTERRUPT_FLAG_ADDRESS - IO_START) as usize] & 0x1F
+    }
+
+    // What the CPU sees. While OAM DMA is copying, everything but HRAM reads 0xFF
+    pub fn read(&self, address: u16) -> u8 {
+        if self.oam_dma.is_active() && !(HRAM_START..=HRAM_END).contains(&address) {
+            return 0xFF;
+        }
+        self.read_unblocked(address)
+    }
+
+    // What is mapped at address even while OAM DMA blocks the CPU, for debuggers
+    pub fn peek(&self, address: u16) -> u8 {
+        self.read_unblocked(address)
+    }
+
+    // The bank mapped at address: the ROM bank below 0x8000, the VRAM bank and the WRAM
+    // bank at 0xD000-0xDFFF. Everything else has only the one
+    pub fn bank_at(&self, address: u16) -> usize {
+        match address {
+            0x0000..=ROM_END => self.cartridge.rom_bank_at(address),
+            VRAM_START..=VRAM_END => self.vram.bank(),
+            0xD000..=0xDFFF | 0xF000..=ECHO_RAM_END => self.wram.bank(),
+            _ => 0,
+        }
+    }
+
+    fn read_unblocked(&self, address: u16) -> u8 {
+        if let Some(flat) = &self.flat {
+            return flat[address as usize];
+        }
+        if let Some(value) = self.boot_rom.as_ref().and_then(|boot_rom| boot_rom.read(address)) {
+            return value;
+        }
+        match address {
+            0x0000..=ROM_END => self.cartridge.read_rom(address),
+            VRAM_START..=VRAM_END => self.vram.read(address),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.cartridge.read_ram(address),
+            WRAM_START..=ECHO_RAM_END => self.wram.read(address),
+            OAM_START..=OAM_END => self.oam[(address - OAM_START) as usize],
+            IO_START..=IO_END => self.read_io(address),
+            HRAM_START..=HRAM_END => self.hram[(address - HRAM_START) as usize],
+            INTERRUPT_ENABLE_ADDRESS => self.interrupt_enable,
+            // 0xFEA0-0xFEFF is not usable
+            _ => self.model.unusable_area_read(address),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        if let Some(flat) = &mut self.flat {
+            flat[address as usize] = value;
+            return;
+        }
+        match address {
+            // Writes to ROM go to the mapper's registers
+            0x0000..=ROM_END => self.cartridge.write_rom(address, value),
+            VRAM_START..=VRAM_END => self.vram.write(address, value),
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.cartridge.write_ram(address, value),
+            WRAM_START..=ECHO_RAM_END => self.wram.write(address, value),
+            OAM_START..=OAM_END => self.oam[(address - OAM_START) as usize] = value,
+            IO_START..=IO_END => self.write_io(address, value),
+            HRAM_START..=HRAM_END => self.hram[(address - HRAM_START) as usize] = value,
+            INTERRUPT_ENABLE_ADDRESS => self.interrupt_enable = value,
+            _ => {}
+        }
+    }
+
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            JOYPAD_ADDRESS => match self.sgb.as_ref().and_then(|sgb| sgb.joypad_id()) {
+                Some(id) => 0xC0 | self.joypad.select() | id,
+                None => self.joypad.read(),
+            },
+            SB_ADDRESS | SC_ADDRESS => self.serial.read(address),
+            BOOT_ADDRESS => 0xFF,
+            DIV_ADDRESS..=TAC_ADDRESS => self.timer.read(address, self.scheduler.now()),
+            INTERRUPT_FLAG_ADDRESS => 0xE0 | self.io[(address - IO_START) as usize],
+            NR10_ADDRESS..=WAVE_RAM_END => self.apu.read(address),
+            DMA_ADDRESS => self.oam_dma.read(),
+            LCDC_ADDRESS..=WX_ADDRESS => self.ppu.read(address),
+            KEY1_ADDRESS if self.cgb_mode => self.speed.read(),
+            VBK_ADDRESS => self.vram.read_vbk(),
+            HDMA1_ADDRESS..=HDMA5_ADDRESS if self.cgb_mode => self.hdma.read(address),
+            BCPS_ADDRESS..=OCPD_ADDRESS if self.cgb_mode => self.palettes.read(address),
+            SVBK_ADDRESS => self.wram.read_svbk(),
+            KEY1_ADDRESS | HDMA1_ADDRESS..=HDMA5_ADDRESS | BCPS_ADDRESS..=OCPD_ADDRESS => 0xFF,
+            _ => self.io[(address - IO_START) as usize],
+        }
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            JOYPAD_ADDRESS => {
+                self.joypad.write(value);
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_joypad(value);
+                }
+            }
+            SB_ADDRESS => self.serial.write(address, value),
+            SC_ADDRESS => {
+                self.serial.write(address, value);
+                self.schedule_serial();
+            }
+            BOOT_ADDRESS => {
+                if value != 0 {
+                    self.boot_rom = None;
+                }
+            }
+            DIV_ADDRESS..=TAC_ADDRESS => self.timer.write(address, value, &mut self.scheduler),
+            INTERRUPT_FLAG_ADDRESS => self.io[(address - IO_START) as usize] = value & 0x1F,
+            NR10_ADDRESS..=WAVE_RAM_END => self.apu.write(address, value, &mut self.scheduler, self.speed.double_speed),
+            DMA_ADDRESS => self.oam_dma.write(value),
+            LCDC_ADDRESS..=WX_ADDRESS => self.ppu.write(address, value, &mut self.scheduler, self.speed.double_speed),
+            KEY1_ADDRESS if self.cgb_mode => self.speed.write(value),
+            VBK_ADDRESS => self.vram.write_vbk(value),
+            HDMA1_ADDRESS..=HDMA5_ADDRESS if self.cgb_mode => {
+                self.hdma.write(address, value);
+                if self.hdma.mode() == HdmaMode::GeneralPurpose {
+                    self.run_general_purpose_dma();
+                }
+            }
+            BCPS_ADDRESS..=OCPD_ADDRESS if self.cgb_mode => self.palettes.write(address, value),
+            SVBK_ADDRESS => self.wram.write_svbk(value),
+            KEY1_ADDRESS | HDMA1_ADDRESS..=HDMA5_ADDRESS | BCPS_ADDRESS..=OCPD_ADDRESS => {}
+            _ => self.io[(address - IO_START) as usize] = value,
+        }
+    }
+
+    fn run_general_purpose_dma(&mut self) {
+        // Like the CPU, the DMA reads nothing but 0xFF while OAM DMA has the bus
+        let blocked = self.oam_dma.is_active();
+        let (cartridge, wram, boot_rom) = (&self.cartridge, &self.wram, self.boot_rom.as_ref());
+        let read = |address| if blocked { 0xFF } else { hdma_source(cartridge, wram, boot_rom, address) };
+        let stall = self.hdma.run_general_purpose(read, &mut self.vram, self.speed.double_speed);
+        self.dma_stall_cycles += stall;
+    }
+
+    // A running HBlank HDMA copies a block at the start of every HBlank
+    fn run_hblank_dma(&mut self) {
+        if self.hdma.mode() != HdmaMode::HBlank {
+            return;
+        }
+        let (cartridge, wram, boot_rom) = (&self.cartridge, &self.wram, self.boot_rom.as_ref());
+        let read = |address| hdma_source(cartridge, wram, boot_rom, address);
+        let stall = self.hdma.hblank(read, &mut self.vram, self.cpu_halted, self.speed.double_speed);
+        self.dma_stall_cycles += stall;
+    }
+
+    // The OAM bug: on the models that have it, the CPU putting an address in 0xFE00-0xFEFF
+    // on the bus while the PPU scans OAM garbles the row the PPU is reading. A write, or a
+    // 16 bit INC or DEC of such an address, mixes the first word of the row with the one
+    // before it and copies over the rest of that row. The first row is never touched
+    pub fn oam_bug_write(&mut self, address: u16) {
+        if let Some(row) = self.oam_bug_row(address) {
+            let (a, b, c) = self.oam_bug_words(row);
+            self.corrupt_oam_row(row, ((a ^ c) & (b ^ c)) ^ c);
+        }
+    }
+
+    // Like oam_bug_write, for reads, which mix the words differently
+    pub fn oam_bug_read(&mut self, address: u16) {
+        if let Some(row) = self.oam_bug_row(address) {
+            let (a, b, c) = self.oam_bug_words(row);
+            self.corrupt_oam_row(row, b | (a & c));
+        }
+    }
+
+    fn oam_bug_row(&self, address: u16) -> Option<usize> {
+        if !self.model.has_oam_bug() || !(OAM_START..=0xFEFF).contains(&address) {
+            return None;
+        }
+        self.ppu.oam_scan_row(self.scheduler.now()).filter(|row| *row > 0)
+    }
+
+    // The first word of row, and the first and third words of the row before it
+    fn oam_bug_words(&self, row: usize) -> (u16, u16, u16) {
+        let word = |offset: usize| u16::from_le_bytes([self.oam[offset], self.oam[offset + 1]]);
+        return (word(row * 8), word(row * 8 - 8), word(row * 8 - 4))
+    }
+
+    fn corrupt_oam_row(&mut self, row: usize, first_word: u16) {
+        let start = row * 8;
+        self.oam[start..start + 2].copy_from_slice(&first_word.to_le_bytes());
+        self.oam.copy_within(start - 6..start, start + 2);
+    }
+
+    // M-cycles the CPU has to sit out for DMA since the last call
+    pub fn take_dma_stall_cycles(&mut self) -> u32 {
+        std::mem::replace(&mut self.dma_stall_cycles, 0)
+    }
+}
+impl std::fmt::Debug for MemoryBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MemoryBus")
+            .field("mapper", &self.cartridge.kind())
+            .field("rom_size", &self.cartridge.rom().len())
+            .field("model", &self.model)
+            .field("boot_rom_mapped", &self.boot_rom_mapped())
+            .field("cgb_mode", &self.cgb_mode)
+            .field("vram_bank", &self.vram.bank())
+            .field("wram_bank", &self.wram.bank())
+            .field("interrupt_enable", &self.interrupt_enable)
+            .field("speed", &self.speed)
+            .finish()
+    }
+}
+
+// The model isn't saved, savestate::load only restores onto a bus of the same model
+impl SaveState for MemoryBus {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.cartridge.save_state(writer);
+        writer.write_bool(self.boot_rom.is_some());
+        if let Some(boot_rom) = &self.boot_rom {
+            writer.write_bytes(boot_rom.data());
+        }
+        self.vram.save_state(writer);
+        self.wram.save_state(writer);
+        writer.write_bytes(&self.oam);
+        writer.write_bytes(&self.io);
+        writer.write_bytes(&self.hram);
+        writer.write_u8(self.interrupt_enable);
+        self.serial.save_state(writer);
+        self.speed.save_state(writer);
+        self.palettes.save_state(writer);
+        self.hdma.save_state(writer);
+        writer.write_bool(self.sgb.is_some());
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(writer);
+        }
+        self.timer.save_state(writer);
+        self.oam_dma.save_state(writer);
+        self.scheduler.save_state(writer);
+        self.joypad.save_state(writer);
+        self.ppu.save_state(writer);
+        self.apu.save_state(writer);
+        writer.write_bool(self.cpu_halted);
+        writer.write_bool(self.cgb_mode);
+        writer.write_u32(self.dma_stall_cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cartridge.load_state(reader)?;
+        self.boot_rom = if reader.read_bool()? {
+            let data = reader.read_bytes()?;
+            Some(BootRom::new(data).map_err(|_| SaveStateError::Invalid("boot ROM"))?)
+        } else {
+            None
+        };
+        self.vram.load_state(reader)?;
+        self.wram.load_state(reader)?;
+        reader.read_bytes_into(&mut self.oam)?;
+        reader.read_bytes_into(&mut self.io)?;
+        reader.read_bytes_into(&mut self.hram)?;
+        self.interrupt_enable = reader.read_u8()?;
+        self.serial.load_state(reader)?;
+        self.speed.load_state(reader)?;
+        self.palettes.load_state(reader)?;
+        self.hdma.load_state(reader)?;
+        if reader.read_bool()? {
+            self.sgb.get_or_insert_with(Sgb::new).load_state(reader)?;
+        } else {
+            self.sgb = None;
+        }
+        self.timer.load_state(reader)?;
+        self.oam_dma.load_state(reader)?;
+        self.scheduler.load_state(reader)?;
+        self.joypad.load_state(reader)?;
+        self.ppu.load_state(reader)?;
+        self.apu.load_state(reader)?;
+        self.cpu_halted = reader.read_bool()?;
+        self.cgb_mode = reader.read_bool()?;
+        self.dma_stall_cycles = reader.read_u32()?;
+        return Ok(())
+    }
+}
+
+// What an HDMA reads from its source. It can only copy from ROM, cartridge RAM and WRAM, so
+// this borrows just those and leaves VRAM free to be written. Anywhere else reads 0xFF
+fn hdma_source(cartridge: &Cartridge, wram: &WorkRam, boot_rom: Option<&BootRom>, address: u16) -> u8 {
+    if let Some(value) = boot_rom.and_then(|boot_rom| boot_rom.read(address)) {
+        return value;
+    }
+    match address {
+        0x0000..=ROM_END => cartridge.read_rom(address),
+        EXTERNAL_RAM_START..=EXTERNAL_RAM_END => cartridge.read_ram(address),
+        WRAM_START..=ECHO_RAM_END => wram.read(address),
+        _ => 0xFF,
+    }
+}
@@ -0,0 +1,157 @@
+use super::vram::VideoRam;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const HDMA1_ADDRESS: u16 = 0xFF51; // Source, high byte
+pub const HDMA2_ADDRESS: u16 = 0xFF52; // Source, low byte
+pub const HDMA3_ADDRESS: u16 = 0xFF53; // Destination, high byte
+pub const HDMA4_ADDRESS: u16 = 0xFF54; // Destination, low byte
+pub const HDMA5_ADDRESS: u16 = 0xFF55; // Length, mode and start
+
+pub const BLOCK_SIZE: u16 = 0x10;
+// The CPU sits idle for 8 M-cycles while a block is copied. The DMA does not speed up
+// with the CPU, so in double speed the same copy costs 16 of the faster M-cycles
+pub const MCYCLES_PER_BLOCK: u32 = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HdmaMode {
+    Idle,
+    GeneralPurpose, // Copy everything at once, halting the CPU until it is done
+    HBlank,         // Copy one block at the start of every HBlank
+}
+
+// CGB VRAM DMA. The source can be anywhere in ROM, SRAM or WRAM, the destination is always
+// inside the VRAM bank currently selected by VBK
+#[derive(Clone, Debug)]
+pub struct Hdma {
+    source: u16,
+    destination: u16,
+    blocks_remaining: u8,
+    mode: HdmaMode,
+    cancelled: bool,
+}
+impl Hdma {
+    pub fn new() -> Hdma {
+        return Hdma {
+            source: 0,
+            destination: 0x8000,
+            blocks_remaining: 0,
+            mode: HdmaMode::Idle,
+            cancelled: false,
+        }
+    }
+
+    pub fn mode(&self) -> HdmaMode {
+        self.mode
+    }
+
+    // HDMA1-4 are write only. HDMA5 has bit 7 clear while an HBlank transfer is running,
+    // with the number of blocks still to go minus one in the low bits. Once it has finished
+    // that is 0xFF, or the blocks that were left with bit 7 set if it was cancelled
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            HDMA5_ADDRESS => match self.mode {
+                HdmaMode::HBlank => self.blocks_remaining.wrapping_sub(1) & 0x7F,
+                _ if self.cancelled => 0x80 | (self.blocks_remaining.wrapping_sub(1) & 0x7F),
+                _ => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            // The low 4 bits of both addresses are ignored so blocks are always aligned
+            HDMA1_ADDRESS => self.source = (value as u16) << 8 | (self.source & 0x00F0),
+            HDMA2_ADDRESS => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            HDMA3_ADDRESS => self.destination = 0x8000 | ((value & 0x1F) as u16) << 8 | (self.destination & 0x00F0),
+            HDMA4_ADDRESS => self.destination = (self.destination & 0xFF00) | (value & 0xF0) as u16,
+            HDMA5_ADDRESS => {
+                let hblank = value & 0x80 != 0;
+                if self.mode == HdmaMode::HBlank && !hblank {
+                    // Writing with bit 7 clear during an HBlank transfer stops it
+                    self.mode = HdmaMode::Idle;
+                    self.cancelled = true;
+                    return;
+                }
+                self.blocks_remaining = (value & 0x7F) + 1;
+                self.cancelled = false;
+                self.mode = if hblank { HdmaMode::HBlank } else { HdmaMode::GeneralPurpose };
+            }
+            _ => {}
+        }
+    }
+
+    // Performs a pending general purpose transfer in one go. Returns the number of
+    // M-cycles the CPU is halted for
+    pub fn run_general_purpose<F: FnMut(u16) -> u8>(&mut self, read: F, vram: &mut VideoRam, double_speed: bool) -> u32 {
+        if self.mode != HdmaMode::GeneralPurpose {
+            return 0;
+        }
+        let mut read = read;
+        let mut blocks = 0;
+        while self.mode == HdmaMode::GeneralPurpose {
+            self.copy_block(&mut read, vram);
+            blocks += 1;
+        }
+        return blocks * block_cost(double_speed)
+    }
+
+    // Called when the PPU enters HBlank on a visible line. Copies one block of a running
+    // HBlank transfer, unless the CPU is halted, in which case the block waits for the next
+    // HBlank after it wakes up. Returns the number of M-cycles the CPU is halted for
+    pub fn hblank<F: FnMut(u16) -> u8>(&mut self, read: F, vram: &mut VideoRam, cpu_halted: bool, double_speed: bool) -> u32 {
+        if self.mode != HdmaMode::HBlank || cpu_halted {
+            return 0;
+        }
+        let mut read = read;
+        self.copy_block(&mut read, vram);
+        return block_cost(double_speed)
+    }
+
+    fn copy_block<F: FnMut(u16) -> u8>(&mut self, read: &mut F, vram: &mut VideoRam) {
+        let bank = vram.bank();
+        for offset in 0..BLOCK_SIZE {
+            let value = read(self.source.wrapping_add(offset));
+            vram.write_bank(bank, self.destination + offset, value);
+        }
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.destination += BLOCK_SIZE;
+        self.blocks_remaining -= 1;
+        // The transfer also ends early if the destination runs off the end of VRAM
+        if self.blocks_remaining == 0 || self.destination > 0x9FFF {
+            if self.destination > 0x9FFF {
+                self.destination = 0x8000;
+            }
+            self.blocks_remaining = 0;
+            self.mode = HdmaMode::Idle;
+        }
+    }
+}
+
+fn block_cost(double_speed: bool) -> u32 {
+    if double_speed { MCYCLES_PER_BLOCK * 2 } else { MCYCLES_PER_BLOCK }
+}
+
+impl SaveState for Hdma {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.source);
+        writer.write_u16(self.destination);
+        writer.write_u8(self.blocks_remaining);
+        writer.write_u8(self.mode as u8);
+        writer.write_bool(self.cancelled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.source = reader.read_u16()?;
+        self.destination = reader.read_u16()?;
+        self.blocks_remaining = reader.read_u8()?;
+        self.mode = match reader.read_u8()? {
+            0 => HdmaMode::Idle,
+            1 => HdmaMode::GeneralPurpose,
+            2 => HdmaMode::HBlank,
+            _ => return Err(SaveStateError::Invalid("HDMA mode")),
+        };
+        self.cancelled = reader.read_bool()?;
+        return Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+pub mod boot;
+pub mod bus;
+pub mod hdma;
+pub mod oam_dma;
+pub mod vram;
+pub mod wram;
+
+pub const VRAM_START: u16 = 0x8000;
+pub const VRAM_END: u16 = 0x9FFF;
+pub const WRAM_START: u16 = 0xC000;
+pub const WRAM_END: u16 = 0xDFFF;
+pub const ECHO_RAM_START: u16 = 0xE000;
+pub const ECHO_RAM_END: u16 = 0xFDFF;
+
+pub const VBK_ADDRESS: u16 = 0xFF4F;  // CGB VRAM bank select
+pub const SVBK_ADDRESS: u16 = 0xFF70; // CGB WRAM bank select
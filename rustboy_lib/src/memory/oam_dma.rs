@@ -0,0 +1,86 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const DMA_ADDRESS: u16 = 0xFF46;
+
+// Bytes copied into OAM, one per M-cycle
+pub const OAM_DMA_LENGTH: u16 = 0xA0;
+
+// OAM DMA, started by writing the upper byte of the source address to 0xFF46. After a
+// cycle of setup it copies one byte per M-cycle for 160 M-cycles. While it runs the CPU
+// can only use HRAM, which is why games run the DMA routine from there
+#[derive(Debug)]
+pub struct OamDma {
+    register: u8,
+    source: u16,
+    index: u16,
+    active: bool,
+    starting: Option<u16>, // Source of a transfer that begins on the next M-cycle
+}
+impl OamDma {
+    pub fn new() -> OamDma {
+        return OamDma {
+            register: 0xFF,
+            source: 0,
+            index: OAM_DMA_LENGTH,
+            active: false,
+            starting: None,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+        // Sources from 0xE000 up hit the echo of work RAM
+        let source = (value as u16) << 8;
+        self.starting = Some(if source >= 0xE000 { source - 0x2000 } else { source });
+    }
+
+    // Whether bytes are being copied, the setup cycle leaves the bus alone
+    pub fn is_active(&self) -> bool {
+        self.active && self.index > 0
+    }
+
+    // Advances the DMA by one M-cycle and returns the source address and OAM offset of
+    // the byte to copy, if any
+    pub fn step(&mut self) -> Option<(u16, usize)> {
+        let transfer = if self.active && self.index < OAM_DMA_LENGTH {
+            let transfer = (self.source + self.index, self.index as usize);
+            self.index += 1;
+            Some(transfer)
+        } else {
+            self.active = false;
+            None
+        };
+        if let Some(source) = self.starting.take() {
+            self.source = source;
+            self.index = 0;
+            self.active = true;
+        }
+        return transfer
+    }
+}
+
+impl SaveState for OamDma {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register);
+        writer.write_u16(self.source);
+        writer.write_u16(self.index);
+        writer.write_bool(self.active);
+        writer.write_bool(self.starting.is_some());
+        writer.write_u16(self.starting.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.register = reader.read_u8()?;
+        self.source = reader.read_u16()?;
+        self.index = reader.read_u16()?;
+        self.active = reader.read_bool()?;
+        let starting = reader.read_bool()?;
+        let source = reader.read_u16()?;
+        self.starting = if starting { Some(source) } else { None };
+        return Ok(())
+    }
+}
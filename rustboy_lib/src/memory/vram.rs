@@ -0,0 +1,75 @@
+use super::VRAM_START;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const VRAM_BANK_SIZE: usize = 0x2000;
+
+// Video RAM at 0x8000-0x9FFF. The CGB has a second bank, selected through VBK, which holds
+// extra tile data and the background map attributes
+#[derive(Clone, Debug)]
+pub struct VideoRam {
+    data: Vec<u8>,
+    bank: usize,
+    cgb_mode: bool,
+}
+impl VideoRam {
+    pub fn new(cgb_mode: bool) -> VideoRam {
+        let banks = if cgb_mode { 2 } else { 1 };
+        return VideoRam {
+            data: vec![0; banks * VRAM_BANK_SIZE],
+            bank: 0,
+            cgb_mode,
+        }
+    }
+
+    // CPU side access, through whichever bank VBK selects
+    pub fn read(&self, address: u16) -> u8 {
+        self.read_bank(self.bank, address)
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        self.write_bank(self.bank, address, value);
+    }
+
+    // Access to a specific bank regardless of VBK, as the PPU and HDMA see it
+    pub fn read_bank(&self, bank: usize, address: u16) -> u8 {
+        self.data[bank * VRAM_BANK_SIZE + (address - VRAM_START) as usize]
+    }
+
+    pub fn write_bank(&mut self, bank: usize, address: u16, value: u8) {
+        self.data[bank * VRAM_BANK_SIZE + (address - VRAM_START) as usize] = value;
+    }
+
+    // VBK. Only bit 0 exists, the rest read back as 1
+    pub fn read_vbk(&self) -> u8 {
+        if self.cgb_mode { 0xFE | self.bank as u8 } else { 0xFF }
+    }
+
+    pub fn write_vbk(&mut self, value: u8) {
+        if self.cgb_mode {
+            self.bank = (value & 0b1) as usize;
+        }
+    }
+
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+}
+
+impl SaveState for VideoRam {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.cgb_mode);
+        writer.write_u8(self.bank as u8);
+        writer.write_bytes(&self.data);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cgb_mode = reader.read_bool()?;
+        self.bank = reader.read_u8()? as usize;
+        self.data = reader.read_bytes()?;
+        let banks = if self.cgb_mode { 2 } else { 1 };
+        if self.data.len() != banks * VRAM_BANK_SIZE || self.bank >= banks {
+            return Err(SaveStateError::Invalid("VRAM"));
+        }
+        return Ok(())
+    }
+}
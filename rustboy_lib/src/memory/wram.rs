@@ -0,0 +1,82 @@
+use super::{ECHO_RAM_START, WRAM_START};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const WRAM_BANK_SIZE: usize = 0x1000;
+pub const CGB_WRAM_BANKS: usize = 8;
+
+// Work RAM. 0xC000-0xCFFF is always bank 0, 0xD000-0xDFFF is bank 1 on a DMG and any of
+// banks 1-7 on a CGB as chosen by SVBK. 0xE000-0xFDFF mirrors 0xC000-0xDDFF
+#[derive(Clone, Debug)]
+pub struct WorkRam {
+    data: Vec<u8>,
+    bank: usize,
+    svbk: u8,
+    cgb_mode: bool,
+}
+impl WorkRam {
+    pub fn new(cgb_mode: bool) -> WorkRam {
+        let banks = if cgb_mode { CGB_WRAM_BANKS } else { 2 };
+        return WorkRam {
+            data: vec![0; banks * WRAM_BANK_SIZE],
+            bank: 1,
+            svbk: 0,
+            cgb_mode,
+        }
+    }
+
+    fn index(&self, address: u16) -> usize {
+        let address = if address >= ECHO_RAM_START { address - 0x2000 } else { address };
+        let offset = (address - WRAM_START) as usize;
+        if offset < WRAM_BANK_SIZE {
+            offset
+        } else {
+            self.bank * WRAM_BANK_SIZE + offset - WRAM_BANK_SIZE
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        self.data[self.index(address)]
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let index = self.index(address);
+        self.data[index] = value;
+    }
+
+    // SVBK. Only the low 3 bits exist. Selecting bank 0 maps bank 1, but still reads back as 0
+    pub fn read_svbk(&self) -> u8 {
+        if self.cgb_mode { 0xF8 | self.svbk } else { 0xFF }
+    }
+
+    pub fn write_svbk(&mut self, value: u8) {
+        if self.cgb_mode {
+            self.svbk = value & 0b111;
+            self.bank = (self.svbk as usize).max(1);
+        }
+    }
+
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+}
+
+impl SaveState for WorkRam {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.cgb_mode);
+        writer.write_u8(self.bank as u8);
+        writer.write_u8(self.svbk);
+        writer.write_bytes(&self.data);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.cgb_mode = reader.read_bool()?;
+        self.bank = reader.read_u8()? as usize;
+        self.svbk = reader.read_u8()?;
+        self.data = reader.read_bytes()?;
+        let banks = if self.cgb_mode { CGB_WRAM_BANKS } else { 2 };
+        if self.data.len() != banks * WRAM_BANK_SIZE || self.bank >= banks {
+            return Err(SaveStateError::Invalid("WRAM"));
+        }
+        return Ok(())
+    }
+}
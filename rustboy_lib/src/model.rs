@@ -0,0 +1,78 @@
+// The Game Boy hardware revision being emulated
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    Dmg0, // Early original Game Boy, only sold in Japan
+    #[default]
+    Dmg,  // Original Game Boy
+    Mgb,  // Game Boy Pocket
+    Sgb,  // Super Game Boy
+    Cgb,  // Game Boy Color
+    Agb,  // Game Boy Advance running a Game Boy cartridge
+}
+impl Model {
+    pub const ALL: [Model; 6] = [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Cgb, Model::Agb];
+
+    // Whether the hardware has the CGB features at all. They are only switched on for
+    // cartridges that ask for them, see Model::runs_in_cgb_mode
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::Sgb)
+    }
+
+    pub fn runs_in_cgb_mode(&self, cartridge_is_cgb: bool) -> bool {
+        self.is_cgb() && cartridge_is_cgb
+    }
+
+    // Accessing certain 16 bit registers in 0xFE00-0xFEFF while the PPU scans OAM
+    // corrupts it. The bug was fixed in the CGB
+    pub fn has_oam_bug(&self) -> bool {
+        !self.is_cgb()
+    }
+
+    // While channel 3 is playing, the CPU only sees wave RAM on the DMG if it happens to
+    // read on the exact cycle the APU does (0xFF otherwise). The CGB always returns the
+    // byte the APU is currently playing
+    pub fn wave_ram_readable_while_playing(&self) -> bool {
+        self.is_cgb()
+    }
+
+    // What reading the unusable area at 0xFEA0-0xFEFF returns. DMG models read 0, the later
+    // CGB revisions and the AGB repeat the high nibble of the low address byte
+    pub fn unusable_area_read(&self, address: u16) -> u8 {
+        if self.is_cgb() {
+            let nibble = (address & 0xF0) as u8;
+            nibble | nibble >> 4
+        } else {
+            0x00
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Cgb => "cgb",
+            Model::Agb => "agb",
+        }
+    }
+}
+impl std::fmt::Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+impl std::str::FromStr for Model {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Model, String> {
+        Model::ALL
+            .into_iter()
+            .find(|model| model.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown model '{}', expected one of dmg0, dmg, mgb, sgb, cgb, agb", name))
+    }
+}
@@ -0,0 +1,151 @@
+use crate::cartridge::mbc::Rtc;
+use crate::joypad::Button;
+use crate::model::Model;
+use crate::savestate::{crc32, SaveState, SaveStateError, StateReader, StateWriter};
+
+// A movie is the input of a run, enough to play it back exactly on the same ROM:
+//
+//   "RBMV"  magic
+//   u32     format version
+//   u32     CRC-32 of the ROM
+//   u8      Model
+//   u8      0: starts at power on, followed by the cartridge RAM and RTC it had
+//           1: starts from a save state, followed by the state
+//   ...     joypad changes: u32 count, then frame u64, cycle u64, button u8, pressed u8
+//   ...     CRC-32 of the framebuffer at the end of every frame: u32 count, then u32s
+//
+// All numbers are little endian, like save states
+pub const MOVIE_MAGIC: [u8; 4] = *b"RBMV";
+pub const MOVIE_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieStart {
+    // The console is reset with this battery backed state, so saves don't make playback
+    // go differently
+    PowerOn { ram: Vec<u8>, rtc: Option<Rtc> },
+    State(Vec<u8>),
+}
+
+// A button going down or up. The frame counts from the start of the movie and is there for
+// people reading it, playback goes by the CPU's M-cycle count
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub cycle: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_crc32: u32,
+    pub model: Model,
+    pub start: MovieStart,
+    pub events: Vec<InputEvent>,
+    pub checksums: Vec<u32>, // One per frame, see frame_checksum
+}
+impl Movie {
+    pub fn frames(&self) -> u64 {
+        self.checksums.len() as u64
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        for byte in MOVIE_MAGIC {
+            writer.write_u8(byte);
+        }
+        writer.write_u32(MOVIE_VERSION);
+        writer.write_u32(self.rom_crc32);
+        writer.write_u8(self.model as u8);
+        match &self.start {
+            MovieStart::PowerOn { ram, rtc } => {
+                writer.write_u8(0);
+                writer.write_bytes(ram);
+                writer.write_bool(rtc.is_some());
+                if let Some(rtc) = rtc {
+                    rtc.save_state(&mut writer);
+                }
+            }
+            MovieStart::State(state) => {
+                writer.write_u8(1);
+                writer.write_bytes(state);
+            }
+        }
+        writer.write_u32(self.events.len() as u32);
+        for event in &self.events {
+            writer.write_u64(event.frame);
+            writer.write_u64(event.cycle);
+            writer.write_u8(event.button as u8);
+            writer.write_bool(event.pressed);
+        }
+        writer.write_u32(self.checksums.len() as u32);
+        for &checksum in &self.checksums {
+            writer.write_u32(checksum);
+        }
+        return writer.finish()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, SaveStateError> {
+        let mut reader = StateReader::new(data);
+        let mut magic = [0; 4];
+        for byte in &mut magic {
+            *byte = reader.read_u8().map_err(|_| SaveStateError::BadMagic)?;
+        }
+        if magic != MOVIE_MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = reader.read_u32()?;
+        if version != MOVIE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        let rom_crc32 = reader.read_u32()?;
+        let model = *Model::ALL.get(reader.read_u8()? as usize).ok_or(SaveStateError::Invalid("model"))?;
+        let start = match reader.read_u8()? {
+            0 => {
+                let ram = reader.read_bytes()?;
+                let rtc = if reader.read_bool()? {
+                    let mut rtc = Rtc::new();
+                    rtc.load_state(&mut reader)?;
+                    Some(rtc)
+                } else {
+                    None
+                };
+                MovieStart::PowerOn { ram, rtc }
+            }
+            1 => MovieStart::State(reader.read_bytes()?),
+            _ => return Err(SaveStateError::Invalid("movie start")),
+        };
+        let mut events = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            let frame = reader.read_u64()?;
+            let cycle = reader.read_u64()?;
+            let button = *Button::ALL.get(reader.read_u8()? as usize).ok_or(SaveStateError::Invalid("button"))?;
+            let pressed = reader.read_bool()?;
+            events.push(InputEvent { frame, cycle, button, pressed });
+        }
+        if events.windows(2).any(|pair| pair[1].cycle < pair[0].cycle) {
+            return Err(SaveStateError::Invalid("event order"));
+        }
+        let mut checksums = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            checksums.push(reader.read_u32()?);
+        }
+        if !reader.is_empty() {
+            return Err(SaveStateError::Invalid("length"));
+        }
+        return Ok(Movie { rom_crc32, model, start, events, checksums })
+    }
+}
+
+// What came of playing a movie back. Frames count from 1, the first frame after the start
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub frames: u64, // Frames played, up to and including the first that didn't match
+    pub first_divergence: Option<u64>,
+}
+
+// CRC-32 of the RGB555 pixels, little endian
+pub fn frame_checksum(frame: &[u16]) -> u32 {
+    let bytes: Vec<u8> = frame.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+    return crc32(&bytes)
+}
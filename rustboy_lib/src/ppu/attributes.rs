@@ -0,0 +1,57 @@
+// Background map attributes, stored in VRAM bank 1 at the same offset as the tile index
+// in bank 0
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BgAttributes {
+    pub priority: bool, // Bit 7: BG colours 1-3 are drawn over objects
+    pub y_flip: bool,   // Bit 6
+    pub x_flip: bool,   // Bit 5
+    pub bank: u8,       // Bit 3: VRAM bank the tile data comes from
+    pub palette: u8,    // Bits 0-2: BG palette number
+}
+impl std::convert::From<u8> for BgAttributes {
+    fn from(byte: u8) -> BgAttributes {
+        return BgAttributes {
+            priority: (byte >> 7) & 0b1 == 0b1,
+            y_flip: (byte >> 6) & 0b1 == 0b1,
+            x_flip: (byte >> 5) & 0b1 == 0b1,
+            bank: (byte >> 3) & 0b1,
+            palette: byte & 0b111,
+        }
+    }
+}
+
+// Byte 3 of an OAM entry. The DMG palette bit is only used outside of CGB mode, the bank
+// and CGB palette bits only inside of it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpriteAttributes {
+    pub behind_bg: bool,   // Bit 7: BG colours 1-3 are drawn over this object
+    pub y_flip: bool,      // Bit 6
+    pub x_flip: bool,      // Bit 5
+    pub dmg_palette: u8,   // Bit 4: OBP0 or OBP1
+    pub bank: u8,          // Bit 3: VRAM bank the tile data comes from
+    pub cgb_palette: u8,   // Bits 0-2: OBJ palette number
+}
+impl std::convert::From<u8> for SpriteAttributes {
+    fn from(byte: u8) -> SpriteAttributes {
+        return SpriteAttributes {
+            behind_bg: (byte >> 7) & 0b1 == 0b1,
+            y_flip: (byte >> 6) & 0b1 == 0b1,
+            x_flip: (byte >> 5) & 0b1 == 0b1,
+            dmg_palette: (byte >> 4) & 0b1,
+            bank: (byte >> 3) & 0b1,
+            cgb_palette: byte & 0b111,
+        }
+    }
+}
+
+// Whether an opaque object pixel is drawn over the BG/window pixel below it in CGB mode.
+// On a CGB, LCDC bit 0 no longer turns the background off: clearing it strips the BG and
+// window of any priority so objects always come out on top. Otherwise BG colour 0 is
+// always behind objects, and colours 1-3 win if either the BG map attribute or the object
+// asks for the BG to be in front
+pub fn cgb_object_has_priority(lcdc_master_priority: bool, bg_color: u8, bg: BgAttributes, sprite: SpriteAttributes) -> bool {
+    if !lcdc_master_priority || bg_color == 0 {
+        return true;
+    }
+    return !(bg.priority || sprite.behind_bg)
+}
@@ -0,0 +1,41 @@
+// How CGB RGB555 colours are turned into the 24-bit colours we output
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorCorrection {
+    // Scale each 5-bit channel straight up to 8 bits. Accurate to the data, but far more
+    // saturated than the real screen ever looked
+    #[default]
+    None,
+    // Mix the channels and pull the brightness down to mimic the washed out colours of the
+    // CGB LCD, using the curve from byuu's higan
+    Lcd,
+}
+impl ColorCorrection {
+    pub fn to_rgb888(&self, color: u16) -> [u8; 3] {
+        let r = (color & 0x1F) as u32;
+        let g = ((color >> 5) & 0x1F) as u32;
+        let b = ((color >> 10) & 0x1F) as u32;
+        match self {
+            ColorCorrection::None => [scale_5_to_8(r), scale_5_to_8(g), scale_5_to_8(b)],
+            ColorCorrection::Lcd => {
+                let r_out = r * 26 + g * 4 + b * 2;
+                let g_out = g * 24 + b * 8;
+                let b_out = r * 6 + g * 4 + b * 22;
+                [(r_out.min(960) >> 2) as u8, (g_out.min(960) >> 2) as u8, (b_out.min(960) >> 2) as u8]
+            }
+        }
+    }
+
+    // Converts a whole RGB555 frame into packed RGB888 bytes
+    pub fn convert_frame(&self, frame: &[u16]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(frame.len() * 3);
+        for &color in frame {
+            output.extend_from_slice(&self.to_rgb888(color));
+        }
+        return output
+    }
+}
+
+// Repeats the top bits into the bottom so 0x1F becomes 0xFF rather than 0xF8
+fn scale_5_to_8(value: u32) -> u8 {
+    ((value << 3) | (value >> 2)) as u8
+}
@@ -0,0 +1,267 @@
+use crate::cartridge::header::CartridgeHeader;
+use super::palette::CgbPalettes;
+
+// When a DMG cartridge is started on a CGB, the boot ROM colourises it. Nintendo's own
+// titles are recognised by a checksum of the header title and get a hand picked set of
+// palettes, everything else gets the default. Holding a direction (and optionally A or B)
+// while the logo scrolls overrides the choice. The tables below are the ones in the CGB
+// boot ROM, laid out as in SameBoy's reimplementation of it
+
+// Sum of the title bytes for every title the boot ROM knows about. Entries from
+// FIRST_DUPLICATE_CHECKSUM on share a checksum with another title, so the 4th letter of
+// the title has to match DUPLICATE_FOURTH_LETTERS as well
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    // Duplicates
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3, 0x46,
+    0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+const FIRST_DUPLICATE_CHECKSUM: usize = 65;
+const DUPLICATE_FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Palette combination used by each entry of TITLE_CHECKSUMS
+const COMBINATION_PER_CHECKSUM: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    // Duplicates
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17, 46,
+    6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+// Every palette the boot ROM can pick from, as RGB555 colours
+const PALETTE_COLORS: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+// Where OBJ0, OBJ1 and BG start within PALETTE_COLORS, counted in colours. Most start on a
+// palette boundary, but a few of Nintendo's combinations start part way through one and
+// run on into the next
+const fn palette(index: usize) -> usize {
+    index * 4
+}
+const PALETTE_COMBINATIONS: [[usize; 3]; 51] = [
+    [palette(4), palette(4), palette(29)],   // 0, Right + A, also the default
+    [palette(18), palette(18), palette(18)], // 1, Right
+    [palette(20), palette(20), palette(20)],
+    [palette(24), palette(24), palette(24)], // 3, Down + A
+    [palette(9), palette(9), palette(9)],
+    [palette(0), palette(0), palette(0)],    // 5, Up
+    [palette(27), palette(27), palette(27)], // 6, Right + B
+    [palette(5), palette(5), palette(5)],    // 7, Left + B
+    [palette(12), palette(12), palette(12)], // 8, Down
+    [palette(26), palette(26), palette(26)],
+    [palette(16), palette(8), palette(8)],
+    [palette(4), palette(28), palette(28)],
+    [palette(4), palette(2), palette(2)],
+    [palette(3), palette(4), palette(4)],
+    [palette(4), palette(29), palette(29)],
+    [palette(28), palette(4), palette(28)],
+    [palette(2), palette(17), palette(2)],
+    [palette(16), palette(16), palette(8)],
+    [palette(4), palette(4), palette(7)],
+    [palette(4), palette(4), palette(18)],
+    [palette(4), palette(4), palette(20)],
+    [palette(19), palette(19), palette(9)],
+    [palette(4) - 1, palette(4) - 1, palette(11)],
+    [palette(17), palette(17), palette(2)],
+    [palette(4), palette(4), palette(2)],
+    [palette(4), palette(4), palette(3)],
+    [palette(28), palette(28), palette(0)],
+    [palette(3), palette(3), palette(0)],
+    [palette(0), palette(0), palette(1)],    // 28, Up + B
+    [palette(18), palette(22), palette(18)],
+    [palette(20), palette(22), palette(20)],
+    [palette(24), palette(22), palette(24)],
+    [palette(16), palette(22), palette(8)],
+    [palette(17), palette(4), palette(13)],
+    [palette(28) - 1, palette(0), palette(14)],
+    [palette(28) - 1, palette(4), palette(15)],
+    [palette(19), palette(22), palette(9)],
+    [palette(16), palette(28), palette(10)],
+    [palette(4), palette(23), palette(28)],
+    [palette(17), palette(22), palette(2)],
+    [palette(4), palette(0), palette(2)],    // 40, Left + A
+    [palette(4), palette(28), palette(3)],
+    [palette(28), palette(3), palette(0)],
+    [palette(3), palette(28), palette(4)],   // 43, Up + A
+    [palette(21), palette(28), palette(4)],
+    [palette(3), palette(28), palette(0)],
+    [palette(25), palette(3), palette(28)],
+    [palette(0), palette(28), palette(8)],
+    [palette(4), palette(3), palette(28)],   // 48, Left
+    [palette(28), palette(3), palette(6)],   // 49, Down + B
+    [palette(4), palette(28), palette(29)],
+];
+
+// Buttons held during the boot animation to pick the palettes by hand
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PaletteOverride {
+    Up, UpA, UpB,
+    Down, DownA, DownB,
+    Left, LeftA, LeftB,
+    Right, RightA, RightB,
+}
+impl PaletteOverride {
+    pub const ALL: [PaletteOverride; 12] = [
+        PaletteOverride::Up, PaletteOverride::UpA, PaletteOverride::UpB,
+        PaletteOverride::Down, PaletteOverride::DownA, PaletteOverride::DownB,
+        PaletteOverride::Left, PaletteOverride::LeftA, PaletteOverride::LeftB,
+        PaletteOverride::Right, PaletteOverride::RightA, PaletteOverride::RightB,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PaletteOverride::Up => "up",
+            PaletteOverride::UpA => "up-a",
+            PaletteOverride::UpB => "up-b",
+            PaletteOverride::Down => "down",
+            PaletteOverride::DownA => "down-a",
+            PaletteOverride::DownB => "down-b",
+            PaletteOverride::Left => "left",
+            PaletteOverride::LeftA => "left-a",
+            PaletteOverride::LeftB => "left-b",
+            PaletteOverride::Right => "right",
+            PaletteOverride::RightA => "right-a",
+            PaletteOverride::RightB => "right-b",
+        }
+    }
+
+    fn combination(&self) -> usize {
+        match self {
+            PaletteOverride::Up => 5,
+            PaletteOverride::UpA => 43,
+            PaletteOverride::UpB => 28,
+            PaletteOverride::Down => 8,
+            PaletteOverride::DownA => 3,
+            PaletteOverride::DownB => 49,
+            PaletteOverride::Left => 48,
+            PaletteOverride::LeftA => 40,
+            PaletteOverride::LeftB => 7,
+            PaletteOverride::Right => 1,
+            PaletteOverride::RightA => 0,
+            PaletteOverride::RightB => 6,
+        }
+    }
+}
+impl std::fmt::Display for PaletteOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+impl std::str::FromStr for PaletteOverride {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<PaletteOverride, String> {
+        PaletteOverride::ALL
+            .into_iter()
+            .find(|palette_override| palette_override.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown palette '{}', expected a direction optionally with -a or -b, like up-a", name))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CompatibilityPalettes {
+    pub background: [u16; 4],
+    pub object0: [u16; 4],
+    pub object1: [u16; 4],
+}
+impl CompatibilityPalettes {
+    // The palettes the CGB boot ROM sets up for a DMG cartridge
+    pub fn for_cartridge(header: &CartridgeHeader, palette_override: Option<PaletteOverride>) -> CompatibilityPalettes {
+        let combination = match palette_override {
+            Some(palette_override) => palette_override.combination(),
+            None => COMBINATION_PER_CHECKSUM[title_index(header).unwrap_or(0)] as usize & 0x7F,
+        };
+        return CompatibilityPalettes::from_combination(combination)
+    }
+
+    fn from_combination(combination: usize) -> CompatibilityPalettes {
+        let [object0, object1, background] = PALETTE_COMBINATIONS[combination];
+        return CompatibilityPalettes {
+            background: colors_at(background),
+            object0: colors_at(object0),
+            object1: colors_at(object1),
+        }
+    }
+
+    // Writes the colours to BG palette 0 and OBJ palettes 0 and 1, which is all a DMG game
+    // running in compatibility mode uses
+    pub fn load_into(&self, palettes: &mut CgbPalettes) {
+        for color in 0..4 {
+            palettes.background.set_color(0, color as u8, self.background[color]);
+            palettes.object.set_color(0, color as u8, self.object0[color]);
+            palettes.object.set_color(1, color as u8, self.object1[color]);
+        }
+    }
+}
+
+fn colors_at(offset: usize) -> [u16; 4] {
+    let mut colors = [0; 4];
+    for (i, color) in colors.iter_mut().enumerate() {
+        *color = PALETTE_COLORS[(offset + i) / 4][(offset + i) % 4];
+    }
+    return colors
+}
+
+// Only Nintendo published games get their own palettes
+pub fn is_nintendo_title(header: &CartridgeHeader) -> bool {
+    match header.old_licensee {
+        0x01 => true,
+        0x33 => &header.new_licensee == b"01",
+        _ => false,
+    }
+}
+
+pub fn title_checksum(header: &CartridgeHeader) -> u8 {
+    header.title.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// Position of the cartridge in the boot ROM's title tables, if it is in there
+pub fn title_index(header: &CartridgeHeader) -> Option<usize> {
+    if !is_nintendo_title(header) {
+        return None;
+    }
+    let checksum = title_checksum(header);
+    let fourth_letter = header.title[3];
+    return TITLE_CHECKSUMS.iter().enumerate().position(|(index, &entry)| {
+        entry == checksum
+            && (index < FIRST_DUPLICATE_CHECKSUM
+                || DUPLICATE_FOURTH_LETTERS[index - FIRST_DUPLICATE_CHECKSUM] == fourth_letter)
+    })
+}
@@ -0,0 +1,472 @@
+use super::attributes::{cgb_object_has_priority, BgAttributes, SpriteAttributes};
+use super::palette::{CgbPalettes, PaletteRam};
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::memory::vram::VideoRam;
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::scheduler::{EventKind, Scheduler};
+
+pub const LCDC_ADDRESS: u16 = 0xFF40; // LCD control
+pub const STAT_ADDRESS: u16 = 0xFF41; // LCD status
+pub const SCY_ADDRESS: u16 = 0xFF42;
+pub const SCX_ADDRESS: u16 = 0xFF43;
+pub const LY_ADDRESS: u16 = 0xFF44;   // Line being drawn, read only
+pub const LYC_ADDRESS: u16 = 0xFF45;  // Raises the STAT interrupt when it matches LY
+pub const BGP_ADDRESS: u16 = 0xFF47;
+pub const OBP0_ADDRESS: u16 = 0xFF48;
+pub const OBP1_ADDRESS: u16 = 0xFF49;
+pub const WY_ADDRESS: u16 = 0xFF4A;
+pub const WX_ADDRESS: u16 = 0xFF4B;
+
+pub const DOTS_PER_LINE: u64 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+pub const DOTS_PER_FRAME: u64 = DOTS_PER_LINE * LINES_PER_FRAME as u64;
+const OAM_SCAN_DOTS: u64 = 80;
+const DRAWING_DOTS: u64 = 172;
+// Roughly what fetching one object adds to mode 3
+const OBJECT_PENALTY_DOTS: u64 = 6;
+const MAX_OBJECTS_PER_LINE: usize = 10;
+
+// The four DMG shades as RGB555, lightest first
+pub const DMG_SHADES: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// The LCD controller. Mode changes are scheduled events, and each line is rendered in one
+// go when mode 3 ends, using the registers as they are at that point. Effects that change
+// registers in the middle of a line are not reproduced
+#[derive(Debug)]
+pub struct Ppu {
+    lcdc: u8,
+    stat: u8, // Only the interrupt enable bits 3-6
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: PpuMode,
+    mode_start: u64, // Scheduler time the current mode started at
+    drawing_dots: u64,
+    window_line: u8, // Lines of the window drawn so far this frame
+    stat_line: bool, // The STAT interrupt fires on a rising edge of this
+    cgb_mode: bool,
+    cgb_hardware: bool, // Colours come from palette RAM, even for DMG games on a CGB
+    screen: Vec<u8>, // Colour numbers after BGP/OBP, which the SGB colours in
+    frame: Vec<u16>, // RGB555
+    // Lines are drawn into these and copied over the finished frame at VBlank
+    drawing_screen: Vec<u8>,
+    drawing_frame: Vec<u16>,
+    vblank_interrupt: bool,
+    stat_interrupt: bool,
+    frame_ready: bool,
+}
+impl Ppu {
+    pub fn new(cgb_mode: bool, cgb_hardware: bool) -> Ppu {
+        return Ppu {
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: PpuMode::HBlank,
+            mode_start: 0,
+            drawing_dots: DRAWING_DOTS,
+            window_line: 0,
+            stat_line: false,
+            cgb_mode,
+            cgb_hardware,
+            screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame: vec![DMG_SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            drawing_screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            drawing_frame: vec![DMG_SHADES[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            vblank_interrupt: false,
+            stat_interrupt: false,
+            frame_ready: false,
+        }
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn lcdc(&self) -> u8 {
+        self.lcdc
+    }
+
+    pub fn mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    // The 8 byte row of OAM the PPU is reading at scheduler time now, while it scans OAM.
+    // It goes through two objects, one row, every M-cycle. Only the DMG has the OAM bug
+    // that needs this, so there's no double speed to account for
+    pub fn oam_scan_row(&self, now: u64) -> Option<usize> {
+        if !self.lcd_enabled() || self.mode != PpuMode::OamScan {
+            return None;
+        }
+        let row = (now.saturating_sub(self.mode_start) / 4) as usize;
+        return Some(row).filter(|row| *row < 20)
+    }
+
+    // The last finished frame as RGB555, SCREEN_WIDTH * SCREEN_HEIGHT pixels
+    pub fn frame(&self) -> &[u16] {
+        &self.frame
+    }
+
+    // The last finished frame as colour numbers 0-3 after the DMG palettes
+    pub fn screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            LCDC_ADDRESS => self.lcdc,
+            STAT_ADDRESS => {
+                let coincidence = (self.ly == self.lyc) as u8;
+                let mode = if self.lcd_enabled() { self.mode as u8 } else { 0 };
+                0x80 | self.stat | coincidence << 2 | mode
+            }
+            SCY_ADDRESS => self.scy,
+            SCX_ADDRESS => self.scx,
+            LY_ADDRESS => self.ly,
+            LYC_ADDRESS => self.lyc,
+            BGP_ADDRESS => self.bgp,
+            OBP0_ADDRESS => self.obp0,
+            OBP1_ADDRESS => self.obp1,
+            WY_ADDRESS => self.wy,
+            WX_ADDRESS => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, scheduler: &mut Scheduler, double_speed: bool) {
+        match address {
+            LCDC_ADDRESS => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = value;
+                if was_enabled && !self.lcd_enabled() {
+                    // LY goes straight to 0 and the screen blanks until the LCD is back on
+                    self.ly = 0;
+                    self.mode = PpuMode::HBlank;
+                    self.window_line = 0;
+                    self.frame.fill(DMG_SHADES[0]);
+                    self.screen.fill(0);
+                    self.drawing_frame.fill(DMG_SHADES[0]);
+                    self.drawing_screen.fill(0);
+                    scheduler.cancel(EventKind::PpuMode);
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.ly = 0;
+                    self.mode = PpuMode::OamScan;
+                    self.mode_start = scheduler.now();
+                    self.schedule(scheduler, OAM_SCAN_DOTS, double_speed);
+                }
+            }
+            STAT_ADDRESS => self.stat = value & 0x78,
+            SCY_ADDRESS => self.scy = value,
+            SCX_ADDRESS => self.scx = value,
+            LYC_ADDRESS => self.lyc = value,
+            BGP_ADDRESS => self.bgp = value,
+            OBP0_ADDRESS => self.obp0 = value,
+            OBP1_ADDRESS => self.obp1 = value,
+            WY_ADDRESS => self.wy = value,
+            WX_ADDRESS => self.wx = value,
+            _ => {}
+        }
+        self.update_stat_line();
+    }
+
+    // Moves on to the next mode when the PpuMode event due at time fires and returns the
+    // mode entered
+    pub fn handle_event(&mut self, time: u64, scheduler: &mut Scheduler, double_speed: bool, vram: &VideoRam, oam: &[u8], palettes: &CgbPalettes) -> PpuMode {
+        self.mode_start = time;
+        match self.mode {
+            PpuMode::OamScan => {
+                self.mode = PpuMode::Drawing;
+                let objects = self.line_objects(oam).len() as u64;
+                self.drawing_dots = DRAWING_DOTS + (self.scx & 0b111) as u64 + objects * OBJECT_PENALTY_DOTS;
+                self.schedule(scheduler, self.drawing_dots, double_speed);
+            }
+            PpuMode::Drawing => {
+                self.render_line(vram, oam, palettes);
+                self.mode = PpuMode::HBlank;
+                self.schedule(scheduler, DOTS_PER_LINE - OAM_SCAN_DOTS - self.drawing_dots, double_speed);
+            }
+            PpuMode::HBlank => {
+                self.ly += 1;
+                if self.ly as usize == SCREEN_HEIGHT {
+                    self.mode = PpuMode::VBlank;
+                    self.vblank_interrupt = true;
+                    self.frame_ready = true;
+                    self.frame.copy_from_slice(&self.drawing_frame);
+                    self.screen.copy_from_slice(&self.drawing_screen);
+                    self.schedule(scheduler, DOTS_PER_LINE, double_speed);
+                } else {
+                    self.mode = PpuMode::OamScan;
+                    self.schedule(scheduler, OAM_SCAN_DOTS, double_speed);
+                }
+            }
+            PpuMode::VBlank => {
+                self.ly += 1;
+                if self.ly == LINES_PER_FRAME {
+                    self.ly = 0;
+                    self.window_line = 0;
+                    self.mode = PpuMode::OamScan;
+                    self.schedule(scheduler, OAM_SCAN_DOTS, double_speed);
+                } else {
+                    self.schedule(scheduler, DOTS_PER_LINE, double_speed);
+                }
+            }
+        }
+        self.update_stat_line();
+        return self.mode
+    }
+
+    // Keeps the current mode going at the same pace when the CPU changes speed
+    pub fn switch_speed(&mut self, scheduler: &mut Scheduler, double_speed: bool) {
+        self.mode_start = scheduler.rescale_time(self.mode_start, double_speed);
+        scheduler.rescale(EventKind::PpuMode, double_speed);
+    }
+
+    // Whether the VBlank interrupt (IF bit 0) has been raised since the last call
+    pub fn take_vblank_interrupt(&mut self) -> bool {
+        std::mem::replace(&mut self.vblank_interrupt, false)
+    }
+
+    // Whether the STAT interrupt (IF bit 1) has been raised since the last call
+    pub fn take_stat_interrupt(&mut self) -> bool {
+        std::mem::replace(&mut self.stat_interrupt, false)
+    }
+
+    // Whether a frame has been finished since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_ready, false)
+    }
+
+    // Schedules the end of the current mode. The PPU runs at the same speed in double speed
+    // mode, so it takes twice the CPU cycles. Counting from when the mode started rather
+    // than from now keeps the CPU's 4 cycle steps from adding up
+    fn schedule(&self, scheduler: &mut Scheduler, dots: u64, double_speed: bool) {
+        let cycles = if double_speed { dots * 2 } else { dots };
+        scheduler.cancel(EventKind::PpuMode);
+        scheduler.schedule_at(EventKind::PpuMode, self.mode_start + cycles);
+    }
+
+    fn update_stat_line(&mut self) {
+        let line = self.lcd_enabled() && (
+            (self.stat & 0x40 != 0 && self.ly == self.lyc)
+                || (self.stat & 0x20 != 0 && self.mode == PpuMode::OamScan)
+                || (self.stat & 0x10 != 0 && self.mode == PpuMode::VBlank)
+                || (self.stat & 0x08 != 0 && self.mode == PpuMode::HBlank)
+        );
+        if line && !self.stat_line {
+            self.stat_interrupt = true;
+        }
+        self.stat_line = line;
+    }
+
+    fn object_height(&self) -> i32 {
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    // OAM indexes of the objects on this line, at most 10, in the order they win priority
+    fn line_objects(&self, oam: &[u8]) -> Vec<usize> {
+        if self.lcdc & 0x02 == 0 {
+            return Vec::new();
+        }
+        let ly = self.ly as i32;
+        let height = self.object_height();
+        let mut objects: Vec<usize> = (0..40)
+            .filter(|index| {
+                let y = oam[index * 4] as i32 - 16;
+                ly >= y && ly < y + height
+            })
+            .take(MAX_OBJECTS_PER_LINE)
+            .collect();
+        // Outside of CGB mode the object further left wins, then the one first in OAM
+        if !self.cgb_mode {
+            objects.sort_by_key(|index| oam[index * 4 + 1]);
+        }
+        return objects
+    }
+
+    // Colour number and attributes of the BG or window map pixel at x, y of the 256x256 map
+    fn map_pixel(&self, vram: &VideoRam, map_base: u16, x: u8, y: u8) -> (u8, BgAttributes) {
+        let map_address = map_base + (y as u16 / 8) * 32 + x as u16 / 8;
+        let tile = vram.read_bank(0, map_address);
+        let attributes = if self.cgb_mode {
+            BgAttributes::from(vram.read_bank(1, map_address))
+        } else {
+            BgAttributes::default()
+        };
+        let row = if attributes.y_flip { 7 - y % 8 } else { y % 8 };
+        let column = if attributes.x_flip { 7 - x % 8 } else { x % 8 };
+        let tile_address = if self.lcdc & 0x10 != 0 {
+            0x8000 + tile as u16 * 16
+        } else {
+            (0x9000i32 + (tile as i8) as i32 * 16) as u16
+        };
+        let color = tile_color(vram, attributes.bank as usize, tile_address + row as u16 * 2, 7 - column);
+        return (color, attributes)
+    }
+
+    fn render_line(&mut self, vram: &VideoRam, oam: &[u8], palettes: &CgbPalettes) {
+        let ly = self.ly;
+        let mut bg_colors = [0u8; SCREEN_WIDTH];
+        let mut bg_attributes = [BgAttributes::default(); SCREEN_WIDTH];
+        // LCDC bit 0 turns the BG and window off on a DMG, on a CGB it is the priority bit
+        let bg_enabled = self.cgb_mode || self.lcdc & 0x01 != 0;
+        if bg_enabled {
+            let map_base = if self.lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+            let y = ly.wrapping_add(self.scy);
+            for (x, (color, attributes)) in bg_colors.iter_mut().zip(bg_attributes.iter_mut()).enumerate() {
+                (*color, *attributes) = self.map_pixel(vram, map_base, (x as u8).wrapping_add(self.scx), y);
+            }
+            let window_x = self.wx as i32 - 7;
+            if self.lcdc & 0x20 != 0 && ly >= self.wy && window_x < SCREEN_WIDTH as i32 {
+                let map_base = if self.lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+                for x in window_x.max(0)..SCREEN_WIDTH as i32 {
+                    let pixel = self.map_pixel(vram, map_base, (x - window_x) as u8, self.window_line);
+                    (bg_colors[x as usize], bg_attributes[x as usize]) = pixel;
+                }
+                self.window_line += 1;
+            }
+        }
+
+        // The first opaque object pixel in priority order is the one that gets drawn
+        let mut objects: [Option<(u8, SpriteAttributes)>; SCREEN_WIDTH] = [None; SCREEN_WIDTH];
+        let height = self.object_height();
+        for index in self.line_objects(oam) {
+            let entry = &oam[index * 4..index * 4 + 4];
+            let attributes = SpriteAttributes::from(entry[3]);
+            let object_x = entry[1] as i32 - 8;
+            let mut row = ly as i32 - (entry[0] as i32 - 16);
+            if attributes.y_flip {
+                row = height - 1 - row;
+            }
+            let tile = if height == 16 { entry[2] & 0xFE } else { entry[2] };
+            let bank = if self.cgb_mode { attributes.bank as usize } else { 0 };
+            let address = 0x8000 + tile as u16 * 16 + row as u16 * 2;
+            for pixel in 0..8 {
+                let x = object_x + pixel;
+                if !(0..SCREEN_WIDTH as i32).contains(&x) || objects[x as usize].is_some() {
+                    continue;
+                }
+                let bit = if attributes.x_flip { pixel } else { 7 - pixel } as u8;
+                let color = tile_color(vram, bank, address, bit);
+                if color != 0 {
+                    objects[x as usize] = Some((color, attributes));
+                }
+            }
+        }
+
+        let line_start = ly as usize * SCREEN_WIDTH;
+        for x in 0..SCREEN_WIDTH {
+            let bg_color = bg_colors[x];
+            let object = objects[x].filter(|(_, attributes)| {
+                if self.cgb_mode {
+                    cgb_object_has_priority(self.lcdc & 0x01 != 0, bg_color, bg_attributes[x], *attributes)
+                } else {
+                    !(attributes.behind_bg && bg_color != 0)
+                }
+            });
+            let (color, rgb) = match object {
+                Some((color, attributes)) if self.cgb_mode => (color, palettes.object.color(attributes.cgb_palette, color)),
+                Some((color, attributes)) => {
+                    let palette = if attributes.dmg_palette == 0 { self.obp0 } else { self.obp1 };
+                    let shade = (palette >> (color * 2)) & 0b11;
+                    (shade, self.dmg_color(&palettes.object, attributes.dmg_palette, shade))
+                }
+                None if self.cgb_mode => (bg_color, palettes.background.color(bg_attributes[x].palette, bg_color)),
+                None => {
+                    let shade = if bg_enabled { (self.bgp >> (bg_color * 2)) & 0b11 } else { 0 };
+                    (shade, self.dmg_color(&palettes.background, 0, shade))
+                }
+            };
+            self.drawing_screen[line_start + x] = color;
+            self.drawing_frame[line_start + x] = rgb;
+        }
+    }
+
+    // A CGB running a DMG game looks the shade up in the palettes its boot ROM picked
+    fn dmg_color(&self, palette_ram: &PaletteRam, palette: u8, shade: u8) -> u16 {
+        if self.cgb_hardware {
+            palette_ram.color(palette, shade)
+        } else {
+            DMG_SHADES[shade as usize]
+        }
+    }
+}
+
+// Colour number of one pixel of a tile row, bit 7 being the leftmost pixel
+fn tile_color(vram: &VideoRam, bank: usize, row_address: u16, bit: u8) -> u8 {
+    let low = vram.read_bank(bank, row_address);
+    let high = vram.read_bank(bank, row_address + 1);
+    ((high >> bit) & 0b1) << 1 | ((low >> bit) & 0b1)
+}
+
+impl SaveState for Ppu {
+    fn save_state(&self, writer: &mut StateWriter) {
+        let registers = [self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0, self.obp1, self.wy, self.wx];
+        writer.write_bytes(&registers);
+        writer.write_u8(self.mode as u8);
+        writer.write_u64(self.mode_start);
+        writer.write_u64(self.drawing_dots);
+        writer.write_u8(self.window_line);
+        for flag in [self.stat_line, self.cgb_mode, self.cgb_hardware, self.vblank_interrupt, self.stat_interrupt, self.frame_ready] {
+            writer.write_bool(flag);
+        }
+        writer.write_bytes(&self.screen);
+        writer.write_u16s(&self.frame);
+        writer.write_bytes(&self.drawing_screen);
+        writer.write_u16s(&self.drawing_frame);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        let mut registers = [0; 11];
+        reader.read_bytes_into(&mut registers)?;
+        [self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0, self.obp1, self.wy, self.wx] = registers;
+        self.mode = match reader.read_u8()? {
+            0 => PpuMode::HBlank,
+            1 => PpuMode::VBlank,
+            2 => PpuMode::OamScan,
+            3 => PpuMode::Drawing,
+            _ => return Err(SaveStateError::Invalid("PPU mode")),
+        };
+        if self.ly >= LINES_PER_FRAME {
+            return Err(SaveStateError::Invalid("LY"));
+        }
+        self.mode_start = reader.read_u64()?;
+        self.drawing_dots = reader.read_u64()?;
+        self.window_line = reader.read_u8()?;
+        self.stat_line = reader.read_bool()?;
+        self.cgb_mode = reader.read_bool()?;
+        self.cgb_hardware = reader.read_bool()?;
+        self.vblank_interrupt = reader.read_bool()?;
+        self.stat_interrupt = reader.read_bool()?;
+        self.frame_ready = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.screen)?;
+        reader.read_u16s_into(&mut self.frame)?;
+        reader.read_bytes_into(&mut self.drawing_screen)?;
+        reader.read_u16s_into(&mut self.drawing_frame)?;
+        return Ok(())
+    }
+}
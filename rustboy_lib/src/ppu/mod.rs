@@ -0,0 +1,8 @@
+pub mod attributes;
+pub mod color;
+pub mod compat;
+pub mod lcd;
+pub mod palette;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
@@ -0,0 +1,128 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const BCPS_ADDRESS: u16 = 0xFF68; // Background palette index
+pub const BCPD_ADDRESS: u16 = 0xFF69; // Background palette data
+pub const OCPS_ADDRESS: u16 = 0xFF6A; // Object palette index
+pub const OCPD_ADDRESS: u16 = 0xFF6B; // Object palette data
+
+pub const PALETTE_COUNT: usize = 8;
+pub const COLORS_PER_PALETTE: usize = 4;
+const PALETTE_RAM_SIZE: usize = PALETTE_COUNT * COLORS_PER_PALETTE * 2;
+
+const AUTO_INCREMENT_BIT: u8 = 7;
+
+// One bank of CGB palette memory: 8 palettes of 4 colours, each colour a little endian
+// RGB555 value. The CPU reaches it a byte at a time through an index register
+// (BCPS/OCPS) and a data register (BCPD/OCPD)
+#[derive(Clone, Debug)]
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+    index: u8,
+    auto_increment: bool,
+}
+impl PaletteRam {
+    pub fn new() -> PaletteRam {
+        return PaletteRam {
+            data: [0; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    // Bit 6 of the index register is unused and reads back as 1
+    pub fn read_index(&self) -> u8 {
+        0x40 | (self.auto_increment as u8) << AUTO_INCREMENT_BIT | self.index
+    }
+
+    pub fn write_index(&mut self, value: u8) {
+        self.auto_increment = (value >> AUTO_INCREMENT_BIT) & 0b1 == 0b1;
+        self.index = value & 0x3F;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    // Only writes move the index on, reads leave it where it is
+    pub fn write_data(&mut self, value: u8) {
+        self.data[self.index as usize] = value;
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    // RGB555 colour as the PPU sees it
+    pub fn color(&self, palette: u8, color: u8) -> u16 {
+        let offset = ((palette as usize & 0b111) * COLORS_PER_PALETTE + (color as usize & 0b11)) * 2;
+        (self.data[offset + 1] as u16) << 8 | self.data[offset] as u16
+    }
+
+    pub fn set_color(&mut self, palette: u8, color: u8, value: u16) {
+        let offset = ((palette as usize & 0b111) * COLORS_PER_PALETTE + (color as usize & 0b11)) * 2;
+        self.data[offset] = (value & 0xFF) as u8;
+        self.data[offset + 1] = (value >> 8) as u8;
+    }
+}
+
+// Background and object palette memory together, as mapped at 0xFF68-0xFF6B
+#[derive(Clone, Debug)]
+pub struct CgbPalettes {
+    pub background: PaletteRam,
+    pub object: PaletteRam,
+}
+impl CgbPalettes {
+    pub fn new() -> CgbPalettes {
+        return CgbPalettes {
+            background: PaletteRam::new(),
+            object: PaletteRam::new(),
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            BCPS_ADDRESS => self.background.read_index(),
+            BCPD_ADDRESS => self.background.read_data(),
+            OCPS_ADDRESS => self.object.read_index(),
+            OCPD_ADDRESS => self.object.read_data(),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            BCPS_ADDRESS => self.background.write_index(value),
+            BCPD_ADDRESS => self.background.write_data(value),
+            OCPS_ADDRESS => self.object.write_index(value),
+            OCPD_ADDRESS => self.object.write_data(value),
+            _ => {}
+        }
+    }
+}
+
+impl SaveState for PaletteRam {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.data);
+        writer.write_u8(self.index);
+        writer.write_bool(self.auto_increment);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.data)?;
+        self.index = reader.read_u8()? & 0x3F;
+        self.auto_increment = reader.read_bool()?;
+        return Ok(())
+    }
+}
+
+impl SaveState for CgbPalettes {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.background.save_state(writer);
+        self.object.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.background.load_state(reader)?;
+        self.object.load_state(reader)?;
+        return Ok(())
+    }
+}
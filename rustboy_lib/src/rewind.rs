@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+// How much history the rewind buffer keeps. Taking a snapshot every few frames instead of
+// every one keeps the cost down, at the price of rewinding in steps of that many frames
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RewindOptions {
+    pub interval: u32, // Frames between snapshots, 0 turns rewinding off
+    pub frames: u32, // How far back it goes
+    pub max_bytes: usize, // Older snapshots are dropped once the deltas take up more than this
+}
+impl RewindOptions {
+    // A minute at 60 frames a second, a snapshot every 4 frames
+    pub fn new() -> RewindOptions {
+        return RewindOptions {
+            interval: 4,
+            frames: 3600,
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+
+    pub fn disabled() -> RewindOptions {
+        return RewindOptions { interval: 0, ..RewindOptions::new() }
+    }
+}
+
+// A snapshot older than the newest one, stored as the difference to the snapshot after it
+#[derive(Clone, Debug)]
+struct Delta {
+    frame: u64,
+    data: Vec<u8>,
+}
+
+// A ring of save states. Only the newest is kept whole, every older one is XORed against
+// the one after it and the zero runs packed, which leaves a few KB for a frame where not
+// much happened. Going back a step undoes one delta, dropping the oldest just forgets it
+#[derive(Clone, Debug)]
+pub struct RewindBuffer {
+    options: RewindOptions,
+    newest: Option<(u64, Vec<u8>)>,
+    deltas: VecDeque<Delta>, // Oldest first
+    delta_bytes: usize,
+}
+impl RewindBuffer {
+    pub fn new(options: RewindOptions) -> RewindBuffer {
+        return RewindBuffer {
+            options,
+            newest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    pub fn options(&self) -> RewindOptions {
+        self.options
+    }
+
+    // Whether a snapshot should be taken at the end of this frame
+    pub fn is_due(&self, frame: u64) -> bool {
+        self.options.interval != 0 && frame.is_multiple_of(self.options.interval as u64)
+    }
+
+    // Number of snapshots held, including the newest
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    // Bytes used by the stored snapshots
+    pub fn size(&self) -> usize {
+        self.delta_bytes + self.newest.as_ref().map_or(0, |(_, state)| state.len())
+    }
+
+    // The frame the oldest snapshot was taken at
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.deltas.front().map(|delta| delta.frame).or(self.newest.as_ref().map(|(frame, _)| *frame))
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((previous_frame, previous)) = self.newest.take() {
+            let data = encode_delta(&previous, &state);
+            self.delta_bytes += data.len();
+            self.deltas.push_back(Delta { frame: previous_frame, data });
+        }
+        self.newest = Some((frame, state));
+        let capacity = (self.options.frames / self.options.interval.max(1)) as usize;
+        while !self.deltas.is_empty() && (self.deltas.len() >= capacity.max(1) || self.delta_bytes > self.options.max_bytes) {
+            let oldest = self.deltas.pop_front().unwrap();
+            self.delta_bytes -= oldest.data.len();
+        }
+    }
+
+    // Goes back to the newest snapshot taken at or before frame, or the oldest one if the
+    // buffer doesn't reach back that far. Everything after it is thrown away, and it stays
+    // in the buffer so rewinding can carry on from there. Returns the snapshot's frame and
+    // state, or None if there is nothing to go back to
+    pub fn rewind_to(&mut self, frame: u64) -> Option<(u64, &[u8])> {
+        let (mut newest_frame, mut state) = self.newest.take()?;
+        while newest_frame > frame {
+            let Some(delta) = self.deltas.pop_back() else {
+                break;
+            };
+            self.delta_bytes -= delta.data.len();
+            state = decode_delta(&state, &delta.data);
+            newest_frame = delta.frame;
+        }
+        let (frame, state) = self.newest.insert((newest_frame, state));
+        Some((*frame, state.as_slice()))
+    }
+}
+
+// The older state's length, then the XOR of the two states as pairs of zero run length
+// and literal bytes, both lengths as LEB128. The shorter state counts as 0 padded
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let length = older.len().max(newer.len());
+    let xor = |index: usize| older.get(index).copied().unwrap_or(0) ^ newer.get(index).copied().unwrap_or(0);
+    let mut data = Vec::new();
+    write_varint(&mut data, older.len());
+    let mut index = 0;
+    while index < length {
+        let start = index;
+        while index < length && xor(index) == 0 {
+            index += 1;
+        }
+        write_varint(&mut data, index - start);
+        let literal_start = index;
+        // A single zero byte between differences costs less as a literal
+        while index < length && (xor(index) != 0 || (index + 1 < length && xor(index + 1) != 0)) {
+            index += 1;
+        }
+        write_varint(&mut data, index - literal_start);
+        data.extend((literal_start..index).map(xor));
+    }
+    return data
+}
+
+fn decode_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let older_length = read_varint(delta, &mut position);
+    let mut state = newer.to_vec();
+    state.resize(older_length.max(newer.len()), 0);
+    let mut index = 0;
+    while position < delta.len() {
+        index += read_varint(delta, &mut position);
+        let literals = read_varint(delta, &mut position);
+        for &byte in &delta[position..position + literals] {
+            state[index] ^= byte;
+            index += 1;
+        }
+        position += literals;
+    }
+    state.truncate(older_length);
+    return state
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,237 @@
+use crate::cpu::CPU;
+use crate::memory::bus::MemoryBus;
+use crate::serial::link::Disconnected;
+
+// A save state is a small header followed by every component's state in a fixed order:
+//
+//   "RBST"  magic
+//   u32     format version, bumped whenever the layout of any component changes
+//   u32     CRC-32 of the ROM the state was taken with
+//   u8      Model
+//   ...     CPU, then the bus and everything on it
+//
+// All numbers are little endian. Host side things like the serial link, the sample buffer
+// and the boot ROM file picked on the command line are not part of it
+pub const STATE_MAGIC: [u8; 4] = *b"RBST";
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    RomMismatch { expected: u32, found: u32 }, // CRC-32 of the state's ROM and of the loaded one
+    ModelMismatch,
+    Truncated,
+    Invalid(&'static str), // A value that can't have come from a state we wrote
+}
+impl std::fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "save state version {} is not supported, expected {}", version, STATE_VERSION),
+            SaveStateError::RomMismatch { expected, found } => write!(f, "save state is for a ROM with CRC-32 {:08X}, this one is {:08X}", expected, found),
+            SaveStateError::ModelMismatch => write!(f, "save state is for a different Game Boy model"),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+impl std::error::Error for SaveStateError {}
+
+// Implemented by every component that holds emulated state. load_state reads back exactly
+// what save_state wrote, in the same order
+pub trait SaveState {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError>;
+}
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        return StateWriter { data: Vec::new() }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Length prefixed
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn write_u16s(&mut self, values: &[u16]) {
+        self.write_u32(values.len() as u32);
+        for &value in values {
+            self.write_u16(value);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        return StateReader { data, position: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self.position.checked_add(length).ok_or(SaveStateError::Truncated)?;
+        let bytes = self.data.get(self.position..end).ok_or(SaveStateError::Truncated)?;
+        self.position = end;
+        return Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("boolean")),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, SaveStateError> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, SaveStateError> {
+        let length = self.read_u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    // For fixed size memories, which have to come back at exactly the size they are
+    pub fn read_bytes_into(&mut self, bytes: &mut [u8]) -> Result<(), SaveStateError> {
+        let length = self.read_u32()? as usize;
+        if length != bytes.len() {
+            return Err(SaveStateError::Invalid("memory size"));
+        }
+        bytes.copy_from_slice(self.take(length)?);
+        return Ok(())
+    }
+
+    pub fn read_u16s_into(&mut self, values: &mut [u16]) -> Result<(), SaveStateError> {
+        let length = self.read_u32()? as usize;
+        if length != values.len() {
+            return Err(SaveStateError::Invalid("memory size"));
+        }
+        for value in values.iter_mut() {
+            *value = self.read_u16()?;
+        }
+        return Ok(())
+    }
+}
+
+// CRC-32 as used by zip and PNG, to tie states to the ROM they came from
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    return !crc
+}
+
+// Snapshots the whole machine
+pub fn save(cpu: &CPU) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    for byte in STATE_MAGIC {
+        writer.write_u8(byte);
+    }
+    writer.write_u32(STATE_VERSION);
+    writer.write_u32(crc32(cpu.bus.rom()));
+    writer.write_u8(cpu.model as u8);
+    cpu.save_state(&mut writer);
+    return writer.finish()
+}
+
+// Restores a snapshot taken by save. The state has to be for the same ROM and model, and
+// the machine is left untouched if anything about it is wrong
+pub fn load(cpu: &mut CPU, data: &[u8]) -> Result<(), SaveStateError> {
+    let mut reader = StateReader::new(data);
+    if reader.take(STATE_MAGIC.len()).map_err(|_| SaveStateError::BadMagic)? != STATE_MAGIC {
+        return Err(SaveStateError::BadMagic);
+    }
+    let version = reader.read_u32()?;
+    if version != STATE_VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    let expected = reader.read_u32()?;
+    let found = crc32(cpu.bus.rom());
+    if expected != found {
+        return Err(SaveStateError::RomMismatch { expected, found });
+    }
+    if reader.read_u8()? != cpu.model as u8 {
+        return Err(SaveStateError::ModelMismatch);
+    }
+    // Load into a copy so a bad state can't leave the machine half restored
+    let mut restored = CPU::new();
+    restored.model = cpu.model;
+    restored.bus = MemoryBus::with_cartridge(cpu.bus.cartridge.clone(), cpu.model, cpu.cgb_mode);
+    restored.load_state(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(SaveStateError::Invalid("length"));
+    }
+    // The link cable stays plugged in, the debugger's watchpoints stay set and a trace or
+    // bus log carries on
+    let link = cpu.bus.serial.set_link(Box::new(Disconnected));
+    restored.bus.serial.set_link(link);
+    restored.watch = std::mem::take(&mut cpu.watch);
+    restored.trace = cpu.trace.take();
+    restored.bus_log = cpu.bus_log.take();
+    *cpu = restored;
+    return Ok(())
+}
@@ -0,0 +1,141 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+// Things that happen at a known point in the future. Components schedule these instead of
+// being ticked every cycle, and catch up on whatever else happened in between when the
+// event fires or when the CPU touches their registers
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EventKind {
+    TimerOverflow,     // TIMA wraps around to 0
+    TimerReload,       // TMA is loaded into TIMA and the interrupt raised, 4 T-cycles after overflow
+    SerialBit,         // The serial port shifts the next bit
+    PpuMode,           // The PPU moves on to its next mode
+    ApuFrameSequencer, // Length, sweep and envelope clocks, 512 Hz
+    ApuSample,         // The APU outputs a sample at the host sample rate
+    RtcSecond,         // The MBC3 clock ticks
+}
+impl EventKind {
+    pub const ALL: [EventKind; 7] = [
+        EventKind::TimerOverflow,
+        EventKind::TimerReload,
+        EventKind::SerialBit,
+        EventKind::PpuMode,
+        EventKind::ApuFrameSequencer,
+        EventKind::ApuSample,
+        EventKind::RtcSecond,
+    ];
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Event {
+    pub time: u64,
+    pub kind: EventKind,
+}
+
+// A clock counting T-cycles at the CPU's speed, plus the queue of pending events ordered by
+// when they are due. Events due at the same time fire in EventKind order
+#[derive(Debug)]
+pub struct Scheduler {
+    now: u64,
+    events: BinaryHeap<Reverse<Event>>,
+}
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        return Scheduler {
+            now: 0,
+            events: BinaryHeap::new(),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self, cycles: u64) {
+        self.now += cycles;
+    }
+
+    // Schedules an event a number of T-cycles from now
+    pub fn schedule(&mut self, kind: EventKind, delay: u64) {
+        self.schedule_at(kind, self.now + delay);
+    }
+
+    pub fn schedule_at(&mut self, kind: EventKind, time: u64) {
+        self.events.push(Reverse(Event { time, kind }));
+    }
+
+    // Drops every pending event of a kind, for when a register write changes the outcome
+    pub fn cancel(&mut self, kind: EventKind) {
+        self.events.retain(|Reverse(event)| event.kind != kind);
+    }
+
+    // Schedules an event, replacing any pending one of the same kind
+    pub fn reschedule(&mut self, kind: EventKind, delay: u64) {
+        self.cancel(kind);
+        self.schedule(kind, delay);
+    }
+
+    // Where a time in the clock from before a speed switch ends up after it, staying just as
+    // far from now in real time. Double speed fits twice the cycles in the same time
+    pub fn rescale_time(&self, time: u64, double_speed: bool) -> u64 {
+        let scale = |cycles: u64| if double_speed { cycles * 2 } else { cycles / 2 };
+        if time >= self.now {
+            self.now + scale(time - self.now)
+        } else {
+            self.now.saturating_sub(scale(self.now - time))
+        }
+    }
+
+    // Moves the pending events of a kind for a speed switch, see rescale_time. Only things
+    // clocked in real time need this, the ones clocked by the CPU speed up along with it
+    pub fn rescale(&mut self, kind: EventKind, double_speed: bool) {
+        let events = std::mem::take(&mut self.events);
+        for Reverse(event) in events {
+            let time = if event.kind == kind { self.rescale_time(event.time, double_speed) } else { event.time };
+            self.schedule_at(event.kind, time);
+        }
+    }
+
+    pub fn is_scheduled(&self, kind: EventKind) -> bool {
+        self.events.iter().any(|Reverse(event)| event.kind == kind)
+    }
+
+    pub fn next_event_time(&self) -> Option<u64> {
+        self.events.peek().map(|Reverse(event)| event.time)
+    }
+
+    // Takes the next event that is due by now, in the order they were due
+    pub fn pop_due(&mut self) -> Option<Event> {
+        match self.events.peek() {
+            Some(Reverse(event)) if event.time <= self.now => self.events.pop().map(|Reverse(event)| event),
+            _ => None,
+        }
+    }
+}
+
+impl SaveState for Scheduler {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.now);
+        // Sorted, so equal states always serialise to the same bytes
+        let mut events: Vec<Event> = self.events.iter().map(|Reverse(event)| *event).collect();
+        events.sort();
+        writer.write_u32(events.len() as u32);
+        for event in events {
+            writer.write_u64(event.time);
+            writer.write_u8(event.kind as u8);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.now = reader.read_u64()?;
+        self.events.clear();
+        for _ in 0..reader.read_u32()? {
+            let time = reader.read_u64()?;
+            let kind = *EventKind::ALL.get(reader.read_u8()? as usize).ok_or(SaveStateError::Invalid("event"))?;
+            self.schedule_at(kind, time);
+        }
+        return Ok(())
+    }
+}
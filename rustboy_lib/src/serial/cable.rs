@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+
+use super::link::SerialLink;
+use crate::gameboy::GameBoy;
+
+#[derive(Debug, Default)]
+struct CableState {
+    // Byte each end has ready while it waits on the other end's clock
+    listening: [Option<u8>; 2],
+    // Byte clocked over from the other end, not yet picked up by poll_external
+    delivered: [Option<u8>; 2],
+}
+
+// An in-memory link cable between two emulators in the same process. Nothing ever blocks:
+// a transfer only reaches the other end if it is listening on an external clock at that
+// moment, otherwise the sender reads 0xFF. Step both ends with Lockstep so the outcome
+// does not depend on thread timing
+#[derive(Clone, Debug)]
+pub struct CableEnd {
+    state: Arc<Mutex<CableState>>,
+    side: usize,
+}
+impl CableEnd {
+    // Both ends of a fresh cable
+    pub fn pair() -> (CableEnd, CableEnd) {
+        let state = Arc::new(Mutex::new(CableState::default()));
+        return (
+            CableEnd { state: state.clone(), side: 0 },
+            CableEnd { state, side: 1 },
+        )
+    }
+}
+impl SerialLink for CableEnd {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        let mut state = self.state.lock().unwrap();
+        let other = 1 - self.side;
+        match state.listening[other].take() {
+            Some(incoming) => {
+                state.delivered[other] = Some(outgoing);
+                incoming
+            }
+            None => 0xFF,
+        }
+    }
+    fn poll_external(&mut self, outgoing: u8) -> Option<u8> {
+        let mut state = self.state.lock().unwrap();
+        match state.delivered[self.side].take() {
+            Some(incoming) => {
+                state.listening[self.side] = None;
+                Some(incoming)
+            }
+            None => {
+                state.listening[self.side] = Some(outgoing);
+                None
+            }
+        }
+    }
+}
+
+// Runs two linked machines side by side, handing each the same small slice of T-cycles in
+// turn so neither gets further than one slice ahead of the other
+#[derive(Debug)]
+pub struct Lockstep<T> {
+    pub first: T,
+    pub second: T,
+    slice: u32,
+    elapsed: u64,
+}
+impl<T> Lockstep<T> {
+    pub fn new(first: T, second: T, slice: u32) -> Lockstep<T> {
+        return Lockstep {
+            first,
+            second,
+            slice: slice.max(1),
+            elapsed: 0,
+        }
+    }
+
+    // T-cycles both machines have run for
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    pub fn run_for<F: FnMut(&mut T, u32)>(&mut self, cycles: u32, mut step: F) {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let slice = remaining.min(self.slice);
+            step(&mut self.first, slice);
+            step(&mut self.second, slice);
+            self.elapsed += slice as u64;
+            remaining -= slice;
+        }
+    }
+
+    // Keep stepping until condition holds for the pair or max_cycles have passed.
+    // Returns whether the condition was met
+    pub fn run_until<F, C>(&mut self, max_cycles: u32, mut step: F, mut condition: C) -> bool
+    where
+        F: FnMut(&mut T, u32),
+        C: FnMut(&T, &T) -> bool,
+    {
+        let mut remaining = max_cycles;
+        while remaining > 0 {
+            if condition(&self.first, &self.second) {
+                return true;
+            }
+            let slice = remaining.min(self.slice);
+            self.run_for(slice, &mut step);
+            remaining -= slice;
+        }
+        return condition(&self.first, &self.second)
+    }
+}
+
+// Two whole Game Boys joined by a cable, taking turns to run a slice of M-cycles each like
+// Lockstep. They can only stop between instructions, so every turn runs a machine until it
+// has caught up with the shared clock, leaving it less than an instruction ahead
+#[derive(Debug)]
+pub struct LinkedGameBoys {
+    pub first: GameBoy,
+    pub second: GameBoy,
+    slice: u64,
+    elapsed: u64,
+    start: [u64; 2], // Each machine's M-cycle count when they were linked
+}
+impl LinkedGameBoys {
+    // Plugs the two ends of a new cable into the Game Boys' serial ports
+    pub fn new(mut first: GameBoy, mut second: GameBoy, slice: u32) -> LinkedGameBoys {
+        let (first_end, second_end) = CableEnd::pair();
+        first.cpu.bus.serial.set_link(Box::new(first_end));
+        second.cpu.bus.serial.set_link(Box::new(second_end));
+        let start = [first.cpu.cycles, second.cpu.cycles];
+        return LinkedGameBoys { first, second, slice: slice.max(1) as u64, elapsed: 0, start }
+    }
+
+    // M-cycles both machines have run for, at least
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    pub fn run_for(&mut self, cycles: u64) {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let slice = remaining.min(self.slice);
+            self.elapsed += slice;
+            for (gameboy, start) in [(&mut self.first, self.start[0]), (&mut self.second, self.start[1])] {
+                while gameboy.cpu.cycles - start < self.elapsed {
+                    gameboy.step();
+                }
+            }
+            remaining -= slice;
+        }
+    }
+
+    // Keep running until condition holds for the pair or max_cycles have passed. Returns
+    // whether the condition was met
+    pub fn run_until<C: FnMut(&GameBoy, &GameBoy) -> bool>(&mut self, max_cycles: u64, mut condition: C) -> bool {
+        let mut remaining = max_cycles;
+        while remaining > 0 {
+            if condition(&self.first, &self.second) {
+                return true;
+            }
+            let slice = remaining.min(self.slice);
+            self.run_for(slice);
+            remaining -= slice;
+        }
+        return condition(&self.first, &self.second)
+    }
+}
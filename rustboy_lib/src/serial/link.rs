@@ -0,0 +1,70 @@
+use std::sync::{Arc, Mutex};
+
+// The other end of the link cable. The serial controller only ever moves whole bytes
+// across the cable, the bit-by-bit shifting is timed on our side
+pub trait SerialLink: Send + std::fmt::Debug {
+    // This Game Boy drives the clock (SC bit 0 set): send outgoing and return the byte
+    // the partner shifted back at the same time
+    fn exchange(&mut self, outgoing: u8) -> u8;
+    // This Game Boy waits on the partner's clock (SC bit 0 clear). Returns the partner's byte
+    // once they start a transfer, in which case outgoing is what they receive in return
+    fn poll_external(&mut self, outgoing: u8) -> Option<u8>;
+}
+
+// Nothing plugged in. The input line floats high, so every transfer reads 0xFF,
+// and an externally clocked transfer never finishes because no clock ever arrives
+#[derive(Debug, Default)]
+pub struct Disconnected;
+impl SerialLink for Disconnected {
+    fn exchange(&mut self, _outgoing: u8) -> u8 {
+        0xFF
+    }
+    fn poll_external(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+// Records every byte sent over the cable. Test ROMs such as Blargg's print their results
+// this way. Clones share the same buffer so one can be handed to the serial controller
+// while the other is kept around to read the output
+#[derive(Clone, Debug, Default)]
+pub struct CaptureLink {
+    output: Arc<Mutex<Vec<u8>>>,
+}
+impl CaptureLink {
+    pub fn new() -> CaptureLink {
+        return CaptureLink {
+            output: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+    pub fn output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.output.lock().unwrap()).into_owned()
+    }
+    pub fn clear(&self) {
+        self.output.lock().unwrap().clear();
+    }
+}
+impl SerialLink for CaptureLink {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.output.lock().unwrap().push(outgoing);
+        0xFF
+    }
+    fn poll_external(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+// SOUT wired straight back into SIN: whatever gets sent is received
+#[derive(Debug, Default)]
+pub struct Loopback;
+impl SerialLink for Loopback {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        outgoing
+    }
+    fn poll_external(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
@@ -0,0 +1,150 @@
+pub mod cable;
+pub mod link;
+pub mod tcp;
+
+use link::{Disconnected, SerialLink};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const SB_ADDRESS: u16 = 0xFF01; // Serial transfer data
+pub const SC_ADDRESS: u16 = 0xFF02; // Serial transfer control
+
+// The internal clock runs at 8192 Hz, so one bit is shifted every 512 T-cycles
+pub const CYCLES_PER_BIT: u32 = 512;
+
+const TRANSFER_START_BIT: u8 = 7;
+const CLOCK_SELECT_BIT: u8 = 0;
+
+#[derive(Debug)]
+pub struct Serial {
+    data: u8,               // SB, shifted out MSB first while bits come in from the partner
+    transfer_enabled: bool, // SC bit 7, cleared by hardware once all 8 bits are through
+    internal_clock: bool,   // SC bit 0, true when this Game Boy drives the clock
+    incoming: Option<u8>,   // The partner's byte for the transfer in progress
+    bits_shifted: u8,
+    cycle_counter: u32,
+    interrupt_requested: bool,
+    link: Box<dyn SerialLink>,
+}
+impl Serial {
+    pub fn new() -> Serial {
+        return Serial::with_link(Box::new(Disconnected))
+    }
+    pub fn with_link(link: Box<dyn SerialLink>) -> Serial {
+        return Serial {
+            data: 0,
+            transfer_enabled: false,
+            internal_clock: false,
+            incoming: None,
+            bits_shifted: 0,
+            cycle_counter: 0,
+            interrupt_requested: false,
+            link,
+        }
+    }
+
+    // Swap what is plugged into the link port, handing back the previous partner
+    pub fn set_link(&mut self, link: Box<dyn SerialLink>) -> Box<dyn SerialLink> {
+        std::mem::replace(&mut self.link, link)
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SB_ADDRESS => self.data,
+            // Unused SC bits always read back as 1
+            SC_ADDRESS => {
+                0x7E | (self.transfer_enabled as u8) << TRANSFER_START_BIT
+                    | (self.internal_clock as u8) << CLOCK_SELECT_BIT
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            SB_ADDRESS => self.data = value,
+            SC_ADDRESS => {
+                self.transfer_enabled = (value >> TRANSFER_START_BIT) & 0b1 == 1;
+                self.internal_clock = (value >> CLOCK_SELECT_BIT) & 0b1 == 1;
+                self.incoming = None;
+                self.bits_shifted = 0;
+                self.cycle_counter = 0;
+                if self.transfer_enabled && self.internal_clock {
+                    self.incoming = Some(self.link.exchange(self.data));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Advance the serial controller by a number of T-cycles
+    pub fn step(&mut self, cycles: u32) {
+        if !self.transfer_enabled {
+            return;
+        }
+        if self.incoming.is_none() {
+            // Waiting on the partner's clock, nothing moves until they start sending
+            self.incoming = self.link.poll_external(self.data);
+            if self.incoming.is_none() {
+                return;
+            }
+        }
+        self.cycle_counter += cycles;
+        while self.cycle_counter >= CYCLES_PER_BIT && self.transfer_enabled {
+            self.cycle_counter -= CYCLES_PER_BIT;
+            self.shift_bit();
+        }
+    }
+
+    fn shift_bit(&mut self) {
+        let incoming = self.incoming.unwrap_or(0xFF);
+        let bit_in = (incoming >> (7 - self.bits_shifted)) & 0b1;
+        self.data = (self.data << 1) | bit_in;
+        self.bits_shifted += 1;
+        if self.bits_shifted == 8 {
+            self.transfer_enabled = false;
+            self.incoming = None;
+            self.bits_shifted = 0;
+            self.cycle_counter = 0;
+            self.interrupt_requested = true;
+        }
+    }
+
+    pub fn is_transferring(&self) -> bool {
+        self.transfer_enabled
+    }
+
+    // Whether the serial interrupt (IF bit 3) has been raised since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::replace(&mut self.interrupt_requested, false)
+    }
+}
+
+// Whatever is plugged into the link port stays plugged in
+impl SaveState for Serial {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.data);
+        writer.write_bool(self.transfer_enabled);
+        writer.write_bool(self.internal_clock);
+        writer.write_bool(self.incoming.is_some());
+        writer.write_u8(self.incoming.unwrap_or(0));
+        writer.write_u8(self.bits_shifted);
+        writer.write_u32(self.cycle_counter);
+        writer.write_bool(self.interrupt_requested);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.data = reader.read_u8()?;
+        self.transfer_enabled = reader.read_bool()?;
+        self.internal_clock = reader.read_bool()?;
+        let incoming = reader.read_bool()?;
+        let byte = reader.read_u8()?;
+        self.incoming = if incoming { Some(byte) } else { None };
+        self.bits_shifted = reader.read_u8()?;
+        if self.bits_shifted >= 8 {
+            return Err(SaveStateError::Invalid("serial bit count"));
+        }
+        self.cycle_counter = reader.read_u32()?;
+        self.interrupt_requested = reader.read_bool()?;
+        return Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::link::SerialLink;
+
+// Every message over the socket is two bytes: a kind followed by the data byte
+const MESSAGE_TRANSFER: u8 = 0x01; // Sent by the side driving the clock
+const MESSAGE_REPLY: u8 = 0x02;    // The clocked side's byte in return
+
+// How long the side driving the clock waits for the partner to answer before giving up
+// on the transfer and reading 0xFF, as if nothing was plugged in
+pub const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// A link cable to another emulator over TCP on localhost. Whoever drives the clock sends
+// its byte and blocks until the partner's serial controller answers with its own byte,
+// which keeps both emulators in step at every transfer boundary
+#[derive(Debug)]
+pub struct TcpLink {
+    stream: TcpStream,
+    received: Vec<u8>,
+    reply_timeout: Duration,
+    missed_replies: usize, // Replies that timed out and should be discarded when they do arrive
+    connected: bool,
+}
+impl TcpLink {
+    // Wait on 127.0.0.1:<port> for the other emulator to connect
+    pub fn host(port: u16) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let (stream, _) = listener.accept()?;
+        return TcpLink::from_stream(stream)
+    }
+    // Connect to an emulator hosting on 127.0.0.1:<port>
+    pub fn connect(port: u16) -> io::Result<TcpLink> {
+        return TcpLink::connect_to((Ipv4Addr::LOCALHOST, port))
+    }
+    // Connect to an emulator hosting somewhere else, like "192.168.1.2:5555"
+    pub fn connect_to<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+        let stream = TcpStream::connect(address)?;
+        return TcpLink::from_stream(stream)
+    }
+    pub fn from_stream(stream: TcpStream) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+        return Ok(TcpLink {
+            stream,
+            received: Vec::new(),
+            reply_timeout: DEFAULT_REPLY_TIMEOUT,
+            missed_replies: 0,
+            connected: true,
+        })
+    }
+
+    pub fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.reply_timeout = timeout;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn send(&mut self, kind: u8, value: u8) {
+        if self.stream.write_all(&[kind, value]).is_err() {
+            self.connected = false;
+        }
+    }
+
+    // Read one message, blocking for at most timeout, or not at all when timeout is None
+    fn receive(&mut self, timeout: Option<Duration>) -> Option<(u8, u8)> {
+        let nonblocking = timeout.is_none();
+        if self.stream.set_nonblocking(nonblocking).is_err()
+            || self.stream.set_read_timeout(timeout).is_err()
+        {
+            self.connected = false;
+            return None;
+        }
+        let mut buffer = [0u8; 2];
+        while self.received.len() < 2 {
+            match self.stream.read(&mut buffer[..2 - self.received.len()]) {
+                Ok(0) => {
+                    self.connected = false;
+                    return None;
+                }
+                Ok(count) => self.received.extend_from_slice(&buffer[..count]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    return None;
+                }
+                Err(_) => {
+                    self.connected = false;
+                    return None;
+                }
+            }
+        }
+        let message = (self.received[0], self.received[1]);
+        self.received.clear();
+        return Some(message)
+    }
+}
+impl SerialLink for TcpLink {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        if !self.connected {
+            return 0xFF;
+        }
+        self.send(MESSAGE_TRANSFER, outgoing);
+        while self.connected {
+            match self.receive(Some(self.reply_timeout)) {
+                Some((MESSAGE_REPLY, _)) if self.missed_replies > 0 => self.missed_replies -= 1,
+                Some((MESSAGE_REPLY, value)) => return value,
+                // Both sides started a transfer on their own clock at the same time. Each
+                // takes the other's byte as the answer, so neither needs to reply
+                Some((MESSAGE_TRANSFER, value)) => return value,
+                Some(_) => {}
+                None => {
+                    if self.connected {
+                        self.missed_replies += 1;
+                    }
+                    break;
+                }
+            }
+        }
+        return 0xFF
+    }
+
+    fn poll_external(&mut self, outgoing: u8) -> Option<u8> {
+        while self.connected {
+            match self.receive(None)? {
+                (MESSAGE_TRANSFER, value) => {
+                    self.send(MESSAGE_REPLY, outgoing);
+                    return Some(value);
+                }
+                (MESSAGE_REPLY, _) if self.missed_replies > 0 => self.missed_replies -= 1,
+                _ => {}
+            }
+        }
+        return None
+    }
+}
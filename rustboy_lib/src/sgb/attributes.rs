@@ -0,0 +1,155 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+// The SGB colours the game screen through a map of 20x18 cells, one per 8x8 pixel tile,
+// each picking one of the 4 active palettes
+pub const ATTRIBUTE_COLUMNS: usize = 20;
+pub const ATTRIBUTE_ROWS: usize = 18;
+pub const ATTRIBUTE_FILE_SIZE: usize = ATTRIBUTE_COLUMNS * ATTRIBUTE_ROWS / 4;
+pub const ATTRIBUTE_FILE_COUNT: usize = 45;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeMap {
+    cells: [u8; ATTRIBUTE_COLUMNS * ATTRIBUTE_ROWS],
+}
+impl AttributeMap {
+    pub fn new() -> AttributeMap {
+        return AttributeMap {
+            cells: [0; ATTRIBUTE_COLUMNS * ATTRIBUTE_ROWS],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.cells[y * ATTRIBUTE_COLUMNS + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, palette: u8) {
+        if x < ATTRIBUTE_COLUMNS && y < ATTRIBUTE_ROWS {
+            self.cells[y * ATTRIBUTE_COLUMNS + x] = palette & 0b11;
+        }
+    }
+
+    // ATTR_BLK: up to 18 rectangles, each colouring the cells inside it, on its edge and/or
+    // outside of it
+    pub fn apply_blocks(&mut self, data: &[u8]) {
+        let count = data.first().copied().unwrap_or(0).min(18) as usize;
+        for block in data[1..].chunks_exact(6).take(count) {
+            let control = block[0];
+            let change_inside = control & 0b001 != 0;
+            let mut change_line = control & 0b010 != 0;
+            let change_outside = control & 0b100 != 0;
+            let inside_palette = block[1] & 0b11;
+            let mut line_palette = (block[1] >> 2) & 0b11;
+            let outside_palette = (block[1] >> 4) & 0b11;
+            // With only the inside or only the outside selected, the edge goes along with it
+            if control == 0b001 {
+                change_line = true;
+                line_palette = inside_palette;
+            } else if control == 0b100 {
+                change_line = true;
+                line_palette = outside_palette;
+            }
+            let (x1, y1, x2, y2) = (block[2] as usize, block[3] as usize, block[4] as usize, block[5] as usize);
+            for y in 0..ATTRIBUTE_ROWS {
+                for x in 0..ATTRIBUTE_COLUMNS {
+                    let within = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let on_line = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    if on_line {
+                        if change_line {
+                            self.set(x, y, line_palette);
+                        }
+                    } else if within {
+                        if change_inside {
+                            self.set(x, y, inside_palette);
+                        }
+                    } else if change_outside {
+                        self.set(x, y, outside_palette);
+                    }
+                }
+            }
+        }
+    }
+
+    // ATTR_LIN: whole rows or columns. Bits 0-4 are the line number, bits 5-6 the palette
+    // and bit 7 picks a row (1) or a column (0)
+    pub fn apply_lines(&mut self, data: &[u8]) {
+        let count = data.first().copied().unwrap_or(0) as usize;
+        for &line in data[1..].iter().take(count) {
+            let number = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0b11;
+            if line & 0x80 != 0 {
+                for x in 0..ATTRIBUTE_COLUMNS {
+                    self.set(x, number, palette);
+                }
+            } else {
+                for y in 0..ATTRIBUTE_ROWS {
+                    self.set(number, y, palette);
+                }
+            }
+        }
+    }
+
+    // ATTR_DIV: split the screen in two at a row or column, with a third palette for the
+    // dividing line itself
+    pub fn apply_division(&mut self, data: &[u8]) {
+        let (control, position) = (data[0], data[1] as usize);
+        let after_palette = control & 0b11;
+        let before_palette = (control >> 2) & 0b11;
+        let line_palette = (control >> 4) & 0b11;
+        let horizontal = control & 0x40 != 0;
+        for y in 0..ATTRIBUTE_ROWS {
+            for x in 0..ATTRIBUTE_COLUMNS {
+                let coordinate = if horizontal { y } else { x };
+                let palette = match coordinate.cmp(&position) {
+                    std::cmp::Ordering::Less => before_palette,
+                    std::cmp::Ordering::Equal => line_palette,
+                    std::cmp::Ordering::Greater => after_palette,
+                };
+                self.set(x, y, palette);
+            }
+        }
+    }
+
+    // ATTR_CHR: individual cells from a starting point, packed 4 to a byte with the first
+    // cell in the top bits, running left to right (direction 0) or top to bottom (1)
+    pub fn apply_characters(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[0] as usize, data[1] as usize);
+        let count = ((data[3] as usize) << 8 | data[2] as usize).min(ATTRIBUTE_COLUMNS * ATTRIBUTE_ROWS);
+        let vertical = data[4] == 1;
+        for index in 0..count {
+            let Some(&byte) = data.get(5 + index / 4) else { break };
+            let palette = (byte >> (6 - (index % 4) * 2)) & 0b11;
+            self.set(x, y, palette);
+            if vertical {
+                y += 1;
+                if y == ATTRIBUTE_ROWS {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == ATTRIBUTE_COLUMNS {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    // An attribute file from ATTR_TRN, same packing as ATTR_CHR in reading order
+    pub fn load_file(&mut self, file: &[u8]) {
+        for (index, cell) in self.cells.iter_mut().enumerate() {
+            *cell = (file[index / 4] >> (6 - (index % 4) * 2)) & 0b11;
+        }
+    }
+}
+
+impl SaveState for AttributeMap {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.cells);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.cells)?;
+        return Ok(())
+    }
+}
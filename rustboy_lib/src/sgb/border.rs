@@ -0,0 +1,98 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+// The border is drawn by the SNES from 4 bits per pixel tiles, a 32x28 tile map and the
+// SGB palettes 4-7, all sent by the game through VRAM transfers
+pub const BORDER_TILE_COUNT: usize = 256;
+pub const BORDER_TILE_SIZE: usize = 32;
+pub const BORDER_MAP_WIDTH: usize = 32;
+pub const BORDER_MAP_HEIGHT: usize = 28;
+pub const BORDER_PALETTE_COUNT: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct Border {
+    pub tiles: Vec<u8>,
+    pub map: Vec<u16>,
+    pub palettes: [[u16; 16]; BORDER_PALETTE_COUNT],
+}
+impl Border {
+    pub fn new() -> Border {
+        return Border {
+            tiles: vec![0; BORDER_TILE_COUNT * BORDER_TILE_SIZE],
+            map: vec![0; BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT],
+            palettes: [[0; 16]; BORDER_PALETTE_COUNT],
+        }
+    }
+
+    // CHR_TRN: 128 tiles at a time, the upper half when upper is set
+    pub fn load_tiles(&mut self, data: &[u8], upper: bool) {
+        let half = BORDER_TILE_COUNT / 2 * BORDER_TILE_SIZE;
+        let start = if upper { half } else { 0 };
+        let length = half.min(data.len());
+        self.tiles[start..start + length].copy_from_slice(&data[..length]);
+    }
+
+    // PCT_TRN: the tile map followed by the palettes at offset 0x800
+    pub fn load_map(&mut self, data: &[u8]) {
+        for (index, entry) in self.map.iter_mut().enumerate() {
+            *entry = read_word(data, index * 2);
+        }
+        for (palette, colors) in self.palettes.iter_mut().enumerate() {
+            for (color, value) in colors.iter_mut().enumerate() {
+                *value = read_word(data, 0x800 + (palette * 16 + color) * 2);
+            }
+        }
+    }
+
+    // Colour index (0-15) of a pixel of a tile in SNES planar format: each row is stored
+    // as bitplanes 0 and 1 in the first 16 bytes of the tile and bitplanes 2 and 3 in the
+    // last 16
+    pub fn tile_pixel(&self, tile: usize, x: usize, y: usize) -> u8 {
+        let base = (tile % BORDER_TILE_COUNT) * BORDER_TILE_SIZE;
+        let shift = 7 - x;
+        let plane0 = (self.tiles[base + y * 2] >> shift) & 0b1;
+        let plane1 = (self.tiles[base + y * 2 + 1] >> shift) & 0b1;
+        let plane2 = (self.tiles[base + 16 + y * 2] >> shift) & 0b1;
+        let plane3 = (self.tiles[base + 16 + y * 2 + 1] >> shift) & 0b1;
+        plane0 | plane1 << 1 | plane2 << 2 | plane3 << 3
+    }
+
+    // The border colour at a screen position, or None where it is transparent
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = self.map[(y / 8) * BORDER_MAP_WIDTH + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        let palette = ((entry >> 10) & 0b111) as usize;
+        let tile_x = if entry & 0x4000 != 0 { 7 - x % 8 } else { x % 8 };
+        let tile_y = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        let color = self.tile_pixel(tile, tile_x, tile_y);
+        if color == 0 {
+            return None;
+        }
+        // Only palettes 4-7 belong to the border
+        Some(self.palettes[palette.saturating_sub(4) % BORDER_PALETTE_COUNT][color as usize])
+    }
+}
+
+pub fn read_word(data: &[u8], offset: usize) -> u16 {
+    let low = data.get(offset).copied().unwrap_or(0) as u16;
+    let high = data.get(offset + 1).copied().unwrap_or(0) as u16;
+    high << 8 | low
+}
+
+impl SaveState for Border {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.tiles);
+        writer.write_u16s(&self.map);
+        for palette in &self.palettes {
+            writer.write_u16s(palette);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.tiles)?;
+        reader.read_u16s_into(&mut self.map)?;
+        for palette in &mut self.palettes {
+            reader.read_u16s_into(palette)?;
+        }
+        return Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+pub mod attributes;
+pub mod border;
+pub mod packet;
+
+use attributes::{AttributeMap, ATTRIBUTE_COLUMNS, ATTRIBUTE_FILE_COUNT, ATTRIBUTE_FILE_SIZE};
+use border::{read_word, Border};
+use packet::{PacketReceiver, PACKET_SIZE};
+use crate::memory::vram::VideoRam;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+// Where the Game Boy screen sits inside of the border
+pub const GAME_SCREEN_X: usize = 48;
+pub const GAME_SCREEN_Y: usize = 40;
+
+pub const SYSTEM_PALETTE_COUNT: usize = 512;
+pub const VRAM_TRANSFER_SIZE: usize = 0x1000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SgbCommand {
+    Pal01,
+    Pal23,
+    Pal03,
+    Pal12,
+    AttrBlk,
+    AttrLin,
+    AttrDiv,
+    AttrChr,
+    PalSet,
+    PalTrn,
+    MltReq,
+    ChrTrn,
+    PctTrn,
+    AttrTrn,
+    AttrSet,
+    MaskEn,
+    Unsupported(u8), // Sound, SNES program upload and the rest we don't handle
+}
+impl std::convert::From<u8> for SgbCommand {
+    fn from(code: u8) -> SgbCommand {
+        match code {
+            0x00 => SgbCommand::Pal01,
+            0x01 => SgbCommand::Pal23,
+            0x02 => SgbCommand::Pal03,
+            0x03 => SgbCommand::Pal12,
+            0x04 => SgbCommand::AttrBlk,
+            0x05 => SgbCommand::AttrLin,
+            0x06 => SgbCommand::AttrDiv,
+            0x07 => SgbCommand::AttrChr,
+            0x0A => SgbCommand::PalSet,
+            0x0B => SgbCommand::PalTrn,
+            0x11 => SgbCommand::MltReq,
+            0x13 => SgbCommand::ChrTrn,
+            0x14 => SgbCommand::PctTrn,
+            0x15 => SgbCommand::AttrTrn,
+            0x16 => SgbCommand::AttrSet,
+            0x17 => SgbCommand::MaskEn,
+            code => SgbCommand::Unsupported(code),
+        }
+    }
+}
+
+// Data the game has asked to send over VRAM. The SGB grabs it from the next frame the
+// Game Boy displays, see vram_transfer_data
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VramTransfer {
+    SystemPalettes,
+    BorderTiles { upper: bool },
+    BorderMap,
+    AttributeFiles,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ScreenMask {
+    #[default]
+    None,
+    Freeze, // Keep showing the last frame
+    Black,
+    Color0, // Fill with colour 0 of palette 0
+}
+
+// The Super Game Boy side of things: command packets, palettes, the attribute map and the
+// border, put together into the 256x224 picture the SNES outputs
+#[derive(Clone, Debug)]
+pub struct Sgb {
+    receiver: PacketReceiver,
+    packets: Vec<u8>,
+    packets_expected: usize,
+    pub palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    pub attributes: AttributeMap,
+    attribute_files: Vec<u8>,
+    pub border: Border,
+    pub mask: ScreenMask,
+    frozen_screen: Vec<u8>,
+    player_count: u8,
+    current_player: u8,
+    pending_transfer: Option<VramTransfer>,
+    joypad_select: u8,
+}
+impl Sgb {
+    pub fn new() -> Sgb {
+        return Sgb {
+            receiver: PacketReceiver::new(),
+            packets: Vec::new(),
+            packets_expected: 0,
+            palettes: [[0x7FFF, 0x56B5, 0x294A, 0x0000]; 4],
+            system_palettes: vec![[0; 4]; SYSTEM_PALETTE_COUNT],
+            attributes: AttributeMap::new(),
+            attribute_files: vec![0; ATTRIBUTE_FILE_COUNT * ATTRIBUTE_FILE_SIZE],
+            border: Border::new(),
+            mask: ScreenMask::None,
+            frozen_screen: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            player_count: 1,
+            current_player: 0,
+            pending_transfer: None,
+            joypad_select: 0x30,
+        }
+    }
+
+    // Every write to P1 goes through here
+    pub fn write_joypad(&mut self, value: u8) {
+        // With more than one controller, releasing P15 moves on to the next one
+        if self.player_count > 1 && value & 0x20 != 0 && self.joypad_select & 0x20 == 0 {
+            self.current_player = (self.current_player + 1) % self.player_count;
+        }
+        self.joypad_select = value & 0x30;
+        if let Some(packet) = self.receiver.write(value) {
+            self.receive_packet(packet);
+        }
+    }
+
+    // With MLT_REQ on and neither button row selected, P1 reads the current controller's
+    // ID in the low bits: 0xF for player 1, 0xE for player 2 and so on
+    pub fn joypad_id(&self) -> Option<u8> {
+        if self.player_count > 1 && self.joypad_select == 0x30 {
+            Some(0x0F - self.current_player)
+        } else {
+            None
+        }
+    }
+
+    pub fn player_count(&self) -> u8 {
+        self.player_count
+    }
+
+    fn receive_packet(&mut self, packet: [u8; PACKET_SIZE]) {
+        if self.packets_expected == 0 {
+            // The first byte of a command has the command in the top 5 bits and the number
+            // of packets it spans in the bottom 3
+            self.packets_expected = (packet[0] & 0b111).max(1) as usize;
+            self.packets.clear();
+        }
+        self.packets.extend_from_slice(&packet);
+        self.packets_expected -= 1;
+        if self.packets_expected == 0 {
+            let data = std::mem::take(&mut self.packets);
+            self.execute(&data);
+        }
+    }
+
+    pub fn execute(&mut self, data: &[u8]) {
+        let command = SgbCommand::from(data[0] >> 3);
+        match command {
+            SgbCommand::Pal01 => self.set_palette_pair(data, 0, 1),
+            SgbCommand::Pal23 => self.set_palette_pair(data, 2, 3),
+            SgbCommand::Pal03 => self.set_palette_pair(data, 0, 3),
+            SgbCommand::Pal12 => self.set_palette_pair(data, 1, 2),
+            SgbCommand::AttrBlk => self.attributes.apply_blocks(&data[1..]),
+            SgbCommand::AttrLin => self.attributes.apply_lines(&data[1..]),
+            SgbCommand::AttrDiv => self.attributes.apply_division(&data[1..]),
+            SgbCommand::AttrChr => self.attributes.apply_characters(&data[1..]),
+            SgbCommand::PalSet => {
+                for palette in 0..4 {
+                    let index = read_word(data, 1 + palette * 2) as usize % SYSTEM_PALETTE_COUNT;
+                    self.palettes[palette] = self.system_palettes[index];
+                }
+                // Colour 0 is shared, the first palette's wins
+                for palette in 1..4 {
+                    self.palettes[palette][0] = self.palettes[0][0];
+                }
+                let flags = data[9];
+                if flags & 0x80 != 0 {
+                    self.apply_attribute_file(flags & 0x3F);
+                }
+                if flags & 0x40 != 0 {
+                    self.mask = ScreenMask::None;
+                }
+            }
+            SgbCommand::PalTrn => self.pending_transfer = Some(VramTransfer::SystemPalettes),
+            SgbCommand::ChrTrn => self.pending_transfer = Some(VramTransfer::BorderTiles { upper: data[1] & 0b1 != 0 }),
+            SgbCommand::PctTrn => self.pending_transfer = Some(VramTransfer::BorderMap),
+            SgbCommand::AttrTrn => self.pending_transfer = Some(VramTransfer::AttributeFiles),
+            SgbCommand::AttrSet => {
+                self.apply_attribute_file(data[1] & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = ScreenMask::None;
+                }
+            }
+            SgbCommand::MaskEn => {
+                self.mask = match data[1] & 0b11 {
+                    1 => ScreenMask::Freeze,
+                    2 => ScreenMask::Black,
+                    3 => ScreenMask::Color0,
+                    _ => ScreenMask::None,
+                };
+            }
+            SgbCommand::MltReq => {
+                self.player_count = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            }
+            SgbCommand::Unsupported(_) => {}
+        }
+    }
+
+    // PAL01, PAL23, PAL03 and PAL12 set two palettes at once along with the shared colour 0
+    fn set_palette_pair(&mut self, data: &[u8], first: usize, second: usize) {
+        let color0 = read_word(data, 1);
+        for palette in 0..4 {
+            self.palettes[palette][0] = color0;
+        }
+        for color in 1..4 {
+            self.palettes[first][color] = read_word(data, 1 + color * 2);
+            self.palettes[second][color] = read_word(data, 7 + color * 2);
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file < ATTRIBUTE_FILE_COUNT {
+            let start = file * ATTRIBUTE_FILE_SIZE;
+            let data = self.attribute_files[start..start + ATTRIBUTE_FILE_SIZE].to_vec();
+            self.attributes.load_file(&data);
+        }
+    }
+
+    pub fn pending_transfer(&self) -> Option<VramTransfer> {
+        self.pending_transfer
+    }
+
+    // Hand over the 4 KiB a pending VRAM transfer was waiting on
+    pub fn complete_transfer(&mut self, data: &[u8]) {
+        match self.pending_transfer.take() {
+            Some(VramTransfer::SystemPalettes) => {
+                for (index, palette) in self.system_palettes.iter_mut().enumerate() {
+                    for (color, value) in palette.iter_mut().enumerate() {
+                        *value = read_word(data, (index * 4 + color) * 2);
+                    }
+                }
+            }
+            Some(VramTransfer::BorderTiles { upper }) => self.border.load_tiles(data, upper),
+            Some(VramTransfer::BorderMap) => self.border.load_map(data),
+            Some(VramTransfer::AttributeFiles) => {
+                let length = self.attribute_files.len().min(data.len());
+                self.attribute_files[..length].copy_from_slice(&data[..length]);
+            }
+            None => {}
+        }
+    }
+
+    // Builds the 256x224 RGB555 SGB picture from a Game Boy frame of shades (0-3 after
+    // BGP/OBP have been applied), colouring it through the attribute map and framing it
+    // with the border
+    pub fn render(&mut self, screen: &[u8]) -> Vec<u16> {
+        if self.mask != ScreenMask::Freeze {
+            self.frozen_screen.copy_from_slice(&screen[..SCREEN_WIDTH * SCREEN_HEIGHT]);
+        }
+        let backdrop = self.palettes[0][0];
+        let mut output = vec![backdrop; SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT];
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    ScreenMask::Black => 0x0000,
+                    ScreenMask::Color0 => backdrop,
+                    ScreenMask::None | ScreenMask::Freeze => {
+                        let palette = self.attributes.get(x / 8, y / 8) as usize;
+                        let shade = self.frozen_screen[y * SCREEN_WIDTH + x] as usize & 0b11;
+                        self.palettes[palette][shade]
+                    }
+                };
+                output[(GAME_SCREEN_Y + y) * SGB_SCREEN_WIDTH + GAME_SCREEN_X + x] = color;
+            }
+        }
+        for y in 0..SGB_SCREEN_HEIGHT {
+            for x in 0..SGB_SCREEN_WIDTH {
+                if let Some(color) = self.border.pixel(x, y) {
+                    output[y * SGB_SCREEN_WIDTH + x] = color;
+                }
+            }
+        }
+        return output
+    }
+}
+
+// The 4 KiB a VRAM transfer sends: the tiles of the first 256 background map entries, in
+// map order, using the tile data area and map LCDC currently selects
+pub fn vram_transfer_data(vram: &VideoRam, lcdc: u8) -> Vec<u8> {
+    let map_base: u16 = if lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+    let unsigned_tiles = lcdc & 0x10 != 0;
+    let mut data = Vec::with_capacity(VRAM_TRANSFER_SIZE);
+    for index in 0..VRAM_TRANSFER_SIZE / 16 {
+        let (column, row) = (index % ATTRIBUTE_COLUMNS, index / ATTRIBUTE_COLUMNS);
+        let tile = vram.read_bank(0, map_base + (row * 32 + column) as u16);
+        let tile_address = if unsigned_tiles {
+            0x8000 + tile as u16 * 16
+        } else {
+            (0x9000i32 + (tile as i8) as i32 * 16) as u16
+        };
+        for offset in 0..16 {
+            data.push(vram.read_bank(0, tile_address + offset));
+        }
+    }
+    return data
+}
+
+impl SaveState for Sgb {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.receiver.save_state(writer);
+        writer.write_bytes(&self.packets);
+        writer.write_u8(self.packets_expected as u8);
+        for palette in self.palettes.iter().chain(&self.system_palettes) {
+            writer.write_u16s(palette);
+        }
+        self.attributes.save_state(writer);
+        writer.write_bytes(&self.attribute_files);
+        self.border.save_state(writer);
+        writer.write_u8(self.mask as u8);
+        writer.write_bytes(&self.frozen_screen);
+        writer.write_u8(self.player_count);
+        writer.write_u8(self.current_player);
+        let transfer = match self.pending_transfer {
+            None => 0,
+            Some(VramTransfer::SystemPalettes) => 1,
+            Some(VramTransfer::BorderTiles { upper: false }) => 2,
+            Some(VramTransfer::BorderTiles { upper: true }) => 3,
+            Some(VramTransfer::BorderMap) => 4,
+            Some(VramTransfer::AttributeFiles) => 5,
+        };
+        writer.write_u8(transfer);
+        writer.write_u8(self.joypad_select);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.receiver.load_state(reader)?;
+        self.packets = reader.read_bytes()?;
+        self.packets_expected = reader.read_u8()? as usize;
+        for palette in self.palettes.iter_mut().chain(&mut self.system_palettes) {
+            reader.read_u16s_into(palette)?;
+        }
+        self.attributes.load_state(reader)?;
+        reader.read_bytes_into(&mut self.attribute_files)?;
+        self.border.load_state(reader)?;
+        self.mask = match reader.read_u8()? {
+            0 => ScreenMask::None,
+            1 => ScreenMask::Freeze,
+            2 => ScreenMask::Black,
+            3 => ScreenMask::Color0,
+            _ => return Err(SaveStateError::Invalid("SGB screen mask")),
+        };
+        reader.read_bytes_into(&mut self.frozen_screen)?;
+        self.player_count = reader.read_u8()?;
+        self.current_player = reader.read_u8()?;
+        if self.player_count == 0 || self.current_player >= self.player_count {
+            return Err(SaveStateError::Invalid("SGB player"));
+        }
+        self.pending_transfer = match reader.read_u8()? {
+            0 => None,
+            1 => Some(VramTransfer::SystemPalettes),
+            2 => Some(VramTransfer::BorderTiles { upper: false }),
+            3 => Some(VramTransfer::BorderTiles { upper: true }),
+            4 => Some(VramTransfer::BorderMap),
+            5 => Some(VramTransfer::AttributeFiles),
+            _ => return Err(SaveStateError::Invalid("SGB transfer")),
+        };
+        self.joypad_select = reader.read_u8()? & 0x30;
+        return Ok(())
+    }
+}
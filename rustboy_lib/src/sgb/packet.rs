@@ -0,0 +1,83 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+
+pub const PACKET_SIZE: usize = 16;
+
+// Receives command packets the game sends by pulsing P14/P15 of the joypad register.
+// Writing 0x00 (both low) is the reset pulse that starts a packet, 0x20 (P14 low) sends a
+// 0 bit and 0x10 (P15 low) sends a 1 bit, with a write of 0x30 between every pulse. Each
+// packet is 128 bits, least significant bit first, followed by a 0 stop bit
+#[derive(Clone, Debug)]
+pub struct PacketReceiver {
+    buffer: [u8; PACKET_SIZE],
+    bits_received: usize,
+    receiving: bool,
+    pending_bit: Option<u8>,
+}
+impl PacketReceiver {
+    pub fn new() -> PacketReceiver {
+        return PacketReceiver {
+            buffer: [0; PACKET_SIZE],
+            bits_received: 0,
+            receiving: false,
+            pending_bit: None,
+        }
+    }
+
+    // Feed in the P14/P15 bits of a write to P1. Returns a packet once its stop bit arrives
+    pub fn write(&mut self, value: u8) -> Option<[u8; PACKET_SIZE]> {
+        match value & 0x30 {
+            0x00 => {
+                self.buffer = [0; PACKET_SIZE];
+                self.bits_received = 0;
+                self.receiving = true;
+                self.pending_bit = None;
+                None
+            }
+            0x20 => {
+                self.pending_bit = Some(0);
+                None
+            }
+            0x10 => {
+                self.pending_bit = Some(1);
+                None
+            }
+            _ => {
+                let bit = self.pending_bit.take()?;
+                if !self.receiving {
+                    return None;
+                }
+                if self.bits_received == PACKET_SIZE * 8 {
+                    // The stop bit. A 1 here means the transfer went wrong, so drop the packet
+                    self.receiving = false;
+                    return if bit == 0 { Some(self.buffer) } else { None };
+                }
+                self.buffer[self.bits_received / 8] |= bit << (self.bits_received % 8);
+                self.bits_received += 1;
+                None
+            }
+        }
+    }
+}
+
+impl SaveState for PacketReceiver {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.buffer);
+        writer.write_u8(self.bits_received as u8);
+        writer.write_bool(self.receiving);
+        writer.write_bool(self.pending_bit.is_some());
+        writer.write_u8(self.pending_bit.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        reader.read_bytes_into(&mut self.buffer)?;
+        self.bits_received = reader.read_u8()? as usize;
+        if self.bits_received > PACKET_SIZE * 8 {
+            return Err(SaveStateError::Invalid("SGB packet"));
+        }
+        self.receiving = reader.read_bool()?;
+        let pending = reader.read_bool()?;
+        let bit = reader.read_u8()?;
+        self.pending_bit = if pending { Some(bit) } else { None };
+        return Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::Path;
+
+// Labels from a .sym file as rgblink and no$gmb write them: one "bank:address name" per
+// line, both in hexadecimal, with ; starting a comment
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub bank: usize,
+    pub address: u16,
+    pub name: String,
+}
+
+// Kept sorted by bank and address. Several names for the same place keep the order they
+// were added in, and the first one is the one shown
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+}
+impl Symbols {
+    pub fn new() -> Symbols {
+        return Symbols::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = || format!("line {}: expected \"bank:address name\", got \"{}\"", number + 1, line);
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(error)?;
+            let (bank, address) = location.split_once(':').ok_or_else(error)?;
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| error())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| error())?;
+            symbols.push(Symbol { bank, address, name: String::from(name.trim()) });
+        }
+        // Sorting once is stable like insert, and doesn't shift the rest for every line
+        symbols.sort_by_key(|symbol| (symbol.bank, symbol.address));
+        return Ok(Symbols { symbols })
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        return Symbols::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub fn insert(&mut self, bank: usize, address: u16, name: &str) {
+        let index = self.symbols.partition_point(|symbol| (symbol.bank, symbol.address) <= (bank, address));
+        self.symbols.insert(index, Symbol { bank, address, name: String::from(name) });
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    // The symbols in the same format parse reads
+    pub fn to_sym(&self) -> String {
+        self.symbols.iter().map(|symbol| format!("{:02x}:{:04x} {}\n", symbol.bank, symbol.address, symbol.name)).collect()
+    }
+
+    // The name for address in bank, or in any bank when it isn't known which is mapped
+    pub fn label_at(&self, bank: Option<usize>, address: u16) -> Option<&str> {
+        let symbol = match bank {
+            Some(bank) => {
+                let index = self.symbols.partition_point(|symbol| (symbol.bank, symbol.address) < (bank, address));
+                self.symbols.get(index).filter(|symbol| (symbol.bank, symbol.address) == (bank, address))
+            }
+            None => self.symbols.iter().find(|symbol| symbol.address == address),
+        };
+        symbol.map(|symbol| symbol.name.as_str())
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    // The closest label at or before address in bank and how far past it address is, for
+    // naming places inside functions and tables. It has to be in the same part of memory,
+    // a label at the end of ROM0 says nothing about WRAM
+    pub fn locate(&self, bank: usize, address: u16) -> Option<(&str, u16)> {
+        let index = self.symbols.partition_point(|symbol| (symbol.bank, symbol.address) <= (bank, address));
+        let symbol = self.symbols[..index].last().filter(|symbol| symbol.bank == bank && region(symbol.address) == region(address))?;
+        // The first name added for that address, like label_at
+        let first = self.symbols.partition_point(|other| (other.bank, other.address) < (symbol.bank, symbol.address));
+        Some((self.symbols[first].name.as_str(), address - symbol.address))
+    }
+
+    // A place named after the label it is at or past, like "Main" or "Main+$3"
+    pub fn name(&self, bank: usize, address: u16) -> Option<String> {
+        match self.locate(bank, address)? {
+            (label, 0) => Some(String::from(label)),
+            (label, offset) => Some(format!("{}+${:X}", label, offset)),
+        }
+    }
+}
+
+// The start of the part of the memory map address is in
+fn region(address: u16) -> u16 {
+    match address {
+        0x0000..0x4000 => 0x0000,
+        0x4000..0x8000 => 0x4000,
+        0x8000..0xA000 => 0x8000,
+        0xA000..0xC000 => 0xA000,
+        0xC000..0xD000 => 0xC000,
+        0xD000..0xFE00 => 0xD000,
+        0xFE00..0xFF80 => 0xFE00,
+        _ => 0xFF80,
+    }
+}
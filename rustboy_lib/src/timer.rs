@@ -0,0 +1,211 @@
+use crate::savestate::{SaveState, SaveStateError, StateReader, StateWriter};
+use crate::scheduler::{EventKind, Scheduler};
+
+pub const DIV_ADDRESS: u16 = 0xFF04;  // Divider, the upper 8 bits of the system counter
+pub const TIMA_ADDRESS: u16 = 0xFF05; // Timer counter
+pub const TMA_ADDRESS: u16 = 0xFF06;  // Timer modulo, reloaded into TIMA on overflow
+pub const TAC_ADDRESS: u16 = 0xFF07;  // Timer control
+
+const TIMER_ENABLE_BIT: u8 = 2;
+
+// TIMA reads 0 for this many T-cycles after overflowing, before TMA is loaded into it
+const RELOAD_DELAY: u64 = 4;
+
+// DIV and TIMA are both driven by a 16 bit counter that goes up every T-cycle. TIMA is
+// incremented whenever the counter bit picked by TAC, ANDed with the enable bit, goes from
+// 1 to 0. Modelling it this way gives the odd increments on DIV and TAC writes for free.
+//
+// Nothing is stepped per cycle. The state is kept as of the last sync and the number of
+// falling edges since then is worked out when needed. The next overflow is scheduled, so
+// TIMA never wraps between two syncs
+#[derive(Debug)]
+pub struct Timer {
+    counter: u16, // As of last_sync
+    last_sync: u64,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // A write to TIMA while the reload is pending cancels it
+    reload_pending: bool,
+    interrupt_requested: bool,
+}
+impl Timer {
+    pub fn new() -> Timer {
+        return Timer {
+            counter: 0,
+            last_sync: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_pending: false,
+            interrupt_requested: false,
+        }
+    }
+
+    pub fn counter(&self, now: u64) -> u16 {
+        self.counter.wrapping_add((now - self.last_sync) as u16)
+    }
+
+    // For setting up the state a boot ROM leaves behind
+    pub fn set_counter(&mut self, counter: u16, scheduler: &mut Scheduler) {
+        self.sync(scheduler);
+        self.counter = counter;
+        self.schedule_overflow(scheduler);
+    }
+
+    pub fn read(&self, address: u16, now: u64) -> u8 {
+        match address {
+            DIV_ADDRESS => (self.counter(now) >> 8) as u8,
+            TIMA_ADDRESS => self.tima.wrapping_add(self.falling_edges(now) as u8),
+            TMA_ADDRESS => self.tma,
+            // Unused TAC bits always read back as 1
+            TAC_ADDRESS => 0xF8 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, scheduler: &mut Scheduler) {
+        self.sync(scheduler);
+        match address {
+            DIV_ADDRESS => {
+                let before = self.timer_bit();
+                self.counter = 0;
+                if before && !self.timer_bit() {
+                    self.increment_tima(scheduler);
+                }
+            }
+            TIMA_ADDRESS => {
+                self.tima = value;
+                if self.reload_pending {
+                    self.reload_pending = false;
+                    scheduler.cancel(EventKind::TimerReload);
+                }
+            }
+            TMA_ADDRESS => self.tma = value,
+            TAC_ADDRESS => {
+                let before = self.timer_bit();
+                self.tac = value & 0b111;
+                if before && !self.timer_bit() {
+                    self.increment_tima(scheduler);
+                }
+            }
+            _ => {}
+        }
+        self.schedule_overflow(scheduler);
+    }
+
+    pub fn handle_event(&mut self, kind: EventKind, scheduler: &mut Scheduler) {
+        match kind {
+            EventKind::TimerOverflow => {
+                // Catching up runs TIMA into the overflow
+                self.sync(scheduler);
+                self.schedule_overflow(scheduler);
+            }
+            EventKind::TimerReload => {
+                self.sync(scheduler);
+                if self.reload_pending {
+                    self.reload_pending = false;
+                    self.tima = self.tma;
+                    self.interrupt_requested = true;
+                }
+                self.schedule_overflow(scheduler);
+            }
+            _ => {}
+        }
+    }
+
+    // Whether the timer interrupt (IF bit 2) has been raised since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::replace(&mut self.interrupt_requested, false)
+    }
+
+    // Brings counter and TIMA up to now
+    fn sync(&mut self, scheduler: &mut Scheduler) {
+        let now = scheduler.now();
+        let edges = self.falling_edges(now);
+        self.counter = self.counter(now);
+        self.last_sync = now;
+        let (result, overflowed) = self.tima.overflowing_add(edges as u8);
+        self.tima = result;
+        // The overflow event wakes the timer up for it, so only the last edge can overflow
+        if overflowed {
+            let since_edge = self.period().map_or(0, |period| self.counter as u64 % period);
+            self.reload_pending = true;
+            scheduler.reschedule(EventKind::TimerReload, RELOAD_DELAY.saturating_sub(since_edge));
+        }
+    }
+
+    // Falling edges of the selected counter bit between the last sync and now
+    fn falling_edges(&self, now: u64) -> u64 {
+        match self.period() {
+            Some(period) => {
+                let start = self.counter as u64;
+                let end = start + (now - self.last_sync);
+                end / period - start / period
+            }
+            None => 0,
+        }
+    }
+
+    // T-cycles between two TIMA increments, or None while the timer is off
+    fn period(&self) -> Option<u64> {
+        if (self.tac >> TIMER_ENABLE_BIT) & 0b1 == 0 {
+            return None;
+        }
+        let bit = match self.tac & 0b11 {
+            0b00 => 9, // 4096 Hz
+            0b01 => 3, // 262144 Hz
+            0b10 => 5, // 65536 Hz
+            _ => 7,    // 16384 Hz
+        };
+        return Some(1 << (bit + 1))
+    }
+
+    fn schedule_overflow(&self, scheduler: &mut Scheduler) {
+        scheduler.cancel(EventKind::TimerOverflow);
+        if let Some(period) = self.period() {
+            let to_next_edge = period - (self.counter as u64 % period);
+            let edges_left = 0x100 - self.tima as u64;
+            scheduler.schedule(EventKind::TimerOverflow, to_next_edge + (edges_left - 1) * period);
+        }
+    }
+
+    fn timer_bit(&self) -> bool {
+        match self.period() {
+            Some(period) => self.counter as u64 & (period / 2) != 0,
+            None => false,
+        }
+    }
+
+    fn increment_tima(&mut self, scheduler: &mut Scheduler) {
+        let (result, overflowed) = self.tima.overflowing_add(1);
+        self.tima = result;
+        if overflowed {
+            self.reload_pending = true;
+            scheduler.reschedule(EventKind::TimerReload, RELOAD_DELAY);
+        }
+    }
+}
+
+impl SaveState for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.counter);
+        writer.write_u64(self.last_sync);
+        writer.write_u8(self.tima);
+        writer.write_u8(self.tma);
+        writer.write_u8(self.tac);
+        writer.write_bool(self.reload_pending);
+        writer.write_bool(self.interrupt_requested);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), SaveStateError> {
+        self.counter = reader.read_u16()?;
+        self.last_sync = reader.read_u64()?;
+        self.tima = reader.read_u8()?;
+        self.tma = reader.read_u8()?;
+        self.tac = reader.read_u8()? & 0b111;
+        self.reload_pending = reader.read_bool()?;
+        self.interrupt_requested = reader.read_bool()?;
+        return Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::cpu::CPU;
+use crate::symbols::Symbols;
+
+// Execution traces in the format of gameboy-doctor, which most emulators can also log: one
+// line per instruction with the state the CPU starts it in, like
+//   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+// so that a run can be compared line by line with a reference emulator's. gameboy-doctor's
+// own logs are made with LY always reading $90, so a normal run parts ways with them at the
+// first LY poll
+
+pub fn format_state(cpu: &CPU) -> String {
+    let registers = &cpu.registers;
+    let memory: Vec<String> = (0..4).map(|offset| format!("{:02X}", cpu.bus.peek(cpu.pc.wrapping_add(offset)))).collect();
+    return format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+        registers.a, u8::from(registers.f), registers.b, registers.c, registers.d, registers.e, registers.h, registers.l,
+        cpu.sp, cpu.pc, memory.join(","),
+    )
+}
+
+// What the CPU did on the bus in one M-cycle, as logged into CPU::bus_log
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BusCycle {
+    Idle, // No memory access
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+// Which instructions make it into the trace: those with PC in start-end, both included,
+// and with bank mapped there when there is one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub start: u16,
+    pub end: u16,
+    pub bank: Option<usize>,
+}
+impl TraceFilter {
+    pub fn new() -> TraceFilter {
+        return TraceFilter { start: 0x0000, end: 0xFFFF, bank: None }
+    }
+
+    fn matches(&self, cpu: &CPU) -> bool {
+        (self.start..=self.end).contains(&cpu.pc) && self.bank.is_none_or(|bank| cpu.bus.bank_at(cpu.pc) == bank)
+    }
+}
+
+pub enum TraceSink {
+    Writer(Box<dyn Write + Send>),
+    Callback(Box<dyn FnMut(&str) + Send>),
+}
+
+// Set as the CPU's trace to log every instruction it runs. Writing stops at the first
+// error, which finish hands back
+pub struct Tracer {
+    sink: TraceSink,
+    filter: TraceFilter,
+    symbols: Option<Symbols>,
+    lines: u64,
+    error: Option<std::io::Error>,
+}
+impl Tracer {
+    pub fn new(sink: TraceSink, filter: TraceFilter) -> Tracer {
+        return Tracer { sink, filter, symbols: None, lines: 0, error: None }
+    }
+
+    pub fn create(path: &Path, filter: TraceFilter) -> std::io::Result<Tracer> {
+        let file = std::fs::File::create(path)?;
+        return Ok(Tracer::new(TraceSink::Writer(Box::new(BufWriter::new(file))), filter))
+    }
+
+    // Ends each line with where PC is in terms of these labels, after a ";". The diff
+    // ignores anything after PCMEM, so this doesn't get in the way of comparing
+    pub fn with_symbols(mut self, symbols: Symbols) -> Tracer {
+        self.symbols = Some(symbols);
+        return self
+    }
+
+    // Lines written so far
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.error.is_some() || !self.filter.matches(cpu) {
+            return;
+        }
+        let mut line = format_state(cpu);
+        if let Some(symbols) = &self.symbols && let Some(name) = symbols.name(cpu.bus.bank_at(cpu.pc), cpu.pc) {
+            line.push_str(&format!(" ; {}", name));
+        }
+        match &mut self.sink {
+            TraceSink::Writer(writer) => {
+                if let Err(error) = writeln!(writer, "{}", line) {
+                    self.error = Some(error);
+                    return;
+                }
+            }
+            TraceSink::Callback(callback) => callback(&line),
+        }
+        self.lines += 1;
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        match self.sink {
+            TraceSink::Writer(mut writer) => writer.flush(),
+            TraceSink::Callback(_) => Ok(()),
+        }
+    }
+}
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Tracer").field("filter", &self.filter).field("lines", &self.lines).finish_non_exhaustive()
+    }
+}
+
+// Where two traces first differ, with the lines leading up to it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceMismatch {
+    pub line: usize, // Counting from 1
+    pub ours: Option<String>, // None when that trace ended first
+    pub reference: Option<String>,
+    pub context: Vec<String>, // The same in both
+    pub fields: Vec<String>, // The names of the values that differ
+}
+impl std::fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.fields.is_empty() {
+            writeln!(f, "traces differ at line {}", self.line)?;
+        } else {
+            writeln!(f, "traces differ at line {} in {}", self.line, self.fields.join(", "))?;
+        }
+        for (index, line) in self.context.iter().enumerate() {
+            writeln!(f, "  {:>8}  {}", self.line - self.context.len() + index, line)?;
+        }
+        writeln!(f, "- {:>8}  {}", self.line, self.ours.as_deref().unwrap_or("(end of trace)"))?;
+        write!(f, "+ {:>8}  {}", self.line, self.reference.as_deref().unwrap_or("(end of trace)"))
+    }
+}
+
+// Compares our trace with a reference one line by line, by the values up to and including
+// PCMEM so that comments and upper or lower case hexadecimal don't matter. Both are read as
+// they go, since traces of whole test ROMs run into millions of lines
+pub fn diff_traces(ours: impl BufRead, reference: impl BufRead, context: usize) -> std::io::Result<Option<TraceMismatch>> {
+    let mut ours = ours.lines();
+    let mut reference = reference.lines();
+    let mut previous = VecDeque::new();
+    let mut line = 0;
+    loop {
+        line += 1;
+        let (our_line, reference_line) = match (ours.next().transpose()?, reference.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (Some(our_line), Some(reference_line)) => (our_line, reference_line),
+            (our_line, reference_line) => {
+                let context = previous.into_iter().collect();
+                return Ok(Some(TraceMismatch { line, ours: our_line, reference: reference_line, context, fields: Vec::new() }));
+            }
+        };
+        let (our_fields, reference_fields) = (fields(&our_line), fields(&reference_line));
+        if our_fields != reference_fields {
+            let mut names: Vec<String> = our_fields
+                .iter()
+                .zip(&reference_fields)
+                .filter(|(ours, reference)| ours != reference)
+                .map(|(ours, _)| ours.0.clone())
+                .collect();
+            if our_fields.len() != reference_fields.len() && names.is_empty() {
+                names.push(String::from("the number of values"));
+            }
+            let context = previous.into_iter().collect();
+            return Ok(Some(TraceMismatch { line, ours: Some(our_line), reference: Some(reference_line), context, fields: names }));
+        }
+        if context > 0 {
+            if previous.len() == context {
+                previous.pop_front();
+            }
+            previous.push_back(our_line);
+        }
+    }
+}
+
+// The NAME:value pairs of a line, up to PCMEM
+fn fields(line: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    for word in line.split_whitespace() {
+        let Some((name, value)) = word.split_once(':') else {
+            break;
+        };
+        fields.push((name.to_ascii_uppercase(), value.to_ascii_uppercase()));
+        if name.eq_ignore_ascii_case("PCMEM") {
+            break;
+        }
+    }
+    return fields
+}
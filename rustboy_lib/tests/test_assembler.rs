@@ -0,0 +1,181 @@
+use rustboy_lib::assembler::{assemble, assemble_at, SectionType};
+use rustboy_lib::cpu::instructions::Instructions;
+use rustboy_lib::disassembler::decode;
+use rustboy_lib::disassembler::recursive::CodeMap;
+use rustboy_lib::symbols::Symbols;
+
+
+#[cfg(test)]
+mod assembler_tests {
+    use super::*;
+    fn use_test_bytes(source: &str) -> Vec<u8> {
+        let program = assemble(source).unwrap_or_else(|error| panic!("{}", error));
+        return program.sections[0].bytes.clone()
+    }
+    #[test]
+    fn test_encode_is_decode_backwards() {
+        for opcode in 0..=255u8 {
+            let bytes = [opcode, 0x34, 0x12];
+            let (instruction, length) = decode(&bytes).unwrap();
+            assert_eq!(instruction.encode(), bytes[..length], "{:02X}: {}", opcode, instruction);
+            let prefixed = [0xCB, opcode];
+            assert_eq!(decode(&prefixed).unwrap().0.encode(), prefixed);
+        }
+    }
+    #[test]
+    fn test_instructions() {
+        let bytes = use_test_bytes("
+            ld a, 5
+            ld b, a
+            ld [hl], $FF
+            ld hl, $C000
+            ld [hl+], a
+            ld a, [hld]
+            ldh [$FF40], a
+            ldh a, [c]
+            ld [$C123], a
+            ld [$D000], sp
+            ld hl, sp - 2
+            add a, b
+            ADD [HL]
+            xor a
+            cp $10
+            add hl, de
+            add sp, -1
+            inc [hl]
+            dec bc
+            bit 7, h
+            res 0, [hl]
+            swap a
+            push af
+            pop de
+            jp hl
+            ret nc
+            rst $38
+            stop
+        ");
+        assert_eq!(bytes, [
+            0x3E, 0x05, 0x47, 0x36, 0xFF, 0x21, 0x00, 0xC0, 0x22, 0x3A, 0xE0, 0x40, 0xF2,
+            0xEA, 0x23, 0xC1, 0x08, 0x00, 0xD0, 0xF8, 0xFE, 0x80, 0x86, 0xAF, 0xFE, 0x10,
+            0x19, 0xE8, 0xFF, 0x34, 0x0B, 0xCB, 0x7C, 0xCB, 0x86, 0xCB, 0x37, 0xF5, 0xD1,
+            0xE9, 0xD0, 0xFF, 0x10, 0x00,
+        ]);
+    }
+    #[test]
+    fn test_labels_and_data() {
+        let program = assemble("
+DEF COUNT EQU 3
+TILE_SIZE equ 16
+
+SECTION \"Main\", ROM0[$0150]
+Main:
+    ld b, COUNT * 2 + 1
+.loop:                 ; the loop under Main
+    dec b
+    jr nz, .loop
+    call Helper
+    jp Main.loop
+Helper::
+    ld hl, Table + TILE_SIZE / 8
+.loop
+    jr c, .loop
+    ret
+Table:
+    db 1, -1, HIGH($1234), LOW($1234), %1010, \"Hi\", (1 << 4) | 2
+    dw Main, $BEEF
+    ds 3, $AA
+    db @ & $FF
+
+SECTION \"Bank 2\", ROMX[$4000], BANK[2]
+Far:
+    jp Far
+
+SECTION \"Variables\", WRAM0[$C000]
+wCounter: ds 2
+").unwrap_or_else(|error| panic!("{}", error));
+        let main = &program.sections[0];
+        assert_eq!((main.kind, main.address, main.bank), (SectionType::Rom0, 0x0150, 0));
+        assert_eq!(main.bytes, [
+            0x06, 0x07,             // 0x0150: ld b, 7
+            0x05,                   // 0x0152: dec b
+            0x20, 0xFD,             // 0x0153: jr nz, $0152
+            0xCD, 0x5B, 0x01,       // 0x0155: call Helper
+            0xC3, 0x52, 0x01,       // 0x0158: jp Main.loop
+            0x21, 0x63, 0x01,       // 0x015B: ld hl, Table + 2
+            0x38, 0xFE,             // 0x015E: jr c, $015E
+            0xC9,                   // 0x0160: ret
+            0x01, 0xFF, 0x12, 0x34, 0x0A, b'H', b'i', 0x12,
+            0x50, 0x01, 0xEF, 0xBE,
+            0xAA, 0xAA, 0xAA,
+            0x70,
+        ]);
+        assert_eq!((program.sections[1].kind, program.sections[1].bank), (SectionType::RomX, 2));
+        assert_eq!(program.sections[1].bytes, [0xC3, 0x00, 0x40]);
+        assert_eq!(program.sections[2].bytes, [0x00, 0x00]);
+
+        assert_eq!(program.symbols.label_at(Some(0), 0x0152), Some("Main.loop"));
+        assert_eq!(program.symbols.label_at(Some(0), 0x015E), Some("Helper.loop"));
+        assert_eq!(program.symbols.label_at(Some(2), 0x4000), Some("Far"));
+        assert_eq!(program.symbols.label_at(Some(0), 0xC000), Some("wCounter"));
+        assert!(program.symbols.label_at(None, 3).is_none());
+
+        let rom = program.rom();
+        assert_eq!(rom.len(), 0x10000);
+        assert_eq!(rom[0x0150..0x0153], [0x06, 0x07, 0x05]);
+        assert_eq!(rom[0x8000..0x8003], [0xC3, 0x00, 0x40]);
+        assert_eq!(Symbols::parse(&program.symbols.to_sym()).unwrap(), program.symbols);
+
+        // Patching leaves everything the sections don't cover alone
+        let mut rom = vec![0xEE; 0x8000];
+        assemble("SECTION \"Fix\", ROM0[$0100]\n    nop").unwrap().patch(&mut rom);
+        assert_eq!(rom[0xFF..0x102], [0xEE, 0x00, 0xEE]);
+        assert_eq!(rom.len(), 0x8000);
+    }
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| {
+            let error = assemble(source).unwrap_err();
+            (error.line, error.message)
+        };
+        assert_eq!(error("nop\n  ld a, [hl], b"), (2, String::from("ld can't take those operands")));
+        assert_eq!(error("frob a"), (1, String::from("unknown instruction frob")));
+        assert_eq!(error("jp Nowhere"), (1, String::from("Nowhere isn't defined")));
+        assert_eq!(error("Here:\nHere:"), (2, String::from("Here is already defined")));
+        assert_eq!(error(".local:"), (1, String::from("local label .local has no global label before it")));
+        assert_eq!(error("ld a, 256"), (1, String::from("256 doesn't fit in a byte")));
+        assert_eq!(error("rst 3"), (1, String::from("rst can't go to $3")));
+        assert_eq!(error("jr Far\nds 200\nFar:"), (1, String::from("jr target is 200 bytes away, more than jr can reach")));
+        assert_eq!(error("SECTION \"x\", ROMX[$0100]"), (1, String::from("$0100 isn't in RomX, which is $4000-$7FFF")));
+        assert!(assemble_at("nop", 0xE000).is_err());
+    }
+    #[test]
+    fn test_assembles_disassembly() {
+        // Code and data from all over a two bank ROM come back out the same
+        let mut rom = vec![0; 0x8000];
+        rom[0x38] = 0xC9;
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        let mut offset = 0x150;
+        for opcode in (0..=255u8).filter(|opcode| !matches!(opcode, 0x10 | 0x18 | 0xC3 | 0xC9 | 0xD9 | 0xE9 | 0xCB)) {
+            let bytes = match Instructions::decode(opcode, || 0x01) {
+                Instructions::INVALID(_) => continue,
+                instruction => instruction.encode(),
+            };
+            // Jumps and calls go to the RST handler so that the code goes on after them
+            let bytes = match bytes.len() {
+                3 if matches!(opcode, 0xC2 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xD4 | 0xDC | 0xCD) => vec![opcode, 0x38, 0x00],
+                _ => bytes,
+            };
+            rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+            offset += bytes.len();
+        }
+        for opcode in 0..=255u8 {
+            rom[offset..offset + 2].copy_from_slice(&[0xCB, opcode]);
+            offset += 2;
+        }
+        rom[offset..offset + 2].copy_from_slice(&[0x18, 0xFE]);
+        rom[0x4000..0x4010].copy_from_slice(b"not code at all!");
+        let asm = CodeMap::analyze(&rom).to_asm(&rom, &Symbols::parse("00:c000 wValue\n00:ff44 rLY").unwrap());
+        let program = assemble(&asm).unwrap_or_else(|error| panic!("{}\n{}", error, asm));
+        assert_eq!(program.rom(), rom);
+    }
+}
@@ -0,0 +1,157 @@
+use rustboy_lib::audio::{
+    apu::{Apu, NR52_ADDRESS},
+    wav::{WavWriter, WavRecorder, stem_path, to_pcm16},
+    AudioChannel, AudioFrame, StereoSample
+};
+use rustboy_lib::scheduler::{EventKind, Scheduler};
+use std::io::Cursor;
+use std::path::Path;
+
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+    fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+    #[test]
+    fn test_pcm16_conversion() {
+        assert_eq!(to_pcm16(0.0), 0);
+        assert_eq!(to_pcm16(1.0), i16::MAX);
+        assert_eq!(to_pcm16(-1.0), -i16::MAX);
+        assert_eq!(to_pcm16(4.0), i16::MAX);
+        assert_eq!(to_pcm16(f32::NAN), 0);
+    }
+    #[test]
+    fn test_wav_header() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 2, 48000).unwrap();
+        writer.write_stereo(StereoSample::new(1.0, -1.0)).unwrap();
+        writer.write_stereo(StereoSample::new(0.0, 0.0)).unwrap();
+        assert_eq!(writer.frames_written(), 2);
+        let bytes = writer.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(read_u32(&bytes, 4), 36 + 8);
+        assert_eq!(&bytes[8..12], b"WAVE");
+        assert_eq!(&bytes[12..16], b"fmt ");
+        assert_eq!(read_u16(&bytes, 20), 1);
+        assert_eq!(read_u16(&bytes, 22), 2);
+        assert_eq!(read_u32(&bytes, 24), 48000);
+        assert_eq!(read_u32(&bytes, 28), 48000 * 4);
+        assert_eq!(read_u16(&bytes, 32), 4);
+        assert_eq!(read_u16(&bytes, 34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(read_u32(&bytes, 40), 8);
+        assert_eq!(read_u16(&bytes, 44) as i16, i16::MAX);
+        assert_eq!(read_u16(&bytes, 46) as i16, -i16::MAX);
+    }
+    #[test]
+    fn test_stem_path() {
+        let path = Path::new("out/run.wav");
+        assert_eq!(stem_path(path, AudioChannel::Square1), Path::new("out/run_square1.wav"));
+        assert_eq!(stem_path(path, AudioChannel::Noise), Path::new("out/run_noise.wav"));
+    }
+    #[test]
+    fn test_recorder_with_stems() {
+        let dir = std::env::temp_dir().join(format!("rustboy_wav_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("capture.wav");
+
+        let mut recorder = WavRecorder::create(&path, 44100, true).unwrap();
+        let mut frame = AudioFrame { mixed: StereoSample::new(0.5, 0.5), ..Default::default() };
+        frame.channels[AudioChannel::Wave as usize] = StereoSample::new(0.5, 0.0);
+        for _ in 0..10 {
+            recorder.push_frame(&frame).unwrap();
+        }
+        assert_eq!(recorder.frames_written(), 10);
+        recorder.finish().unwrap();
+
+        let mixed = std::fs::read(&path).unwrap();
+        assert_eq!(read_u32(&mixed, 40), 40);
+        for channel in AudioChannel::ALL {
+            let stem = std::fs::read(stem_path(&path, channel)).unwrap();
+            assert_eq!(stem.len(), mixed.len());
+        }
+        let wave = std::fs::read(stem_path(&path, AudioChannel::Wave)).unwrap();
+        assert_eq!(read_u16(&wave, 44) as i16, to_pcm16(0.5));
+        assert_eq!(read_u16(&wave, 46) as i16, 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    // Fires the APU's events up to the scheduler time given
+    fn run_apu_until(apu: &mut Apu, scheduler: &mut Scheduler, time: u64) {
+        while scheduler.next_event_time().is_some_and(|next| next <= time) {
+            let next = scheduler.next_event_time().unwrap();
+            scheduler.advance(next - scheduler.now());
+            let event = scheduler.pop_due().unwrap();
+            apu.handle_event(event.kind, scheduler, false);
+        }
+        scheduler.advance(time - scheduler.now());
+    }
+    #[test]
+    fn test_apu_registers() {
+        let mut scheduler = Scheduler::new();
+        let mut apu = Apu::new(0, false);
+        apu.start(&mut scheduler, false);
+        assert_eq!(apu.read(NR52_ADDRESS), 0x70);
+        // Writes are ignored while the APU is off, apart from wave RAM
+        apu.write(0xFF12, 0xF0, &mut scheduler, false);
+        apu.write(0xFF30, 0x12, &mut scheduler, false);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        assert_eq!(apu.read(0xFF30), 0x12);
+
+        apu.write(NR52_ADDRESS, 0x80, &mut scheduler, false);
+        apu.write(0xFF11, 0x80, &mut scheduler, false);
+        apu.write(0xFF12, 0xF0, &mut scheduler, false);
+        apu.write(0xFF14, 0x87, &mut scheduler, false);
+        assert_eq!(apu.read(0xFF11), 0xBF);
+        assert_eq!(apu.read(0xFF14), 0xBF);
+        assert_eq!(apu.read(NR52_ADDRESS), 0xF1);
+        // Turning the DAC off stops the channel
+        apu.write(0xFF12, 0x00, &mut scheduler, false);
+        assert_eq!(apu.read(NR52_ADDRESS), 0xF0);
+
+        apu.write(0xFF12, 0xF0, &mut scheduler, false);
+        apu.write(NR52_ADDRESS, 0x00, &mut scheduler, false);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        assert_eq!(apu.read(0xFF30), 0x12);
+    }
+    #[test]
+    fn test_apu_length_counter() {
+        let mut scheduler = Scheduler::new();
+        let mut apu = Apu::new(0, false);
+        apu.start(&mut scheduler, false);
+        assert!(!scheduler.is_scheduled(EventKind::ApuSample));
+        apu.write(NR52_ADDRESS, 0x80, &mut scheduler, false);
+        apu.write(0xFF21, 0xF0, &mut scheduler, false);
+        // 2 length clocks left, with length clocks every other 512 Hz step
+        apu.write(0xFF20, 62, &mut scheduler, false);
+        apu.write(0xFF23, 0xC0, &mut scheduler, false);
+        assert_eq!(apu.read(NR52_ADDRESS) & 0x08, 0x08);
+        run_apu_until(&mut apu, &mut scheduler, 8192 * 2);
+        assert_eq!(apu.read(NR52_ADDRESS) & 0x08, 0x08);
+        run_apu_until(&mut apu, &mut scheduler, 8192 * 3);
+        assert_eq!(apu.read(NR52_ADDRESS) & 0x08, 0x00);
+    }
+    #[test]
+    fn test_apu_samples() {
+        let mut scheduler = Scheduler::new();
+        let mut apu = Apu::new(48000, false);
+        apu.start(&mut scheduler, false);
+        apu.write(NR52_ADDRESS, 0x80, &mut scheduler, false);
+        apu.write(0xFF24, 0x77, &mut scheduler, false);
+        apu.write(0xFF25, 0x22, &mut scheduler, false);
+        apu.write(0xFF17, 0xF0, &mut scheduler, false);
+        apu.write(0xFF19, 0x80, &mut scheduler, false);
+        run_apu_until(&mut apu, &mut scheduler, 4_194_304 / 100);
+        let samples = apu.take_samples();
+        assert_eq!(samples.len(), 480);
+        assert!(samples.iter().all(|frame| frame.channel(AudioChannel::Square1) == StereoSample::default()));
+        assert!(samples.iter().any(|frame| frame.mixed.left != 0.0));
+        assert!(samples.iter().all(|frame| frame.mixed == frame.channel(AudioChannel::Square2)));
+    }
+}
@@ -0,0 +1,120 @@
+use rustboy_lib::cartridge::{
+    header::{CartridgeHeader, CgbSupport},
+    mbc::{Cartridge, MapperKind, ROM_BANK_SIZE},
+    CartridgeError
+};
+
+
+#[cfg(test)]
+mod cartridge_tests {
+    use super::*;
+    fn use_test_rom(title: &[u8], cgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x143] = cgb_flag;
+        rom[0x147] = 0x01;
+        rom[0x14E] = 0xBE;
+        rom[0x14F] = 0xEF;
+        return rom
+    }
+    #[test]
+    fn test_parse_header() {
+        let header = CartridgeHeader::parse(&use_test_rom(b"TETRIS", 0x00)).unwrap();
+        assert_eq!(header.title_string(), "TETRIS");
+        assert_eq!(header.cgb_support, CgbSupport::None);
+        assert_eq!(header.cartridge_type, 0x01);
+        assert_eq!(header.global_checksum, 0xBEEF);
+    }
+    #[test]
+    fn test_cgb_flag() {
+        let header = CartridgeHeader::parse(&use_test_rom(b"POKEMON CRYSTAL", 0xC0)).unwrap();
+        assert_eq!(header.cgb_support, CgbSupport::Only);
+        assert_eq!(header.title_string(), "POKEMON CRYSTAL");
+        assert!(header.is_cgb());
+        let header = CartridgeHeader::parse(&use_test_rom(b"ZELDA", 0x80)).unwrap();
+        assert_eq!(header.cgb_support, CgbSupport::Compatible);
+    }
+    #[test]
+    fn test_rom_too_small() {
+        assert_eq!(CartridgeHeader::parse(&[0; 0x100]), Err(CartridgeError::RomTooSmall(0x100)));
+    }
+    // Every bank starts with its own number so reads show which one is mapped
+    fn use_banked_rom(cartridge_type: u8, banks: usize, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        return rom
+    }
+    #[test]
+    fn test_mbc1_banking() {
+        let mut cartridge = Cartridge::new(use_banked_rom(0x03, 64, 0x03));
+        assert_eq!(cartridge.kind(), MapperKind::Mbc1);
+        assert!(cartridge.has_battery());
+        assert_eq!(cartridge.read_rom(0x4000), 1);
+        // Bank 0 can't be selected into the upper half
+        cartridge.write_rom(0x2000, 0x00);
+        assert_eq!(cartridge.read_rom(0x4000), 1);
+        cartridge.write_rom(0x2000, 0x05);
+        cartridge.write_rom(0x4000, 0x01);
+        assert_eq!(cartridge.read_rom(0x4000), 0x25);
+        assert_eq!(cartridge.read_rom(0x0000), 0x00);
+        // In mode 1 BANK2 also applies to the lower half
+        cartridge.write_rom(0x6000, 0x01);
+        assert_eq!(cartridge.read_rom(0x0000), 0x20);
+
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        cartridge.write_rom(0x4000, 0x02);
+        assert_eq!(cartridge.read_ram(0xA000), 0x00);
+        cartridge.write_rom(0x4000, 0x01);
+        assert_eq!(cartridge.read_ram(0xA000), 0x42);
+    }
+    #[test]
+    fn test_mbc3_rtc() {
+        let mut cartridge = Cartridge::new(use_banked_rom(0x10, 8, 0x03));
+        cartridge.write_rom(0x2000, 0x07);
+        assert_eq!(cartridge.read_rom(0x4000), 7);
+        cartridge.write_rom(0x0000, 0x0A);
+        for _ in 0..61 {
+            cartridge.rtc.as_mut().unwrap().tick_second();
+        }
+        // The registers only change when latched
+        cartridge.write_rom(0x4000, 0x08);
+        assert_eq!(cartridge.read_ram(0xA000), 0x00);
+        cartridge.write_rom(0x6000, 0x00);
+        cartridge.write_rom(0x6000, 0x01);
+        assert_eq!(cartridge.read_ram(0xA000), 1);
+        cartridge.write_rom(0x4000, 0x09);
+        assert_eq!(cartridge.read_ram(0xA000), 1);
+    }
+    #[test]
+    fn test_rom_with_ram() {
+        // ROM+RAM+BATTERY has no mapper, so its RAM can be used straight away
+        let mut cartridge = Cartridge::new(use_banked_rom(0x09, 2, 0x02));
+        assert_eq!(cartridge.kind(), MapperKind::None);
+        assert!(cartridge.has_battery());
+        cartridge.write_ram(0xA123, 0x42);
+        assert_eq!(cartridge.read_ram(0xA123), 0x42);
+        cartridge.write_rom(0x0000, 0x00);
+        assert_eq!(cartridge.read_ram(0xA123), 0x42);
+        assert_eq!(cartridge.ram()[0x123], 0x42);
+    }
+    #[test]
+    fn test_mbc5_and_unsupported_mappers() {
+        let mut cartridge = Cartridge::new(use_banked_rom(0x19, 512, 0x00));
+        cartridge.write_rom(0x2000, 0x02);
+        cartridge.write_rom(0x3000, 0x01);
+        assert_eq!(cartridge.rom_bank(), 0x102);
+        // MBC5 can map bank 0 into the upper half
+        cartridge.write_rom(0x2000, 0x00);
+        cartridge.write_rom(0x3000, 0x00);
+        assert_eq!(cartridge.read_rom(0x4000), 0);
+
+        let header = CartridgeHeader::parse(&use_banked_rom(0x22, 2, 0x00)).unwrap();
+        assert_eq!(Cartridge::validate(&header), Err(CartridgeError::UnsupportedMapper(0x22)));
+    }
+}
@@ -1,8 +1,18 @@
 use rustboy_lib::cpu::{
     registers::{Registers, FlagsRegister}, 
-    instructions::{Instructions, RegisterTarget, VirtualRegisterTarget, BitPosition},
+    instructions::{Instructions, RegisterTarget, VirtualRegisterTarget, BitPosition, JumpTest, LoadType, Indirect},
+    interrupts::Interrupt,
+    speed::SPEED_SWITCH_CYCLES,
+    timing::{instruction_cycles, OPCODE_CYCLES},
     CPU
 };
+use rustboy_lib::assembler::assemble_at;
+use rustboy_lib::audio::apu::{NR14_ADDRESS, NR52_ADDRESS};
+use rustboy_lib::cartridge::header::CartridgeHeader;
+use rustboy_lib::memory::bus::MemoryBus;
+use rustboy_lib::memory::boot::BootRom;
+use rustboy_lib::model::Model;
+use rustboy_lib::ppu::lcd::{LY_ADDRESS, STAT_ADDRESS};
 
 
 #[cfg(test)]
@@ -26,6 +36,7 @@ mod cpu_tests {
                 l: 7
             },
             // pc: 1,
+            ..CPU::new()
         }
     }
     fn check_flags_register(cpu_flags: FlagsRegister, compare_flags: FlagsRegister) {
@@ -191,4 +202,434 @@ mod cpu_tests {
         assert_eq!(cpu.registers.h, 6);
         check_flags_register(cpu.registers.f, FlagsRegister{zero:false, subtract: false, half_carry:false, carry:false});
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_stop_dmg() {
+        let mut cpu = use_test_cpu();
+        cpu.bus.speed.write(0x01);
+        cpu.execute(Instructions::STOP);
+        assert!(cpu.stopped);
+        assert!(!cpu.bus.speed.double_speed);
+    }
+    #[test]
+    fn test_stop_speed_switch() {
+        let mut cpu = CPU::new_cgb();
+        assert_eq!(cpu.registers.a, 0x11);
+        cpu.execute(Instructions::STOP);
+        assert!(cpu.stopped);
+
+        let mut cpu = CPU::new_cgb();
+        cpu.bus.speed.write(0x01);
+        assert_eq!(cpu.bus.speed.read(), 0x7F);
+        cpu.execute(Instructions::STOP);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.bus.speed.read(), 0xFE);
+        assert!(cpu.bus.double_speed());
+
+        cpu.bus.speed.write(0x01);
+        cpu.execute(Instructions::STOP);
+        assert_eq!(cpu.bus.speed.read(), 0x7E);
+    }
+    // Runs everything but the CPU for a number of M-cycles
+    fn idle(cpu: &mut CPU, cycles: u32) {
+        for _ in 0..cycles {
+            cpu.bus.tick();
+        }
+    }
+    #[test]
+    fn test_speed_switch_mid_line() {
+        let mut cpu = CPU::power_on(use_test_rom(0x80, 0x42), Model::Cgb, None).unwrap();
+        let ly = cpu.bus.read(LY_ADDRESS);
+        while cpu.bus.read(LY_ADDRESS) == ly {
+            idle(&mut cpu, 1);
+        }
+        let line = cpu.bus.read(LY_ADDRESS);
+        idle(&mut cpu, 40);
+        assert_eq!(cpu.bus.read(STAT_ADDRESS) & 0b11, 3);
+
+        cpu.bus.speed.write(0x01);
+        let start = cpu.cycles;
+        cpu.execute(Instructions::STOP);
+        assert_eq!(cpu.cycles - start, SPEED_SWITCH_CYCLES as u64);
+        assert_eq!(cpu.bus.read(0xFF04), (SPEED_SWITCH_CYCLES * 4 / 256) as u8);
+        // 160 dots before the switch and 4100 during it is 9 lines and 156 dots, which
+        // leaves 300 dots of the line at 2 dots per M-cycle
+        assert_eq!(cpu.bus.read(LY_ADDRESS), line + 9);
+        assert_eq!(cpu.bus.read(STAT_ADDRESS) & 0b11, 3);
+        idle(&mut cpu, 149);
+        assert_eq!(cpu.bus.read(LY_ADDRESS), line + 9);
+        assert_eq!(cpu.bus.read(STAT_ADDRESS) & 0b11, 0);
+        idle(&mut cpu, 1);
+        assert_eq!(cpu.bus.read(LY_ADDRESS), line + 10);
+        assert_eq!(cpu.bus.read(STAT_ADDRESS) & 0b11, 2);
+        idle(&mut cpu, 228);
+        assert_eq!(cpu.bus.read(LY_ADDRESS), line + 11);
+    }
+    // How long channel 1 plays with 4 steps of length, in normal speed T-cycles, switching
+    // to double speed after a number of M-cycles
+    fn length_timing(switch_after: Option<u32>) -> u64 {
+        let mut cpu = CPU::power_on(use_test_rom(0x80, 0x42), Model::Cgb, None).unwrap();
+        cpu.bus.write(0xFF12, 0xF0);
+        cpu.bus.write(0xFF11, 60);
+        cpu.bus.write(NR14_ADDRESS, 0xC0);
+        let mut time = 0;
+        let mut cycles = 0;
+        while cpu.bus.read(NR52_ADDRESS) & 0b1 != 0 {
+            if switch_after == Some(cycles) {
+                cpu.bus.speed.write(0x01);
+                cpu.execute(Instructions::STOP);
+                time += SPEED_SWITCH_CYCLES as u64 * 2;
+            }
+            idle(&mut cpu, 1);
+            cycles += 1;
+            time += if cpu.bus.double_speed() { 2 } else { 4 };
+        }
+        return time
+    }
+    #[test]
+    fn test_speed_switch_length_timing() {
+        let normal = length_timing(None);
+        assert!(normal > 3 * 16384 && normal <= 4 * 16384);
+        for switch_after in [1000, 5000, 10000] {
+            assert!(length_timing(Some(switch_after)).abs_diff(normal) < 4, "switching after {}", switch_after);
+        }
+    }
+    #[test]
+    fn test_cgb_cartridge_detection() {
+        let mut rom = vec![0; 0x8000];
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert!(!CPU::for_cartridge(&header).cgb_mode);
+        rom[0x143] = 0x80;
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        let cpu = CPU::for_cartridge(&header);
+        assert!(cpu.cgb_mode);
+        assert_eq!(cpu.registers.a, 0x11);
+    }
+    fn use_test_rom(cgb_flag: u8, header_checksum: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13F].copy_from_slice(b"POKEMON RED");
+        rom[0x143] = cgb_flag;
+        rom[0x14B] = 0x01;
+        rom[0x14D] = header_checksum;
+        return rom
+    }
+    #[test]
+    fn test_post_boot_dmg() {
+        let cpu = CPU::power_on(use_test_rom(0x00, 0x42), Model::Dmg, None).unwrap();
+        assert_eq!(cpu.registers.get_af(), 0x01B0);
+        assert_eq!(cpu.registers.get_bc(), 0x0013);
+        assert_eq!(cpu.registers.get_de(), 0x00D8);
+        assert_eq!(cpu.registers.get_hl(), 0x014D);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.pc, 0x0100);
+        assert_eq!(cpu.bus.read(0xFF40), 0x91);
+        assert_eq!(cpu.bus.read(0xFF04), 0xAB);
+        assert!(!cpu.cgb_mode);
+
+        let cpu = CPU::power_on(use_test_rom(0x00, 0x00), Model::Mgb, None).unwrap();
+        assert_eq!(cpu.registers.get_af(), 0xFF80);
+    }
+    #[test]
+    fn test_post_boot_sgb() {
+        let cpu = CPU::power_on(use_test_rom(0x00, 0x42), Model::Sgb, None).unwrap();
+        assert_eq!(cpu.registers.get_af(), 0x0100);
+        assert_eq!(cpu.registers.get_bc(), 0x0014);
+        assert_eq!(cpu.registers.get_hl(), 0xC060);
+        assert_eq!(cpu.bus.read(0xFF26), 0xF0);
+    }
+    #[test]
+    fn test_post_boot_cgb() {
+        let cpu = CPU::power_on(use_test_rom(0x80, 0x42), Model::Cgb, None).unwrap();
+        assert!(cpu.cgb_mode);
+        assert_eq!(cpu.registers.get_af(), 0x1180);
+        assert_eq!(cpu.registers.get_bc(), 0x0000);
+        assert_eq!(cpu.registers.get_de(), 0xFF56);
+        assert_eq!(cpu.registers.get_hl(), 0x000D);
+        assert_eq!(cpu.bus.read(0xFF4D), 0x7E);
+
+        let cpu = CPU::power_on(use_test_rom(0x80, 0x42), Model::Agb, None).unwrap();
+        assert_eq!(cpu.registers.get_af(), 0x1100);
+        assert_eq!(cpu.registers.get_bc(), 0x0100);
+
+        // DMG cartridge on a CGB, B holds the title checksum
+        let cpu = CPU::power_on(use_test_rom(0x00, 0x42), Model::Cgb, None).unwrap();
+        assert!(!cpu.cgb_mode);
+        assert_eq!(cpu.registers.get_af(), 0x1180);
+        assert_eq!(cpu.registers.get_bc(), 0x1400);
+        assert_eq!(cpu.registers.get_de(), 0x0008);
+        assert_eq!(cpu.bus.read(0xFF4D), 0xFF);
+    }
+    #[test]
+    fn test_boot_rom_mapping() {
+        let mut boot = vec![0x31; 0x100];
+        boot[0] = 0xAA;
+        let mut rom = use_test_rom(0x00, 0x42);
+        rom[0] = 0x55;
+        let mut cpu = CPU::power_on(rom, Model::Dmg, Some(BootRom::new(boot).unwrap())).unwrap();
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.registers.get_af(), 0x0000);
+        assert_eq!(cpu.bus.read(0x0000), 0xAA);
+        assert_eq!(cpu.bus.read(0x0100), 0x00);
+        cpu.bus.write(0xFF50, 0x00);
+        assert_eq!(cpu.bus.read(0x0000), 0xAA);
+        cpu.bus.write(0xFF50, 0x01);
+        assert!(!cpu.bus.boot_rom_mapped());
+        assert_eq!(cpu.bus.read(0x0000), 0x55);
+    }
+    #[test]
+    fn test_cgb_boot_rom_skips_header() {
+        let mut rom = use_test_rom(0x80, 0x42);
+        rom[0x0200] = 0x77;
+        let cpu = CPU::power_on(rom, Model::Cgb, Some(BootRom::new(vec![0x11; 0x900]).unwrap())).unwrap();
+        assert_eq!(cpu.bus.read(0x00FF), 0x11);
+        assert_eq!(cpu.bus.read(0x0143), 0x80);
+        assert_eq!(cpu.bus.read(0x0200), 0x11);
+        assert_eq!(cpu.bus.read(0x0900), 0x00);
+        assert!(BootRom::new(vec![0; 0x200]).is_err());
+    }
+    fn use_program_cpu(program: &[u8]) -> CPU {
+        let mut cpu = use_test_cpu();
+        for (offset, byte) in program.iter().enumerate() {
+            cpu.bus.write(0xC000 + offset as u16, *byte);
+        }
+        cpu.pc = 0xC000;
+        cpu.sp = 0xDFF0;
+        return cpu
+    }
+    // Like use_program_cpu with the program in assembly. ROM sections go in the cartridge
+    fn cpu_with_program(source: &str) -> CPU {
+        let program = assemble_at(source, 0xC000).unwrap_or_else(|error| panic!("{}", error));
+        let mut cpu = use_program_cpu(&[]);
+        cpu.bus = MemoryBus::new(program.rom(), Model::Dmg, false);
+        for section in program.sections.iter().filter(|section| !section.kind.is_rom()) {
+            for (offset, byte) in section.bytes.iter().enumerate() {
+                cpu.bus.write(section.address + offset as u16, *byte);
+            }
+        }
+        return cpu
+    }
+    #[test]
+    fn test_set_and_rotates() {
+        let mut cpu = use_test_cpu();
+        cpu.execute(Instructions::SET(RegisterTarget::B, BitPosition::B7));
+        assert_eq!(cpu.registers.b, 0x82);
+        cpu.execute(Instructions::RLC(RegisterTarget::B));
+        assert_eq!(cpu.registers.b, 0x05);
+        assert!(cpu.registers.f.carry);
+        cpu.execute(Instructions::RL(RegisterTarget::C));
+        assert_eq!(cpu.registers.c, 0x07);
+        assert!(!cpu.registers.f.carry);
+        cpu.registers.d = 0x80;
+        cpu.execute(Instructions::SLA(RegisterTarget::D));
+        check_flags_register(cpu.registers.f, FlagsRegister{zero:true, subtract:false, carry: true, half_carry: false});
+        cpu.execute(Instructions::INC(RegisterTarget::E));
+        check_flags_register(cpu.registers.f, FlagsRegister{zero:false, subtract:false, carry: true, half_carry: false});
+    }
+    #[test]
+    fn test_decode() {
+        let mut bytes = [0x34, 0x12].into_iter();
+        assert_eq!(Instructions::decode(0x01, || bytes.next().unwrap()), Instructions::LD(LoadType::Word(VirtualRegisterTarget::BC, 0x1234)));
+        assert_eq!(Instructions::decode(0x7E, || 0), Instructions::LD(LoadType::Byte(RegisterTarget::A, RegisterTarget::HLI)));
+        assert_eq!(Instructions::decode(0xFE, || 0x42), Instructions::CP(RegisterTarget::D8(0x42)));
+        assert_eq!(Instructions::decode(0x20, || 0xFE), Instructions::JR(JumpTest::NotZero, -2));
+        assert_eq!(Instructions::decode(0x3A, || 0), Instructions::LD(LoadType::AFromIndirect(Indirect::HLDec)));
+        assert_eq!(Instructions::decode(0xCB, || 0xFE), Instructions::SET(RegisterTarget::HLI, BitPosition::B7));
+        assert_eq!(Instructions::decode(0xFF, || 0), Instructions::RST(0x38));
+        assert_eq!(Instructions::decode(0xDD, || 0), Instructions::INVALID(0xDD));
+    }
+    #[test]
+    fn test_program() {
+        // ld hl, $C100; ld a, 5; ld [hl+], a; add a, [hl]; dec hl; push hl; pop bc
+        let mut cpu = use_program_cpu(&[0x21, 0x00, 0xC1, 0x3E, 0x05, 0x22, 0x86, 0x2B, 0xE5, 0xC1]);
+        cpu.bus.write(0xC101, 0x10);
+        for _ in 0..7 {
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.read(0xC100), 0x05);
+        assert_eq!(cpu.registers.a, 0x15);
+        assert_eq!(cpu.registers.get_bc(), 0xC100);
+        assert_eq!(cpu.sp, 0xDFF0);
+        assert_eq!(cpu.pc, 0xC00A);
+        assert_eq!(cpu.cycles, 3 + 2 + 2 + 2 + 2 + 4 + 3);
+    }
+    #[test]
+    fn test_call_and_ret() {
+        // call $C010; ... $C010: ret
+        let mut program = vec![0xCD, 0x10, 0xC0];
+        program.resize(0x10, 0x00);
+        program.push(0xC9);
+        let mut cpu = use_program_cpu(&program);
+        assert_eq!(cpu.step(), 6);
+        assert_eq!(cpu.pc, 0xC010);
+        assert_eq!(cpu.sp, 0xDFEE);
+        assert_eq!(cpu.bus.read(0xDFEE), 0x03);
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.pc, 0xC003);
+    }
+    #[test]
+    fn test_assembled_program() {
+        let mut cpu = cpu_with_program("
+            ld a, 5
+            add a, b
+            ld hl, Values
+            ld c, [hl]
+            call Double
+            halt
+        Double:
+            sla c
+            ret
+        Values:
+            db $21
+        ");
+        for _ in 0..7 {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers.a, 7);
+        assert_eq!(cpu.registers.c, 0x42);
+        assert_eq!(cpu.pc, 0xC00A);
+    }
+    #[test]
+    fn test_assembled_rom_sections() {
+        let mut cpu = cpu_with_program("
+            call Add
+            ld [wResult], a
+        SECTION \"Home\", ROM0[$0100]
+        Add:
+            ld a, [Operand]
+            add a, a
+            ret
+        Operand:
+            db 21
+        SECTION \"Variables\", WRAM0[$C100]
+        wResult: db $FF
+        ");
+        assert_eq!(cpu.bus.read(0xC100), 0xFF);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(cpu.bus.read(0xC100), 42);
+        assert_eq!(cpu.pc, 0xC006);
+    }
+    #[test]
+    fn test_cycle_table() {
+        for opcode in 0..=255u8 {
+            if OPCODE_CYCLES[opcode as usize] == 0 {
+                continue;
+            }
+            let mut cpu = use_program_cpu(&[opcode, 0x00, 0x00]);
+            let taken = matches!(opcode, 0x20 | 0x30 | 0xC0 | 0xD0 | 0xC2 | 0xD2 | 0xC4 | 0xD4);
+            assert_eq!(cpu.step(), instruction_cycles(opcode, 0, taken) as u32, "opcode {:02X}", opcode);
+        }
+        for opcode in 0..=255u8 {
+            let mut cpu = use_program_cpu(&[0xCB, opcode]);
+            assert_eq!(cpu.step(), instruction_cycles(0xCB, opcode, false) as u32, "opcode CB {:02X}", opcode);
+        }
+    }
+    #[test]
+    fn test_instruction_results() {
+        // (program, A, F, A after, F after) for the instructions that are easy to get
+        // subtly wrong, with F as Z N H C in the upper nibble
+        let cases: [(&[u8], u8, u8, u8, u8); 18] = [
+            (&[0x07], 0x85, 0x00, 0x0B, 0x10),       // rlca
+            (&[0x0F], 0x01, 0x00, 0x80, 0x10),       // rrca
+            (&[0x17], 0x01, 0x10, 0x03, 0x00),       // rla takes the carry into bit 0
+            (&[0x17], 0x80, 0x80, 0x00, 0x10),       // and never sets Z
+            (&[0x1F], 0x01, 0x10, 0x80, 0x10),       // rra
+            (&[0xCB, 0x17], 0x80, 0x00, 0x00, 0x90), // rl a does set Z
+            (&[0xCB, 0x17], 0x01, 0x10, 0x03, 0x00),
+            (&[0xCB, 0x1F], 0x01, 0x00, 0x00, 0x90), // rr a
+            (&[0x27], 0x9A, 0x00, 0x00, 0x90),       // daa after an addition
+            (&[0x27], 0x0F, 0x60, 0x09, 0x40),       // daa after a subtraction
+            (&[0xCE, 0x00], 0x0F, 0x10, 0x10, 0x20), // adc a, 0 carries into bit 4
+            (&[0xCE, 0xFF], 0x00, 0x10, 0x00, 0xB0),
+            (&[0xDE, 0x00], 0x10, 0x10, 0x0F, 0x60), // sbc a, 0 borrows from bit 4
+            (&[0xDE, 0xFF], 0x00, 0x10, 0x00, 0xF0),
+            (&[0x2F], 0x35, 0x00, 0xCA, 0x60),       // cpl
+            (&[0x37], 0x00, 0xE0, 0x00, 0x90),       // scf
+            (&[0x3F], 0x00, 0xF0, 0x00, 0x80),       // ccf
+            (&[0x3F], 0x00, 0x80, 0x00, 0x90),
+        ];
+        for (program, a, f, expected_a, expected_f) in cases {
+            let mut cpu = use_program_cpu(program);
+            cpu.registers.a = a;
+            cpu.registers.f = FlagsRegister::from(f);
+            cpu.step();
+            assert_eq!((cpu.registers.a, u8::from(cpu.registers.f)), (expected_a, expected_f), "{:02X?} with A={:02X} F={:02X}", program, a, f);
+        }
+    }
+    #[test]
+    fn test_memory_access_timing() {
+        // DIV goes up every 64 M-cycles. Starting 2 M-cycles short of that, ld a, [hl]
+        // reads it on its second cycle and sees the new value
+        let mut cpu = use_program_cpu(&[0x7E]);
+        cpu.registers.set_hl(0xFF04);
+        cpu.bus.timer.set_counter(0x100 - 8, &mut cpu.bus.scheduler);
+        cpu.step();
+        assert_eq!(cpu.registers.a, 0x01);
+
+        let mut cpu = use_program_cpu(&[0x7E]);
+        cpu.registers.set_hl(0xFF04);
+        cpu.bus.timer.set_counter(0x100 - 12, &mut cpu.bus.scheduler);
+        cpu.step();
+        assert_eq!(cpu.registers.a, 0x00);
+
+        // ld a, [$FF04] only reads on its fourth cycle
+        let mut cpu = use_program_cpu(&[0xFA, 0x04, 0xFF]);
+        cpu.bus.timer.set_counter(0x100 - 16, &mut cpu.bus.scheduler);
+        cpu.step();
+        assert_eq!(cpu.registers.a, 0x01);
+    }
+    #[test]
+    fn test_interrupt_dispatch() {
+        // ei; nop; nop
+        let mut cpu = use_program_cpu(&[0xFB, 0x00, 0x00]);
+        cpu.bus.write(0xFFFF, Interrupt::Timer.bit() | Interrupt::Serial.bit());
+        cpu.bus.request_interrupt(Interrupt::Serial);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.ime);
+        // The instruction after EI still runs before the interrupt
+        cpu.step();
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, Interrupt::Timer.vector());
+        assert!(!cpu.ime);
+        assert_eq!(cpu.bus.read(0xFF0F), 0xE0 | Interrupt::Serial.bit());
+        assert_eq!(cpu.bus.read(0xDFEF), 0xC0);
+        assert_eq!(cpu.bus.read(0xDFEE), 0x02);
+    }
+    #[test]
+    fn test_halt() {
+        // halt; inc a
+        let mut cpu = use_program_cpu(&[0x76, 0x3C]);
+        cpu.bus.write(0xFFFF, Interrupt::Timer.bit());
+        cpu.step();
+        assert!(cpu.halted);
+        cpu.step();
+        assert!(cpu.halted);
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.halted);
+        // IME is off, so execution carries on after HALT
+        cpu.step();
+        assert_eq!(cpu.registers.a, 2);
+
+        // HALT with IME off and an interrupt already pending runs the next byte twice
+        let mut cpu = use_program_cpu(&[0x76, 0x3C]);
+        cpu.bus.write(0xFFFF, Interrupt::Timer.bit());
+        cpu.bus.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.halted);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers.a, 3);
+        assert_eq!(cpu.pc, 0xC002);
+    }
+    #[test]
+    fn test_invalid_opcode_locks() {
+        let mut cpu = use_program_cpu(&[0xD3, 0x3C]);
+        cpu.step();
+        assert!(cpu.locked);
+        cpu.step();
+        assert_eq!(cpu.pc, 0xC001);
+    }
+}
@@ -0,0 +1,213 @@
+use rustboy_lib::cpu::instructions::{Indirect, Instructions, JumpTest, LoadType, RegisterTarget, BitPosition};
+use rustboy_lib::debugger::condition::Condition;
+use rustboy_lib::debugger::watch::{Access, MemoryAccess, WatchKind};
+use rustboy_lib::debugger::{disassemble_around, format_pc, format_registers, parse_address, parse_location, Debugger, StopReason};
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::symbols::Symbols;
+
+
+#[cfg(test)]
+mod debugger_tests {
+    use super::*;
+    // An MBC1 cartridge that calls 0x4000 in bank 2 and then bank 3, and then counts in
+    // 0xC000 forever, copying the count to 0xC010
+    fn use_test_gameboy() -> GameBoy {
+        let mut rom = vec![0; 0x10000];
+        let home = [
+            0x31, 0xFE, 0xFF,       // 0x0150: ld sp, $FFFE
+            0x3E, 0x02,             // 0x0153: ld a, 2
+            0xEA, 0x00, 0x20,       // 0x0155: ld [$2000], a
+            0xCD, 0x00, 0x40,       // 0x0158: call $4000
+            0x3E, 0x03,             // 0x015B: ld a, 3
+            0xEA, 0x00, 0x20,       // 0x015D: ld [$2000], a
+            0xCD, 0x00, 0x40,       // 0x0160: call $4000
+            0x21, 0x00, 0xC0,       // 0x0163: ld hl, $C000
+            0x34,                   // 0x0166: inc [hl]
+            0x7E,                   // 0x0167: ld a, [hl]
+            0xEA, 0x10, 0xC0,       // 0x0168: ld [$C010], a
+            0x18, 0xF9,             // 0x016B: jr $0166
+        ];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150..0x150 + home.len()].copy_from_slice(&home);
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        // Bank 2 calls a function of its own, bank 3 just returns
+        rom[0x8000..0x8006].copy_from_slice(&[0x3E, 0x22, 0xCD, 0x10, 0x40, 0xC9]);
+        rom[0x8010..0x8013].copy_from_slice(&[0x06, 0x07, 0xC9]);
+        rom[0xC000..0xC003].copy_from_slice(&[0x3E, 0x33, 0xC9]);
+        return GameBoy::new(rom, GameBoyOptions::new()).unwrap()
+    }
+    const LIMIT: u64 = 100_000;
+    #[test]
+    fn test_breakpoints() {
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        let banked = debugger.add_breakpoint(0x4000, Some(3), None);
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Breakpoint(banked));
+        assert_eq!((gameboy.cpu.pc, gameboy.cpu.registers.a), (0x4000, 0x03));
+
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        let any_bank = debugger.add_breakpoint(0x4000, None, None);
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Breakpoint(any_bank));
+        assert_eq!(gameboy.cpu.registers.a, 0x02);
+        // Doesn't stop again where it already is
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Breakpoint(any_bank));
+        assert_eq!(gameboy.cpu.registers.a, 0x03);
+        assert!(debugger.remove(any_bank));
+        assert!(!debugger.remove(any_bank));
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Limit);
+    }
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        let condition = "a == 0x33".parse::<Condition>().unwrap();
+        let id = debugger.add_breakpoint(0x4002, None, Some(condition));
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Breakpoint(id));
+        assert_eq!(gameboy.cpu.bus.bank_at(0x4000), 3);
+        assert_eq!(debugger.breakpoints()[0].to_string(), "#1 $4002 if a == $33");
+    }
+    #[test]
+    fn test_stepping() {
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run_to(&mut gameboy, 0x0158, LIMIT), StopReason::Done);
+        assert_eq!(debugger.step(&mut gameboy), StopReason::Done);
+        assert_eq!(gameboy.cpu.pc, 0x4000);
+
+        let mut gameboy = use_test_gameboy();
+        debugger.run_to(&mut gameboy, 0x0158, LIMIT);
+        assert_eq!(debugger.step_over(&mut gameboy, LIMIT), StopReason::Done);
+        assert_eq!(gameboy.cpu.pc, 0x015B);
+        assert_eq!((gameboy.cpu.registers.a, gameboy.cpu.registers.b), (0x22, 0x07));
+        // Not a call, so just a step
+        assert_eq!(debugger.step_over(&mut gameboy, LIMIT), StopReason::Done);
+        assert_eq!(gameboy.cpu.pc, 0x015D);
+
+        let mut gameboy = use_test_gameboy();
+        debugger.run_to(&mut gameboy, 0x4010, LIMIT);
+        assert_eq!(debugger.step_out(&mut gameboy, LIMIT), StopReason::Done);
+        assert_eq!(gameboy.cpu.pc, 0x4005);
+        assert_eq!(debugger.step_out(&mut gameboy, LIMIT), StopReason::Done);
+        assert_eq!(gameboy.cpu.pc, 0x015B);
+        assert_eq!(gameboy.cpu.sp, 0xFFFE);
+
+        // Stepping over stops at breakpoints inside the call
+        let mut gameboy = use_test_gameboy();
+        debugger.run_to(&mut gameboy, 0x0158, LIMIT);
+        let id = debugger.add_breakpoint(0x4010, None, None);
+        assert_eq!(debugger.step_over(&mut gameboy, LIMIT), StopReason::Breakpoint(id));
+    }
+    #[test]
+    fn test_watchpoints() {
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        let write = debugger.add_watchpoint(0xC010, 0xC010, WatchKind::Write);
+        let access = MemoryAccess { address: 0xC010, value: 0x01, access: Access::Write };
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Watchpoint(write, access));
+        assert_eq!(gameboy.cpu.pc, 0x016B);
+        let access = MemoryAccess { address: 0xC010, value: 0x02, access: Access::Write };
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Watchpoint(write, access));
+
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        let read = debugger.add_watchpoint(0xC00F, 0xC000, WatchKind::Read);
+        let access = MemoryAccess { address: 0xC000, value: 0x00, access: Access::Read };
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Watchpoint(read, access));
+        assert_eq!(gameboy.cpu.pc, 0x0167);
+        assert_eq!(debugger.watchpoints()[0].to_string(), "#1 $C000-$C00F read");
+
+        // Opcode fetches aren't reads
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x0166, 0x016C, WatchKind::Read);
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Limit);
+    }
+    #[test]
+    fn test_conditions() {
+        let condition: Condition = "hl >= $C000 && [hl] != %10 && [$C010]<=10".parse().unwrap();
+        assert_eq!(condition.to_string(), "hl >= $C000 && [hl] != $02 && [$C010] <= $0A");
+        assert!("a = 3".parse::<Condition>().is_err());
+        assert!("q == 1".parse::<Condition>().is_err());
+        assert!("a == $10000".parse::<Condition>().is_err());
+
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        debugger.run_to(&mut gameboy, 0x0168, LIMIT);
+        assert!(condition.holds(&gameboy.cpu));
+        assert!(!"pc != $0168".parse::<Condition>().unwrap().holds(&gameboy.cpu));
+
+        let symbols = Symbols::new();
+        assert_eq!(parse_location("3:$4000", &symbols), Ok((Some(3), 0x4000)));
+        assert_eq!(parse_location("0x150", &symbols), Ok((None, 0x150)));
+        assert!(parse_location("x:1", &symbols).is_err());
+    }
+    #[test]
+    fn test_disassembly() {
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        debugger.run_to(&mut gameboy, 0x015B, LIMIT);
+        let lines = disassemble_around(&gameboy.cpu.bus, 0x015B, 2, 2);
+        let addresses: Vec<u16> = lines.iter().map(|line| line.address).collect();
+        assert_eq!(addresses, [0x0155, 0x0158, 0x015B, 0x015D]);
+        assert_eq!(lines[1].to_string(), "$0158  CD 00 40  call $4000");
+        let lines = disassemble_around(&gameboy.cpu.bus, 0x016B, 0, 1);
+        assert_eq!(lines[0].to_string(), "$016B  18 F9     jr @-5 ; $0166");
+        assert!(format_registers(&gameboy.cpu).contains("PC=00:015B"));
+
+        assert_eq!(Instructions::LD(LoadType::AFromIndirect(Indirect::High(0x44))).to_string(), "ldh a, [$FF44]");
+        assert_eq!(Instructions::LD(LoadType::IndirectFromA(Indirect::HLInc)).to_string(), "ld [hli], a");
+        assert_eq!(Instructions::LD(LoadType::HLFromSPOffset(-3)).to_string(), "ld hl, sp - 3");
+        assert_eq!(Instructions::JR(JumpTest::NotZero, -2).to_string(), "jr nz, @+0");
+        assert_eq!(Instructions::BIT(RegisterTarget::HLI, BitPosition::B7).to_string(), "bit 7, [hl]");
+        assert_eq!(Instructions::CP(RegisterTarget::D8(0x90)).to_string(), "cp a, $90");
+        assert_eq!(Instructions::RST(0x38).to_string(), "rst $38");
+        assert_eq!(Instructions::INVALID(0xD3).to_string(), "db $D3");
+    }
+    #[test]
+    fn test_labels() {
+        let symbols = Symbols::parse("00:0150 Main\n00:0166 Main.loop\n02:4000 BankTwo\n02:4010 BankTwo.helper\n00:c000 wCount").unwrap();
+        assert_eq!(parse_location("BankTwo.helper", &symbols), Ok((Some(2), 0x4010)));
+        assert_eq!(parse_location("Main.loop", &symbols), Ok((None, 0x0166)));
+        assert_eq!(parse_address("wCount", &symbols), Ok(0xC000));
+        assert!(parse_location("Nowhere", &symbols).is_err());
+
+        let mut gameboy = use_test_gameboy();
+        let mut debugger = Debugger::new();
+        let (bank, address) = parse_location("BankTwo.helper", &symbols).unwrap();
+        let id = debugger.add_breakpoint(address, bank, None);
+        assert_eq!(debugger.run(&mut gameboy, LIMIT), StopReason::Breakpoint(id));
+        assert_eq!(format_pc(&gameboy.cpu, &symbols), "02:4010 BankTwo.helper");
+        let line = &disassemble_around(&gameboy.cpu.bus, 0x4002, 0, 1)[0];
+        assert_eq!(line.labelled(2, &symbols), "$4002  CD 10 40  call BankTwo.helper");
+
+        debugger.run_to(&mut gameboy, 0x0168, LIMIT);
+        assert_eq!(format_pc(&gameboy.cpu, &symbols), "00:0168 Main.loop+$2");
+        let line = &disassemble_around(&gameboy.cpu.bus, 0x016B, 0, 1)[0];
+        assert_eq!(line.labelled(0, &symbols), "$016B  18 F9     jr Main.loop");
+        let line = &disassemble_around(&gameboy.cpu.bus, 0x0168, 0, 1)[0];
+        assert_eq!(line.labelled(0, &symbols), "$0168  EA 10 C0  ld [$C010], a");
+    }
+    #[test]
+    fn test_symbol_lookup() {
+        // Out of order, with two names for $0200 and a big table of labels in bank 1
+        let mut text = String::from("00:0200 Second\n00:c000 wStart\n00:0100 Start\n00:0200 Alias\n");
+        for index in 0..2000 {
+            text.push_str(&format!("01:{:04x} Table{}\n", 0x4000 + index * 8, index));
+        }
+        let symbols = Symbols::parse(&text).unwrap();
+        assert_eq!(symbols.len(), 2004);
+        assert_eq!(symbols.iter().next().unwrap().name, "Start");
+        assert_eq!(symbols.name(0, 0x0200), Some(String::from("Second")));
+        assert_eq!(symbols.name(0, 0x0234), Some(String::from("Second+$34")));
+        assert_eq!(symbols.label_at(Some(0), 0x0200), Some("Second"));
+        assert_eq!(symbols.locate(1, 0x4000 + 1234 * 8 + 5), Some(("Table1234", 5)));
+        assert_eq!(symbols.locate(1, 0x7FFF), Some(("Table1999", 0x7FFF - (0x4000 + 1999 * 8))));
+        // Nothing before the first label, and none carried into another region or bank
+        assert_eq!(symbols.locate(0, 0x00FF), None);
+        assert_eq!(symbols.locate(0, 0x4000), None);
+        assert_eq!(symbols.locate(2, 0x4000), None);
+        assert_eq!(symbols.name(0, 0xC001), Some(String::from("wStart+$1")));
+        assert_eq!(Symbols::parse(&symbols.to_sym()).unwrap(), symbols);
+    }
+}
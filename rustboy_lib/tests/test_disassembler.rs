@@ -0,0 +1,110 @@
+use rustboy_lib::cpu::instructions::{Instructions, JumpTest, LoadType, RegisterTarget};
+use rustboy_lib::disassembler::{decode, disassemble, format_instruction, listing, DisassemblyLine};
+use rustboy_lib::symbols::Symbols;
+
+
+#[cfg(test)]
+mod disassembler_tests {
+    use super::*;
+    fn use_test_symbols() -> Symbols {
+        return Symbols::parse("\
+; File generated by rgblink
+00:0150 Main
+00:0153 Main.loop
+01:4000 Bank1Func
+02:4000 Bank2Func
+02:4005 Bank2Table
+00:c000 wCounter
+00:ff80 hScratch
+").unwrap()
+    }
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(&[0x3E, 0x05, 0xFF]), Some((Instructions::LD(LoadType::Byte(RegisterTarget::A, RegisterTarget::D8(0x05))), 2)));
+        assert_eq!(decode(&[0xCB, 0x7C]).map(|(_, length)| length), Some(2));
+        assert_eq!(decode(&[0xCD, 0x00]), None);
+        assert_eq!(decode(&[]), None);
+    }
+    #[test]
+    fn test_linear_sweep() {
+        let bytes = [0x00, 0xD3, 0xDB, 0xE4, 0x20, 0xFE, 0xCD, 0x00];
+        let lines = disassemble(&bytes, 0x0150);
+        assert_eq!(lines, [
+            DisassemblyLine { address: 0x0150, bytes: vec![0x00], instruction: Some(Instructions::NOP) },
+            DisassemblyLine { address: 0x0151, bytes: vec![0xD3, 0xDB, 0xE4], instruction: None },
+            DisassemblyLine { address: 0x0154, bytes: vec![0x20, 0xFE], instruction: Some(Instructions::JR(JumpTest::NotZero, -2)) },
+            // The call is cut off by the end, so the sweep carries on after its opcode
+            DisassemblyLine { address: 0x0156, bytes: vec![0xCD], instruction: None },
+            DisassemblyLine { address: 0x0157, bytes: vec![0x00], instruction: Some(Instructions::NOP) },
+        ]);
+        assert_eq!(lines[1].to_string(), "$0151  D3 DB E4  db $D3, $DB, $E4");
+        assert_eq!(lines[2].to_string(), "$0154  20 FE     jr nz, @+0 ; $0154");
+        // Long runs of data are split into lines of 8
+        let lines = disassemble(&[0xDD; 20], 0x4000);
+        let lengths: Vec<usize> = lines.iter().map(|line| line.bytes.len()).collect();
+        assert_eq!(lengths, [8, 8, 4]);
+    }
+    #[test]
+    fn test_labels() {
+        let symbols = use_test_symbols();
+        assert_eq!(symbols.len(), 7);
+        assert_eq!(symbols.label_at(Some(2), 0x4000), Some("Bank2Func"));
+        assert_eq!(symbols.label_at(Some(3), 0x4000), None);
+        assert_eq!(symbols.label_at(None, 0x4000), Some("Bank1Func"));
+
+        let format = |instruction, address, bank| format_instruction(&instruction, address, bank, &symbols);
+        assert_eq!(format(Instructions::JR(JumpTest::Always, -3), 0x0154, 0), "jr Main.loop");
+        assert_eq!(format(Instructions::JR(JumpTest::Carry, 0x10), 0x0154, 0), "jr c, $0166");
+        assert_eq!(format(Instructions::CALL(JumpTest::Zero, 0x0150), 0x4000, 1), "call z, Main");
+        // Which bank 0x4000 is depends on the code calling it
+        assert_eq!(format(Instructions::CALL(JumpTest::Always, 0x4005), 0x4000, 2), "call Bank2Table");
+        assert_eq!(format(Instructions::CALL(JumpTest::Always, 0x4005), 0x4000, 1), "call $4005");
+        assert_eq!(format(Instructions::CALL(JumpTest::Always, 0x4005), 0x0200, 0), "call Bank2Table");
+        let load = Instructions::LD(LoadType::AFromIndirect(rustboy_lib::cpu::instructions::Indirect::Word(0xC000)));
+        assert_eq!(format(load, 0x0150, 0), "ld a, [wCounter]");
+        let load = Instructions::LD(LoadType::IndirectFromA(rustboy_lib::cpu::instructions::Indirect::High(0x80)));
+        assert_eq!(format(load, 0x0150, 0), "ldh [hScratch], a");
+        // Immediates aren't taken for addresses
+        let load = Instructions::LD(LoadType::Word(rustboy_lib::cpu::instructions::VirtualRegisterTarget::HL, 0xC000));
+        assert_eq!(format(load, 0x0150, 0), "ld hl, $C000");
+
+        assert!(Symbols::parse("00:0150").is_err());
+        assert!(Symbols::parse("zz:0150 Main").is_err());
+        assert_eq!(Symbols::parse("\n; nothing\n"), Ok(Symbols::new()));
+
+        let symbols = use_test_symbols();
+        let main = symbols.find("Main").unwrap();
+        assert_eq!((main.bank, main.address), (0, 0x0150));
+        assert!(symbols.find("main").is_none());
+        assert_eq!(symbols.locate(0, 0x0155), Some(("Main.loop", 2)));
+        assert_eq!(symbols.name(2, 0x4009), Some(String::from("Bank2Table+$4")));
+        assert_eq!(symbols.name(0, 0xC000), Some(String::from("wCounter")));
+        assert_eq!(symbols.name(0, 0x0100), None);
+        assert_eq!(symbols.name(3, 0x4000), None);
+        // Labels don't reach into the next part of memory
+        assert_eq!(symbols.name(0, 0x8000), None);
+        assert_eq!(symbols.name(0, 0xD000), None);
+    }
+    #[test]
+    fn test_listing() {
+        let symbols = use_test_symbols();
+        let bytes = [0x3E, 0x22, 0xCD, 0x05, 0x40, 0x01, 0x02, 0x03, 0xC9];
+        let mut lines = disassemble(&bytes[..5], 0x4000);
+        lines.extend(disassemble(&[0xDD, 0xDD], 0x4005));
+        let text = listing(&lines, 2, &symbols);
+        assert_eq!(text, "\
+Bank2Func:
+    ld a, $22                    ; $4000: 3E 22
+    call Bank2Table              ; $4002: CD 05 40
+Bank2Table:
+    db $DD, $DD                  ; $4005: DD DD
+");
+        // A label in the middle of some data splits it
+        let lines = disassemble(&[0xDD, 0xDD, 0xDD], 0x4003);
+        assert_eq!(listing(&lines, 2, &symbols), concat!(
+            "    db $DD, $DD                  ; $4003: DD DD\n",
+            "Bank2Table:\n",
+            "    db $DD                       ; $4005: DD\n",
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+use rustboy_lib::cpu::interrupts::Interrupt;
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::joypad::Button;
+use rustboy_lib::model::Model;
+use rustboy_lib::ppu::lcd::DMG_SHADES;
+use rustboy_lib::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use rustboy_lib::sgb::{SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
+
+
+#[cfg(test)]
+mod gameboy_tests {
+    use super::*;
+    // A cartridge that spins on JR -2 at the entry point
+    fn use_test_rom(cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100] = 0x18;
+        rom[0x101] = 0xFE;
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        return rom
+    }
+    fn use_test_gameboy() -> GameBoy {
+        return GameBoy::new(use_test_rom(0x00, 0x00), GameBoyOptions::new()).unwrap()
+    }
+    #[test]
+    fn test_run_frame_timing() {
+        let mut gameboy = use_test_gameboy();
+        assert_eq!(gameboy.run_frame().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(gameboy.cpu.bus.ppu.ly(), 144);
+        let start = gameboy.cpu.cycles;
+        gameboy.run_frame();
+        // 70224 dots, give or take the instruction that was running when VBlank started
+        assert!((gameboy.cpu.cycles - start).abs_diff(17556) <= 3);
+        assert_ne!(gameboy.cpu.bus.read(0xFF0F) & Interrupt::VBlank.bit(), 0);
+    }
+    #[test]
+    fn test_run_frame_with_lcd_off() {
+        let mut gameboy = use_test_gameboy();
+        gameboy.cpu.bus.write(0xFF40, 0x00);
+        let start = gameboy.cpu.cycles;
+        let frame = gameboy.run_frame();
+        assert!(frame.iter().all(|&color| color == DMG_SHADES[0]));
+        assert!(gameboy.cpu.cycles - start >= 17556);
+    }
+    #[test]
+    fn test_background_rendering() {
+        let mut gameboy = use_test_gameboy();
+        // Tile 1 is a checkerboard of colours 3 and 0 on every row, placed top left
+        for row in 0..8 {
+            gameboy.cpu.bus.write(0x8010 + row * 2, 0xAA);
+            gameboy.cpu.bus.write(0x8011 + row * 2, 0xAA);
+        }
+        gameboy.cpu.bus.write(0x9800, 0x01);
+        gameboy.cpu.bus.write(0xFF47, 0xE4);
+        gameboy.run_frame();
+        let frame = gameboy.run_frame();
+        assert_eq!(frame[0], DMG_SHADES[3]);
+        assert_eq!(frame[1], DMG_SHADES[0]);
+        assert_eq!(frame[7 * SCREEN_WIDTH + 6], DMG_SHADES[3]);
+        assert_eq!(frame[8], DMG_SHADES[0]);
+    }
+    #[test]
+    fn test_press_and_release() {
+        let mut gameboy = use_test_gameboy();
+        gameboy.cpu.bus.write(0xFF0F, 0x00);
+        gameboy.cpu.bus.write(0xFF00, 0x20);
+        gameboy.press(Button::Down);
+        gameboy.step();
+        assert_eq!(gameboy.cpu.bus.read(0xFF00), 0xE7);
+        assert_ne!(gameboy.cpu.bus.read(0xFF0F) & Interrupt::Joypad.bit(), 0);
+        // Buttons don't show up while only the d-pad is selected
+        gameboy.press(Button::Start);
+        assert_eq!(gameboy.cpu.bus.read(0xFF00), 0xE7);
+        gameboy.release(Button::Down);
+        assert_eq!(gameboy.cpu.bus.read(0xFF00), 0xEF);
+    }
+    #[test]
+    fn test_audio_samples() {
+        let mut gameboy = use_test_gameboy();
+        gameboy.run_frame();
+        gameboy.audio_samples();
+        gameboy.run_frame();
+        // 48000 Hz at 59.7 frames a second
+        assert!(gameboy.audio_samples().len().abs_diff(804) <= 1);
+
+        let options = GameBoyOptions { sample_rate: 0, ..GameBoyOptions::new() };
+        let mut gameboy = GameBoy::new(use_test_rom(0x00, 0x00), options).unwrap();
+        gameboy.run_frame();
+        assert!(gameboy.audio_samples().is_empty());
+    }
+    #[test]
+    fn test_reset_keeps_cartridge_ram() {
+        let mut gameboy = GameBoy::new(use_test_rom(0x03, 0x02), GameBoyOptions::new()).unwrap();
+        gameboy.cpu.bus.write(0x0000, 0x0A);
+        gameboy.cpu.bus.write(0xA123, 0x42);
+        gameboy.cpu.bus.write(0xC000, 0x42);
+        gameboy.run_frame();
+        gameboy.reset();
+        assert_eq!(gameboy.cpu.pc, 0x0100);
+        assert_eq!(gameboy.cpu.bus.read(0xC000), 0x00);
+        gameboy.cpu.bus.write(0x0000, 0x0A);
+        assert_eq!(gameboy.cpu.bus.read(0xA123), 0x42);
+    }
+    #[test]
+    fn test_unsupported_mapper() {
+        assert!(GameBoy::new(use_test_rom(0xFC, 0x00), GameBoyOptions::new()).is_err());
+    }
+    #[test]
+    fn test_sgb_framebuffer() {
+        let mut rom = use_test_rom(0x00, 0x00);
+        rom[0x146] = 0x03;
+        rom[0x14B] = 0x33;
+        let options = GameBoyOptions { model: Model::Sgb, ..GameBoyOptions::new() };
+        let mut gameboy = GameBoy::new(rom, options).unwrap();
+        assert_eq!(gameboy.framebuffer_size(), (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT));
+        assert_eq!(gameboy.run_frame().len(), SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT);
+    }
+}
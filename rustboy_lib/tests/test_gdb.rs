@@ -0,0 +1,164 @@
+use rustboy_lib::debugger::gdb::{checksum, encode_packet, GdbServer, GdbStub, Incoming, PacketReader, Reply, Resume, TARGET_XML};
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+
+#[cfg(test)]
+mod gdb_tests {
+    use super::*;
+    // Counts up in 0xC000 forever
+    fn use_test_gameboy() -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150..0x156].copy_from_slice(&[
+            0x21, 0x00, 0xC0, // 0x0150: ld hl, $C000
+            0x34,             // 0x0153: inc [hl]
+            0x18, 0xFD,       // 0x0154: jr $0153
+        ]);
+        return GameBoy::new(rom, GameBoyOptions::new()).unwrap()
+    }
+    fn packet(text: &str) -> Reply {
+        Reply::Packet(String::from(text))
+    }
+    #[test]
+    fn test_packets() {
+        assert_eq!(checksum(b"OK"), 0x9A);
+        assert_eq!(encode_packet("OK"), b"$OK#9a");
+        assert_eq!(encode_packet("a#b"), b"$a}\x03b#43");
+
+        let mut reader = PacketReader::new();
+        reader.push(b"+$m0,4#fd\x03$g#00$a}\x03");
+        assert_eq!(reader.take(), Some(Incoming::Ack));
+        assert_eq!(reader.take(), Some(Incoming::Packet(String::from("m0,4"))));
+        assert_eq!(reader.take(), Some(Incoming::Interrupt));
+        assert_eq!(reader.take(), Some(Incoming::Corrupt));
+        // Not all there yet
+        assert_eq!(reader.take(), None);
+        reader.push(b"b#43-");
+        assert_eq!(reader.take(), Some(Incoming::Packet(String::from("a#b"))));
+        assert_eq!(reader.take(), Some(Incoming::Nack));
+        assert_eq!(reader.take(), None);
+    }
+    #[test]
+    fn test_registers_and_memory() {
+        let mut gameboy = use_test_gameboy();
+        let mut stub = GdbStub::new();
+        gameboy.cpu.registers.set_bc(0x1234);
+        let Reply::Packet(registers) = stub.handle(&mut gameboy, "g") else {
+            panic!("expected registers");
+        };
+        assert_eq!(registers.len(), 24);
+        assert_eq!(&registers[4..8], "3412");
+        assert_eq!(&registers[20..24], "0001");
+        assert_eq!(stub.handle(&mut gameboy, "p5"), packet("0001"));
+        assert_eq!(stub.handle(&mut gameboy, "P5=5001"), packet("OK"));
+        assert_eq!(gameboy.cpu.pc, 0x0150);
+        assert_eq!(stub.handle(&mut gameboy, "p6"), packet("E01"));
+        // The low nibble of F doesn't exist
+        let registers = format!("ff12{}", &registers[4..]);
+        assert_eq!(stub.handle(&mut gameboy, &format!("G{}", registers)), packet("OK"));
+        assert_eq!(gameboy.cpu.registers.get_af(), 0x12F0);
+        assert_eq!(gameboy.cpu.pc, 0x0100);
+
+        assert_eq!(stub.handle(&mut gameboy, "m100,4"), packet("00c35001"));
+        assert_eq!(stub.handle(&mut gameboy, "Mc000,2:abcd"), packet("OK"));
+        assert_eq!(stub.handle(&mut gameboy, "mc000,2"), packet("abcd"));
+        assert_eq!(stub.handle(&mut gameboy, "Mc000,2:ab"), packet("E01"));
+        assert_eq!(stub.handle(&mut gameboy, "mzz"), packet("E01"));
+        assert_eq!(stub.handle(&mut gameboy, "vMustReplyEmpty"), packet(""));
+
+        let mut xml = String::new();
+        loop {
+            let Reply::Packet(chunk) = stub.handle(&mut gameboy, &format!("qXfer:features:read:target.xml:{:x},100", xml.len())) else {
+                panic!("expected part of the target description");
+            };
+            xml.push_str(&chunk[1..]);
+            if chunk.starts_with('l') {
+                break;
+            }
+        }
+        assert_eq!(xml, TARGET_XML);
+    }
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let mut gameboy = use_test_gameboy();
+        let mut stub = GdbStub::new();
+        assert_eq!(stub.handle(&mut gameboy, "Z0,153,1"), packet("OK"));
+        assert_eq!(stub.handle(&mut gameboy, "c"), Reply::Resume(Resume::Continue));
+        assert_eq!(stub.resume(&mut gameboy, Resume::Continue, || false), "S05");
+        assert_eq!(gameboy.cpu.pc, 0x0153);
+        assert_eq!(stub.resume(&mut gameboy, Resume::Continue, || false), "S05");
+        assert_eq!(gameboy.cpu.bus.peek(0xC000), 0x01);
+        assert_eq!(stub.handle(&mut gameboy, "?"), packet("S05"));
+
+        assert_eq!(stub.handle(&mut gameboy, "vCont;s:1"), Reply::Resume(Resume::Step));
+        assert_eq!(stub.resume(&mut gameboy, Resume::Step, || false), "S05");
+        assert_eq!(gameboy.cpu.pc, 0x0154);
+        assert_eq!(stub.handle(&mut gameboy, "s150"), Reply::Resume(Resume::Step));
+        assert_eq!(gameboy.cpu.pc, 0x0150);
+
+        assert_eq!(stub.handle(&mut gameboy, "z0,153,1"), packet("OK"));
+        assert_eq!(stub.handle(&mut gameboy, "z0,153,1"), packet("E01"));
+        assert!(stub.debugger().breakpoints().is_empty());
+        // Nothing left to stop at, so only an interrupt does
+        let mut polls = 0;
+        assert_eq!(stub.resume(&mut gameboy, Resume::Continue, || { polls += 1; polls == 3 }), "S02");
+    }
+    #[test]
+    fn test_watchpoints() {
+        let mut gameboy = use_test_gameboy();
+        let mut stub = GdbStub::new();
+        assert_eq!(stub.handle(&mut gameboy, "Z2,c000,1"), packet("OK"));
+        assert_eq!(stub.resume(&mut gameboy, Resume::Continue, || false), "T05watch:c000;");
+        assert_eq!(gameboy.cpu.pc, 0x0154);
+        assert_eq!(stub.handle(&mut gameboy, "z2,c000,1"), packet("OK"));
+        assert_eq!(stub.handle(&mut gameboy, "Z3,bfff,2"), packet("OK"));
+        assert_eq!(stub.resume(&mut gameboy, Resume::Continue, || false), "T05rwatch:c000;");
+        assert_eq!(stub.handle(&mut gameboy, "Z4,c000,1"), packet("OK"));
+        assert_eq!(stub.handle(&mut gameboy, "z3,bfff,2"), packet("OK"));
+        assert_eq!(stub.resume(&mut gameboy, Resume::Continue, || false), "T05awatch:c000;");
+        assert_eq!(stub.handle(&mut gameboy, "Z9,c000,1"), packet(""));
+    }
+    #[test]
+    fn test_session_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // The GameBoy stays on this thread, the client runs on another
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut reader = PacketReader::new();
+            // Sends bytes as they are and waits for the packet that comes back
+            let mut exchange = |bytes: &[u8]| -> String {
+                stream.write_all(bytes).unwrap();
+                let mut buffer = [0u8; 1024];
+                loop {
+                    match reader.take() {
+                        Some(Incoming::Packet(reply)) => return reply,
+                        Some(_) => continue,
+                        None => {
+                            let count = stream.read(&mut buffer).unwrap();
+                            reader.push(&buffer[..count]);
+                        }
+                    }
+                }
+            };
+            let mut replies = Vec::new();
+            for text in ["qSupported:swbreak+", "QStartNoAckMode", "Z2,c000,1", "c", "p3", "z2,c000,1"] {
+                replies.push(exchange(&encode_packet(text)));
+            }
+            let mut interrupted = encode_packet("c");
+            interrupted.push(0x03);
+            replies.push(exchange(&interrupted));
+            replies.push(exchange(&encode_packet("D")));
+            replies
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let mut gameboy = use_test_gameboy();
+        GdbServer::from_stream(stream).unwrap().serve(&mut gameboy).unwrap();
+        let replies = client.join().unwrap();
+        assert!(replies[0].starts_with("PacketSize="));
+        assert_eq!(replies[1..6], ["OK", "OK", "T05watch:c000;", "00c0", "OK"]);
+        assert_eq!(replies[6..], ["S02", "OK"]);
+    }
+}
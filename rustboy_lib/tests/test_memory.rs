@@ -0,0 +1,176 @@
+use rustboy_lib::memory::{
+    bus::MemoryBus,
+    hdma::{Hdma, HdmaMode, HDMA1_ADDRESS, HDMA2_ADDRESS, HDMA3_ADDRESS, HDMA4_ADDRESS, HDMA5_ADDRESS},
+    oam_dma::DMA_ADDRESS,
+    vram::VideoRam,
+    wram::WorkRam
+};
+use rustboy_lib::model::Model;
+
+
+#[cfg(test)]
+mod memory_tests {
+    use super::*;
+    #[test]
+    fn test_wram_banking() {
+        let mut wram = WorkRam::new(true);
+        wram.write(0xC000, 0x11);
+        for bank in 1..8 {
+            wram.write_svbk(bank);
+            wram.write(0xD000, bank * 0x10);
+        }
+        for bank in 1..8 {
+            wram.write_svbk(bank);
+            assert_eq!(wram.read(0xD000), bank * 0x10);
+            assert_eq!(wram.read(0xC000), 0x11);
+        }
+        wram.write_svbk(0);
+        assert_eq!(wram.read_svbk(), 0xF8);
+        assert_eq!(wram.read(0xD000), 0x10);
+    }
+    #[test]
+    fn test_wram_echo() {
+        let mut wram = WorkRam::new(true);
+        wram.write_svbk(3);
+        wram.write(0xD123, 0x42);
+        assert_eq!(wram.read(0xF123), 0x42);
+        wram.write(0xE001, 0x24);
+        assert_eq!(wram.read(0xC001), 0x24);
+    }
+    #[test]
+    fn test_wram_dmg_ignores_svbk() {
+        let mut wram = WorkRam::new(false);
+        wram.write(0xD000, 0x42);
+        wram.write_svbk(5);
+        assert_eq!(wram.read_svbk(), 0xFF);
+        assert_eq!(wram.read(0xD000), 0x42);
+    }
+    #[test]
+    fn test_vram_banking() {
+        let mut vram = VideoRam::new(true);
+        vram.write(0x9800, 0x01);
+        vram.write_vbk(0xFF);
+        assert_eq!(vram.read_vbk(), 0xFF);
+        assert_eq!(vram.read(0x9800), 0x00);
+        vram.write(0x9800, 0x82);
+        assert_eq!(vram.read_bank(0, 0x9800), 0x01);
+        assert_eq!(vram.read_bank(1, 0x9800), 0x82);
+        vram.write_vbk(0);
+        assert_eq!(vram.read_vbk(), 0xFE);
+        assert_eq!(vram.read(0x9800), 0x01);
+    }
+    fn use_test_hdma(source: u16, destination: u16, hdma5: u8) -> Hdma {
+        let mut hdma = Hdma::new();
+        hdma.write(HDMA1_ADDRESS, (source >> 8) as u8);
+        hdma.write(HDMA2_ADDRESS, source as u8);
+        hdma.write(HDMA3_ADDRESS, (destination >> 8) as u8);
+        hdma.write(HDMA4_ADDRESS, destination as u8);
+        hdma.write(HDMA5_ADDRESS, hdma5);
+        return hdma
+    }
+    fn source_byte(address: u16) -> u8 {
+        (address & 0xFF) as u8 ^ 0x5A
+    }
+    #[test]
+    fn test_general_purpose_dma() {
+        let mut vram = VideoRam::new(true);
+        vram.write_vbk(1);
+        let mut hdma = use_test_hdma(0xC123, 0x8805, 0x02);
+        assert_eq!(hdma.mode(), HdmaMode::GeneralPurpose);
+        assert_eq!(hdma.run_general_purpose(source_byte, &mut vram, false), 24);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0xFF);
+        for offset in 0..0x30 {
+            assert_eq!(vram.read_bank(1, 0x8800 + offset), source_byte(0xC120 + offset));
+        }
+        assert_eq!(vram.read_bank(0, 0x8800), 0);
+        assert_eq!(vram.read_bank(1, 0x8830), 0);
+    }
+    #[test]
+    fn test_hblank_dma() {
+        let mut vram = VideoRam::new(true);
+        let mut hdma = use_test_hdma(0x4000, 0x9000, 0x82);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0x02);
+        assert_eq!(hdma.run_general_purpose(source_byte, &mut vram, false), 0);
+
+        assert_eq!(hdma.hblank(source_byte, &mut vram, false, true), 16);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0x01);
+        assert_eq!(vram.read_bank(0, 0x900F), source_byte(0x400F));
+        assert_eq!(vram.read_bank(0, 0x9010), 0);
+
+        assert_eq!(hdma.hblank(source_byte, &mut vram, true, true), 0);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0x01);
+
+        hdma.hblank(source_byte, &mut vram, false, false);
+        hdma.hblank(source_byte, &mut vram, false, false);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0xFF);
+        assert_eq!(hdma.hblank(source_byte, &mut vram, false, false), 0);
+        assert_eq!(vram.read_bank(0, 0x902F), source_byte(0x402F));
+    }
+    #[test]
+    fn test_hblank_dma_cancel() {
+        let mut vram = VideoRam::new(true);
+        let mut hdma = use_test_hdma(0x4000, 0x9000, 0x84);
+        hdma.hblank(source_byte, &mut vram, false, false);
+        hdma.write(HDMA5_ADDRESS, 0x00);
+        assert_eq!(hdma.mode(), HdmaMode::Idle);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0x83);
+        assert_eq!(hdma.hblank(source_byte, &mut vram, false, false), 0);
+    }
+    #[test]
+    fn test_dma_stops_at_end_of_vram() {
+        let mut vram = VideoRam::new(true);
+        let mut hdma = use_test_hdma(0xC000, 0x9FE0, 0x7F);
+        assert_eq!(hdma.run_general_purpose(source_byte, &mut vram, false), 16);
+        assert_eq!(hdma.read(HDMA5_ADDRESS), 0xFF);
+        assert_eq!(vram.read_bank(0, 0x9FFF), source_byte(0xC01F));
+    }
+    #[test]
+    fn test_dma_through_the_bus() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4000..0x4010].copy_from_slice(&[0x5A; 0x10]);
+        let mut bus = MemoryBus::new(rom, Model::Cgb, true);
+        for offset in 0..0x20 {
+            bus.write(0xC000 + offset, offset as u8);
+        }
+        let start = |bus: &mut MemoryBus, source: u16, destination: u16, hdma5: u8| {
+            bus.write(HDMA1_ADDRESS, (source >> 8) as u8);
+            bus.write(HDMA2_ADDRESS, source as u8);
+            bus.write(HDMA3_ADDRESS, (destination >> 8) as u8);
+            bus.write(HDMA4_ADDRESS, destination as u8);
+            bus.write(HDMA5_ADDRESS, hdma5);
+        };
+        start(&mut bus, 0xC000, 0x8000, 0x01);
+        assert_eq!(bus.read(0x8000), 0x00);
+        assert_eq!(bus.read(0x801F), 0x1F);
+        start(&mut bus, 0x4000, 0x8100, 0x00);
+        assert_eq!(bus.read(0x810F), 0x5A);
+        // VRAM can't be a source
+        start(&mut bus, 0x8000, 0x8200, 0x00);
+        assert_eq!(bus.read(0x8201), 0xFF);
+    }
+    #[test]
+    fn test_oam_dma() {
+        let mut bus = MemoryBus::new(Vec::new(), Model::Dmg, false);
+        for offset in 0..0xA0 {
+            bus.write(0xC100 + offset, offset as u8);
+        }
+        bus.write(0xFF80, 0x42);
+        bus.write(DMA_ADDRESS, 0xC1);
+        assert_eq!(bus.read(DMA_ADDRESS), 0xC1);
+        // One cycle of setup during which the bus is still free
+        bus.tick();
+        assert_eq!(bus.read(0xC100), 0x00);
+        bus.tick();
+        assert_eq!(bus.read(0xC100), 0xFF);
+        assert_eq!(bus.read(0xFF80), 0x42);
+        for _ in 0..159 {
+            bus.tick();
+        }
+        assert_eq!(bus.read(0xC100), 0xFF);
+        bus.tick();
+        assert_eq!(bus.read(0xC100), 0x00);
+        for offset in 0..0xA0 {
+            assert_eq!(bus.read(0xFE00 + offset), offset as u8);
+        }
+    }
+}
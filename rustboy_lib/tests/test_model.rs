@@ -0,0 +1,111 @@
+use rustboy_lib::cpu::CPU;
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::model::Model;
+use rustboy_lib::ppu::compat::PaletteOverride;
+
+
+#[cfg(test)]
+mod model_tests {
+    use super::*;
+    fn use_test_rom(cgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13F].copy_from_slice(b"POKEMON RED");
+        rom[0x143] = cgb_flag;
+        rom[0x14B] = 0x01;
+        rom[0x14D] = 0x42;
+        return rom
+    }
+    #[test]
+    fn test_parse_model() {
+        assert_eq!("cgb".parse::<Model>(), Ok(Model::Cgb));
+        assert_eq!("DMG0".parse::<Model>(), Ok(Model::Dmg0));
+        assert!("gba".parse::<Model>().is_err());
+        for model in Model::ALL {
+            assert_eq!(model.to_string().parse::<Model>(), Ok(model));
+        }
+    }
+    #[test]
+    fn test_same_rom_on_every_model() {
+        let expected_a = [
+            (Model::Dmg0, 0x01),
+            (Model::Dmg, 0x01),
+            (Model::Mgb, 0xFF),
+            (Model::Sgb, 0x01),
+            (Model::Cgb, 0x11),
+            (Model::Agb, 0x11),
+        ];
+        for (model, a) in expected_a {
+            let cpu = CPU::power_on(use_test_rom(0x80), model, None).unwrap();
+            assert_eq!(cpu.model, model);
+            assert_eq!(cpu.bus.model(), model);
+            assert_eq!(cpu.registers.a, a, "{}", model);
+            assert_eq!(cpu.cgb_mode, model.is_cgb(), "{}", model);
+            assert_eq!(cpu.bus.read(0xFF70) != 0xFF, model.is_cgb(), "{}", model);
+        }
+    }
+    // Runs program at 0xC000 with HL pointing into OAM, starting right as the LCD turns on
+    // so that its second M-cycle lands on the PPU reading OAM row 2. Returns that row
+    fn use_test_oam_bug(model: Model, program: &[u8]) -> Vec<u8> {
+        let mut cpu = CPU::power_on(use_test_rom(0x00), model, None).unwrap();
+        cpu.bus.write(0xFF40, 0x00);
+        for index in 0..0xA0u16 {
+            cpu.bus.write(0xFE00 + index, (index * 37 + 11) as u8);
+            cpu.bus.write(0xC000 + index, *program.get(index as usize).unwrap_or(&0x00));
+        }
+        cpu.pc = 0xC000;
+        cpu.registers.set_hl(0xFE00);
+        cpu.bus.write(0xFF40, 0x80);
+        cpu.step();
+        return (0xFE10..0xFE18).map(|address| cpu.bus.peek(address)).collect()
+    }
+    #[test]
+    fn test_oam_bug() {
+        let untouched = [0x5B, 0x80, 0xA5, 0xCA, 0xEF, 0x14, 0x39, 0x5E];
+        // inc hl garbles the first word and copies the rest of row 1
+        assert_eq!(use_test_oam_bug(Model::Dmg, &[0x23]), [0x53, 0xC8, 0x7D, 0xA2, 0xC7, 0xEC, 0x11, 0x36]);
+        // ld a, [hl] mixes the words another way
+        assert_eq!(use_test_oam_bug(Model::Dmg, &[0x7E]), [0x73, 0xD8, 0x7D, 0xA2, 0xC7, 0xEC, 0x11, 0x36]);
+        assert_eq!(use_test_oam_bug(Model::Cgb, &[0x23]), untouched);
+        assert_eq!(use_test_oam_bug(Model::Dmg, &[0x00]), untouched);
+    }
+    #[test]
+    fn test_model_quirks() {
+        assert!(Model::Dmg.has_oam_bug());
+        assert!(Model::Sgb.has_oam_bug());
+        assert!(!Model::Cgb.has_oam_bug());
+        assert!(!Model::Mgb.wave_ram_readable_while_playing());
+        assert!(Model::Agb.wave_ram_readable_while_playing());
+
+        let dmg = CPU::power_on(use_test_rom(0x00), Model::Dmg, None).unwrap();
+        let agb = CPU::power_on(use_test_rom(0x00), Model::Agb, None).unwrap();
+        assert_eq!(dmg.bus.read(0xFEA5), 0x00);
+        assert_eq!(agb.bus.read(0xFEA5), 0xAA);
+        assert_eq!(agb.bus.read(0xFED0), 0xDD);
+    }
+    #[test]
+    fn test_dmg_cartridge_on_cgb_gets_palettes() {
+        let cgb = CPU::power_on(use_test_rom(0x00), Model::Cgb, None).unwrap();
+        assert_eq!(cgb.bus.palettes.background.color(0, 1), 0x421F);
+        let dmg = CPU::power_on(use_test_rom(0x00), Model::Dmg, None).unwrap();
+        assert_eq!(dmg.bus.palettes.background.color(0, 1), 0x0000);
+    }
+    #[test]
+    fn test_palette_override() {
+        assert_eq!("up-a".parse::<PaletteOverride>(), Ok(PaletteOverride::UpA));
+        assert!("up-c".parse::<PaletteOverride>().is_err());
+        for palette_override in PaletteOverride::ALL {
+            assert_eq!(palette_override.to_string().parse::<PaletteOverride>(), Ok(palette_override));
+        }
+
+        // Left + B is grayscale whatever the title says
+        let options = GameBoyOptions { model: Model::Cgb, palette_override: Some(PaletteOverride::LeftB), ..GameBoyOptions::new() };
+        let mut gameboy = GameBoy::new(use_test_rom(0x00), options.clone()).unwrap();
+        assert_eq!(gameboy.cpu.bus.palettes.background.color(0, 1), 0x5294);
+        assert_eq!(gameboy.cpu.bus.palettes.background.color(0, 2), 0x294A);
+        gameboy.reset();
+        assert_eq!(gameboy.cpu.bus.palettes.background.color(0, 1), 0x5294);
+        // A CGB game sets up its own palettes, so they start out zeroed
+        let gameboy = GameBoy::new(use_test_rom(0x80), options).unwrap();
+        assert_eq!(gameboy.cpu.bus.palettes.background.color(0, 1), 0x0000);
+    }
+}
@@ -0,0 +1,189 @@
+use rustboy_lib::cartridge::mbc::Rtc;
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::joypad::Button;
+use rustboy_lib::model::Model;
+use rustboy_lib::movie::{frame_checksum, Movie, MovieStart, ReplayReport};
+use rustboy_lib::rewind::RewindOptions;
+use rustboy_lib::savestate::SaveStateError;
+
+
+#[cfg(test)]
+mod movie_tests {
+    use super::*;
+    // Scrolls the background by whatever the d-pad reads, so input shows up on screen
+    fn use_test_rom(title: &[u8]) -> Vec<u8> {
+        let program = [
+            0x3E, 0x20, 0xE0, 0x00, // LD A,0x20; LDH (P1),A
+            0xF0, 0x00,             // loop: LDH A,(P1)
+            0xE0, 0x43,             // LDH (SCX),A
+            0x18, 0xFA,             // JR loop
+        ];
+        return use_test_rom_with(title, &program)
+    }
+    fn use_test_rom_with(title: &[u8], program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        return rom
+    }
+    fn use_test_gameboy(title: &[u8]) -> GameBoy {
+        return use_test_gameboy_with(title, GameBoyOptions::new())
+    }
+    fn use_test_gameboy_with(title: &[u8], options: GameBoyOptions) -> GameBoy {
+        let mut gameboy = GameBoy::new(use_test_rom(title), options).unwrap();
+        for row in 0..16 {
+            gameboy.cpu.bus.write(0x8000 + row, 0xF0);
+        }
+        return gameboy
+    }
+    fn record(gameboy: &mut GameBoy) -> Movie {
+        gameboy.start_recording();
+        for frame in 0..30 {
+            match frame {
+                3 => gameboy.press(Button::Right),
+                8 => gameboy.press(Button::Up),
+                12 => gameboy.release(Button::Right),
+                20 => gameboy.release(Button::Up),
+                _ => {}
+            }
+            gameboy.run_frame();
+            // Mid frame, so the change lands partway down the screen
+            if frame == 24 {
+                for _ in 0..2000 {
+                    gameboy.step();
+                }
+                gameboy.press(Button::Left);
+            }
+        }
+        return gameboy.stop_recording().unwrap()
+    }
+    #[test]
+    fn test_record_and_replay() {
+        let mut gameboy = use_test_gameboy(b"TEST");
+        gameboy.run_frame();
+        let movie = record(&mut gameboy);
+        assert_eq!(movie.frames(), 30);
+        assert_eq!(movie.events.len(), 5);
+        assert_eq!(movie.events[0].frame, 3);
+        assert!(!gameboy.is_recording());
+        let expected = (gameboy.cpu.cycles, gameboy.framebuffer().to_vec());
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let mut replay = use_test_gameboy(b"TEST");
+        assert_eq!(replay.replay_movie(&movie), Ok(ReplayReport { frames: 30, first_divergence: None }));
+        assert_eq!((replay.cpu.cycles, replay.framebuffer().to_vec()), expected);
+        assert_eq!(frame_checksum(replay.framebuffer()), movie.checksums[29]);
+    }
+    #[test]
+    fn test_record_a_step_at_a_time() {
+        // Turns the LCD off for a few frames' worth of cycles before scrolling like above
+        let program = [
+            0x3E, 0x00, 0xE0, 0x40, // LD A,0x00; LDH (LCDC),A
+            0x01, 0x00, 0x20,       // LD BC,0x2000
+            0x0B,                   // wait: DEC BC
+            0x78, 0xB1,             // LD A,B; OR C
+            0x20, 0xFB,             // JR NZ,wait
+            0x3E, 0x91, 0xE0, 0x40, // LD A,0x91; LDH (LCDC),A
+            0x3E, 0x20, 0xE0, 0x00, // LD A,0x20; LDH (P1),A
+            0xF0, 0x00,             // loop: LDH A,(P1)
+            0xE0, 0x43,             // LDH (SCX),A
+            0x18, 0xFA,             // JR loop
+        ];
+        let rom = use_test_rom_with(b"TEST", &program);
+        let mut gameboy = GameBoy::new(rom.clone(), GameBoyOptions::new()).unwrap();
+        gameboy.start_recording_from_power_on();
+        for row in 0..16 {
+            gameboy.cpu.bus.write(0x8000 + row, 0xF0);
+        }
+        while gameboy.frame_count() < 20 {
+            gameboy.step();
+            if gameboy.frame_count() == 10 {
+                gameboy.press(Button::Right);
+            }
+        }
+        let movie = gameboy.stop_recording().unwrap();
+        assert_eq!(movie.frames(), 20);
+
+        let mut replay = GameBoy::new(rom, GameBoyOptions::new()).unwrap();
+        replay.play_movie(&movie).unwrap();
+        for row in 0..16 {
+            replay.cpu.bus.write(0x8000 + row, 0xF0);
+        }
+        for (frame, &checksum) in movie.checksums.iter().enumerate() {
+            assert_eq!(frame_checksum(replay.run_frame()), checksum, "frame {}", frame + 1);
+        }
+        assert_eq!(replay.cpu.cycles, gameboy.cpu.cycles);
+    }
+    #[test]
+    fn test_replay_reports_divergence() {
+        let mut gameboy = use_test_gameboy(b"TEST");
+        let movie = record(&mut gameboy);
+        // Releasing Right a frame late changes the screen from frame 13 on
+        let mut late = movie.clone();
+        late.events[2].cycle += 17556;
+        assert_eq!(gameboy.replay_movie(&late), Ok(ReplayReport { frames: 13, first_divergence: Some(13) }));
+
+        let mut dropped = movie.clone();
+        dropped.events.pop();
+        assert_eq!(gameboy.replay_movie(&dropped).unwrap().first_divergence, Some(26));
+    }
+    #[test]
+    fn test_power_on_movie() {
+        let mut gameboy = use_test_gameboy(b"TEST");
+        gameboy.cpu.bus.write(0x0000, 0x0A);
+        gameboy.cpu.bus.write(0xA000, 0x42);
+        gameboy.run_frame();
+        gameboy.start_recording_from_power_on();
+        assert_eq!(gameboy.frame_count(), 0);
+        gameboy.press(Button::Down);
+        gameboy.run_frame();
+        let movie = gameboy.stop_recording().unwrap();
+        let MovieStart::PowerOn { ram, rtc } = &movie.start else {
+            panic!("expected a power on movie");
+        };
+        assert_eq!(ram[0], 0x42);
+        assert_eq!(rtc, &Some(Rtc::new()));
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()), Ok(movie.clone()));
+
+        let mut replay = GameBoy::new(use_test_rom(b"TEST"), GameBoyOptions::new()).unwrap();
+        assert_eq!(replay.replay_movie(&movie).unwrap().first_divergence, None);
+        replay.cpu.bus.write(0x0000, 0x0A);
+        assert_eq!(replay.cpu.bus.read(0xA000), 0x42);
+    }
+    #[test]
+    fn test_rewind_while_recording() {
+        let options = GameBoyOptions { rewind: RewindOptions::new(), ..GameBoyOptions::new() };
+        let mut gameboy = use_test_gameboy_with(b"TEST", options);
+        gameboy.start_recording();
+        for frame in 0..12 {
+            if frame == 9 {
+                gameboy.press(Button::Right);
+            }
+            gameboy.run_frame();
+        }
+        assert_eq!(gameboy.rewind_frames(4), 4);
+        gameboy.press(Button::Left);
+        for _ in 0..4 {
+            gameboy.run_frame();
+        }
+        let movie = gameboy.stop_recording().unwrap();
+        assert_eq!(movie.frames(), 12);
+        assert_eq!(movie.events.len(), 1);
+        assert_eq!(movie.events[0].button, Button::Left);
+        let mut replay = use_test_gameboy(b"TEST");
+        assert_eq!(replay.replay_movie(&movie).unwrap().first_divergence, None);
+    }
+    #[test]
+    fn test_rejects_other_rom_and_model() {
+        let mut gameboy = use_test_gameboy(b"TEST");
+        let movie = record(&mut gameboy);
+        let mut other = use_test_gameboy(b"OTHER");
+        assert!(matches!(other.play_movie(&movie), Err(SaveStateError::RomMismatch { .. })));
+        let options = GameBoyOptions { model: Model::Cgb, ..GameBoyOptions::new() };
+        let mut other = GameBoy::new(use_test_rom(b"TEST"), options).unwrap();
+        assert_eq!(other.play_movie(&movie), Err(SaveStateError::ModelMismatch));
+        assert_eq!(Movie::from_bytes(b"RBST"), Err(SaveStateError::BadMagic));
+    }
+}
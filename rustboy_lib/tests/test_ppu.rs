@@ -0,0 +1,171 @@
+use rustboy_lib::cartridge::header::CartridgeHeader;
+use rustboy_lib::ppu::{
+    attributes::{BgAttributes, SpriteAttributes, cgb_object_has_priority},
+    color::ColorCorrection,
+    compat::{CompatibilityPalettes, PaletteOverride, title_checksum, title_index},
+    lcd::{Ppu, PpuMode, LCDC_ADDRESS, LYC_ADDRESS, STAT_ADDRESS},
+    palette::{CgbPalettes, BCPS_ADDRESS, BCPD_ADDRESS, OCPS_ADDRESS, OCPD_ADDRESS}
+};
+use rustboy_lib::memory::vram::VideoRam;
+use rustboy_lib::scheduler::Scheduler;
+
+
+#[cfg(test)]
+mod ppu_tests {
+    use super::*;
+    #[test]
+    fn test_palette_auto_increment() {
+        let mut palettes = CgbPalettes::new();
+        palettes.write(BCPS_ADDRESS, 0x80 | 0x08);
+        palettes.write(BCPD_ADDRESS, 0x1F);
+        palettes.write(BCPD_ADDRESS, 0x7C);
+        assert_eq!(palettes.read(BCPS_ADDRESS), 0xC0 | 0x0A);
+        assert_eq!(palettes.background.color(1, 0), 0x7C1F);
+
+        palettes.write(BCPS_ADDRESS, 0x3F);
+        palettes.write(BCPD_ADDRESS, 0x12);
+        palettes.write(BCPD_ADDRESS, 0x34);
+        assert_eq!(palettes.read(BCPS_ADDRESS), 0x7F);
+        assert_eq!(palettes.read(BCPD_ADDRESS), 0x34);
+    }
+    #[test]
+    fn test_palette_index_wraps() {
+        let mut palettes = CgbPalettes::new();
+        palettes.write(OCPS_ADDRESS, 0xBF);
+        palettes.write(OCPD_ADDRESS, 0xAA);
+        palettes.write(OCPD_ADDRESS, 0xBB);
+        assert_eq!(palettes.read(OCPS_ADDRESS), 0xC1);
+        assert_eq!(palettes.object.color(7, 3) >> 8, 0xAA);
+        assert_eq!(palettes.object.color(0, 0), 0xBB);
+        assert_eq!(palettes.background.color(0, 0), 0x0000);
+    }
+    #[test]
+    fn test_attributes() {
+        let bg = BgAttributes::from(0b1010_1101);
+        assert_eq!(bg, BgAttributes { priority: true, y_flip: false, x_flip: true, bank: 1, palette: 5 });
+        let sprite = SpriteAttributes::from(0b0101_0011);
+        assert_eq!(sprite, SpriteAttributes { behind_bg: false, y_flip: true, x_flip: false, dmg_palette: 1, bank: 0, cgb_palette: 3 });
+    }
+    #[test]
+    fn test_cgb_priority() {
+        let bg_priority = BgAttributes::from(0x80);
+        let behind_bg = SpriteAttributes::from(0x80);
+        let plain_bg = BgAttributes::default();
+        let plain_sprite = SpriteAttributes::default();
+        assert!(cgb_object_has_priority(true, 2, plain_bg, plain_sprite));
+        assert!(!cgb_object_has_priority(true, 2, bg_priority, plain_sprite));
+        assert!(!cgb_object_has_priority(true, 2, plain_bg, behind_bg));
+        assert!(cgb_object_has_priority(true, 0, bg_priority, behind_bg));
+        assert!(cgb_object_has_priority(false, 2, bg_priority, behind_bg));
+    }
+    #[test]
+    fn test_color_conversion() {
+        assert_eq!(ColorCorrection::None.to_rgb888(0x7FFF), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(ColorCorrection::None.to_rgb888(0x001F), [0xFF, 0x00, 0x00]);
+        assert_eq!(ColorCorrection::None.to_rgb888(0x0210), [0x84, 0x84, 0x00]);
+        assert_eq!(ColorCorrection::Lcd.to_rgb888(0x7FFF), [0xF0, 0xF0, 0xF0]);
+        assert_eq!(ColorCorrection::Lcd.to_rgb888(0x001F), [0xC9, 0x00, 0x2E]);
+        assert_eq!(ColorCorrection::None.convert_frame(&[0x0000, 0x7C00]), vec![0, 0, 0, 0, 0, 0xFF]);
+    }
+    fn use_test_header(title: &[u8], old_licensee: u8) -> CartridgeHeader {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14B] = old_licensee;
+        return CartridgeHeader::parse(&rom).unwrap()
+    }
+    #[test]
+    fn test_compat_title_lookup() {
+        let red = use_test_header(b"POKEMON RED", 0x01);
+        assert_eq!(title_checksum(&red), 0x14);
+        assert_eq!(title_index(&red), Some(22));
+        let palettes = CompatibilityPalettes::for_cartridge(&red, None);
+        assert_eq!(palettes.background, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        assert_eq!(palettes.object0, [0x7FFF, 0x1BEF, 0x0200, 0x0000]);
+
+        // Same checksum as SUPER MARIOLAND, told apart by the 4th letter
+        let blue = use_test_header(b"POKEMON BLUE", 0x01);
+        assert_eq!(title_index(&blue), Some(72));
+        assert_eq!(CompatibilityPalettes::for_cartridge(&blue, None).background, [0x7FFF, 0x7E8C, 0x7C00, 0x0000]);
+        assert_eq!(title_index(&use_test_header(b"SUPER MARIOLAND", 0x01)), Some(66));
+    }
+    #[test]
+    fn test_compat_default_palettes() {
+        let homebrew = use_test_header(b"HOMEBREW GAME", 0x01);
+        assert_eq!(title_index(&homebrew), None);
+        let default = CompatibilityPalettes::for_cartridge(&homebrew, None);
+        assert_eq!(default.background, [0x7FFF, 0x1BEF, 0x6180, 0x0000]);
+        assert_eq!(default.object0, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+        // Not published by Nintendo, so the title is never looked at
+        let third_party = use_test_header(b"POKEMON RED", 0x33);
+        assert_eq!(title_index(&third_party), None);
+        assert_eq!(CompatibilityPalettes::for_cartridge(&third_party, None), default);
+    }
+    #[test]
+    fn test_compat_overrides() {
+        let header = use_test_header(b"POKEMON RED", 0x01);
+        let grayscale = CompatibilityPalettes::for_cartridge(&header, Some(PaletteOverride::LeftB));
+        assert_eq!(grayscale.background, [0x7FFF, 0x5294, 0x294A, 0x0000]);
+        assert_eq!(grayscale.object1, grayscale.background);
+        let inverted = CompatibilityPalettes::for_cartridge(&header, Some(PaletteOverride::RightB));
+        assert_eq!(inverted.background, [0x0000, 0x4200, 0x037F, 0x7FFF]);
+
+        let mut palettes = CgbPalettes::new();
+        inverted.load_into(&mut palettes);
+        assert_eq!(palettes.background.color(0, 3), 0x7FFF);
+        assert_eq!(palettes.object.color(1, 2), 0x037F);
+    }
+    // Runs the PPU's own events until the scheduler clock reaches time
+    fn run_ppu_until(ppu: &mut Ppu, scheduler: &mut Scheduler, time: u64) {
+        let vram = VideoRam::new(false);
+        let palettes = CgbPalettes::new();
+        let oam = [0; 0xA0];
+        while scheduler.next_event_time().is_some_and(|next| next <= time) {
+            let next = scheduler.next_event_time().unwrap();
+            scheduler.advance(next - scheduler.now());
+            let event = scheduler.pop_due().unwrap();
+            ppu.handle_event(event.time, scheduler, false, &vram, &oam, &palettes);
+        }
+        scheduler.advance(time - scheduler.now());
+    }
+    #[test]
+    fn test_ppu_mode_timing() {
+        let mut scheduler = Scheduler::new();
+        let mut ppu = Ppu::new(false, false);
+        ppu.write(LCDC_ADDRESS, 0x91, &mut scheduler, false);
+        assert_eq!(ppu.mode(), PpuMode::OamScan);
+        run_ppu_until(&mut ppu, &mut scheduler, 80);
+        assert_eq!(ppu.mode(), PpuMode::Drawing);
+        run_ppu_until(&mut ppu, &mut scheduler, 80 + 172);
+        assert_eq!(ppu.mode(), PpuMode::HBlank);
+        run_ppu_until(&mut ppu, &mut scheduler, 456);
+        assert_eq!((ppu.ly(), ppu.mode()), (1, PpuMode::OamScan));
+        run_ppu_until(&mut ppu, &mut scheduler, 456 * 144);
+        assert_eq!((ppu.ly(), ppu.mode()), (144, PpuMode::VBlank));
+        assert!(ppu.take_vblank_interrupt());
+        assert!(ppu.take_frame_ready());
+        run_ppu_until(&mut ppu, &mut scheduler, 70224);
+        assert_eq!((ppu.ly(), ppu.mode()), (0, PpuMode::OamScan));
+
+        // Turning the LCD off resets LY and reads mode 0
+        ppu.write(LCDC_ADDRESS, 0x11, &mut scheduler, false);
+        assert_eq!(ppu.ly(), 0);
+        assert_eq!(ppu.read(STAT_ADDRESS) & 0b11, 0);
+        assert!(scheduler.next_event_time().is_none());
+    }
+    #[test]
+    fn test_ppu_stat_interrupt() {
+        let mut scheduler = Scheduler::new();
+        let mut ppu = Ppu::new(false, false);
+        ppu.write(LYC_ADDRESS, 2, &mut scheduler, false);
+        ppu.write(STAT_ADDRESS, 0x40, &mut scheduler, false);
+        ppu.write(LCDC_ADDRESS, 0x91, &mut scheduler, false);
+        run_ppu_until(&mut ppu, &mut scheduler, 456 * 2 - 4);
+        assert!(!ppu.take_stat_interrupt());
+        run_ppu_until(&mut ppu, &mut scheduler, 456 * 2);
+        assert!(ppu.take_stat_interrupt());
+        assert_eq!(ppu.read(STAT_ADDRESS), 0x80 | 0x40 | 0x04 | 0x02);
+        // The line stays high for the whole of LY 2, so there is no second interrupt
+        run_ppu_until(&mut ppu, &mut scheduler, 456 * 3 - 4);
+        assert!(!ppu.take_stat_interrupt());
+    }
+}
@@ -0,0 +1,109 @@
+use rustboy_lib::disassembler::recursive::{ByteKind, CodeMap, Reference};
+use rustboy_lib::symbols::Symbols;
+
+
+#[cfg(test)]
+mod recursive_disassembler_tests {
+    use super::*;
+    // Calls into bank 2 and bank 1 after switching to them, then loops forever. Bank 3 is
+    // never switched in
+    fn use_test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x10000];
+        rom[..0x100].fill(0xC9);
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        let home = [
+            0x3E, 0x02,       // 0x0150: ld a, 2
+            0xEA, 0x00, 0x20, // 0x0152: ld [$2000], a
+            0xCD, 0x00, 0x40, // 0x0155: call $4000
+            0xAF,             // 0x0158: xor a
+            0xEA, 0x00, 0x20, // 0x0159: ld [$2000], a
+            0xCD, 0x10, 0x40, // 0x015C: call $4010
+            0x18, 0xFE,       // 0x015F: jr $015F
+            0xDE, 0xAD,       // 0x0161: never run
+        ];
+        rom[0x150..0x150 + home.len()].copy_from_slice(&home);
+        rom[0x4010] = 0xC9;
+        rom[0x8000..0x8008].copy_from_slice(&[
+            0x21, 0x00, 0xC0, // 0x4000: ld hl, $C000
+            0x7E,             // 0x4003: ld a, [hl]
+            0xC8,             // 0x4004: ret z
+            0x18, 0xFC,       // 0x4005: jr $4003
+            0xC9,             // 0x4007: never run
+        ]);
+        return rom
+    }
+    #[test]
+    fn test_finds_code() {
+        let rom = use_test_rom();
+        let map = CodeMap::analyze(&rom);
+        assert_eq!(map.kind(0x0000), ByteKind::Opcode);
+        assert_eq!(map.kind(0x0001), ByteKind::Data);
+        assert_eq!(map.kind(0x0134), ByteKind::Data);
+        assert_eq!((map.kind(0x0150), map.kind(0x0151)), (ByteKind::Opcode, ByteKind::Operand));
+        assert_eq!(map.kind(0x015F), ByteKind::Opcode);
+        assert_eq!(map.kind(0x0161), ByteKind::Data);
+        // Followed into the banks switched in before each call
+        assert_eq!(map.kind(0x4010), ByteKind::Opcode);
+        assert_eq!(map.kind(0x4000), ByteKind::Data);
+        assert_eq!(map.kind(0x8005), ByteKind::Opcode);
+        assert_eq!(map.kind(0x8007), ByteKind::Data);
+        assert!(rom[0xC000..].iter().enumerate().all(|(index, _)| map.kind(0xC000 + index) == ByteKind::Data));
+
+        let references = map.references();
+        assert_eq!(references.get(&0x0100), Some(&Reference::Vector("Entry")));
+        assert_eq!(references.get(&0x8000), Some(&Reference::Call));
+        assert_eq!(references.get(&0x8003), Some(&Reference::Jump));
+        assert_eq!(references.get(&0x015F), Some(&Reference::Jump));
+    }
+    #[test]
+    fn test_unknown_bank() {
+        // Switching to whatever is in memory leaves the call to 0x4000 going anywhere
+        let mut rom = use_test_rom();
+        rom[0x0150..0x0152].copy_from_slice(&[0x7E, 0x00]);
+        let map = CodeMap::analyze(&rom);
+        assert_eq!(map.kind(0x0155), ByteKind::Opcode);
+        assert_eq!(map.kind(0x8000), ByteKind::Data);
+        assert_eq!(map.kind(0x4010), ByteKind::Opcode);
+        // Unless there is only one bank it can be
+        let map = CodeMap::analyze(&rom[..0x8000]);
+        assert_eq!(map.kind(0x4000), ByteKind::Opcode);
+    }
+    #[test]
+    fn test_asm() {
+        let rom = use_test_rom();
+        let symbols = Symbols::parse("00:0150 Main\n00:0151 InsideAnInstruction\n00:c000 wValue\n00:c001 wValue.local").unwrap();
+        let asm = CodeMap::analyze(&rom).to_asm(&rom, &symbols);
+        let lines: Vec<&str> = asm.lines().collect();
+        let find = |line: &str| lines.iter().position(|&other| other == line).unwrap_or_else(|| panic!("no \"{}\" in\n{}", line, asm));
+        assert_eq!(lines[0], "DEF wValue EQU $C000");
+        assert!(!asm.contains("wValue.local") && !asm.contains("InsideAnInstruction"));
+        let main = find("Main:");
+        assert_eq!(lines[main + 1..main + 9], [
+            "    ld a, $02",
+            "    ld [$2000], a",
+            "    call Call_02_4000",
+            "    xor a, a",
+            "    ld [$2000], a",
+            "    call Call_01_4010",
+            "Jump_00_015F:",
+            "    jr Jump_00_015F",
+        ]);
+        assert_eq!(lines[main + 9], "    db $DE, $AD");
+        assert_eq!(lines[find("Entry:") + 1..find("Entry:") + 4], ["    nop", "    jp Main", "    ds 67, $00"]);
+        let bank = find("SECTION \"ROM Bank $02\", ROMX[$4000], BANK[$02]");
+        assert_eq!(lines[bank + 2..bank + 10], [
+            "Call_02_4000:",
+            "    ld hl, $C000",
+            "Jump_02_4003:",
+            "    ld a, [hl]",
+            "    ret z",
+            "    jr Jump_02_4003",
+            "    db $C9",
+            "    ds 16376, $00",
+        ]);
+        let bank = find("SECTION \"ROM Bank $03\", ROMX[$4000], BANK[$03]");
+        assert_eq!(lines[bank + 2..], ["    ds 16384, $00"]);
+    }
+}
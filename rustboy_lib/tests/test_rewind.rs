@@ -0,0 +1,112 @@
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::rewind::{RewindBuffer, RewindOptions};
+
+
+#[cfg(test)]
+mod rewind_tests {
+    use super::*;
+    // Counts in work RAM and scrolls the background by the count, so every frame differs
+    fn use_test_gameboy(rewind: RewindOptions) -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x21, 0x00, 0xC0, // LD HL,0xC000
+            0x34,             // loop: INC (HL)
+            0x7E,             // LD A,(HL)
+            0xE0, 0x43,       // LDH (SCX),A
+            0x18, 0xFA,       // JR loop
+        ];
+        rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+        let options = GameBoyOptions { rewind, ..GameBoyOptions::new() };
+        let mut gameboy = GameBoy::new(rom, options).unwrap();
+        for row in 0..16 {
+            gameboy.cpu.bus.write(0x8000 + row, 0xF0);
+        }
+        return gameboy
+    }
+    fn use_snapshot(frame: u64) -> Vec<u8> {
+        let mut state = vec![0; 4096];
+        state[frame as usize % 4096] = frame as u8;
+        state[100..110].fill(frame as u8);
+        state.resize(4096 + frame as usize % 3, 0xFF);
+        return state
+    }
+    #[test]
+    fn test_rewind_buffer_restores_snapshots() {
+        let mut buffer = RewindBuffer::new(RewindOptions { interval: 1, frames: 100, max_bytes: usize::MAX });
+        assert!(buffer.rewind_to(0).is_none());
+        for frame in 1..=20 {
+            buffer.push(frame, use_snapshot(frame));
+        }
+        assert_eq!(buffer.len(), 20);
+        // Only the newest is stored whole
+        assert!(buffer.size() < 4096 * 2);
+        assert_eq!(buffer.rewind_to(15), Some((15, use_snapshot(15).as_slice())));
+        assert_eq!(buffer.rewind_to(9), Some((9, use_snapshot(9).as_slice())));
+        assert_eq!(buffer.len(), 9);
+        buffer.push(10, use_snapshot(30));
+        assert_eq!(buffer.rewind_to(9), Some((9, use_snapshot(9).as_slice())));
+        assert_eq!(buffer.rewind_to(0), Some((1, use_snapshot(1).as_slice())));
+        assert_eq!(buffer.len(), 1);
+    }
+    #[test]
+    fn test_rewind_buffer_limits() {
+        let mut buffer = RewindBuffer::new(RewindOptions { interval: 2, frames: 20, max_bytes: usize::MAX });
+        assert!(!buffer.is_due(3));
+        assert!(buffer.is_due(4));
+        for frame in (2..=100).step_by(2) {
+            buffer.push(frame, use_snapshot(frame));
+        }
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.oldest_frame(), Some(82));
+
+        let mut buffer = RewindBuffer::new(RewindOptions { interval: 1, frames: 100, max_bytes: 100 });
+        for frame in 1..=50 {
+            buffer.push(frame, use_snapshot(frame));
+        }
+        assert!(buffer.size() <= use_snapshot(50).len() + 100);
+        assert!(buffer.oldest_frame().unwrap() > 1);
+        assert!(!RewindBuffer::new(RewindOptions::disabled()).is_due(4));
+    }
+    #[test]
+    fn test_rewind_frames() {
+        let mut gameboy = use_test_gameboy(RewindOptions { interval: 2, ..RewindOptions::new() });
+        let mut history = vec![(gameboy.cpu.cycles, gameboy.framebuffer().to_vec())];
+        for _ in 0..10 {
+            gameboy.run_frame();
+            history.push((gameboy.cpu.cycles, gameboy.framebuffer().to_vec()));
+        }
+        assert_eq!(gameboy.rewind_frames(4), 4);
+        assert_eq!(gameboy.frame_count(), 6);
+        assert_eq!((gameboy.cpu.cycles, gameboy.framebuffer().to_vec()), history[6]);
+        // Between snapshots it goes back to the one before
+        assert_eq!(gameboy.rewind_frames(1), 2);
+        assert_eq!(gameboy.cpu.cycles, history[4].0);
+        for _ in 0..6 {
+            gameboy.run_frame();
+        }
+        assert_eq!((gameboy.cpu.cycles, gameboy.framebuffer().to_vec()), history[10]);
+        // No further back than the oldest snapshot
+        assert_eq!(gameboy.rewind_frames(100), 8);
+        assert_eq!(gameboy.cpu.cycles, history[2].0);
+    }
+    #[test]
+    fn test_rewind_disabled() {
+        assert_eq!(GameBoyOptions::new().rewind, RewindOptions::disabled());
+        let mut gameboy = use_test_gameboy(RewindOptions::disabled());
+        gameboy.run_frame();
+        gameboy.run_frame();
+        let cycles = gameboy.cpu.cycles;
+        assert_eq!(gameboy.rewind_frames(1), 0);
+        assert_eq!(gameboy.cpu.cycles, cycles);
+    }
+    #[test]
+    fn test_load_state_clears_history() {
+        let mut gameboy = use_test_gameboy(RewindOptions { interval: 1, ..RewindOptions::new() });
+        let state = gameboy.save_state();
+        gameboy.run_frame();
+        gameboy.run_frame();
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.frame_count(), 0);
+        assert_eq!(gameboy.rewind_frames(1), 0);
+    }
+}
@@ -0,0 +1,134 @@
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::model::Model;
+use rustboy_lib::savestate::{crc32, SaveStateError, STATE_VERSION};
+use rustboy_lib::trace::{TraceFilter, TraceSink, Tracer};
+
+
+#[cfg(test)]
+mod savestate_tests {
+    use super::*;
+    // An MBC3 cartridge with RAM and a clock, running a loop that keeps the timer, work RAM
+    // and SCX changing so any state that doesn't come back shows up in the results
+    fn use_test_rom(title: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x3E, 0x05, 0xE0, 0x07, // LD A,0x05; LDH (TAC),A
+            0x3E, 0x80, 0xE0, 0x26, // LD A,0x80; LDH (NR52),A
+            0x21, 0x00, 0xC0,       // LD HL,0xC000
+            0xF0, 0x05,             // loop: LDH A,(TIMA)
+            0x22,                   // LD (HL+),A
+            0xE0, 0x43,             // LDH (SCX),A
+            0x7C, 0xFE, 0xD0,       // LD A,H; CP 0xD0
+            0x20, 0xF6,             // JR NZ,loop
+            0x26, 0xC0,             // LD H,0xC0
+            0x18, 0xF2,             // JR loop
+        ];
+        rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        return rom
+    }
+    fn use_test_gameboy(title: &[u8], model: Model) -> GameBoy {
+        let options = GameBoyOptions { model, ..GameBoyOptions::new() };
+        let mut gameboy = GameBoy::new(use_test_rom(title), options).unwrap();
+        // A checkerboard background, so scrolling changes the picture
+        for row in 0..16 {
+            gameboy.cpu.bus.write(0x8000 + row, 0xAA);
+        }
+        return gameboy
+    }
+    fn run(gameboy: &mut GameBoy, frames: usize) -> (u64, u16, Vec<u16>, Vec<u8>, usize) {
+        let mut samples = 0;
+        for _ in 0..frames {
+            gameboy.run_frame();
+            samples += gameboy.audio_samples().len();
+        }
+        let memory = (0xC000..0xD000).map(|address| gameboy.cpu.bus.read(address)).collect();
+        return (gameboy.cpu.cycles, gameboy.cpu.registers.get_hl(), gameboy.framebuffer().to_vec(), memory, samples)
+    }
+    #[test]
+    fn test_load_replays_exactly() {
+        let mut gameboy = use_test_gameboy(b"TEST", Model::Dmg);
+        run(&mut gameboy, 3);
+        for _ in 0..1234 {
+            gameboy.step();
+        }
+        // Buffered samples aren't part of the state
+        gameboy.audio_samples();
+        let state = gameboy.save_state();
+        let expected = run(&mut gameboy, 5);
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(run(&mut gameboy, 5), expected);
+    }
+    #[test]
+    fn test_round_trip_is_identical() {
+        for model in [Model::Dmg, Model::Cgb] {
+            let mut gameboy = use_test_gameboy(b"TEST", model);
+            run(&mut gameboy, 2);
+            let state = gameboy.save_state();
+            gameboy.load_state(&state).unwrap();
+            assert_eq!(gameboy.save_state(), state);
+        }
+    }
+    #[test]
+    fn test_trace_survives_load() {
+        let mut gameboy = use_test_gameboy(b"TRACE", Model::Dmg);
+        gameboy.cpu.trace = Some(Tracer::new(TraceSink::Callback(Box::new(|_| {})), TraceFilter::new()));
+        gameboy.cpu.bus_log = Some(Vec::new());
+        let state = gameboy.save_state();
+        for _ in 0..10 {
+            gameboy.step();
+        }
+        gameboy.load_state(&state).unwrap();
+        for _ in 0..10 {
+            gameboy.step();
+        }
+        assert_eq!(gameboy.cpu.trace.as_ref().map(|trace| trace.lines()), Some(20));
+        assert!(gameboy.cpu.bus_log.as_ref().is_some_and(|log| log.len() > 20));
+    }
+    #[test]
+    fn test_rejects_other_rom() {
+        let gameboy = use_test_gameboy(b"TEST", Model::Dmg);
+        let mut other = use_test_gameboy(b"OTHER", Model::Dmg);
+        run(&mut other, 1);
+        let cycles = other.cpu.cycles;
+        let result = other.load_state(&gameboy.save_state());
+        let expected = crc32(gameboy.cpu.bus.rom());
+        let found = crc32(other.cpu.bus.rom());
+        assert_eq!(result, Err(SaveStateError::RomMismatch { expected, found }));
+        assert_eq!(other.cpu.cycles, cycles);
+    }
+    #[test]
+    fn test_rejects_other_model() {
+        let gameboy = use_test_gameboy(b"TEST", Model::Dmg);
+        let mut other = use_test_gameboy(b"TEST", Model::Cgb);
+        assert_eq!(other.load_state(&gameboy.save_state()), Err(SaveStateError::ModelMismatch));
+    }
+    #[test]
+    fn test_rejects_broken_states() {
+        let mut gameboy = use_test_gameboy(b"TEST", Model::Dmg);
+        let state = gameboy.save_state();
+
+        let mut bad_magic = state.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(gameboy.load_state(&bad_magic), Err(SaveStateError::BadMagic));
+        assert_eq!(gameboy.load_state(b"RB"), Err(SaveStateError::BadMagic));
+
+        let mut bad_version = state.clone();
+        bad_version[4..8].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        assert_eq!(gameboy.load_state(&bad_version), Err(SaveStateError::UnsupportedVersion(STATE_VERSION + 1)));
+
+        assert_eq!(gameboy.load_state(&state[..state.len() - 1]), Err(SaveStateError::Truncated));
+        let mut too_long = state.clone();
+        too_long.push(0);
+        assert!(matches!(gameboy.load_state(&too_long), Err(SaveStateError::Invalid(_))));
+
+        gameboy.load_state(&state).unwrap();
+    }
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(&[]), 0);
+    }
+}
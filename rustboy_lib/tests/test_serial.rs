@@ -0,0 +1,240 @@
+use rustboy_lib::serial::{
+    cable::{CableEnd, LinkedGameBoys, Lockstep},
+    link::{CaptureLink, Loopback},
+    tcp::TcpLink,
+    Serial, SB_ADDRESS, SC_ADDRESS, CYCLES_PER_BIT
+};
+use rustboy_lib::assembler::assemble;
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::memory::bus::MemoryBus;
+use rustboy_lib::model::Model;
+use rustboy_lib::scheduler::EventKind;
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+
+#[cfg(test)]
+mod serial_tests {
+    use super::*;
+    fn send_byte(serial: &mut Serial, value: u8) {
+        serial.write(SB_ADDRESS, value);
+        serial.write(SC_ADDRESS, 0x81);
+        serial.step(CYCLES_PER_BIT * 8);
+    }
+    #[test]
+    fn test_disconnected_transfer() {
+        let mut serial = Serial::new();
+        serial.write(SB_ADDRESS, 0x42);
+        serial.write(SC_ADDRESS, 0x81);
+        assert_eq!(serial.read(SC_ADDRESS), 0xFF);
+
+        serial.step(CYCLES_PER_BIT * 8 - 1);
+        assert!(serial.is_transferring());
+        assert!(!serial.take_interrupt());
+
+        serial.step(1);
+        assert!(!serial.is_transferring());
+        assert!(serial.take_interrupt());
+        assert!(!serial.take_interrupt());
+        assert_eq!(serial.read(SB_ADDRESS), 0xFF);
+        assert_eq!(serial.read(SC_ADDRESS), 0x7F);
+    }
+    #[test]
+    fn test_bits_shift_msb_first() {
+        let mut serial = Serial::with_link(Box::new(Loopback));
+        serial.write(SB_ADDRESS, 0b1010_0000);
+        serial.write(SC_ADDRESS, 0x81);
+        serial.step(CYCLES_PER_BIT * 3);
+        assert_eq!(serial.read(SB_ADDRESS), 0b0000_0101);
+        serial.step(CYCLES_PER_BIT * 5);
+        assert_eq!(serial.read(SB_ADDRESS), 0b1010_0000);
+        assert!(serial.take_interrupt());
+    }
+    #[test]
+    fn test_capture_link() {
+        let capture = CaptureLink::new();
+        let mut serial = Serial::with_link(Box::new(capture.clone()));
+        for byte in b"Passed" {
+            send_byte(&mut serial, *byte);
+        }
+        assert_eq!(capture.output_string(), "Passed");
+    }
+    #[test]
+    fn test_external_clock_waits() {
+        let mut serial = Serial::new();
+        serial.write(SB_ADDRESS, 0x42);
+        serial.write(SC_ADDRESS, 0x80);
+        assert_eq!(serial.read(SC_ADDRESS), 0xFE);
+        serial.step(CYCLES_PER_BIT * 100);
+        assert!(serial.is_transferring());
+        assert!(!serial.take_interrupt());
+        assert_eq!(serial.read(SB_ADDRESS), 0x42);
+    }
+    fn linked_pair() -> (TcpLink, TcpLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (TcpLink::from_stream(server).unwrap(), TcpLink::from_stream(client).unwrap())
+    }
+    #[test]
+    fn test_tcp_transfer() {
+        let (host, guest) = linked_pair();
+        let guest_thread = std::thread::spawn(move || {
+            let mut serial = Serial::with_link(Box::new(guest));
+            serial.write(SB_ADDRESS, 0x22);
+            serial.write(SC_ADDRESS, 0x80);
+            while serial.is_transferring() {
+                serial.step(CYCLES_PER_BIT);
+            }
+            assert!(serial.take_interrupt());
+            serial.read(SB_ADDRESS)
+        });
+
+        let mut serial = Serial::with_link(Box::new(host));
+        send_byte(&mut serial, 0x11);
+        assert!(serial.take_interrupt());
+        assert_eq!(serial.read(SB_ADDRESS), 0x22);
+        assert_eq!(guest_thread.join().unwrap(), 0x11);
+    }
+    #[test]
+    fn test_tcp_no_reply_reads_ff() {
+        let (mut host, _guest) = linked_pair();
+        host.set_reply_timeout(Duration::from_millis(10));
+        let mut serial = Serial::with_link(Box::new(host));
+        send_byte(&mut serial, 0x11);
+        assert_eq!(serial.read(SB_ADDRESS), 0xFF);
+    }
+    #[test]
+    fn test_tcp_partner_gone() {
+        let (host, guest) = linked_pair();
+        drop(guest);
+        let mut serial = Serial::with_link(Box::new(host));
+        send_byte(&mut serial, 0x11);
+        assert_eq!(serial.read(SB_ADDRESS), 0xFF);
+    }
+    fn linked_serials() -> Lockstep<Serial> {
+        let (first, second) = CableEnd::pair();
+        Lockstep::new(Serial::with_link(Box::new(first)), Serial::with_link(Box::new(second)), 64)
+    }
+    #[test]
+    fn test_cable_transfer() {
+        let mut pair = linked_serials();
+        pair.second.write(SB_ADDRESS, 0xB0);
+        pair.second.write(SC_ADDRESS, 0x80);
+        pair.run_for(CYCLES_PER_BIT, |serial, cycles| serial.step(cycles));
+
+        pair.first.write(SB_ADDRESS, 0xA0);
+        pair.first.write(SC_ADDRESS, 0x81);
+        let done = pair.run_until(
+            CYCLES_PER_BIT * 16,
+            |serial, cycles| serial.step(cycles),
+            |first, second| !first.is_transferring() && !second.is_transferring(),
+        );
+        assert!(done);
+        assert_eq!(pair.first.read(SB_ADDRESS), 0xB0);
+        assert_eq!(pair.second.read(SB_ADDRESS), 0xA0);
+        assert!(pair.first.take_interrupt());
+        assert!(pair.second.take_interrupt());
+    }
+    #[test]
+    fn test_cable_partner_not_listening() {
+        let mut pair = linked_serials();
+        pair.second.write(SB_ADDRESS, 0xB0);
+        send_byte(&mut pair.first, 0xA0);
+        pair.run_for(CYCLES_PER_BIT * 16, |serial, cycles| serial.step(cycles));
+        assert_eq!(pair.first.read(SB_ADDRESS), 0xFF);
+        assert_eq!(pair.second.read(SB_ADDRESS), 0xB0);
+        assert!(!pair.second.take_interrupt());
+    }
+    #[test]
+    fn test_cable_is_deterministic() {
+        let run = || {
+            let mut pair = linked_serials();
+            let mut received = Vec::new();
+            for byte in 0..16u8 {
+                pair.second.write(SB_ADDRESS, byte ^ 0xFF);
+                pair.second.write(SC_ADDRESS, 0x80);
+                pair.first.write(SB_ADDRESS, byte);
+                pair.run_for(100, |serial, cycles| serial.step(cycles));
+                pair.first.write(SC_ADDRESS, 0x81);
+                pair.run_for(CYCLES_PER_BIT * 8, |serial, cycles| serial.step(cycles));
+                received.push((pair.first.read(SB_ADDRESS), pair.second.read(SB_ADDRESS)));
+            }
+            (received, pair.elapsed())
+        };
+        let (received, elapsed) = run();
+        assert_eq!(received[3], (0xFC, 0x03));
+        assert_eq!(elapsed, 16 * (100 + CYCLES_PER_BIT as u64 * 8));
+        assert_eq!(run(), (received, elapsed));
+    }
+    // Swaps three bytes over the cable, keeping what comes back at $C000. The side on the
+    // internal clock waits longer before each byte so that the other side is listening
+    fn use_test_linked_gameboy(control: u8, delay: u8, outgoing: [u8; 3]) -> (GameBoy, u16) {
+        let source = format!("
+SECTION \"Entry\", ROM0[$0100]
+    jp Main
+SECTION \"Main\", ROM0[$0150]
+Main:
+    ld de, Outgoing
+    ld hl, $C000
+    ld b, 3
+.next:
+    ld a, [de]
+    inc de
+    ldh [$FF01], a
+    ld c, {}
+.delay:
+    dec c
+    jr nz, .delay
+    ld a, {}
+    ldh [$FF02], a
+.wait:
+    ldh a, [$FF02]
+    bit 7, a
+    jr nz, .wait
+    ldh a, [$FF01]
+    ld [hl+], a
+    dec b
+    jr nz, .next
+Done:
+    jr Done
+Outgoing:
+    db {}, {}, {}
+", delay, control, outgoing[0], outgoing[1], outgoing[2]);
+        let program = assemble(&source).unwrap();
+        let done = program.symbols.find("Done").unwrap().address;
+        return (GameBoy::new(program.rom(), GameBoyOptions::new()).unwrap(), done)
+    }
+    #[test]
+    fn test_linked_gameboys() {
+        let (first, done) = use_test_linked_gameboy(0x81, 0, [0x11, 0x22, 0x33]);
+        let (second, _) = use_test_linked_gameboy(0x80, 1, [0xA1, 0xA2, 0xA3]);
+        let mut pair = LinkedGameBoys::new(first, second, 16);
+        assert!(pair.run_until(100_000, |first, second| first.cpu.pc == done && second.cpu.pc == done));
+        let received = |gameboy: &GameBoy| [0xC000, 0xC001, 0xC002].map(|address| gameboy.cpu.bus.peek(address));
+        assert_eq!(received(&pair.first), [0xA1, 0xA2, 0xA3]);
+        assert_eq!(received(&pair.second), [0x11, 0x22, 0x33]);
+        // Neither machine got more than an instruction ahead of the other
+        let elapsed = pair.elapsed();
+        assert!(pair.first.cpu.cycles.abs_diff(pair.second.cpu.cycles) <= 6);
+        assert!(pair.first.cpu.cycles >= elapsed && pair.second.cpu.cycles >= elapsed);
+    }
+    #[test]
+    fn test_bus_schedules_transfer() {
+        let mut bus = MemoryBus::new(Vec::new(), Model::Dmg, false);
+        let capture = CaptureLink::new();
+        bus.serial.set_link(Box::new(capture.clone()));
+        bus.write(SB_ADDRESS, b'A');
+        bus.write(SC_ADDRESS, 0x81);
+        assert!(bus.scheduler.is_scheduled(EventKind::SerialBit));
+        for _ in 0..(8 * CYCLES_PER_BIT / 4 - 1) {
+            bus.tick();
+        }
+        assert_eq!(bus.read(0xFF0F) & 0b1000, 0);
+        bus.tick();
+        assert_eq!(bus.read(0xFF0F) & 0b1000, 0b1000);
+        assert_eq!(bus.read(SC_ADDRESS), 0x7F);
+        assert!(!bus.scheduler.is_scheduled(EventKind::SerialBit));
+        assert_eq!(capture.output_string(), "A");
+    }
+}
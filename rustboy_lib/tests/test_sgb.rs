@@ -0,0 +1,167 @@
+use rustboy_lib::cpu::CPU;
+use rustboy_lib::model::Model;
+use rustboy_lib::sgb::{
+    Sgb, ScreenMask, VramTransfer, SGB_SCREEN_WIDTH, GAME_SCREEN_X, GAME_SCREEN_Y
+};
+
+
+#[cfg(test)]
+mod sgb_tests {
+    use super::*;
+    fn send_packet<F: FnMut(u8)>(mut write: F, packet: &[u8]) {
+        write(0x00);
+        write(0x30);
+        for bit in 0..128 {
+            let byte = packet.get(bit / 8).copied().unwrap_or(0);
+            write(if (byte >> (bit % 8)) & 0b1 == 1 { 0x10 } else { 0x20 });
+            write(0x30);
+        }
+        write(0x20);
+        write(0x30);
+    }
+    fn command(code: u8, packets: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![code << 3 | packets];
+        data.extend_from_slice(payload);
+        data.resize(16 * packets as usize, 0);
+        return data
+    }
+    fn use_sgb_cpu() -> CPU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x146] = 0x03;
+        rom[0x14B] = 0x33;
+        return CPU::power_on(rom, Model::Sgb, None).unwrap()
+    }
+    #[test]
+    fn test_pal01_over_joypad() {
+        let mut cpu = use_sgb_cpu();
+        let packet = command(0x00, 1, &[0x1F, 0x00, 0xE0, 0x03, 0x00, 0x7C, 0x00, 0x00, 0xFF, 0x7F, 0x10, 0x42, 0x08, 0x21]);
+        send_packet(|value| cpu.bus.write(0xFF00, value), &packet);
+        let sgb = cpu.bus.sgb.as_ref().unwrap();
+        assert_eq!(sgb.palettes[0], [0x001F, 0x03E0, 0x7C00, 0x0000]);
+        assert_eq!(sgb.palettes[1], [0x001F, 0x7FFF, 0x4210, 0x2108]);
+        assert_eq!(sgb.palettes[2][0], 0x001F);
+    }
+    #[test]
+    fn test_sgb_needs_header_support() {
+        let cpu = CPU::power_on(vec![0; 0x8000], Model::Sgb, None).unwrap();
+        assert!(cpu.bus.sgb.is_none());
+        assert!(CPU::power_on(vec![0; 0x8000], Model::Dmg, None).unwrap().bus.sgb.is_none());
+    }
+    #[test]
+    fn test_multiplayer_joypad_id() {
+        let mut cpu = use_sgb_cpu();
+        cpu.bus.write(0xFF00, 0x30);
+        assert_eq!(cpu.bus.read(0xFF00), 0xFF);
+        send_packet(|value| cpu.bus.write(0xFF00, value), &command(0x11, 1, &[0x01]));
+        assert_eq!(cpu.bus.read(0xFF00), 0xFF);
+        cpu.bus.write(0xFF00, 0x10);
+        cpu.bus.write(0xFF00, 0x30);
+        assert_eq!(cpu.bus.read(0xFF00), 0xFE);
+        cpu.bus.write(0xFF00, 0x10);
+        cpu.bus.write(0xFF00, 0x30);
+        assert_eq!(cpu.bus.read(0xFF00), 0xFF);
+    }
+    #[test]
+    fn test_attr_blk() {
+        let mut sgb = Sgb::new();
+        // Inside only, so the edge takes the inside palette too
+        sgb.execute(&command(0x04, 1, &[1, 0b001, 0b00_00_10, 2, 2, 5, 4]));
+        assert_eq!(sgb.attributes.get(2, 2), 2);
+        assert_eq!(sgb.attributes.get(3, 3), 2);
+        assert_eq!(sgb.attributes.get(5, 4), 2);
+        assert_eq!(sgb.attributes.get(6, 4), 0);
+        // Outside and line
+        sgb.execute(&command(0x04, 1, &[1, 0b110, 0b11_01_00, 2, 2, 5, 4]));
+        assert_eq!(sgb.attributes.get(2, 2), 1);
+        assert_eq!(sgb.attributes.get(3, 3), 2);
+        assert_eq!(sgb.attributes.get(0, 0), 3);
+    }
+    #[test]
+    fn test_attr_lin_div_chr() {
+        let mut sgb = Sgb::new();
+        sgb.execute(&command(0x05, 1, &[2, 0x80 | 0x40 | 3, 0x20 | 7]));
+        assert_eq!(sgb.attributes.get(19, 3), 2);
+        assert_eq!(sgb.attributes.get(7, 17), 1);
+        assert_eq!(sgb.attributes.get(8, 17), 0);
+
+        sgb.execute(&command(0x06, 1, &[0x40 | 0b10_01_11, 9]));
+        assert_eq!(sgb.attributes.get(4, 8), 1);
+        assert_eq!(sgb.attributes.get(4, 9), 2);
+        assert_eq!(sgb.attributes.get(4, 10), 3);
+
+        sgb.execute(&command(0x07, 1, &[19, 0, 3, 0, 0, 0b01_10_11_00]));
+        assert_eq!(sgb.attributes.get(19, 0), 1);
+        assert_eq!(sgb.attributes.get(0, 1), 2);
+        assert_eq!(sgb.attributes.get(1, 1), 3);
+    }
+    #[test]
+    fn test_pal_trn_and_pal_set() {
+        let mut sgb = Sgb::new();
+        sgb.execute(&command(0x0B, 1, &[]));
+        assert_eq!(sgb.pending_transfer(), Some(VramTransfer::SystemPalettes));
+        let mut data = vec![0; 0x1000];
+        for palette in 0..512usize {
+            for color in 0..4usize {
+                let value = (palette * 4 + color) as u16;
+                data[(palette * 4 + color) * 2] = value as u8;
+                data[(palette * 4 + color) * 2 + 1] = (value >> 8) as u8;
+            }
+        }
+        sgb.complete_transfer(&data);
+        assert_eq!(sgb.pending_transfer(), None);
+
+        sgb.mask = ScreenMask::Black;
+        sgb.execute(&command(0x0A, 1, &[0x05, 0x00, 0x10, 0x00, 0xFF, 0x01, 0x00, 0x00, 0x40]));
+        assert_eq!(sgb.palettes[0], [20, 21, 22, 23]);
+        assert_eq!(sgb.palettes[1], [20, 65, 66, 67]);
+        assert_eq!(sgb.palettes[2], [20, 2045, 2046, 2047]);
+        assert_eq!(sgb.mask, ScreenMask::None);
+    }
+    #[test]
+    fn test_attr_trn_and_attr_set() {
+        let mut sgb = Sgb::new();
+        sgb.execute(&command(0x15, 1, &[]));
+        let mut data = vec![0; 0x1000];
+        data[90 * 3] = 0b11_10_01_00;
+        sgb.complete_transfer(&data);
+        sgb.execute(&command(0x16, 1, &[3]));
+        assert_eq!(sgb.attributes.get(0, 0), 3);
+        assert_eq!(sgb.attributes.get(1, 0), 2);
+        assert_eq!(sgb.attributes.get(3, 0), 0);
+    }
+    #[test]
+    fn test_render_with_border() {
+        let mut sgb = Sgb::new();
+        sgb.execute(&command(0x00, 1, &[0x00, 0x00, 0x1F, 0x00, 0xE0, 0x03, 0x00, 0x7C]));
+        // Tile 1 has its top left pixel set to colour 1
+        sgb.execute(&command(0x13, 1, &[0]));
+        let mut tiles = vec![0; 0x1000];
+        tiles[32] = 0x80;
+        sgb.complete_transfer(&tiles);
+        // Map entry 0 uses tile 1 with palette 4, whose colour 1 is 0x1234
+        sgb.execute(&command(0x14, 1, &[]));
+        let mut map = vec![0; 0x1000];
+        map[0] = 0x01;
+        map[1] = 0x10;
+        map[0x802] = 0x34;
+        map[0x803] = 0x12;
+        sgb.complete_transfer(&map);
+
+        let mut screen = vec![0; 160 * 144];
+        screen[0] = 1;
+        screen[1] = 3;
+        let output = sgb.render(&screen);
+        assert_eq!(output.len(), 256 * 224);
+        assert_eq!(output[0], 0x1234);
+        assert_eq!(output[1], 0x0000);
+        let game = GAME_SCREEN_Y * SGB_SCREEN_WIDTH + GAME_SCREEN_X;
+        assert_eq!(output[game], 0x001F);
+        assert_eq!(output[game + 1], 0x7C00);
+
+        sgb.execute(&command(0x17, 1, &[1]));
+        let output = sgb.render(&vec![2; 160 * 144]);
+        assert_eq!(output[game], 0x001F);
+        sgb.execute(&command(0x17, 1, &[2]));
+        assert_eq!(sgb.render(&screen)[game], 0x0000);
+    }
+}
@@ -0,0 +1,477 @@
+use rustboy_lib::cpu::registers::FlagsRegister;
+use rustboy_lib::cpu::CPU;
+use rustboy_lib::memory::bus::MemoryBus;
+use rustboy_lib::trace::BusCycle;
+use std::path::PathBuf;
+
+
+// Runs the single-step SM83 tests (github.com/SingleStepTests/sm83), one JSON file per
+// opcode like "00.json" or "cb 7c.json", each an array of cases like
+//   {"name": "3e 0001",
+//    "initial": {"pc": 49152, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0,
+//                "h": 0, "l": 0, "ime": 0, "ie": 0, "ram": [[49152, 62], [49153, 66]]},
+//    "final": {...},
+//    "cycles": [[49152, 62, "r-m"], [49153, 66, "r-m"]]}
+// where every cycle is the address and data on the bus and whether it was read or written.
+// The suite is too big to keep in the repository, so test_suite is ignored by default. Point
+// SM83_TESTS at its v1 directory, or put it in tests/sm83, and run
+//   cargo test --test test_sm83 -- --ignored
+// The cases in SAMPLE always run
+#[cfg(test)]
+mod sm83_tests {
+    use super::*;
+    const SAMPLE: &str = r#"[
+        {"name": "00 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 176, "h": 6, "l": 7, "ime": 0, "ie": 0,
+                     "ram": [[49152, 0]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 1, "b": 2, "c": 3, "d": 4, "e": 5, "f": 176, "h": 6, "l": 7, "ime": 0,
+                   "ram": [[49152, 0]]},
+         "cycles": [[49152, 0, "r-m"]]},
+        {"name": "3e 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 62], [49153, 66]]},
+         "final": {"pc": 49154, "sp": 53248, "a": 66, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 62], [49153, 66]]},
+         "cycles": [[49152, 62, "r-m"], [49153, 66, "r-m"]]},
+        {"name": "c5 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 1, "ie": 0,
+                     "ram": [[49152, 197]]},
+         "final": {"pc": 49153, "sp": 53246, "a": 0, "b": 18, "c": 52, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 1,
+                   "ram": [[49152, 197], [53247, 18], [53246, 52]]},
+         "cycles": [[49152, 197, "r-m"], [53248, null, "---"], [53247, 18, "-wm"], [53246, 52, "-wm"]]},
+        {"name": "cb 7c 0000",
+         "initial": {"pc": 256, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 128, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[256, 203], [257, 124]]},
+         "final": {"pc": 258, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 48, "h": 128, "l": 0, "ime": 0,
+                   "ram": [[256, 203], [257, 124]]},
+         "cycles": [[256, 203, "r-m"], [257, 124, "r-m"]]},
+        {"name": "c3 0000",
+         "initial": {"pc": 65533, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[65533, 195], [65534, 52], [65535, 18]]},
+         "final": {"pc": 4660, "sp": 0, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[65533, 195], [65534, 52], [65535, 18]]},
+         "cycles": [[65533, 195, "r-m"], [65534, 52, "r-m"], [65535, 18, "r-m"], [4660, null, "---"]]},
+        {"name": "07 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 133, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 7]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 11, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 7]]},
+         "cycles": [[49152, 7, "r-m"]]},
+        {"name": "0f 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 1, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 15]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 128, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 15]]},
+         "cycles": [[49152, 15, "r-m"]]},
+        {"name": "17 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 1, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 23]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 3, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 23]]},
+         "cycles": [[49152, 23, "r-m"]]},
+        {"name": "17 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 128, "b": 0, "c": 0, "d": 0, "e": 0, "f": 128, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 23]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 23]]},
+         "cycles": [[49152, 23, "r-m"]]},
+        {"name": "1f 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 1, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 31]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 128, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 31]]},
+         "cycles": [[49152, 31, "r-m"]]},
+        {"name": "27 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 154, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 39]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 144, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 39]]},
+         "cycles": [[49152, 39, "r-m"]]},
+        {"name": "27 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 15, "b": 0, "c": 0, "d": 0, "e": 0, "f": 96, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 39]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 9, "b": 0, "c": 0, "d": 0, "e": 0, "f": 64, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 39]]},
+         "cycles": [[49152, 39, "r-m"]]},
+        {"name": "88 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 15, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 136]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 16, "b": 0, "c": 0, "d": 0, "e": 0, "f": 32, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 136]]},
+         "cycles": [[49152, 136, "r-m"]]},
+        {"name": "98 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 16, "b": 0, "c": 0, "d": 0, "e": 0, "f": 16, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 152]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 15, "b": 0, "c": 0, "d": 0, "e": 0, "f": 96, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 152]]},
+         "cycles": [[49152, 152, "r-m"]]},
+        {"name": "fb 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 251]]},
+         "final": {"pc": 49153, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ei": 1,
+                   "ram": [[49152, 251]]},
+         "cycles": [[49152, 251, "r-m"]]},
+        {"name": "10 0000",
+         "initial": {"pc": 49152, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0,
+                     "ram": [[49152, 16], [49153, 0]]},
+         "final": {"pc": 49154, "sp": 53248, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0, "f": 0, "h": 0, "l": 0, "ime": 0,
+                   "ram": [[49152, 16], [49153, 0]]},
+         "cycles": [[49152, 16, "r-m"]]}
+    ]"#;
+
+    // Just enough JSON for the test files
+    #[derive(Clone, Debug, PartialEq)]
+    enum Json {
+        Null,
+        Bool(bool),
+        Number(i64),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+    impl Json {
+        fn get(&self, key: &str) -> Option<&Json> {
+            match self {
+                Json::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+                _ => None,
+            }
+        }
+
+        fn number(&self) -> Result<i64, String> {
+            match self {
+                Json::Number(number) => Ok(*number),
+                Json::Bool(value) => Ok(*value as i64),
+                other => Err(format!("expected a number, not {:?}", other)),
+            }
+        }
+
+        fn array(&self) -> Result<&[Json], String> {
+            match self {
+                Json::Array(items) => Ok(items),
+                other => Err(format!("expected an array, not {:?}", other)),
+            }
+        }
+
+        fn field(&self, key: &str) -> Result<&Json, String> {
+            self.get(key).ok_or_else(|| format!("missing \"{}\"", key))
+        }
+    }
+
+    struct JsonParser<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+    impl JsonParser<'_> {
+        fn parse(text: &str) -> Result<Json, String> {
+            let mut parser = JsonParser { bytes: text.as_bytes(), position: 0 };
+            let value = parser.value()?;
+            parser.skip_whitespace();
+            if parser.position != parser.bytes.len() {
+                return Err(format!("unexpected text after the value at byte {}", parser.position));
+            }
+            return Ok(value)
+        }
+
+        fn skip_whitespace(&mut self) {
+            while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+                self.position += 1;
+            }
+        }
+
+        fn expect(&mut self, expected: u8) -> Result<(), String> {
+            self.skip_whitespace();
+            if self.bytes.get(self.position) != Some(&expected) {
+                return Err(format!("expected '{}' at byte {}", expected as char, self.position));
+            }
+            self.position += 1;
+            Ok(())
+        }
+
+        // Whether the next byte is this one, which is then skipped
+        fn next_is(&mut self, byte: u8) -> bool {
+            self.skip_whitespace();
+            let found = self.bytes.get(self.position) == Some(&byte);
+            if found {
+                self.position += 1;
+            }
+            return found
+        }
+
+        fn value(&mut self) -> Result<Json, String> {
+            self.skip_whitespace();
+            let rest = &self.bytes[self.position..];
+            for (word, value) in [("null", Json::Null), ("true", Json::Bool(true)), ("false", Json::Bool(false))] {
+                if rest.starts_with(word.as_bytes()) {
+                    self.position += word.len();
+                    return Ok(value);
+                }
+            }
+            match rest.first() {
+                Some(b'"') => Ok(Json::String(self.string()?)),
+                Some(b'[') => {
+                    self.position += 1;
+                    let mut items = Vec::new();
+                    if !self.next_is(b']') {
+                        loop {
+                            items.push(self.value()?);
+                            if self.next_is(b']') {
+                                break;
+                            }
+                            self.expect(b',')?;
+                        }
+                    }
+                    Ok(Json::Array(items))
+                }
+                Some(b'{') => {
+                    self.position += 1;
+                    let mut entries = Vec::new();
+                    if !self.next_is(b'}') {
+                        loop {
+                            self.skip_whitespace();
+                            let key = self.string()?;
+                            self.expect(b':')?;
+                            entries.push((key, self.value()?));
+                            if self.next_is(b'}') {
+                                break;
+                            }
+                            self.expect(b',')?;
+                        }
+                    }
+                    Ok(Json::Object(entries))
+                }
+                Some(byte) if *byte == b'-' || byte.is_ascii_digit() => {
+                    let start = self.position;
+                    self.position += 1;
+                    while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_digit()) {
+                        self.position += 1;
+                    }
+                    let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap();
+                    text.parse().map(Json::Number).map_err(|_| format!("bad number {} at byte {}", text, start))
+                }
+                _ => Err(format!("unexpected character at byte {}", self.position)),
+            }
+        }
+
+        fn string(&mut self) -> Result<String, String> {
+            self.expect(b'"')?;
+            let mut text = Vec::new();
+            loop {
+                let Some(&byte) = self.bytes.get(self.position) else {
+                    return Err(String::from("unterminated string"));
+                };
+                self.position += 1;
+                match byte {
+                    b'"' => break,
+                    b'\\' => {
+                        let escaped = self.bytes.get(self.position).copied().ok_or("unterminated string")?;
+                        self.position += 1;
+                        match escaped {
+                            b'n' => text.push(b'\n'),
+                            b't' => text.push(b'\t'),
+                            b'u' => {
+                                let digits = self.bytes.get(self.position..self.position + 4).ok_or("short \\u escape")?;
+                                let code = u32::from_str_radix(std::str::from_utf8(digits).unwrap_or(""), 16).map_err(|_| "bad \\u escape")?;
+                                self.position += 4;
+                                let character = char::from_u32(code).unwrap_or('?');
+                                text.extend_from_slice(character.to_string().as_bytes());
+                            }
+                            other => text.push(other),
+                        }
+                    }
+                    _ => text.push(byte),
+                }
+            }
+            return String::from_utf8(text).map_err(|error| error.to_string())
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    struct State {
+        pc: u16,
+        sp: u16,
+        registers: [u8; 8], // A, B, C, D, E, F, H, L
+        ime: Option<bool>,
+        ei: Option<bool>, // EI ran, IME turns on after the next instruction
+        ie: Option<u8>,
+        ram: Vec<(u16, u8)>,
+    }
+    impl State {
+        fn from_json(json: &Json) -> Result<State, String> {
+            let byte = |key: &str| json.field(key)?.number().map(|value| value as u8);
+            let mut registers = [0; 8];
+            for (register, name) in registers.iter_mut().zip(["a", "b", "c", "d", "e", "f", "h", "l"]) {
+                *register = byte(name)?;
+            }
+            let mut ram = Vec::new();
+            for entry in json.field("ram")?.array()? {
+                let entry = entry.array()?;
+                if entry.len() != 2 {
+                    return Err(String::from("RAM entries are [address, value]"));
+                }
+                ram.push((entry[0].number()? as u16, entry[1].number()? as u8));
+            }
+            return Ok(State {
+                pc: json.field("pc")?.number()? as u16,
+                sp: json.field("sp")?.number()? as u16,
+                registers,
+                ime: json.get("ime").map(|ime| ime.number()).transpose()?.map(|ime| ime != 0),
+                ei: json.get("ei").map(|ei| ei.number()).transpose()?.map(|ei| ei != 0),
+                ie: json.get("ie").map(|ie| ie.number()).transpose()?.map(|ie| ie as u8),
+                ram,
+            })
+        }
+    }
+
+    struct TestCase {
+        name: String,
+        initial: State,
+        expected: State,
+        cycles: Vec<BusCycle>,
+    }
+    impl TestCase {
+        fn from_json(json: &Json) -> Result<TestCase, String> {
+            let name = match json.field("name")? {
+                Json::String(name) => name.clone(),
+                other => return Err(format!("expected a name, not {:?}", other)),
+            };
+            let mut cycles = Vec::new();
+            for cycle in json.field("cycles")?.array()? {
+                let cycle = cycle.array()?;
+                let kind = match cycle.get(2) {
+                    Some(Json::String(kind)) => kind.as_str(),
+                    _ => return Err(format!("{}: cycles are [address, value, kind]", name)),
+                };
+                // The kind is "r-m" for reads, "-wm" for writes and "---" when idle
+                cycles.push(if kind.contains('r') {
+                    BusCycle::Read(cycle[0].number()? as u16, cycle[1].number()? as u8)
+                } else if kind.contains('w') {
+                    BusCycle::Write(cycle[0].number()? as u16, cycle[1].number()? as u8)
+                } else {
+                    BusCycle::Idle
+                });
+            }
+            let initial = State::from_json(json.field("initial")?).map_err(|error| format!("{}: {}", name, error))?;
+            let expected = State::from_json(json.field("final")?).map_err(|error| format!("{}: {}", name, error))?;
+            return Ok(TestCase { name, initial, expected, cycles })
+        }
+
+        // Runs the one instruction on a CPU with nothing but RAM around it
+        fn run(&self) -> Result<(), String> {
+            let mut cpu = CPU { bus: MemoryBus::flat(), bus_log: Some(Vec::new()), ..CPU::new() };
+            let initial = &self.initial;
+            cpu.pc = initial.pc;
+            cpu.sp = initial.sp;
+            let [a, b, c, d, e, f, h, l] = initial.registers;
+            (cpu.registers.a, cpu.registers.b, cpu.registers.c, cpu.registers.d) = (a, b, c, d);
+            (cpu.registers.e, cpu.registers.h, cpu.registers.l) = (e, h, l);
+            cpu.registers.f = FlagsRegister::from(f);
+            cpu.ime = initial.ime.unwrap_or(false);
+            cpu.ime_pending = initial.ei.unwrap_or(false);
+            // IE is also the byte at 0xFFFF, which the RAM listed goes over
+            if let Some(ie) = initial.ie {
+                cpu.bus.write(0xFFFF, ie);
+            }
+            for &(address, value) in &initial.ram {
+                cpu.bus.write(address, value);
+            }
+            cpu.step();
+
+            let registers = &cpu.registers;
+            let ours = [registers.a, registers.b, registers.c, registers.d, registers.e, u8::from(registers.f), registers.h, registers.l];
+            let expected = &self.expected;
+            let mut differences = Vec::new();
+            for ((name, ours), expected) in ["A", "B", "C", "D", "E", "F", "H", "L"].iter().zip(ours).zip(expected.registers) {
+                if ours != expected {
+                    differences.push(format!("{} is ${:02X}, not ${:02X}", name, ours, expected));
+                }
+            }
+            if cpu.pc != expected.pc {
+                differences.push(format!("PC is ${:04X}, not ${:04X}", cpu.pc, expected.pc));
+            }
+            if cpu.sp != expected.sp {
+                differences.push(format!("SP is ${:04X}, not ${:04X}", cpu.sp, expected.sp));
+            }
+            if let Some(ime) = expected.ime && cpu.ime != ime {
+                differences.push(format!("IME is {}, not {}", cpu.ime, ime));
+            }
+            if let Some(ei) = expected.ei && cpu.ime_pending != ei {
+                differences.push(format!("EI pending is {}, not {}", cpu.ime_pending, ei));
+            }
+            for &(address, value) in &expected.ram {
+                let ours = cpu.bus.peek(address);
+                if ours != value {
+                    differences.push(format!("${:04X} holds ${:02X}, not ${:02X}", address, ours, value));
+                }
+            }
+            let log = cpu.bus_log.take().unwrap();
+            if log != self.cycles {
+                differences.push(format!("the bus cycles are {:?}, not {:?}", log, self.cycles));
+            }
+            if differences.is_empty() {
+                return Ok(());
+            }
+            return Err(format!("{}: {}", self.name, differences.join(", ")))
+        }
+    }
+
+    fn parse_cases(text: &str) -> Result<Vec<TestCase>, String> {
+        JsonParser::parse(text)?.array()?.iter().map(TestCase::from_json).collect()
+    }
+
+    fn use_test_directory() -> PathBuf {
+        match std::env::var_os("SM83_TESTS") {
+            Some(directory) => PathBuf::from(directory),
+            None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("sm83"),
+        }
+    }
+    #[test]
+    fn test_json() {
+        let json = JsonParser::parse(r#" {"a": [1, -2, null, true], "b\"A": {}, "c": []} "#).unwrap();
+        assert_eq!(json.get("a"), Some(&Json::Array(vec![Json::Number(1), Json::Number(-2), Json::Null, Json::Bool(true)])));
+        assert_eq!(json.get("b\"A"), Some(&Json::Object(Vec::new())));
+        assert_eq!(json.get("c"), Some(&Json::Array(Vec::new())));
+        assert!(JsonParser::parse("[1, 2").is_err());
+        assert!(JsonParser::parse("[1] 2").is_err());
+        assert!(JsonParser::parse("{\"a\" 1}").is_err());
+    }
+    #[test]
+    fn test_sample_cases() {
+        let cases = parse_cases(SAMPLE).unwrap();
+        assert_eq!(cases.len(), 16);
+        let failures: Vec<String> = cases.iter().filter_map(|case| case.run().err()).collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+        // Each kind of difference gets reported
+        let mut case = parse_cases(SAMPLE).unwrap().remove(2);
+        case.expected.registers[1] = 0;
+        case.expected.sp = 0;
+        case.expected.ram[1].1 = 0;
+        case.cycles.swap(2, 3);
+        let error = case.run().unwrap_err();
+        assert!(error.starts_with("c5 0000: B is $12, not $00, SP is $CFFE, not $0000, $CFFF holds $12, not $00, the bus cycles are"), "{}", error);
+    }
+    #[test]
+    #[ignore = "needs the SM83 suite in SM83_TESTS or tests/sm83"]
+    fn test_suite() {
+        let directory = use_test_directory();
+        let entries = std::fs::read_dir(&directory)
+            .unwrap_or_else(|error| panic!("can't read the SM83 tests from {}: {}", directory.display(), error));
+        let mut paths: Vec<PathBuf> = entries
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .collect();
+        paths.sort();
+        // Every file runs, with only its first failure kept to keep the report readable
+        let mut failures = Vec::new();
+        let mut count = 0;
+        for path in &paths {
+            let text = std::fs::read_to_string(path).unwrap();
+            let cases = parse_cases(&text).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            count += cases.len();
+            if let Some(failure) = cases.iter().find_map(|case| case.run().err()) {
+                failures.push(failure);
+            }
+        }
+        assert!(failures.is_empty(), "{} of {} files have failures:\n{}", failures.len(), paths.len(), failures.join("\n"));
+        eprintln!("ran {} SM83 tests from {} files", count, paths.len());
+    }
+}
@@ -0,0 +1,108 @@
+use rustboy_lib::scheduler::{EventKind, Scheduler};
+use rustboy_lib::timer::{Timer, DIV_ADDRESS, TIMA_ADDRESS, TMA_ADDRESS, TAC_ADDRESS};
+
+
+#[cfg(test)]
+mod timer_tests {
+    use super::*;
+    struct TestTimer {
+        timer: Timer,
+        scheduler: Scheduler,
+    }
+    impl TestTimer {
+        fn new() -> TestTimer {
+            TestTimer { timer: Timer::new(), scheduler: Scheduler::new() }
+        }
+        // Advances in M-cycles like the bus does, firing events as they come due
+        fn step(&mut self, cycles: u64) {
+            for _ in 0..cycles / 4 {
+                self.scheduler.advance(4);
+                while let Some(event) = self.scheduler.pop_due() {
+                    self.timer.handle_event(event.kind, &mut self.scheduler);
+                }
+            }
+        }
+        fn read(&self, address: u16) -> u8 {
+            self.timer.read(address, self.scheduler.now())
+        }
+        fn write(&mut self, address: u16, value: u8) {
+            self.timer.write(address, value, &mut self.scheduler);
+        }
+        fn set_counter(&mut self, counter: u16) {
+            self.timer.set_counter(counter, &mut self.scheduler);
+        }
+        fn take_interrupt(&mut self) -> bool {
+            self.timer.take_interrupt()
+        }
+    }
+    #[test]
+    fn test_div() {
+        let mut timer = TestTimer::new();
+        timer.step(252);
+        assert_eq!(timer.read(DIV_ADDRESS), 0);
+        timer.step(4);
+        assert_eq!(timer.read(DIV_ADDRESS), 1);
+        timer.write(DIV_ADDRESS, 0x55);
+        assert_eq!(timer.read(DIV_ADDRESS), 0);
+        assert_eq!(timer.read(TAC_ADDRESS), 0xF8);
+    }
+    #[test]
+    fn test_tima_overflow_and_reload() {
+        let mut timer = TestTimer::new();
+        timer.write(TMA_ADDRESS, 0x80);
+        timer.write(TIMA_ADDRESS, 0xFF);
+        // 262144 Hz, once every 4 M-cycles
+        timer.write(TAC_ADDRESS, 0b101);
+        timer.step(12);
+        assert_eq!(timer.read(TIMA_ADDRESS), 0xFF);
+        timer.step(4);
+        // TIMA reads 0 for a cycle before TMA is loaded
+        assert_eq!(timer.read(TIMA_ADDRESS), 0x00);
+        assert!(!timer.take_interrupt());
+        timer.step(4);
+        assert_eq!(timer.read(TIMA_ADDRESS), 0x80);
+        assert!(timer.take_interrupt());
+        assert!(!timer.take_interrupt());
+    }
+    #[test]
+    fn test_tima_write_cancels_reload() {
+        let mut timer = TestTimer::new();
+        timer.write(TMA_ADDRESS, 0x80);
+        timer.write(TIMA_ADDRESS, 0xFF);
+        timer.write(TAC_ADDRESS, 0b101);
+        timer.step(16);
+        timer.write(TIMA_ADDRESS, 0x10);
+        timer.step(4);
+        assert_eq!(timer.read(TIMA_ADDRESS), 0x10);
+        assert!(!timer.take_interrupt());
+    }
+    #[test]
+    fn test_falling_edge_glitches() {
+        // Resetting DIV while the selected bit is high counts as a falling edge
+        let mut timer = TestTimer::new();
+        timer.write(TAC_ADDRESS, 0b100);
+        timer.set_counter(0x0200);
+        timer.write(DIV_ADDRESS, 0);
+        assert_eq!(timer.read(TIMA_ADDRESS), 1);
+        // So does turning the timer off
+        timer.set_counter(0x0200);
+        timer.write(TAC_ADDRESS, 0b000);
+        assert_eq!(timer.read(TIMA_ADDRESS), 2);
+    }
+    #[test]
+    fn test_overflow_is_scheduled() {
+        let mut timer = TestTimer::new();
+        timer.write(TIMA_ADDRESS, 0xFE);
+        assert!(!timer.scheduler.is_scheduled(EventKind::TimerOverflow));
+        // 4096 Hz, TIMA goes up every 1024 T-cycles
+        timer.write(TAC_ADDRESS, 0b100);
+        assert_eq!(timer.scheduler.next_event_time(), Some(2048));
+        timer.scheduler.advance(1500);
+        assert_eq!(timer.read(TIMA_ADDRESS), 0xFF);
+        assert_eq!(timer.read(DIV_ADDRESS), 5);
+        timer.step(548);
+        assert_eq!(timer.read(TIMA_ADDRESS), 0x00);
+        timer.step(4);
+        assert!(timer.take_interrupt());
+    }
+}
@@ -0,0 +1,127 @@
+use rustboy_lib::assembler::assemble;
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::symbols::Symbols;
+use rustboy_lib::trace::{diff_traces, format_state, TraceFilter, TraceMismatch, TraceSink, Tracer};
+use std::sync::{Arc, Mutex};
+
+
+#[cfg(test)]
+mod trace_tests {
+    use super::*;
+    const SOURCE: &str = "
+SECTION \"Entry\", ROM0[$0100]
+    nop
+    jp Main
+SECTION \"Main\", ROM0[$0150]
+Main:
+    ld a, 2
+    ld [$2000], a
+    call Far
+.loop:
+    inc b
+    jr .loop
+SECTION \"Bank 2\", ROMX[$4000], BANK[2]
+Far:
+    ld c, $42
+    ret
+";
+    // An MBC1 cartridge that calls into bank 2 and then counts in B forever. The header
+    // checksum is 0, which leaves H and C clear after the boot ROM
+    fn use_test_gameboy() -> GameBoy {
+        let mut rom = assemble(SOURCE).unwrap().rom();
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        return GameBoy::new(rom, GameBoyOptions::new()).unwrap()
+    }
+    // A tracer that collects its lines where the test can see them
+    fn use_test_tracer(filter: TraceFilter) -> (Tracer, Arc<Mutex<Vec<String>>>) {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let tracer = Tracer::new(TraceSink::Callback(Box::new(move |line| sink.lock().unwrap().push(String::from(line)))), filter);
+        return (tracer, lines)
+    }
+    fn run_instructions(gameboy: &mut GameBoy, count: usize) {
+        for _ in 0..count {
+            gameboy.step();
+        }
+    }
+    #[test]
+    fn test_format() {
+        let gameboy = use_test_gameboy();
+        assert_eq!(format_state(&gameboy.cpu), "A:01 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01");
+    }
+    #[test]
+    fn test_trace() {
+        let mut gameboy = use_test_gameboy();
+        let (tracer, lines) = use_test_tracer(TraceFilter::new());
+        gameboy.cpu.trace = Some(tracer);
+        run_instructions(&mut gameboy, 8);
+        let lines = lines.lock().unwrap().clone();
+        let pcs: Vec<&str> = lines.iter().map(|line| &line[48..55]).collect();
+        assert_eq!(pcs, ["PC:0100", "PC:0101", "PC:0150", "PC:0152", "PC:0155", "PC:4000", "PC:4002", "PC:0158"]);
+        // Each line has the state left by the one before
+        assert!(lines[3].starts_with("A:02 "));
+        assert!(lines[7].contains(" C:42 "));
+        assert_eq!(gameboy.cpu.trace.as_ref().unwrap().lines(), 8);
+        assert!(gameboy.cpu.trace.take().unwrap().finish().is_ok());
+    }
+    #[test]
+    fn test_filters_and_labels() {
+        let mut gameboy = use_test_gameboy();
+        let (tracer, lines) = use_test_tracer(TraceFilter { start: 0x4000, end: 0x7FFF, bank: Some(2) });
+        gameboy.cpu.trace = Some(tracer.with_symbols(assemble(SOURCE).unwrap().symbols));
+        run_instructions(&mut gameboy, 12);
+        let lines = lines.lock().unwrap().clone();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("PC:4000 PCMEM:0E,42,C9,00 ; Far"));
+        assert!(lines[1].ends_with(" ; Far+$2"));
+
+        // Bank 1 is never mapped
+        let mut gameboy = use_test_gameboy();
+        let (tracer, lines) = use_test_tracer(TraceFilter { bank: Some(1), ..TraceFilter::new() });
+        gameboy.cpu.trace = Some(tracer.with_symbols(Symbols::new()));
+        run_instructions(&mut gameboy, 12);
+        assert!(lines.lock().unwrap().is_empty());
+    }
+    #[test]
+    fn test_trace_file() {
+        let path = std::env::temp_dir().join(format!("rustboy_trace_{}.log", std::process::id()));
+        let mut gameboy = use_test_gameboy();
+        gameboy.cpu.trace = Some(Tracer::create(&path, TraceFilter::new()).unwrap());
+        run_instructions(&mut gameboy, 3);
+        gameboy.cpu.trace.take().unwrap().finish().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.starts_with("A:01 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01\n"));
+    }
+    #[test]
+    fn test_diff() {
+        let line = |a: u8, pc: u16| format!("A:{:02X} F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:{:04X} PCMEM:00,C3,50,01", a, pc);
+        let ours = [line(1, 0x100), line(1, 0x101), line(2, 0x150), line(3, 0x152)].join("\n");
+        assert_eq!(diff_traces(ours.as_bytes(), ours.as_bytes(), 2).unwrap(), None);
+        // Case and whatever follows PCMEM don't count
+        let reference = format!("{} ; Entry\n{}", line(1, 0x100).to_lowercase(), line(1, 0x101));
+        assert_eq!(diff_traces(ours.lines().take(2).collect::<Vec<_>>().join("\n").as_bytes(), reference.as_bytes(), 2).unwrap(), None);
+
+        let reference = [line(1, 0x100), line(1, 0x101), line(2, 0x150), line(4, 0x153)].join("\n");
+        let mismatch = diff_traces(ours.as_bytes(), reference.as_bytes(), 2).unwrap().unwrap();
+        assert_eq!(mismatch, TraceMismatch {
+            line: 4,
+            ours: Some(line(3, 0x152)),
+            reference: Some(line(4, 0x153)),
+            context: vec![line(1, 0x101), line(2, 0x150)],
+            fields: vec![String::from("A"), String::from("PC")],
+        });
+        let report = mismatch.to_string();
+        assert!(report.starts_with("traces differ at line 4 in A, PC\n"));
+        assert!(report.contains("         2  A:01"));
+        assert!(report.contains("-        4  A:03"));
+        assert!(report.contains("+        4  A:04"));
+
+        // One trace stopping early is a difference too
+        let mismatch = diff_traces(ours.as_bytes(), line(1, 0x100).as_bytes(), 0).unwrap().unwrap();
+        assert_eq!((mismatch.line, mismatch.reference.as_deref(), mismatch.context.len()), (2, None, 0));
+        assert!(mismatch.to_string().ends_with("(end of trace)"));
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use rustboy_lib::assembler::assemble;
+
+const USAGE: &str = "usage: rustboy_main asm <file.asm> --output <file.gb> [--patch <rom>] [--sym <file.sym>]";
+
+// Assembles a source file into a ROM image. With --patch the ROM sections are written over
+// a copy of an existing ROM instead of a blank one, which is handy for small fixes and
+// hacks. --sym writes the labels out for the debugger and disassembler
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut source = None;
+    let mut output = None;
+    let mut patch = None;
+    let mut sym = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--output" => output = Some(PathBuf::from(value()?)),
+            "--patch" => patch = Some(PathBuf::from(value()?)),
+            "--sym" => sym = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => source = Some(PathBuf::from(arg)),
+        }
+    }
+    let source_path = source.ok_or_else(|| String::from(USAGE))?;
+    let output = output.ok_or_else(|| String::from(USAGE))?;
+    let text = std::fs::read_to_string(&source_path).map_err(|error| format!("{}: {}", source_path.display(), error))?;
+    let program = assemble(&text).map_err(|error| format!("{}: {}", source_path.display(), error))?;
+    let rom = match patch {
+        Some(path) => {
+            let mut rom = std::fs::read(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+            program.patch(&mut rom);
+            rom
+        }
+        None => program.rom(),
+    };
+    std::fs::write(&output, rom).map_err(|error| format!("{}: {}", output.display(), error))?;
+    if let Some(path) = sym {
+        std::fs::write(&path, program.symbols.to_sym()).map_err(|error| format!("{}: {}", path.display(), error))?;
+    }
+    return Ok(())
+}
@@ -0,0 +1,241 @@
+use std::io::{BufRead, Write};
+
+use rustboy_lib::debugger::condition::{parse_number, Condition};
+use rustboy_lib::debugger::watch::{Access, WatchKind};
+use rustboy_lib::debugger::{disassemble_around, format_pc, format_registers, format_stack, parse_address, parse_location, Breakpoint, Debugger, StopReason};
+use rustboy_lib::disassembler::DisassemblyLine;
+use rustboy_lib::gameboy::GameBoy;
+use rustboy_lib::ppu::lcd::DOTS_PER_FRAME;
+use rustboy_lib::symbols::Symbols;
+
+const HELP: &str = "\
+s, step [n]                    run n instructions (1)
+n, next                        step over CALL and RST
+finish                         run until the current function returns
+c, continue [frames]           run until a breakpoint or watchpoint, at most 3600 frames
+u, until <addr>                run to an address
+rewind [frames]                go back about n frames (60), up to a minute of history
+b, break <[bank:]addr> [if c]  add a breakpoint, conditions like \"a == $3C && [hl] != 0\"
+w, watch <addr>[-<end>] [r|w]  add a watchpoint on reads, writes or both
+d, delete <id>                 remove a breakpoint or watchpoint
+l, list                        list breakpoints and watchpoints
+r, regs                        show the registers, stack and code around PC
+x <addr> [n]                   dump n bytes of memory (64)
+dis [addr] [n]                 disassemble n instructions (10)
+q, quit                        exit
+Addresses can also be labels from the .sym file";
+
+const DEFAULT_FRAMES: u64 = 3600;
+
+// A command line debugger on stdin/stdout. Every stop prints where the CPU is. Addresses
+// can be given as labels from symbols, which also name the code it shows
+pub fn run(gameboy: &mut GameBoy, symbols: &Symbols) -> Result<(), String> {
+    let mut debugger = Debugger::new();
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    println!("{}", describe(gameboy, symbols));
+    loop {
+        print!("(rustboy) ");
+        std::io::stdout().flush().map_err(|error| error.to_string())?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|error| error.to_string())?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = words.split_first() else {
+            continue;
+        };
+        match execute(&mut debugger, gameboy, symbols, command, arguments, &line) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(error) => println!("{}", error),
+        }
+    }
+}
+
+// Returns whether to quit
+fn execute(debugger: &mut Debugger, gameboy: &mut GameBoy, symbols: &Symbols, command: &str, arguments: &[&str], line: &str) -> Result<bool, String> {
+    let frame_cycles = DOTS_PER_FRAME / 4 * if gameboy.cpu.bus.double_speed() { 2 } else { 1 };
+    let argument = |index: usize| arguments.get(index).copied().ok_or_else(|| format!("{} needs more arguments, see help", command));
+    match command {
+        "s" | "step" => {
+            let count = match arguments.first() {
+                Some(count) => parse_number(count)?,
+                None => 1,
+            };
+            let mut reason = StopReason::Done;
+            for _ in 0..count {
+                reason = debugger.step(gameboy);
+                if reason != StopReason::Done {
+                    break;
+                }
+            }
+            report(debugger, gameboy, symbols, reason);
+        }
+        "n" | "next" => {
+            let reason = debugger.step_over(gameboy, DEFAULT_FRAMES * frame_cycles);
+            report(debugger, gameboy, symbols, reason);
+        }
+        "finish" => {
+            let reason = debugger.step_out(gameboy, DEFAULT_FRAMES * frame_cycles);
+            report(debugger, gameboy, symbols, reason);
+        }
+        "c" | "continue" => {
+            let frames = match arguments.first() {
+                Some(frames) => parse_number(frames)? as u64,
+                None => DEFAULT_FRAMES,
+            };
+            let reason = debugger.run(gameboy, frames * frame_cycles);
+            report(debugger, gameboy, symbols, reason);
+        }
+        "u" | "until" => {
+            if argument(0)?.contains(':') {
+                return Err(String::from("until doesn't take a bank"));
+            }
+            let address = parse_address(argument(0)?, symbols)?;
+            let reason = debugger.run_to(gameboy, address, DEFAULT_FRAMES * frame_cycles);
+            report(debugger, gameboy, symbols, reason);
+        }
+        "rewind" => {
+            let frames = match arguments.first() {
+                Some(frames) => parse_number(frames)? as u64,
+                None => 60,
+            };
+            let rewound = gameboy.rewind_frames(frames);
+            if rewound == 0 {
+                return Err(String::from("nothing to rewind to"));
+            }
+            println!("rewound {} frames", rewound);
+            println!("{}", describe(gameboy, symbols));
+        }
+        "b" | "break" => {
+            let (bank, address) = parse_location(argument(0)?, symbols)?;
+            let condition = match line.split_once(" if ") {
+                Some((_, condition)) => Some(condition.parse::<Condition>()?),
+                None => None,
+            };
+            let id = debugger.add_breakpoint(address, bank, condition);
+            println!("breakpoint {}", format_breakpoint(debugger.breakpoints().iter().find(|breakpoint| breakpoint.id == id).unwrap(), symbols));
+        }
+        "w" | "watch" => {
+            let (start, end) = match argument(0)?.split_once('-') {
+                Some((start, end)) => (parse_address(start, symbols)?, parse_address(end, symbols)?),
+                None => (parse_address(argument(0)?, symbols)?, parse_address(argument(0)?, symbols)?),
+            };
+            let kind = match arguments.get(1).copied() {
+                Some("r") => WatchKind::Read,
+                Some("w") => WatchKind::Write,
+                Some("rw") | None => WatchKind::Access,
+                Some(other) => return Err(format!("watch kind is r, w or rw, not {}", other)),
+            };
+            let id = debugger.add_watchpoint(start, end, kind);
+            println!("watchpoint {}", debugger.watchpoints().iter().find(|watchpoint| watchpoint.id == id).unwrap());
+        }
+        "d" | "delete" => {
+            let id = parse_number(argument(0)?)? as usize;
+            if !debugger.remove(id) {
+                return Err(format!("nothing with id {}", id));
+            }
+        }
+        "l" | "list" => {
+            for breakpoint in debugger.breakpoints() {
+                println!("breakpoint {}", format_breakpoint(breakpoint, symbols));
+            }
+            for watchpoint in debugger.watchpoints() {
+                println!("watchpoint {}", watchpoint);
+            }
+        }
+        "r" | "regs" => println!("{}", describe(gameboy, symbols)),
+        "x" => {
+            let start = parse_address(argument(0)?, symbols)?;
+            let count = match arguments.get(1) {
+                Some(count) => parse_number(count)?,
+                None => 64,
+            };
+            for row in (0..count).step_by(16) {
+                let address = start.wrapping_add(row);
+                let bytes: Vec<String> = (0..16.min(count - row))
+                    .map(|offset| format!("{:02X}", gameboy.cpu.bus.peek(address.wrapping_add(offset))))
+                    .collect();
+                println!("${:04X}: {}", address, bytes.join(" "));
+            }
+        }
+        "dis" => {
+            let address = match arguments.first() {
+                Some(address) => parse_address(address, symbols)?,
+                None => gameboy.cpu.pc,
+            };
+            let count = match arguments.get(1) {
+                Some(count) => parse_number(count)? as usize,
+                None => 10,
+            };
+            for line in disassemble_around(&gameboy.cpu.bus, address, 0, count) {
+                print!("{}", format_line(&line, gameboy, symbols, "  "));
+            }
+        }
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(true),
+        _ => return Err(format!("unknown command {}, try help", command)),
+    }
+    Ok(false)
+}
+
+fn report(debugger: &Debugger, gameboy: &GameBoy, symbols: &Symbols, reason: StopReason) {
+    match reason {
+        StopReason::Done => {}
+        StopReason::Breakpoint(id) => {
+            if let Some(breakpoint) = debugger.breakpoints().iter().find(|breakpoint| breakpoint.id == id) {
+                println!("hit breakpoint {}", format_breakpoint(breakpoint, symbols));
+            }
+        }
+        StopReason::Watchpoint(id, access) => {
+            let verb = match access.access {
+                Access::Read => "read",
+                Access::Write => "write",
+            };
+            println!("hit watchpoint #{}: {} of ${:02X} at ${:04X}", id, verb, access.value, access.address);
+        }
+        StopReason::Limit => println!("stopped after running for a while without hitting anything"),
+    }
+    println!("{}", describe(gameboy, symbols));
+}
+
+// Registers, the top of the stack and the code around PC
+fn describe(gameboy: &GameBoy, symbols: &Symbols) -> String {
+    let cpu = &gameboy.cpu;
+    let mut text = format_registers(cpu);
+    if !symbols.is_empty() {
+        text.push_str(&format!("\nat {}", format_pc(cpu, symbols)));
+    }
+    text.push_str("\nstack:");
+    for line in format_stack(cpu, 4) {
+        text.push_str(&format!("\n  {}", line));
+    }
+    text.push_str("\ncode:");
+    text.push('\n');
+    for line in disassemble_around(&cpu.bus, cpu.pc, 3, 5) {
+        let marker = if line.address == cpu.pc { "=>" } else { "  " };
+        text.push_str(&format_line(&line, gameboy, symbols, marker));
+    }
+    return text.trim_end().to_string()
+}
+
+// A line of disassembly with the label that sits at it, if any, on a line before it
+fn format_line(line: &DisassemblyLine, gameboy: &GameBoy, symbols: &Symbols, marker: &str) -> String {
+    let bank = gameboy.cpu.bus.bank_at(line.address);
+    let mut text = String::new();
+    if let Some(label) = symbols.label_at(Some(bank), line.address) {
+        text.push_str(&format!("{}:\n", label));
+    }
+    text.push_str(&format!("{}{}\n", marker, line.labelled(bank, symbols)));
+    return text
+}
+
+fn format_breakpoint(breakpoint: &Breakpoint, symbols: &Symbols) -> String {
+    // Breakpoints without a bank are named after what's in bank 0 or 1
+    let bank = breakpoint.bank.unwrap_or(if breakpoint.address < 0x4000 { 0 } else { 1 });
+    match symbols.name(bank, breakpoint.address) {
+        Some(name) => format!("{} ({})", breakpoint, name),
+        None => breakpoint.to_string(),
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use rustboy_lib::debugger::condition::parse_number;
+use rustboy_lib::disassembler::recursive::CodeMap;
+use rustboy_lib::disassembler::{disassemble, listing};
+
+const USAGE: &str = "usage: rustboy_main disasm <rom> [--bank <n>] [--from <addr>] [--to <addr>] [--sym <file.sym>] [--recursive] [--output <file.asm>]";
+
+const BANK_SIZE: usize = 0x4000;
+
+// Prints one ROM bank, or part of it, as assembly. Bank 0 sits at 0x0000 and every other
+// bank at 0x4000, like when it is switched in. Labels come from --sym, or from a .sym file
+// next to the ROM when there is one. With --recursive it instead follows the code from the
+// entry point through the whole ROM and writes assembly that rgbasm turns back into it
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut rom = None;
+    let mut bank = 0;
+    let mut from = None;
+    let mut to = None;
+    let mut sym = None;
+    let mut recursive = false;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--bank" => bank = parse_number(&value()?)? as usize,
+            "--from" => from = Some(parse_number(&value()?)?),
+            "--to" => to = Some(parse_number(&value()?)?),
+            "--sym" => sym = Some(PathBuf::from(value()?)),
+            "--recursive" => recursive = true,
+            "--output" => output = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => rom = Some(PathBuf::from(arg)),
+        }
+    }
+    let rom_path = rom.ok_or_else(|| String::from(USAGE))?;
+    let rom = std::fs::read(&rom_path).map_err(|error| format!("{}: {}", rom_path.display(), error))?;
+    let symbols = crate::load_symbols(&rom_path, sym)?;
+    if recursive {
+        return write_output(output, &CodeMap::analyze(&rom).to_asm(&rom, &symbols));
+    }
+    let banks = rom.len().div_ceil(BANK_SIZE);
+    if bank >= banks {
+        return Err(format!("{} only has {} banks", rom_path.display(), banks));
+    }
+    let base = if bank == 0 { 0x0000 } else { BANK_SIZE as u16 };
+    let from = from.unwrap_or(base);
+    let to = to.unwrap_or(base + (BANK_SIZE - 1) as u16);
+    if from < base || to < from || to as usize >= base as usize + BANK_SIZE {
+        return Err(format!("bank {} is ${:04X}-${:04X}", bank, base, base as usize + BANK_SIZE - 1));
+    }
+    let start = bank * BANK_SIZE + (from - base) as usize;
+    let end = (bank * BANK_SIZE + (to - base) as usize + 1).min(rom.len());
+    let lines = disassemble(&rom[start.min(end)..end], from);
+    return write_output(output, &listing(&lines, bank, &symbols))
+}
+
+fn write_output(output: Option<PathBuf>, text: &str) -> Result<(), String> {
+    match output {
+        Some(path) => std::fs::write(&path, text).map_err(|error| format!("{}: {}", path.display(), error)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
@@ -1,9 +1,241 @@
-// mod rustboy_lib;
+mod debug;
+mod asm;
+mod disasm;
+mod tracediff;
 
-use rustboy_lib::cpu::CPU;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-fn main() {
-    let cpu = CPU::new();
-    dbg!(cpu);
-    println!("Hello, world!");
+use rustboy_lib::audio::wav::WavRecorder;
+use rustboy_lib::debugger::condition::parse_number;
+use rustboy_lib::debugger::gdb::GdbServer;
+use rustboy_lib::gameboy::{GameBoy, GameBoyOptions};
+use rustboy_lib::memory::boot::BootRom;
+use rustboy_lib::movie::{frame_checksum, Movie};
+use rustboy_lib::ppu::color::ColorCorrection;
+use rustboy_lib::rewind::RewindOptions;
+use rustboy_lib::serial::tcp::TcpLink;
+use rustboy_lib::symbols::Symbols;
+use rustboy_lib::trace::{TraceFilter, Tracer};
+
+const USAGE: &str = "usage: rustboy_main <rom> [--model dmg|mgb|sgb|cgb|agb|dmg0] [--boot-rom <file>] [--dmg-palette <up|down|left|right>[-a|-b]] [--frames <n>] [--screenshot <file.ppm>] [--wav <file.wav>] [--wav-stems] [--record <file.movie>] [--replay <file.movie>] [--link-host <port>] [--link-connect <[host:]port>] [--debug] [--gdb <port>] [--sym <file.sym>] [--trace <file.log>] [--trace-range <start>-<end>] [--trace-bank <n>]
+       rustboy_main disasm <rom> [--bank <n>] [--from <addr>] [--to <addr>] [--sym <file.sym>] [--recursive] [--output <file.asm>]
+       rustboy_main asm <file.asm> --output <file.gb> [--patch <rom>] [--sym <file.sym>]
+       rustboy_main tracediff <ours.log> <reference.log> [--context <n>]";
+
+// Runs a ROM headless for --frames frames, unless one of the modes below takes over.
+// --model, --boot-rom and --dmg-palette set up the console.
+// --screenshot saves the last frame, --wav the audio and --wav-stems each channel too.
+// --record saves a movie from power on, --replay plays one back and checks every frame.
+// --link-host and --link-connect plug two of these together with a link cable over TCP.
+// --debug starts in the debugger, which can rewind, and --gdb waits for a GDB client.
+// --sym names places for both, otherwise the .sym file next to the ROM is used if any.
+// --trace logs every instruction in the gameboy-doctor format, with those names.
+// --trace-range and --trace-bank keep the trace to a PC range or bank
+struct Arguments {
+    rom: PathBuf,
+    options: GameBoyOptions,
+    frames: u32,
+    screenshot: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    wav_stems: bool,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    link: Option<Link>,
+    debug: bool,
+    gdb: Option<u16>,
+    sym: Option<PathBuf>,
+    trace: Option<PathBuf>,
+    trace_filter: TraceFilter,
+}
+
+// Which end of a link cable over TCP this is
+enum Link {
+    Host(u16),
+    Connect(String),
+}
+
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut args = std::env::args().skip(1);
+    let mut rom = None;
+    let mut options = GameBoyOptions::new();
+    let mut frames = 60;
+    let mut screenshot = None;
+    let mut wav = None;
+    let mut wav_stems = false;
+    let mut record = None;
+    let mut replay = None;
+    let mut link = None;
+    let mut debug = false;
+    let mut gdb = None;
+    let mut sym = None;
+    let mut trace = None;
+    let mut trace_filter = TraceFilter::new();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--model" => options.model = value()?.parse()?,
+            "--boot-rom" => {
+                let path = value()?;
+                let data = std::fs::read(&path).map_err(|error| format!("{}: {}", path, error))?;
+                options.boot_rom = Some(BootRom::new(data).map_err(|error| error.to_string())?);
+            }
+            "--dmg-palette" => options.palette_override = Some(value()?.parse()?),
+            "--frames" => frames = value()?.parse().map_err(|_| String::from("--frames needs a number"))?,
+            "--screenshot" => screenshot = Some(PathBuf::from(value()?)),
+            "--wav" => wav = Some(PathBuf::from(value()?)),
+            "--wav-stems" => wav_stems = true,
+            "--record" => record = Some(PathBuf::from(value()?)),
+            "--replay" => replay = Some(PathBuf::from(value()?)),
+            "--link-host" => link = Some(Link::Host(value()?.parse().map_err(|_| String::from("--link-host needs a port number"))?)),
+            "--link-connect" => link = Some(Link::Connect(value()?)),
+            "--debug" => debug = true,
+            "--gdb" => gdb = Some(value()?.parse().map_err(|_| String::from("--gdb needs a port number"))?),
+            "--sym" => sym = Some(PathBuf::from(value()?)),
+            "--trace" => trace = Some(PathBuf::from(value()?)),
+            "--trace-range" => {
+                let range = value()?;
+                let (start, end) = range.split_once('-').ok_or("--trace-range needs <start>-<end>")?;
+                (trace_filter.start, trace_filter.end) = (parse_number(start)?, parse_number(end)?);
+            }
+            "--trace-bank" => trace_filter.bank = Some(parse_number(&value()?)? as usize),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => rom = Some(PathBuf::from(arg)),
+        }
+    }
+    let rom = rom.ok_or_else(|| String::from(USAGE))?;
+    if wav_stems && wav.is_none() {
+        return Err(String::from("--wav-stems needs --wav"));
+    }
+    // Those run until the user stops them, and take the audio along with them
+    if wav.is_some() && (debug || gdb.is_some()) {
+        return Err(String::from("--wav can't be used with --debug or --gdb"));
+    }
+    if record.is_some() && replay.is_some() {
+        return Err(String::from("--record can't be used with --replay"));
+    }
+    // The debugger can step back, nothing else here rewinds
+    if debug {
+        options.rewind = RewindOptions::new();
+    }
+    return Ok(Arguments { rom, options, frames, screenshot, wav, wav_stems, record, replay, link, debug, gdb, sym, trace, trace_filter })
+}
+
+// The symbols in sym, or in the .sym file next to the ROM when there is one
+fn load_symbols(rom: &Path, sym: Option<PathBuf>) -> Result<Symbols, String> {
+    match sym.or_else(|| Some(rom.with_extension("sym")).filter(|path| path.exists())) {
+        Some(path) => Symbols::load(&path),
+        None => Ok(Symbols::new()),
+    }
+}
+
+// Writes the audio produced since the last call, when it is being recorded
+fn record_audio(gameboy: &mut GameBoy, recorder: &mut Option<WavRecorder>) -> Result<(), String> {
+    let samples = gameboy.audio_samples();
+    if let Some(recorder) = recorder {
+        for sample in &samples {
+            recorder.push_frame(sample).map_err(|error| error.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// Binary PPM, the simplest image format anything can open
+fn write_ppm(path: &Path, width: usize, height: usize, frame: &[u16]) -> std::io::Result<()> {
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.extend(ColorCorrection::None.convert_frame(frame));
+    std::fs::write(path, data)
+}
+
+fn run(arguments: Arguments) -> Result<(), String> {
+    let rom = std::fs::read(&arguments.rom).map_err(|error| format!("{}: {}", arguments.rom.display(), error))?;
+    let sample_rate = arguments.options.sample_rate;
+    let mut gameboy = GameBoy::new(rom, arguments.options).map_err(|error| error.to_string())?;
+    if let Some(path) = &arguments.trace {
+        let tracer = Tracer::create(path, arguments.trace_filter).map_err(|error| format!("{}: {}", path.display(), error))?;
+        gameboy.cpu.trace = Some(tracer.with_symbols(load_symbols(&arguments.rom, arguments.sym.clone())?));
+    }
+    // Before the link cable goes in, since this powers the console off and on again. The
+    // debuggers record whatever they run too
+    if arguments.record.is_some() {
+        gameboy.start_recording_from_power_on();
+    }
+    if let Some(link) = &arguments.link {
+        let link = match link {
+            Link::Host(port) => {
+                println!("waiting for the other Game Boy on 127.0.0.1:{}", port);
+                TcpLink::host(*port)
+            }
+            // A bare port is on localhost
+            Link::Connect(address) => match address.parse::<u16>() {
+                Ok(port) => TcpLink::connect(port),
+                Err(_) => TcpLink::connect_to(address.as_str()),
+            },
+        };
+        let link = link.map_err(|error| format!("link cable: {}", error))?;
+        gameboy.cpu.bus.serial.set_link(Box::new(link));
+    }
+    let mut recorder = match &arguments.wav {
+        Some(path) => Some(WavRecorder::create(path, sample_rate, arguments.wav_stems).map_err(|error| error.to_string())?),
+        None => None,
+    };
+    if let Some(port) = arguments.gdb {
+        println!("waiting for GDB on 127.0.0.1:{}", port);
+        let mut server = GdbServer::host(port).map_err(|error| error.to_string())?;
+        server.serve(&mut gameboy).map_err(|error| error.to_string())?;
+    } else if arguments.debug {
+        let symbols = load_symbols(&arguments.rom, arguments.sym.clone())?;
+        debug::run(&mut gameboy, &symbols)?;
+    } else if let Some(path) = &arguments.replay {
+        let data = std::fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let movie = Movie::from_bytes(&data).map_err(|error| format!("{}: {}", path.display(), error))?;
+        // Like replay_movie, a frame at a time so the audio can be written as it goes
+        gameboy.play_movie(&movie).map_err(|error| format!("{}: {}", path.display(), error))?;
+        for (index, &checksum) in movie.checksums.iter().enumerate() {
+            let matches = frame_checksum(gameboy.run_frame()) == checksum;
+            record_audio(&mut gameboy, &mut recorder)?;
+            if !matches {
+                return Err(format!("{}: frame {} of {} doesn't match the recording", path.display(), index + 1, movie.frames()));
+            }
+        }
+        println!("{}: all {} frames match", path.display(), movie.frames());
+    } else {
+        for _ in 0..arguments.frames {
+            gameboy.run_frame();
+            record_audio(&mut gameboy, &mut recorder)?;
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish().map_err(|error| error.to_string())?;
+    }
+    if let (Some(path), Some(movie)) = (&arguments.record, gameboy.stop_recording()) {
+        std::fs::write(path, movie.to_bytes()).map_err(|error| format!("{}: {}", path.display(), error))?;
+    }
+    if let (Some(path), Some(tracer)) = (&arguments.trace, gameboy.cpu.trace.take()) {
+        tracer.finish().map_err(|error| format!("{}: {}", path.display(), error))?;
+    }
+    if let Some(path) = &arguments.screenshot {
+        let (width, height) = gameboy.framebuffer_size();
+        write_ppm(path, width, height, gameboy.framebuffer()).map_err(|error| format!("{}: {}", path.display(), error))?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let result = if std::env::args().nth(1).as_deref() == Some("disasm") {
+        disasm::run(std::env::args().skip(2))
+    } else if std::env::args().nth(1).as_deref() == Some("asm") {
+        asm::run(std::env::args().skip(2))
+    } else if std::env::args().nth(1).as_deref() == Some("tracediff") {
+        tracediff::run(std::env::args().skip(2))
+    } else {
+        parse_arguments().and_then(run)
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
 }
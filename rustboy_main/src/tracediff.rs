@@ -0,0 +1,35 @@
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use rustboy_lib::debugger::condition::parse_number;
+use rustboy_lib::trace::diff_traces;
+
+const USAGE: &str = "usage: rustboy_main tracediff <ours.log> <reference.log> [--context <n>]";
+
+const DEFAULT_CONTEXT: usize = 10;
+
+// Compares a --trace log with one from a reference emulator and shows where they first
+// differ, with the lines before it. Fails when they do
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut context = DEFAULT_CONTEXT;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--context" => context = parse_number(&value()?)? as usize,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let [ours, reference] = &paths[..] else {
+        return Err(String::from(USAGE));
+    };
+    let open = |path: &PathBuf| std::fs::File::open(path).map(BufReader::new).map_err(|error| format!("{}: {}", path.display(), error));
+    match diff_traces(open(ours)?, open(reference)?, context).map_err(|error| error.to_string())? {
+        Some(mismatch) => Err(mismatch.to_string()),
+        None => {
+            println!("traces match");
+            Ok(())
+        }
+    }
+}